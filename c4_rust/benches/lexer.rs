@@ -0,0 +1,67 @@
+//! lexing speed on a generated 1MB source
+//! run with `cargo bench --bench lexer`
+
+use c4_rust::lexer::{Lexer, Token};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// about 1MB of functions, comments and blank lines, the mix real files have
+fn generate_source() -> String {
+    let mut source = String::from("#include <stdio.h>\n\n/* generated for the lexer benchmark */\n");
+    let mut i = 0;
+    while source.len() < 1 << 20 {
+        source.push_str(&format!(
+            "// helper number {i}\n\
+             int helper_{i}(int a, char *s) {{\n\
+             \x20   /* a block comment\n\
+             \x20      over two lines */\n\
+             \x20   int total;\n\
+             \x20   total = a * {i} + 0x1f;   // trailing comment\n\
+             \t\tif (total >= 100 && s[0] != 'x') total = total - 1;\r\n\
+             \x20   printf(\"value %d\\n\", total);\n\
+             \x20   return total;\n\
+             }}\n\n"
+        ));
+        i += 1;
+    }
+    source
+}
+
+/// about 1MB that is mostly comments and indentation, like a heavily documented header
+fn generate_commented_source() -> String {
+    let mut source = String::new();
+    let mut i = 0;
+    while source.len() < 1 << 20 {
+        source.push_str(&format!(
+            "/*\n * block {i}: describes what the next declaration is for,\n * wrapped the way long comments are\n */\n\
+             // a line comment that runs on for a while before the code starts again\n\
+             \t\t        \n\
+             int value_{i};\n"
+        ));
+        i += 1;
+    }
+    source
+}
+
+fn lex_all(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = 0;
+    while lexer.next() != Token::Eof {
+        tokens += 1;
+    }
+    tokens
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let source = generate_source();
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(20);
+    group.bench_function("lex_1mb", |b| b.iter(|| lex_all(&source)));
+    let commented = generate_commented_source();
+    group.throughput(Throughput::Bytes(commented.len() as u64));
+    group.bench_function("comments_1mb", |b| b.iter(|| lex_all(&commented)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+//! parse time on a generated 5000-line source with many globals and functions
+//! run with `cargo bench --bench parser`
+
+use c4_rust::parser::Parser;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// globals up front, then functions that read them and their own locals,
+/// so most identifiers are looked up in a table of a few thousand symbols
+fn generate_source() -> String {
+    let mut source = String::new();
+    for i in 0..1000 {
+        source.push_str(&format!("int global_{i};\n"));
+    }
+    let mut i = 0;
+    while source.lines().count() < 5000 {
+        source.push_str(&format!(
+            "int helper_{i}(int a, int b) {{\n\
+             \x20   int total; int step;\n\
+             \x20   total = a + global_{g} * b;\n\
+             \x20   step = 0;\n\
+             \x20   while (step < b) {{ total = total + global_{h} - step; step = step + 1; }}\n\
+             \x20   if (total > global_{g}) total = total - a;\n\
+             \x20   return total;\n\
+             }}\n\n",
+            g = (i * 7) % 1000,
+            h = (i * 13) % 1000,
+        ));
+        i += 1;
+    }
+    source.push_str("int main() { return helper_0(1, 2); }\n");
+    source
+}
+
+fn parse(source: &str) -> usize {
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    parser.parse().unwrap().0.len()
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let source = generate_source();
+    let mut group = c.benchmark_group("parser");
+    group.sample_size(20);
+    group.bench_function("parse_5k_lines", |b| b.iter(|| parse(&source)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_parser);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! interpreter throughput on small programs that stay under the cycle limit
+//! run with `cargo bench --bench vm`
+
+use c4_rust::parser::Parser;
+use c4_rust::vm::VM;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+/// a counting loop, mostly loads, stores and branches
+const LOOP: &str = "int main() {
+    int i; int total;
+    i = 0; total = 0;
+    while (i < 1500) { total = total + i * 3; i = i + 1; }
+    return total & 255;
+}";
+
+/// recursive calls, mostly JSR, ENT, LEV and ADJ
+const FIB: &str = "int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+int main() { return fib(15); }";
+
+/// int stores and loads through a heap pointer
+const MEMORY: &str = "int main() {
+    int *buf; int i; int sum;
+    buf = malloc(600 * sizeof(int));
+    i = 0;
+    while (i < 600) { buf[i] = i; i = i + 1; }
+    sum = 0; i = 0;
+    while (i < 600) { sum = sum + buf[i]; i = i + 1; }
+    return sum & 255;
+}";
+
+fn compile(source: &str) -> (Vec<i64>, Vec<u8>) {
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    parser.parse().unwrap()
+}
+
+fn bench_vm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm");
+    group.sample_size(30);
+    for (name, source) in [("loop", LOOP), ("fib", FIB), ("memory", MEMORY)] {
+        let (code, data) = compile(source);
+        // only the run is timed, not setting up the stack
+        group.bench_function(name, |b| b.iter_batched(
+            || VM::new(code.clone(), data.clone(), false),
+            |mut vm| vm.run().unwrap(),
+            BatchSize::LargeInput,
+        ));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_vm);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+#ifndef C4_RUST_H
+#define C4_RUST_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * native function guest code can call, gets the arguments in order
+ */
+typedef int64_t (*C4HostFn)(void *user, const int64_t *args, size_t argc);
+
+/**
+ * outcome of c4_compile or c4_run, release it with c4_free_result
+ */
+typedef struct C4Result {
+  bool ok;
+  int64_t exit_code;
+  char *output;
+  char *error;
+} C4Result;
+
+/**
+ * makes a C function callable from guest code by name in later compiles and runs
+ *
+ * returns 0, or -1 if name is null or not UTF-8; registering a name again replaces it
+ *
+ * # Safety
+ *
+ * name must be null or a NUL-terminated string
+ */
+int32_t c4_register_host_fn(const char *name, size_t argc, C4HostFn func, void *user);
+
+/**
+ * compiles source without running it
+ *
+ * # Safety
+ *
+ * source must be null or a NUL-terminated string
+ */
+struct C4Result *c4_compile(const char *source);
+
+/**
+ * compiles and runs source, input is what the program reads from fd 0 and may be null when input_len is 0
+ *
+ * # Safety
+ *
+ * source must be null or a NUL-terminated string, input must point at input_len bytes
+ */
+struct C4Result *c4_run(const char *source,
+                        const uint8_t *input,
+                        size_t input_len);
+
+/**
+ * releases a result from c4_compile or c4_run
+ *
+ * # Safety
+ *
+ * result must be null or come from c4_compile or c4_run, and not be freed twice
+ */
+void c4_free_result(struct C4Result *result);
+
+#endif  /* C4_RUST_H */
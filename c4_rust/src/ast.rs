@@ -0,0 +1,280 @@
+//! syntax tree the parser builds before any code is generated
+//! nodes keep the source line their diagnostics are reported at, types stay as written
+//! until code generation looks up the struct tags
+
+use crate::parser::Type;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Index;
+
+/// a whole source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub forward: Vec<Forward>, // what the pre-pass declares before any code, so order doesn't matter
+    pub decls: Vec<Decl>,      // top-level declarations in source order
+    pub arena: Arena,          // every expression and statement the declarations refer to
+}
+
+impl Program {
+    /// the first declaration that didn't parse
+    pub fn first_error(&self) -> Option<&str> {
+        self.decls.iter().find_map(|decl| match decl {
+            Decl::Error(error) => Some(error.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// an expression's position in the arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprId(usize);
+
+/// a statement's position in the arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StmtId(usize);
+
+/// the nodes of a syntax tree, two growing Vecs rather than an allocation per node,
+/// a node names its children by id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Arena {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+}
+
+impl Arena {
+    /// adds an expression whose children are already in the arena
+    pub fn push_expr(&mut self, expr: Expr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId(self.exprs.len() - 1)
+    }
+
+    /// adds a statement whose children are already in the arena
+    pub fn push_stmt(&mut self, stmt: Stmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId(self.stmts.len() - 1)
+    }
+
+    /// whether an '=' appears outside any parentheses or brackets, as in `if (x = 5)`
+    pub fn assigns_unbracketed(&self, id: ExprId) -> bool {
+        match &self[id].kind {
+            ExprKind::Assign(..) => true,
+            ExprKind::Unary(_, operand) | ExprKind::Cast(_, operand) | ExprKind::PostInc(operand) |
+            ExprKind::PostDec(operand) | ExprKind::Member(operand, ..) | ExprKind::Index(operand, _) => self.assigns_unbracketed(*operand),
+            ExprKind::Binary(_, lhs, rhs) | ExprKind::CompoundAssign(_, lhs, rhs) => self.assigns_unbracketed(*lhs) || self.assigns_unbracketed(*rhs),
+            ExprKind::Cond(cond, when_true, when_false) => {
+                self.assigns_unbracketed(*cond) || self.assigns_unbracketed(*when_true) || self.assigns_unbracketed(*when_false)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Index<ExprId> for Arena {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.0]
+    }
+}
+
+impl Index<StmtId> for Arena {
+    type Output = Stmt;
+
+    fn index(&self, id: StmtId) -> &Stmt {
+        &self.stmts[id.0]
+    }
+}
+
+/// a global or function found by the pre-pass, which reads only the top level
+#[derive(Debug, Clone, PartialEq)]
+pub enum Forward {
+    Global { name: String, typ: Type, line: usize },
+    Function { name: String, typ: Type, variadic: bool, body: bool }, // body is false for a prototype
+}
+
+/// one top-level declaration
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    /// `int a, *b = 2;`, typed is false when the type was left out
+    Variables { spec: TypeSpec, typed: bool, vars: Vec<(Declarator, Option<Initializer>)> },
+    Function(Function),
+    /// `enum { A, B = 4 };` with the line of a '}' right after a comma
+    Enum { members: Vec<Enumerator>, trailing_comma: Option<usize> },
+    /// `extern int name(int, char *);`, only the argument count matters
+    Extern { name: String, argc: usize, line: usize },
+    /// a declaration that failed to parse, "Line N: ..."
+    Error(String),
+}
+
+/// a type before the declarator, like `char` or `struct node`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeSpec {
+    Int,
+    Char,
+    Void,
+    Struct(StructSpec),
+}
+
+/// `struct tag`, or `struct tag { members }` which defines the tag
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructSpec {
+    pub tag: String,
+    pub members: Option<Vec<MemberDecl>>, // None when only the tag is named
+    pub line: usize,                      // line of the definition's '{'
+}
+
+/// `int a, *b[4];` inside a struct definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberDecl {
+    pub spec: TypeSpec,
+    pub declarators: Vec<Declarator>,
+}
+
+/// `*name[size]`, what follows the type in a declaration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declarator {
+    pub name: String,
+    pub pointers: usize,
+    pub size: Option<ExprId>, // between the brackets of an array
+    pub line: usize,          // line of the token after it
+}
+
+/// the type of a cast or sizeof, like `char *`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeName {
+    pub spec: TypeSpec,
+    pub pointers: usize,
+}
+
+/// what a global starts out as
+#[derive(Debug, Clone, PartialEq)]
+pub enum Initializer {
+    Expr(ExprId),             // a constant, or a string
+    List(Vec<ExprId>, usize), // `{ 1, 2 }` and the line of its '{'
+}
+
+/// one constant of an enum
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enumerator {
+    pub name: String,
+    pub value: Option<ExprId>, // after '=', else one more than the last
+    pub line: usize,           // line of the name
+}
+
+/// a function definition, or a prototype when there is no body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub spec: TypeSpec,
+    pub typed: bool,
+    pub declarator: Declarator, // name and return type
+    pub params: Params,
+    pub body: Option<Vec<StmtId>>,
+    pub line: usize, // line of the '(' after the name
+}
+
+/// the parameter list of a function
+#[derive(Debug, Clone, PartialEq)]
+pub enum Params {
+    /// `()`, which says nothing about the arguments
+    Unspecified,
+    /// `(int a, char *b, ...)`, `(void)` is an empty list
+    List { params: Vec<Param>, variadic: bool },
+    /// `(a, b) int a; char *b;` with the line of the body's '{'
+    OldStyle { names: Vec<(String, usize)>, decls: Vec<Param>, line: usize },
+}
+
+/// one parameter, an array is a pointer to its first element
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub spec: TypeSpec,
+    pub pointers: usize,
+    pub name: Option<String>, // only a prototype may leave it out
+    pub array: bool,          // written with brackets
+    pub size: Option<ExprId>, // between them, only checked
+    pub line: usize,          // line of the token after it
+}
+
+/// a statement and the line of its first token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Expr(ExprId),
+    /// `int a, *b = p;` at the start of a block item
+    Local(TypeSpec, Vec<(Declarator, Option<ExprId>)>),
+    If(ExprId, StmtId, Option<StmtId>),
+    While(ExprId, StmtId),
+    For(Option<ExprId>, Option<ExprId>, Option<ExprId>, StmtId),
+    Switch(ExprId, StmtId),
+    Case(ExprId, StmtId),
+    Default(StmtId),
+    Break,
+    Continue,
+    Return(Option<ExprId>),
+    Block(Vec<StmtId>),
+    Empty,
+}
+
+/// an expression and the line of the token after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Num(i64),
+    Str(Vec<u8>, usize), // adjacent literals joined, without the 0, and the line of the first
+    Sizeof(TypeName),
+    Var(String),
+    Call(String, Vec<ExprId>),
+    Paren(ExprId),
+    Cast(TypeName, ExprId),
+    Unary(UnaryOp, ExprId),
+    Binary(BinaryOp, ExprId, ExprId),
+    Assign(ExprId, ExprId),
+    CompoundAssign(BinaryOp, ExprId, ExprId),
+    Cond(ExprId, ExprId, ExprId),
+    Index(ExprId, ExprId),
+    Member(ExprId, String, bool), // the struct, the member and whether it was '->'
+    PostInc(ExprId),
+    PostDec(ExprId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Deref,
+    Addr,
+    Not,
+    BitNot,
+    Plus,
+    Neg,
+    PreInc,
+    PreDec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Mod,
+    Shl, Shr, And, Or, Xor,
+    Eq, Ne, Lt, Gt, Le, Ge,
+    Lan, Lor,
+}
+
+impl BinaryOp {
+    /// how the operator is written
+    pub fn spelling(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+", BinaryOp::Sub => "-", BinaryOp::Mul => "*", BinaryOp::Div => "/",
+            BinaryOp::Mod => "%", BinaryOp::Shl => "<<", BinaryOp::Shr => ">>", BinaryOp::And => "&",
+            BinaryOp::Or => "|", BinaryOp::Xor => "^", BinaryOp::Eq => "==", BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<", BinaryOp::Gt => ">", BinaryOp::Le => "<=", BinaryOp::Ge => ">=",
+            BinaryOp::Lan => "&&", BinaryOp::Lor => "||",
+        }
+    }
+}
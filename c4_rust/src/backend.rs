@@ -0,0 +1,116 @@
+//! code generators behind one interface
+//! a backend turns a parsed program into its artifact, so a driver only picks one by name
+
+use crate::ast::Program;
+use crate::codegen::CodeGen;
+use crate::disasm;
+use crate::image::Image;
+use crate::options::CompileOptions;
+use crate::parser::FunctionInfo;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// what a backend produces
+#[derive(Debug, Clone, PartialEq)]
+pub enum Artifact {
+    Image(Image), // bytecode the VM runs, saved as a .c4b file
+    Text(String), // readable output, like an assembly listing
+}
+
+impl Artifact {
+    /// the bytes a file holding the artifact gets
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Artifact::Image(image) => image.to_bytes(),
+            Artifact::Text(text) => text.as_bytes().to_vec(),
+        }
+    }
+
+    /// whether the artifact can be shown on a terminal as it is
+    pub fn is_text(&self) -> bool {
+        matches!(self, Artifact::Text(_))
+    }
+}
+
+/// one target of the compiler
+pub trait Backend {
+    /// name a driver picks the backend by, like "bytecode"
+    fn name(&self) -> &'static str;
+
+    /// compiles the syntax tree the front end parsed once, Err holds the first error as "Line N: ..."
+    fn emit(&mut self, program: &Program, options: &CompileOptions) -> Result<Artifact, String>;
+
+    /// non-fatal diagnostics of the last emit
+    fn warnings(&self) -> &[String] {
+        &[]
+    }
+}
+
+/// the VM's bytecode, the image the command line runs
+#[derive(Default)]
+pub struct Bytecode {
+    warnings: Vec<String>,
+}
+
+impl Backend for Bytecode {
+    fn name(&self) -> &'static str {
+        "bytecode"
+    }
+
+    fn emit(&mut self, program: &Program, options: &CompileOptions) -> Result<Artifact, String> {
+        let (image, _) = compile(program, options, &mut self.warnings)?;
+        Ok(Artifact::Image(image))
+    }
+
+    fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+/// the bytecode as an assembly listing, one function after another
+#[derive(Default)]
+pub struct Listing {
+    warnings: Vec<String>,
+}
+
+impl Backend for Listing {
+    fn name(&self) -> &'static str {
+        "listing"
+    }
+
+    fn emit(&mut self, program: &Program, options: &CompileOptions) -> Result<Artifact, String> {
+        let (image, functions) = compile(program, options, &mut self.warnings)?;
+        Ok(Artifact::Text(disasm::disassemble(&image.code, &functions)))
+    }
+
+    fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+// generates the bytecode from the tree the way Parser::parse does, checks and optimizations included
+fn compile(program: &Program, options: &CompileOptions, warnings: &mut Vec<String>) -> Result<(Image, Vec<FunctionInfo>), String> {
+    let mut gen = CodeGen::new(options.clone());
+    gen.declare_builtins();
+    let result = gen.generate(program, true);
+    *warnings = gen.warnings().to_vec();
+    let (code, data) = result?;
+    let image = Image { code, data, strings: gen.strings().to_vec() };
+    Ok((image, gen.functions().to_vec()))
+}
+
+/// every backend, the default one first
+pub fn backends() -> Vec<Box<dyn Backend>> {
+    Vec::from([Box::new(Bytecode::default()) as Box<dyn Backend>, Box::new(Listing::default())])
+}
+
+/// the backend with this name
+pub fn by_name(name: &str) -> Result<Box<dyn Backend>, String> {
+    let names: Vec<&str> = backends().iter().map(|backend| backend.name()).collect();
+    backends()
+        .into_iter()
+        .find(|backend| backend.name() == name)
+        .ok_or_else(|| format!("unknown backend '{}', expected one of {}", name, names.join(", ")))
+}
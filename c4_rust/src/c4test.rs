@@ -0,0 +1,72 @@
+//! runner for unit tests written in C
+//! every function named test_* is a test, returning 0 means it passed
+
+use crate::host::DefaultHost;
+use crate::options::CompileOptions;
+use crate::parser::{Parser, SymbolClass};
+use crate::vm::{HostFns, SharedProgram, VM};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// outcome of one test function
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,                // function name
+    pub line: usize,                 // line of the definition
+    pub result: Result<i64, String>, // return value, or why it couldn't finish
+}
+
+impl TestResult {
+    /// true when the test returned 0
+    pub fn passed(&self) -> bool {
+        self.result == Ok(0)
+    }
+}
+
+/// compiles source once and runs each test_* function in a fresh VM, in source order,
+/// the VMs share the code and copy the data only when a test writes to it
+pub fn run_tests(source: &str, options: &CompileOptions) -> Result<Vec<TestResult>, String> {
+    let mut parser = Parser::new(source, options.clone());
+    let (code, data) = parser.parse_library()?;
+    let program = SharedProgram::new(&code, data);
+
+    let mut tests: Vec<_> = parser.functions().iter()
+        .filter(|f| f.name.starts_with("test_"))
+        .filter(|f| parser.get_symbols().iter().any(|s| s.name == f.name && s.class == SymbolClass::Fun))
+        .collect();
+    tests.sort_by_key(|f| f.start);
+
+    let mut results = Vec::new();
+    for test in tests {
+        let result = if test.params > 0 {
+            Err("test functions take no arguments".to_string())
+        } else {
+            VM::load_shared(&parser, &program, options, Box::new(DefaultHost::default()), HostFns::new())
+                .and_then(|mut vm| {
+                    vm.set_entry(test.start);
+                    vm.run().map_err(|e| e.to_string())
+                })
+        };
+        results.push(TestResult { name: test.name.clone(), line: test.line, result });
+    }
+    Ok(results)
+}
+
+/// one line per test and a summary, like cargo test
+pub fn report(results: &[TestResult]) -> String {
+    let mut out = String::new();
+    for test in results {
+        let status = match &test.result {
+            Ok(0) => "ok".to_string(),
+            Ok(value) => format!("FAILED (returned {})", value),
+            Err(e) => format!("FAILED ({})", e),
+        };
+        out.push_str(&format!("test {} ... {}\n", test.name, status));
+    }
+    let passed = results.iter().filter(|t| t.passed()).count();
+    let result = if passed == results.len() { "ok" } else { "FAILED" };
+    out.push_str(&format!("\ntest result: {}. {} passed; {} failed\n", result, passed, results.len() - passed));
+    out
+}
@@ -0,0 +1,135 @@
+//! static call graph built from the JSR sites in each function
+//! flags recursion, direct or through other functions
+
+use crate::parser::{FunctionInfo, OpCode};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// who calls whom
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    pub functions: Vec<(String, usize)>,   // name and line of each function, in code order
+    pub calls: Vec<(usize, usize, usize)>, // caller, callee and number of call sites, callers in order
+}
+
+impl CallGraph {
+    /// scans each function's code for calls
+    pub fn build(code: &[i64], functions: &[FunctionInfo]) -> CallGraph {
+        let mut sorted: Vec<&FunctionInfo> = functions.iter().collect();
+        sorted.sort_by_key(|f| f.start);
+        let index_at = |addr: i64| sorted.iter().position(|f| f.start as i64 == addr);
+
+        let mut calls: Vec<(usize, usize, usize)> = Vec::new();
+        for (caller, function) in sorted.iter().enumerate() {
+            let mut pc = function.start;
+            while pc < function.end.min(code.len()) {
+                let Some(op) = OpCode::from_i64(code[pc]) else { break };
+                if op == OpCode::JSR {
+                    if let Some(callee) = code.get(pc + 1).and_then(|&addr| index_at(addr)) {
+                        match calls.iter_mut().find(|(from, to, _)| *from == caller && *to == callee) {
+                            Some(edge) => edge.2 += 1,
+                            None => calls.push((caller, callee, 1)),
+                        }
+                    }
+                }
+                pc += 1 + op.arity();
+            }
+        }
+        let functions = sorted.iter().map(|f| (f.name.clone(), f.line)).collect();
+        CallGraph { functions, calls }
+    }
+
+    /// functions reachable from f through one or more calls
+    fn reachable(&self, f: usize) -> Vec<bool> {
+        let mut seen = vec![false; self.functions.len()];
+        let mut work: Vec<usize> = self.callees(f).collect();
+        while let Some(g) = work.pop() {
+            if !seen[g] {
+                seen[g] = true;
+                work.extend(self.callees(g));
+            }
+        }
+        seen
+    }
+
+    fn callees(&self, f: usize) -> impl Iterator<Item = usize> + '_ {
+        self.calls.iter().filter(move |(from, _, _)| *from == f).map(|(_, to, _)| *to)
+    }
+
+    /// true when f can end up calling itself
+    pub fn is_recursive(&self, f: usize) -> bool {
+        self.reachable(f)[f]
+    }
+
+    /// groups of functions that call each other in a loop, a lone entry calls itself directly
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let reach: Vec<Vec<bool>> = (0..self.functions.len()).map(|f| self.reachable(f)).collect();
+        let mut grouped = vec![false; self.functions.len()];
+        let mut cycles = Vec::new();
+        for f in 0..self.functions.len() {
+            if grouped[f] || !reach[f][f] {
+                continue;
+            }
+            let cycle: Vec<usize> = (f..self.functions.len()).filter(|&g| reach[f][g] && reach[g][f]).collect();
+            for &g in &cycle {
+                grouped[g] = true;
+            }
+            cycles.push(cycle);
+        }
+        cycles
+    }
+
+    /// Graphviz source, recursive functions and the calls that close a loop in red
+    pub fn to_dot(&self) -> String {
+        let recursive: Vec<bool> = (0..self.functions.len()).map(|f| self.is_recursive(f)).collect();
+        let mut out = String::from("digraph calls {\n");
+        for (f, (name, _)) in self.functions.iter().enumerate() {
+            let style = if recursive[f] { " [color=red]" } else { "" };
+            out.push_str(&format!("  \"{}\"{};\n", name.escape_default(), style));
+        }
+        for &(from, to, sites) in &self.calls {
+            let mut attrs = Vec::new();
+            if sites > 1 {
+                attrs.push(format!("label=\"{}\"", sites));
+            }
+            if self.reachable(to)[from] {
+                attrs.push(String::from("color=red"));
+            }
+            let attrs = if attrs.is_empty() { String::new() } else { format!(" [{}]", attrs.join(", ")) };
+            out.push_str(&format!("  \"{}\" -> \"{}\"{};\n",
+                                  self.functions[from].0.escape_default(), self.functions[to].0.escape_default(), attrs));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// the same graph as JSON
+    pub fn to_json(&self) -> String {
+        let name = |f: usize| format!("\"{}\"", self.functions[f].0.escape_default());
+        let functions: Vec<String> = self.functions.iter().enumerate()
+            .map(|(f, (_, line))| format!(
+                "    {{\"name\": {}, \"line\": {}, \"recursive\": {}}}", name(f), line, self.is_recursive(f)
+            ))
+            .collect();
+        let calls: Vec<String> = self.calls.iter()
+            .map(|&(from, to, sites)| format!(
+                "    {{\"caller\": {}, \"callee\": {}, \"sites\": {}}}", name(from), name(to), sites
+            ))
+            .collect();
+        let cycles: Vec<String> = self.cycles().iter()
+            .map(|cycle| format!("    [{}]", cycle.iter().map(|&f| name(f)).collect::<Vec<_>>().join(", ")))
+            .collect();
+
+        // one item per line, or [] when empty
+        let list = |items: &[String]| if items.is_empty() {
+            String::from("[]")
+        } else {
+            format!("[\n{}\n  ]", items.join(",\n"))
+        };
+
+        format!("{{\n  \"functions\": {},\n  \"calls\": {},\n  \"cycles\": {}\n}}\n",
+                list(&functions), list(&calls), list(&cycles))
+    }
+}
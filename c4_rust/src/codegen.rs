@@ -0,0 +1,2004 @@
+//! generates VM code from the syntax tree
+//! checks types, lays out globals and locals and resolves calls in one walk over the declarations
+
+#![deny(clippy::unwrap_used)]
+
+use crate::ast::{
+    Arena, BinaryOp, Decl, Declarator, Enumerator, ExprId, ExprKind, Forward, Function, Initializer, Param, Params,
+    Program, StmtId, StmtKind, StructSpec, TypeSpec, UnaryOp,
+};
+use crate::ir;
+use crate::options::{CompileOptions, Warning};
+use crate::parser::{eval_constant, printf_arg_count, verify_jumps, FunctionInfo, OpCode, StructType, Symbol, SymbolClass, Type};
+use crate::vm::DATA_STACK_THRESHOLD;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// case labels collected while generating a switch body
+#[derive(Default)]
+struct SwitchLabels {
+    cases: Vec<(i64, usize)>, // case value and its code position
+    default: Option<usize>,   // code position of default:
+}
+
+/// generates code
+pub struct CodeGen {
+    symbols: Vec<Symbol>,
+    symbol_index: BTreeMap<String, Vec<usize>>, // positions in symbols of each name, innermost last
+    structs: BTreeMap<String, StructType>, // struct tags defined so far, with their layout
+    code: Vec<i64>,
+    data: Vec<u8>,
+    current_type: Type,
+    locals: usize,
+    max_locals: usize, // most locals live at once in this function, sizes the frame
+    params: usize, // parameters of the function being generated
+    scope_start: usize, // first symbol of the innermost block
+    scope_slots: Vec<Symbol>, // locals of blocks already closed
+    functions: Vec<FunctionInfo>, // every function generated so far
+    switches: Vec<SwitchLabels>, // labels of the switches being generated
+    breaks: Vec<Vec<usize>>, // unpatched break jumps per enclosing loop or switch
+    continues: Vec<(usize, Vec<usize>)>, // switches open outside each enclosing loop, and its unpatched continue jumps
+    predeclared: BTreeSet<String>, // globals and functions the pre-pass declared, not yet reached
+    forward_calls: Vec<(String, usize)>, // JSR operands waiting for a function's address
+    forward_arity: Vec<(String, usize, usize)>, // name, argument count and line of calls checked once the callee is generated
+    variadic: BTreeSet<String>, // functions whose parameter list ends in '...'
+    va_named: Option<usize>, // named parameters of the variadic function being generated
+    host_fns: Vec<(String, usize)>, // name and argument count of each host function, by index
+    warnings: Vec<String>, // non-fatal diagnostics, "Line N: ..."
+    errors: Vec<String>, // declarations that failed under keep_going, "Line N: ..."
+    strings: Vec<(usize, usize)>, // data offset and line of each string literal
+    lines: Vec<(usize, usize)>, // code address and source line of each statement, in code order
+    options: CompileOptions, // settings for this compile
+    debug: bool, // debug flag, copied from options
+}
+
+impl CodeGen {
+    /// empty tables, with the data segment starting after a test string
+    pub fn new(options: impl Into<CompileOptions>) -> Self {
+        let options = options.into();
+        let mut data = Vec::new();
+        
+        // Add a test string at the beginning of the data segment
+        // This ensures we have at least one valid string in the data segment
+        let test_str = "Hello world!\n";
+        for byte in test_str.bytes() {
+            data.push(byte);
+        }
+        data.push(0); // null terminator
+        
+        // Align to 8-byte boundary
+        while data.len() % 8 != 0 {
+            data.push(0);
+        }
+        
+        CodeGen {
+            symbols: Vec::new(),
+            symbol_index: BTreeMap::new(),
+            structs: BTreeMap::new(),
+            code: Vec::new(),
+            data,
+            current_type: Type::Int,
+            locals: 0,
+            max_locals: 0,
+            params: 0,
+            scope_start: 0,
+            scope_slots: Vec::new(),
+            functions: Vec::new(),
+            switches: Vec::new(),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+            predeclared: BTreeSet::new(),
+            forward_calls: Vec::new(),
+            forward_arity: Vec::new(),
+            variadic: BTreeSet::new(),
+            va_named: None,
+            host_fns: Vec::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            strings: Vec::new(),
+            lines: Vec::new(),
+            debug: options.debug,
+            options,
+        }
+    }
+    
+    /// adds the system calls every program can call, Parser::init does it before the first token
+    pub fn declare_builtins(&mut self) {
+        self.add_syscall("open", OpCode::OPEN as i64);
+        self.add_syscall("read", OpCode::READ as i64);
+        self.add_syscall("close", OpCode::CLOS as i64);
+        self.add_syscall("printf", OpCode::PRTF as i64);
+        self.add_syscall("malloc", OpCode::MALC as i64);
+        self.add_syscall("free", OpCode::FREE as i64);
+        self.add_syscall("memset", OpCode::MSET as i64);
+        self.add_syscall("memcmp", OpCode::MCMP as i64);
+        self.add_syscall("exit", OpCode::EXIT as i64);
+        self.add_syscall("clock", OpCode::CLCK as i64);
+        self.add_syscall("usleep", OpCode::USLP as i64);
+        self.add_syscall("hexdump", OpCode::HXDP as i64);
+        self.add_syscall("getenv", OpCode::GENV as i64);
+        self.add_syscall("system", OpCode::SYST as i64);
+        self.add_syscall("popen", OpCode::POPN as i64);
+        self.add_syscall("pclose", OpCode::CLOS as i64);
+    }
+    
+    /// makes a host function callable by name, the index must match VM::add_host_fn
+    pub fn add_host_fn(&mut self, name: &str, argc: usize) -> usize {
+        let index = self.host_fns.len();
+        self.host_fns.push((name.to_string(), argc));
+        self.push_symbol(Symbol {
+            name: name.to_string(),
+            class: SymbolClass::Host,
+            typ: Type::Int,
+            value: index as i64,
+            prev_class: None,
+            prev_type: None,
+            prev_value: None,
+        });
+        index
+    }
+    
+    /// add a system call to the symbol table
+    fn add_syscall(&mut self, name: &str, id: i64) {
+        let symbol = Symbol {
+            name: name.to_string(),
+            class: SymbolClass::Sys,
+            typ: Type::Int,
+            value: id,
+            prev_class: None,
+            prev_type: None,
+            prev_value: None,
+        };
+        
+        self.push_symbol(symbol);
+    }
+    
+    /// find a symbol in the symbol table by name
+    pub(crate) fn find_symbol(&self, name: &str) -> Option<&Symbol> {
+        // latest first, so locals shadow globals
+        self.symbol_position(name).map(|index| &self.symbols[index])
+    }
+    
+    /// position in symbols of the innermost symbol with this name
+    fn symbol_position(&self, name: &str) -> Option<usize> {
+        self.symbol_index.get(name).and_then(|positions| positions.last().copied())
+    }
+    
+    /// appends a symbol, shadowing any earlier one with the same name
+    pub(crate) fn push_symbol(&mut self, symbol: Symbol) -> usize {
+        let index = self.symbols.len();
+        self.symbol_index.entry(symbol.name.clone()).or_default().push(index);
+        self.symbols.push(symbol);
+        index
+    }
+    
+    /// removes the symbols from position start on, they are the newest so each is last under its name
+    fn split_off_symbols(&mut self, start: usize) -> Vec<Symbol> {
+        let removed = self.symbols.split_off(start.min(self.symbols.len()));
+        for symbol in &removed {
+            if let Some(positions) = self.symbol_index.get_mut(&symbol.name) {
+                positions.pop();
+                if positions.is_empty() {
+                    self.symbol_index.remove(&symbol.name);
+                }
+            }
+        }
+        removed
+    }
+    
+    /// drops every local, keeping the symbols declared between them in order
+    fn drop_locals(&mut self) {
+        let first = self.symbols.iter().position(|s| s.class == SymbolClass::Loc).unwrap_or(self.symbols.len());
+        for symbol in self.split_off_symbols(first) {
+            if symbol.class != SymbolClass::Loc {
+                self.push_symbol(symbol);
+            }
+        }
+    }
+    
+    /// add a new symbol to the symbol table, a builtin like read is shadowed rather than redefined
+    fn add_symbol(&mut self, name: &str, class: SymbolClass, typ: Type, value: i64) -> Result<&mut Symbol, String> {
+        // Check if symbol already exists
+        if self.find_symbol(name).is_some_and(|symbol| symbol.class != SymbolClass::Sys) {
+            return Err(format!("Symbol '{}' already defined", name));
+        }
+        
+        // Add new symbol
+        let symbol = Symbol {
+            name: name.to_string(),
+            class,
+            typ,
+            value,
+            prev_class: None,
+            prev_type: None,
+            prev_value: None,
+        };
+        
+        let index = self.push_symbol(symbol);
+        Ok(&mut self.symbols[index])
+    }
+    
+    /// adds a local that shadows an outer symbol, remembering what it hides
+    fn add_shadowing_local(&mut self, name: &str, typ: Type, value: i64, hidden: usize) {
+        let hidden = &self.symbols[hidden];
+        let symbol = Symbol {
+            name: name.to_string(),
+            class: SymbolClass::Loc,
+            typ,
+            value,
+            prev_class: Some(hidden.class),
+            prev_type: Some(hidden.typ.clone()),
+            prev_value: Some(hidden.value),
+        };
+        self.push_symbol(symbol);
+    }
+    
+    /// whether the outermost symbol with this name is one of the syscalls declare_builtins registers
+    fn is_builtin(&self, name: &str) -> bool {
+        self.symbol_index.get(name).and_then(|positions| positions.first()).is_some_and(|&index| self.symbols[index].class == SymbolClass::Sys)
+    }
+    
+    /// warns that a global definition takes a builtin's name, calls anywhere in the program reach it instead
+    fn shadow_builtin(&mut self, name: &str, line: usize) {
+        if self.is_builtin(name) {
+            self.warn_at(line, Warning::ShadowBuiltin, format!("'{}' replaces the builtin function of that name for the whole program", name));
+        }
+    }
+    
+    /// generates every declaration and returns the code and data segments,
+    /// resolving the entry stub's call to main when there is one
+    pub fn generate(&mut self, program: &Program, need_main: bool) -> Result<(Vec<i64>, Vec<u8>), String> {
+        // Entry stub: call main, then exit with its return value
+        self.code.push(OpCode::JSR as i64);
+        let main_slot = self.code.len();
+        self.code.push(0); // patched once main is known
+        self.code.push(OpCode::PSH as i64);
+        self.code.push(OpCode::EXIT as i64);
+        
+        // Declare every global and function the pre-pass found so order doesn't matter
+        self.declare_forward(&program.forward)?;
+        
+        // keep_going moves on to the next declaration
+        for decl in &program.decls {
+            let (code_len, function_count) = (self.code.len(), self.functions.len());
+            let result = match decl {
+                Decl::Error(error) => Err(error.clone()),
+                decl => self.declaration(&program.arena, decl),
+            };
+            if let Err(e) = result {
+                if !self.options.keep_going {
+                    return Err(e);
+                }
+                self.drop_failed_declaration(e, code_len, function_count);
+            }
+        }
+        
+        // Debug: Print all symbols in the table
+        if self.debug {
+            println!("Symbol table contents:");
+            for sym in &self.symbols {
+                println!("Symbol: {}, Class: {:?}, Type: {}, Value: {}",
+                        sym.name, sym.class, sym.typ, sym.value);
+            }
+        }
+        
+        // What compiled stays in code() and functions(), errors() has every failure
+        if let Some(first) = self.errors.first() {
+            return Err(first.clone());
+        }
+        
+        // Calls made before their function was reached
+        for (name, pos) in core::mem::take(&mut self.forward_calls) {
+            match self.find_symbol(&name) {
+                Some(symbol) if symbol.value >= 0 => self.code[pos] = symbol.value,
+                _ => return Err(format!("Function '{}' is called but never defined", name)),
+            }
+        }
+        
+        // Calls made before their function was generated
+        for (name, args, line) in core::mem::take(&mut self.forward_arity) {
+            if let Some(params) = self.functions.iter().find(|f| f.name == name).map(|f| f.params) {
+                self.check_arity(&name, params, args, line)?;
+            }
+        }
+        
+        // Non-void functions with a path that runs off the end, main returns 0 there
+        let missing: Vec<(usize, String)> = ir::missing_returns(&self.code, &self.functions).into_iter()
+            .filter(|f| f.returns_value && f.name != "main")
+            .map(|f| (f.line, f.name.clone()))
+            .collect();
+        for (line, name) in missing {
+            self.warn_at(line, Warning::Return, format!("control can reach the end of non-void function '{}' without a return", name));
+        }
+        
+        // Find main function, a prototype alone still has the first pass's -1
+        match self.find_symbol("main") {
+            Some(main_sym) if main_sym.class == SymbolClass::Fun && main_sym.value >= 0 => self.code[main_slot] = main_sym.value,
+            Some(main_sym) if main_sym.class == SymbolClass::Fun && need_main => return Err("main() not defined".to_string()),
+            Some(main_sym) if main_sym.class != SymbolClass::Fun && need_main => return Err("main is not a function".to_string()),
+            None if need_main => return Err("main() not defined".to_string()),
+            _ => {}
+        }
+        
+        // -Werror turns the warnings above into a failed compile
+        if self.options.werror && !self.warnings.is_empty() {
+            let plural = if self.warnings.len() == 1 { "" } else { "s" };
+            return Err(format!("{} warning{} treated as errors (-Werror)", self.warnings.len(), plural));
+        }
+        
+        // A stray backpatch shows up here rather than as a jump into another function at run time
+        verify_jumps(&self.code, &self.functions)?;
+        
+        // Optimize, -O0 keeps the code exactly as generated
+        if let Some((code, relocation)) = ir::optimize(&self.code, &self.functions, self.options.opt_level) {
+            self.code = code;
+            relocation.relocate(&mut self.functions, &mut self.symbols);
+            self.lines = relocation.relocate_lines(&self.lines);
+            verify_jumps(&self.code, &self.functions)?;
+        }
+        
+        // Return the generated code and data segments
+        Ok((self.code.clone(), self.data.clone()))
+    }
+    
+    /// records a failed declaration and drops the code it emitted
+    fn drop_failed_declaration(&mut self, error: String, code_len: usize, function_count: usize) {
+        self.errors.push(error);
+        self.code.truncate(code_len);
+        self.functions.truncate(function_count);
+        self.lines.retain(|&(addr, _)| addr < code_len);
+        self.forward_calls.retain(|&(_, pos)| pos < code_len);
+        self.drop_locals();
+        for symbol in self.symbols.iter_mut().filter(|s| s.class == SymbolClass::Fun && s.value >= code_len as i64) {
+            symbol.value = -1; // its code is gone, calls must not land on whatever comes next
+        }
+        self.locals = 0;
+        self.max_locals = 0;
+        self.params = 0;
+        self.va_named = None;
+        self.scope_slots.clear();
+        self.switches.clear();
+        self.breaks.clear();
+        self.continues.clear();
+    }
+    
+    /// declares the globals and functions of the pre-pass, a builtin gives way to a definition
+    /// but not to a prototype, which only says how it is called
+    fn declare_forward(&mut self, forward: &[Forward]) -> Result<(), String> {
+        for declaration in forward {
+            match declaration {
+                Forward::Function { name, typ, variadic, body } => {
+                    let fresh = self.find_symbol(name).is_none();
+                    // calls need to know about '...' before the definition is reached
+                    if *variadic {
+                        self.variadic.insert(name.clone());
+                    }
+                    if fresh || (self.is_builtin(name) && *body) {
+                        self.add_symbol(name, SymbolClass::Fun, typ.clone(), -1)?;
+                        self.predeclared.insert(name.clone());
+                    }
+                }
+                Forward::Global { name, typ, line } => {
+                    if self.find_symbol(name).is_none() || self.is_builtin(name) {
+                        self.alloc_global(name, typ.clone(), *line)?;
+                        self.predeclared.insert(name.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// generates one top-level declaration
+    fn declaration(&mut self, ast: &Arena, decl: &Decl) -> Result<(), String> {
+        match decl {
+            Decl::Variables { spec, typed, vars } => self.global_variables(ast, spec, *typed, vars),
+            Decl::Function(function) => self.function(ast, function),
+            Decl::Enum { members, trailing_comma } => self.enumeration(ast, members, *trailing_comma),
+            Decl::Extern { name, argc, line } => self.extern_declaration(name, *argc, *line),
+            Decl::Error(error) => Err(error.clone()),
+        }
+    }
+    
+    /// `int a, *b = 2;` at the top level, storage may already exist from the pre-pass
+    fn global_variables(&mut self, ast: &Arena, spec: &TypeSpec, typed: bool, vars: &[(Declarator, Option<Initializer>)]) -> Result<(), String> {
+        let base_type = self.base_type(ast, spec)?;
+        for (i, (declarator, init)) in vars.iter().enumerate() {
+            let name = &declarator.name;
+            if i == 0 && !typed {
+                self.implicit_int(name, declarator.line)?;
+            }
+            let typ = self.declarator_type(ast, base_type.clone(), declarator)?;
+            if typ.is_struct() && typ.size() == 0 {
+                return Err(format!("Line {}: '{}' has the incomplete type '{}'", declarator.line, name, typ));
+            }
+            self.shadow_builtin(name, declarator.line);
+            if !self.predeclared.remove(name) {
+                self.alloc_global(name, typ.clone(), declarator.line)?;
+            }
+            if let Some(init) = init {
+                self.global_initializer(ast, name, &typ, init)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// `extern int name(int a, char *b);` declares a host function, bound by name when the VM is linked
+    fn extern_declaration(&mut self, name: &str, argc: usize, line: usize) -> Result<(), String> {
+        // A function the embedder registered may be declared again, with the same arity
+        match self.find_symbol(name) {
+            Some(symbol) if symbol.class == SymbolClass::Host => {
+                let registered = self.host_fns[symbol.value as usize].1;
+                if registered != argc {
+                    return Err(format!("Line {}: host function '{}' takes {} arguments, declared with {}", line, name, registered, argc));
+                }
+            }
+            Some(_) => return Err(format!("Line {}: '{}' is already defined, it can't be extern", line, name)),
+            None => {
+                self.add_host_fn(name, argc);
+            }
+        }
+        Ok(())
+    }
+    
+    /// stores a string literal in the data segment, returning its address
+    fn string_literal(&mut self, bytes: &[u8], line: usize) -> usize {
+        let str_start = self.data.len();
+        if self.debug {
+            println!("DEBUG PARSER: Storing string \"{}\" at data segment position: {}", String::from_utf8_lossy(bytes), str_start);
+        }
+        self.data.extend_from_slice(bytes);
+        self.data.push(0);
+        
+        // Align data segment after string
+        while !self.data.len().is_multiple_of(core::mem::size_of::<i64>()) {
+            self.data.push(0);
+        }
+        self.strings.push((str_start, line));
+        str_start
+    }
+    
+    /// fills in a global's data from its initializer: a constant, a string for a char * or char array,
+    /// or a list in braces for an array or struct
+    fn global_initializer(&mut self, ast: &Arena, name: &str, typ: &Type, init: &Initializer) -> Result<(), String> {
+        let (line, string) = match init {
+            Initializer::Expr(value) => match &ast[*value].kind {
+                ExprKind::Str(bytes, line) => (*line, Some(bytes)),
+                _ => (ast[*value].line, None),
+            },
+            Initializer::List(_, line) => (*line, None),
+        };
+        let addr = self.find_symbol(name).map_or(0, |symbol| symbol.value as usize);
+        match (typ, init, string) {
+            (Type::Array(elem, size), _, Some(bytes)) if **elem == Type::Char => {
+                if bytes.len() > *size {
+                    return Err(format!("Line {}: string of {} chars doesn't fit in '{}', an array of {}", line, bytes.len(), name, size));
+                }
+                self.data[addr..addr + bytes.len()].copy_from_slice(bytes);
+            }
+            (Type::Array(elem, size), Initializer::List(values, _), _) => {
+                // missing elements stay 0, a comma may follow the last one
+                for (count, value) in values.iter().enumerate() {
+                    if count == *size {
+                        return Err(format!("Line {}: too many initializers for '{}', an array of {}", ast[*value].line, name, size));
+                    }
+                    self.global_value(ast, name, addr + count * elem.size(), elem, *value)?;
+                }
+            }
+            (Type::Array(..), ..) => {
+                return Err(format!("Line {}: array '{}' needs a list in braces, or a string for a char array", line, name));
+            }
+            (Type::Struct(layout), Initializer::List(values, _), _) => {
+                // members in order, the ones left out stay 0
+                let mut members = layout.members.iter();
+                for &value in values {
+                    let line = ast[value].line;
+                    let Some(member) = members.next() else {
+                        return Err(format!("Line {}: too many initializers for '{}', a struct of {} members", line, name, layout.members.len()));
+                    };
+                    if member.typ.is_aggregate() {
+                        return Err(format!("Line {}: member '{}' of '{}' can't be initialized in the list", line, member.name, name));
+                    }
+                    self.global_value(ast, name, addr + member.offset, &member.typ, value)?;
+                }
+            }
+            (Type::Struct(_), ..) => {
+                return Err(format!("Line {}: struct '{}' needs a list in braces", line, name));
+            }
+            (_, Initializer::List(..), _) => return Err(format!("Line {}: Expected expression", line)),
+            (_, Initializer::Expr(value), _) => self.global_value(ast, name, addr, typ, *value)?,
+        }
+        Ok(())
+    }
+    
+    /// stores one constant, or a string's address for a char *, at addr
+    fn global_value(&mut self, ast: &Arena, name: &str, addr: usize, typ: &Type, value: ExprId) -> Result<(), String> {
+        match (typ, &ast[value].kind) {
+            (Type::Ptr(elem), ExprKind::Str(bytes, line)) if **elem == Type::Char => {
+                let str_start = self.string_literal(bytes, *line);
+                self.store_global_int(addr, str_start as i64);
+            }
+            (_, ExprKind::Str(_, line)) => {
+                return Err(format!("Line {}: a string can only initialize a char * or char array, not '{}'", line, name));
+            }
+            (Type::Char, _) => {
+                let constant = self.constant(ast, value, "global initializer")?;
+                self.check_char_constant(constant, ast[value].line);
+                self.data[addr] = constant as u8;
+            }
+            _ => {
+                let constant = self.constant(ast, value, "global initializer")?;
+                self.store_global_int(addr, constant);
+            }
+        }
+        Ok(())
+    }
+    
+    /// writes an int into the data segment in the byte order the VM reads it in
+    fn store_global_int(&mut self, addr: usize, value: i64) {
+        let bytes = if self.options.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.data[addr..addr + bytes.len()].copy_from_slice(&bytes);
+    }
+    
+    /// reserves aligned data for a global variable
+    fn alloc_global(&mut self, name: &str, typ: Type, line: usize) -> Result<(), String> {
+        // Align data segment before adding global variables
+        while !self.data.len().is_multiple_of(core::mem::size_of::<i64>()) {
+            self.data.push(0);
+        }
+        let aligned_data_len = self.data.len();
+        let type_size = typ.size();
+        
+        // Globals must stay below the stack address range
+        if type_size > DATA_STACK_THRESHOLD - aligned_data_len.min(DATA_STACK_THRESHOLD) {
+            return Err(format!("Line {}: Global '{}' does not fit in the data segment", line, name));
+        }
+        
+        if self.debug {
+            println!("DEBUG PARSER: Added global var '{}' of type {} at data address {}", name, typ, aligned_data_len);
+        }
+        self.add_symbol(name, SymbolClass::Glo, typ, aligned_data_len as i64)?;
+        
+        // Add space in data segment
+        self.data.resize(aligned_data_len + type_size, 0);
+        Ok(())
+    }
+    
+    /// an if, while or for condition, warning when it is a plain assignment since that is
+    /// usually a typo for ==, extra parentheses around it say the assignment is meant
+    fn condition(&mut self, ast: &Arena, cond: ExprId, keyword: &str, line: usize) -> Result<(), String> {
+        self.expr(ast, cond)?;
+        if ast.assigns_unbracketed(cond) {
+            self.warn_at(line, Warning::Parentheses, format!("assignment used as the {} condition, write '==' to compare or wrap it in parentheses", keyword));
+        }
+        Ok(())
+    }
+    
+    /// the body of a while or for, returning its break and continue jumps for the loop to aim
+    fn loop_body(&mut self, ast: &Arena, body: StmtId) -> Result<(Vec<usize>, Vec<usize>), String> {
+        self.breaks.push(Vec::new());
+        self.continues.push((self.switches.len(), Vec::new()));
+        let result = self.stmt(ast, body);
+        let breaks = self.breaks.pop().unwrap_or_default();
+        let (_, continues) = self.continues.pop().unwrap_or_default();
+        result.map(|_| (breaks, continues))
+    }
+    
+    /// points each jump whose operand is at one of these positions at the target
+    fn patch(&mut self, jumps: &[usize], target: usize) {
+        for &pos in jumps {
+            self.code[pos] = target as i64;
+        }
+    }
+    
+    /// value of an expression that has to reduce to a constant, like an enum value or case label
+    fn constant(&mut self, ast: &Arena, expr: ExprId, what: &str) -> Result<i64, String> {
+        let start = self.code.len();
+        self.expr(ast, expr)?;
+        let value = eval_constant(&self.code, start);
+        self.code.truncate(start);
+        value.ok_or_else(|| format!("Line {}: {} must be a constant", ast[expr].line, what))
+    }
+    
+    /// size between the brackets of an array declarator
+    fn array_size(&mut self, ast: &Arena, size: ExprId) -> Result<usize, String> {
+        let value = self.constant(ast, size, "array size")?;
+        usize::try_from(value).map_err(|_| format!("Line {}: array size {} is negative", ast[size].line, value))
+    }
+    
+    /// the type a specifier names, void is int here and a struct with members is defined
+    fn base_type(&mut self, ast: &Arena, spec: &TypeSpec) -> Result<Type, String> {
+        match spec {
+            TypeSpec::Int | TypeSpec::Void => Ok(Type::Int),
+            TypeSpec::Char => Ok(Type::Char),
+            TypeSpec::Struct(spec) => self.struct_type(ast, spec),
+        }
+    }
+    
+    /// the base type with a declarator's pointers and array size applied
+    fn declarator_type(&mut self, ast: &Arena, base_type: Type, declarator: &Declarator) -> Result<Type, String> {
+        let typ = pointer_to(base_type, declarator.pointers);
+        match declarator.size {
+            Some(size) => Ok(Type::Array(Box::new(typ), self.array_size(ast, size)?)),
+            None => Ok(typ),
+        }
+    }
+    
+    /// `struct tag`, or `struct tag { members }` which defines the tag, as a type
+    fn struct_type(&mut self, ast: &Arena, spec: &StructSpec) -> Result<Type, String> {
+        let tag = &spec.tag;
+        let Some(declarations) = &spec.members else {
+            let layout = self.structs.get(tag).cloned().unwrap_or_else(|| StructType::declared(tag));
+            return Ok(Type::Struct(Box::new(layout)));
+        };
+        
+        if self.structs.contains_key(tag) {
+            return Err(format!("Line {}: struct '{}' is already defined", spec.line, tag));
+        }
+        let mut members: Vec<(String, Type)> = Vec::new();
+        for declaration in declarations {
+            let base_type = self.base_type(ast, &declaration.spec)?;
+            for declarator in &declaration.declarators {
+                let name = &declarator.name;
+                let typ = self.declarator_type(ast, base_type.clone(), declarator)?;
+                // a member may point to its own struct but can't contain it
+                if typ.is_struct() && typ.size() == 0 {
+                    return Err(format!("Line {}: member '{}' has the incomplete type '{}'", declarator.line, name, typ));
+                }
+                if members.iter().any(|(member, _)| member == name) {
+                    return Err(format!("Line {}: Duplicate member '{}' in struct '{}'", declarator.line, name, tag));
+                }
+                members.push((name.clone(), typ));
+            }
+        }
+        if members.is_empty() {
+            return Err(format!("Line {}: struct '{}' has no members", spec.line, tag));
+        }
+        let layout = StructType::new(tag, members);
+        self.structs.insert(tag.clone(), layout.clone());
+        Ok(Type::Struct(Box::new(layout)))
+    }
+    
+    /// the type with a struct that was only declared when it was written replaced by its definition,
+    /// so `struct node *next` inside struct node has members once it is used
+    fn complete(&self, typ: Type) -> Type {
+        match typ {
+            Type::Struct(layout) if !layout.is_complete() => match self.structs.get(&layout.name) {
+                Some(full) => Type::Struct(Box::new(full.clone())),
+                None => Type::Struct(layout),
+            },
+            Type::Ptr(base) => Type::Ptr(Box::new(self.complete(*base))),
+            Type::Array(base, size) => Type::Array(Box::new(self.complete(*base)), size),
+            other => other,
+        }
+    }
+    
+    /// `cond ? a : b`, leaving a or b in ax
+    fn conditional(&mut self, ast: &Arena, cond: ExprId, when_true: ExprId, when_false: ExprId) -> Result<(), String> {
+        self.expr(ast, cond)?;
+        if self.current_type.is_struct() {
+            return Err(format!("Line {}: a struct can't be a condition, test one of its members", ast[cond].line));
+        }
+        self.code.push(OpCode::BZ as i64);
+        let branch_pos = self.code.len();
+        self.code.push(0);
+        self.expr(ast, when_true)?;
+        let true_type = self.current_type.decay();
+        self.code.push(OpCode::JMP as i64);
+        let jump_pos = self.code.len();
+        self.code.push(0);
+        self.code[branch_pos] = self.code.len() as i64;
+        self.expr(ast, when_false)?;
+        let false_type = self.current_type.decay();
+        self.code[jump_pos] = self.code.len() as i64;
+        // a pointer on either side wins over the 0 on the other, two chars stay a char
+        self.current_type = match (true_type, false_type) {
+            (Type::Char, Type::Char) => Type::Char,
+            (typ, _) if !matches!(typ, Type::Char | Type::Int) => typ,
+            (_, typ) if !matches!(typ, Type::Char | Type::Int) => typ,
+            _ => Type::Int,
+        };
+        Ok(())
+    }
+    
+    /// `a && b` or `a || b` once a, of lhs_type, is in ax; b is skipped once a decides the result,
+    /// which is 0 or 1 either way
+    fn short_circuit(&mut self, ast: &Arena, op: BinaryOp, lhs_type: Type, rhs: ExprId, line: usize) -> Result<(), String> {
+        let spelling = op.spelling();
+        if lhs_type.is_struct() {
+            return Err(format!("Line {}: a struct can't be an operand of '{}', test one of its members", line, spelling));
+        }
+        self.code.push(if op == BinaryOp::Lan { OpCode::BZ } else { OpCode::BNZ } as i64);
+        let branch_pos = self.code.len();
+        self.code.push(0);
+        self.expr(ast, rhs)?;
+        if self.current_type.is_struct() {
+            return Err(format!("Line {}: a struct can't be an operand of '{}', test one of its members", ast[rhs].line, spelling));
+        }
+        // ax holds whichever operand decided, turned into 0 or 1
+        self.code[branch_pos] = self.code.len() as i64;
+        self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, 0, OpCode::NE as i64]);
+        self.current_type = Type::Int;
+        Ok(())
+    }
+    
+    /// `.member` or `->member` after the struct or pointer to it, of type typ, whose address is in ax
+    fn member_access(&mut self, arrow: bool, typ: Type, name: &str, line: usize) -> Result<(), String> {
+        let layout = match (arrow, self.complete(typ)) {
+            (false, Type::Struct(layout)) => layout,
+            (true, Type::Ptr(base)) if base.is_struct() => match *base {
+                Type::Struct(layout) => layout,
+                _ => unreachable!(),
+            },
+            (false, _) => return Err(format!("Line {}: '.' needs a struct on its left, use '->' through a pointer", line)),
+            (true, typ) => return Err(format!("Line {}: '->' needs a pointer to a struct on its left, not '{}'", line, typ)),
+        };
+        if !layout.is_complete() {
+            return Err(format!("Line {}: struct '{}' is declared but never defined", line, layout.name));
+        }
+        let Some(member) = layout.member(name) else {
+            return Err(format!("Line {}: struct '{}' has no member '{}'", line, layout.name, name));
+        };
+        
+        // the member's address, loaded unless it is an array or struct itself
+        if member.offset > 0 {
+            self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, member.offset as i64, OpCode::ADD as i64]);
+        }
+        self.current_type = self.complete(member.typ.clone());
+        if self.current_type == Type::Char {
+            self.code.push(OpCode::LC as i64);
+        } else if !self.current_type.is_aggregate() {
+            self.code.push(OpCode::LI as i64);
+        }
+        Ok(())
+    }
+    
+    /// enum constants, each one more than the last unless it is given a value
+    fn enumeration(&mut self, ast: &Arena, members: &[Enumerator], trailing_comma: Option<usize>) -> Result<(), String> {
+        let mut value = 0;
+        for member in members {
+            self.shadow_builtin(&member.name, member.line);
+            if let Some(expr) = member.value {
+                value = self.constant(ast, expr, "enum value")?;
+            }
+            self.add_symbol(&member.name, SymbolClass::Num, Type::Int, value)?;
+            value += 1;
+        }
+        
+        // C99 allows a comma after the last constant
+        if let Some(line) = trailing_comma {
+            self.warn_at(line, Warning::TrailingComma, String::from("comma after the last enum constant, C89 doesn't allow it"));
+        }
+        Ok(())
+    }
+    
+    /// a function definition or prototype
+    fn function(&mut self, ast: &Arena, function: &Function) -> Result<(), String> {
+        let return_type = self.base_type(ast, &function.spec)?;
+        let declarator = &function.declarator;
+        if !function.typed {
+            self.implicit_int(&declarator.name, declarator.line)?;
+        }
+        let return_type = self.declarator_type(ast, return_type, declarator)?;
+        if return_type.is_struct() {
+            return Err(format!("Line {}: '{}' can't return a struct, return a pointer to it", function.line, declarator.name));
+        }
+        // void * still returns a value
+        let returns_value = function.spec != TypeSpec::Void || return_type != Type::Int;
+        
+        // Mark current position in the code segment
+        let name = &declarator.name;
+        let fn_pos = self.code.len();
+        let fn_line = function.line;
+        
+        // Save old locals position
+        let old_locals = self.locals;
+        let old_params = self.params;
+        self.locals = 0;
+        
+        // Parameters are numbered in order, they get their offsets once the count is known
+        let mut param_count = 0i64;
+        let mut variadic = false;
+        let mut unnamed = None; // line of the first parameter without a name
+        match &function.params {
+            Params::Unspecified => {}
+            Params::List { params, variadic: ellipsis } => {
+                for param in params {
+                    let typ = pointer_to(self.base_type(ast, &param.spec)?, param.pointers);
+                    match &param.name {
+                        Some(param_name) => {
+                            let typ = self.array_parameter(ast, typ, param)?;
+                            if typ.is_struct() {
+                                return Err(format!("Line {}: parameter '{}' can't be a struct, pass a pointer to it", param.line, param_name));
+                            }
+                            self.add_parameter(param_name, typ, param_count, param.line)?;
+                        }
+                        // only a declaration may leave the name out
+                        None => {
+                            unnamed.get_or_insert(param.line);
+                        }
+                    }
+                    param_count += 1;
+                }
+                variadic = *ellipsis;
+            }
+            // A K&R list only names the parameters, ints until their declarations say otherwise
+            Params::OldStyle { names, .. } => {
+                for (param_name, line) in names {
+                    self.add_parameter(param_name, Type::Int, param_count, *line)?;
+                    param_count += 1;
+                }
+            }
+        }
+        
+        // A declaration without a body, the pre-pass already declared the function
+        let Some(body) = &function.body else {
+            if function.params == Params::Unspecified {
+                self.warn_at(fn_line, Warning::OldStyle, format!("'{}' is declared without a prototype, write '{}(void)' if it takes no arguments", name, name));
+            }
+            self.drop_locals();
+            self.locals = old_locals;
+            self.params = old_params;
+            return Ok(());
+        };
+        if let Some(line) = unnamed {
+            return Err(format!("Line {}: Parameter name expected", line));
+        }
+        if let Params::OldStyle { decls, line, .. } = &function.params {
+            self.warn_at(fn_line, Warning::OldStyle, format!("'{}' has an old-style parameter list, declare the parameters inside the parentheses", name));
+            self.parameter_declarations(ast, decls, *line)?;
+        }
+        self.mark_line_at(fn_line);
+        
+        // Add function to symbol table, or give the pre-declared one its address,
+        // the outermost symbol by that name since a parameter may share it
+        self.shadow_builtin(name, fn_line);
+        if self.predeclared.remove(name) {
+            let positions = self.symbol_index.get(name).map(Vec::as_slice).unwrap_or_default();
+            if let Some(&index) = positions.iter().find(|&&index| self.symbols[index].class != SymbolClass::Sys) {
+                self.symbols[index].value = fn_pos as i64;
+            }
+        } else {
+            self.add_symbol(name, SymbolClass::Fun, return_type, fn_pos as i64)?;
+        }
+        
+        if variadic {
+            // The caller pushes the argument count after the arguments, so the named ones sit at
+            // offsets only known at run time; the prologue copies parameter i into local -(i + 1)
+            self.variadic.insert(name.clone());
+            self.va_named = Some(param_count as usize);
+            for symbol in self.symbols.iter_mut().filter(|s| s.class == SymbolClass::Loc) {
+                symbol.value = -(symbol.value + 1);
+            }
+            self.params = 0;
+        } else {
+            // Parameters sit above the return address, first one highest:
+            // parameter i of n is at bp + (n + 1 - i)
+            for symbol in self.symbols.iter_mut().filter(|s| s.class == SymbolClass::Loc) {
+                symbol.value = param_count + 1 - symbol.value;
+            }
+            self.params = param_count as usize;
+        }
+        
+        // Store parameter count for local offset calculation
+        self.locals = param_count as usize;
+        self.max_locals = self.locals;
+        self.scope_start = self.symbols.len();
+        self.scope_slots.clear();
+        
+        // Generate function entry code, frame size is patched at the end
+        self.code.push(OpCode::ENT as i64);
+        let ent_slot = self.code.len();
+        self.code.push(0);
+        
+        // With n arguments and the count at bp + 2, argument i is at bp + 2 + n - i
+        if variadic {
+            for i in 0..param_count {
+                self.code.extend([OpCode::LEA as i64, -(i + 1), OpCode::PSH as i64]);
+                self.emit_va_base();
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, i, OpCode::SUB as i64, OpCode::LI as i64, OpCode::SI as i64]);
+            }
+        }
+        
+        // Local variable declarations and statements
+        self.block_items(ast, body)?;
+        
+        // Ensure function has a return statement by adding LEV,
+        // falling off the end of main returns 0 like C
+        if name == "main" {
+            self.code.push(OpCode::IMM as i64);
+            self.code.push(0);
+        }
+        self.code.push(OpCode::LEV as i64);
+        
+        // Now the frame size is known
+        let local_count = self.max_locals - self.params;
+        self.code[ent_slot] = local_count as i64;
+        if self.debug {
+            println!("DEBUG PARSER: Function entry - creating stack frame with {} local variables", local_count);
+        }
+        let mut locals: Vec<Symbol> = self.symbols.iter()
+            .filter(|s| s.class == SymbolClass::Loc)
+            .cloned()
+            .collect();
+        locals.append(&mut self.scope_slots);
+        let slots = locals.iter().map(local_slot).collect();
+        let types = locals.into_iter().map(|s| s.typ).collect();
+        self.functions.push(FunctionInfo {
+            name: name.clone(),
+            start: fn_pos,
+            end: self.code.len(),
+            params: param_count as usize,
+            locals: local_count,
+            line: fn_line,
+            slots,
+            types,
+            variadic,
+            returns_value,
+        });
+        
+        // Shadowed globals were never touched, dropping the locals uncovers them
+        self.drop_locals();
+        
+        // Restore old locals count
+        self.locals = old_locals;
+        self.params = old_params;
+        self.va_named = None;
+        
+        Ok(())
+    }
+    
+    /// an array parameter is a pointer to its first element, the size is only checked
+    fn array_parameter(&mut self, ast: &Arena, typ: Type, param: &Param) -> Result<Type, String> {
+        if !param.array {
+            return Ok(typ);
+        }
+        if let Some(size) = param.size {
+            self.array_size(ast, size)?;
+        }
+        Ok(Type::Ptr(Box::new(typ)))
+    }
+    
+    /// adds parameter number index, shadowing any global with its name
+    fn add_parameter(&mut self, name: &str, typ: Type, index: i64, line: usize) -> Result<(), String> {
+        match self.symbol_position(name) {
+            Some(existing) if self.symbols[existing].class == SymbolClass::Loc => {
+                Err(format!("Line {}: Duplicate parameter '{}'", line, name))
+            }
+            Some(existing) => {
+                self.add_shadowing_local(name, typ, index, existing);
+                Ok(())
+            }
+            None => self.add_symbol(name, SymbolClass::Loc, typ, index).map(|_| ()),
+        }
+    }
+    
+    /// `int a; char *b;` between a K&R parameter list and the body, a parameter left out is an implicit int
+    fn parameter_declarations(&mut self, ast: &Arena, decls: &[Param], line: usize) -> Result<(), String> {
+        let mut declared = BTreeSet::new();
+        for param in decls {
+            let Some(name) = &param.name else { continue };
+            let typ = pointer_to(self.base_type(ast, &param.spec)?, param.pointers);
+            let typ = self.array_parameter(ast, typ, param)?;
+            let Some(index) = self.symbol_position(name).filter(|&i| self.symbols[i].class == SymbolClass::Loc) else {
+                return Err(format!("Line {}: '{}' is declared but is not a parameter", param.line, name));
+            };
+            if !declared.insert(name.clone()) {
+                return Err(format!("Line {}: Duplicate parameter '{}'", param.line, name));
+            }
+            self.symbols[index].typ = typ;
+        }
+        let undeclared: Vec<String> = self.symbols.iter()
+            .filter(|s| s.class == SymbolClass::Loc && !declared.contains(&s.name))
+            .map(|s| s.name.clone())
+            .collect();
+        for name in undeclared {
+            self.implicit_int(&name, line)?;
+        }
+        Ok(())
+    }
+    
+    /// declarations and statements of a block, warning once about
+    /// a statement that follows a return with no case label in between
+    fn block_items(&mut self, ast: &Arena, items: &[StmtId]) -> Result<(), String> {
+        let mut returned = false;
+        for &id in items {
+            let item = &ast[id];
+            match item.kind {
+                StmtKind::Local(..) | StmtKind::Empty => {}
+                StmtKind::Case(..) | StmtKind::Default(_) => returned = false,
+                _ if returned => {
+                    self.warn_at(item.line, Warning::Unreachable, String::from("statement after 'return' is never executed"));
+                    returned = false;
+                }
+                _ => {}
+            }
+            self.stmt(ast, id)?;
+            returned |= matches!(item.kind, StmtKind::Return(_));
+        }
+        Ok(())
+    }
+    
+    /// a local declaration like `int a, *b, c[4] = ...;` in the current scope
+    fn local_declaration(&mut self, ast: &Arena, spec: &TypeSpec, vars: &[(Declarator, Option<ExprId>)]) -> Result<(), String> {
+        let base_type = self.base_type(ast, spec)?;
+        for (declarator, init) in vars {
+            let var_name = &declarator.name;
+            let var_type = self.declarator_type(ast, base_type.clone(), declarator)?;
+            if var_type.is_struct() && var_type.size() == 0 {
+                return Err(format!("Line {}: '{}' has the incomplete type '{}'", declarator.line, var_name, var_type));
+            }
+            
+            // Arrays and structs get one slot per byte so scaled indexes and member offsets stay inside them
+            let slots = if var_type.is_aggregate() { var_type.size().max(1) } else { 1 };
+            let offset = self.alloc_local(slots);
+            
+            // Check for duplicate local in this block, outer ones and params may be shadowed
+            match self.symbol_position(var_name) {
+                Some(index) if index >= self.scope_start && self.symbols[index].class == SymbolClass::Loc => {
+                    return Err(format!("Line {}: Duplicate local variable '{}'", declarator.line, var_name));
+                }
+                Some(index) => self.add_shadowing_local(var_name, var_type.clone(), offset, index),
+                None => {
+                    self.add_symbol(var_name, SymbolClass::Loc, var_type.clone(), offset)?;
+                }
+            }
+            
+            // Debug output for locals
+            if self.debug {
+                println!("DEBUG PARSER: Local variable '{}' at offset {}, generating LEA {}",
+                         var_name, offset, offset);
+            }
+            
+            let &Some(init) = init else { continue };
+            if var_type.is_struct() {
+                return Err(format!("Line {}: struct '{}' can't be initialized from an expression, assign its members", declarator.line, var_name));
+            }
+            
+            // The address stays on the stack while the initializer is evaluated
+            self.code.extend([OpCode::LEA as i64, offset, OpCode::PSH as i64]);
+            let rhs_start = self.code.len();
+            self.expr(ast, init)?;
+            if var_type == Type::Char {
+                self.store_char(rhs_start, ast[init].line);
+            } else {
+                self.code.push(OpCode::SI as i64);
+            }
+        }
+        Ok(())
+    }
+    
+    /// ends a block, dropping its locals and handing their slots back
+    fn close_scope(&mut self, outer_scope: usize, outer_locals: usize) {
+        for symbol in self.split_off_symbols(self.scope_start) {
+            if symbol.class == SymbolClass::Loc {
+                self.scope_slots.push(symbol);
+            } else {
+                self.push_symbol(symbol);
+            }
+        }
+        self.scope_start = outer_scope;
+        self.locals = outer_locals;
+    }
+    
+    /// reserves stack slots for a local, returns the LEA offset of the lowest one
+    fn alloc_local(&mut self, slots: usize) -> i64 {
+        // locals sit below the saved bp
+        self.locals += slots;
+        self.max_locals = self.max_locals.max(self.locals);
+        -((self.locals - self.params) as i64)
+    }
+    
+    /// generates an expression, leaving its value in ax and its type in current_type
+    pub(crate) fn expr(&mut self, ast: &Arena, id: ExprId) -> Result<(), String> {
+        let expr = &ast[id];
+        let line = expr.line;
+        match &expr.kind {
+            ExprKind::Num(value) => {
+                self.code.extend([OpCode::IMM as i64, *value]);
+                self.current_type = Type::Int;
+            }
+            ExprKind::Str(bytes, first_line) => {
+                // the address of the string in the data segment
+                let str_start = self.string_literal(bytes, *first_line);
+                self.code.extend([OpCode::IMM as i64, str_start as i64]);
+                self.current_type = Type::Ptr(Box::new(Type::Char));
+            }
+            ExprKind::Sizeof(name) => {
+                let typ = self.base_type(ast, &name.spec)?;
+                if typ.size() == 0 {
+                    return Err(format!("Line {}: sizeof of '{}', which is declared but never defined", line, typ));
+                }
+                let typ = pointer_to(typ, name.pointers);
+                if self.debug {
+                    println!("DEBUG PARSER: sizeof type {} resolved to size {}", typ, typ.size());
+                }
+                self.code.extend([OpCode::IMM as i64, typ.size() as i64]);
+                self.current_type = Type::Int;
+            }
+            ExprKind::Var(name) => self.variable(name, line)?,
+            ExprKind::Call(name, args) => self.call(ast, name, args, line)?,
+            ExprKind::Paren(inner) => self.expr(ast, *inner)?,
+            ExprKind::Cast(name, operand) => {
+                let typ = pointer_to(self.base_type(ast, &name.spec)?, name.pointers);
+                if typ.is_struct() {
+                    return Err(format!("Line {}: can't cast to a struct, only to a pointer to one", line));
+                }
+                self.expr(ast, *operand)?;
+                
+                // A char keeps only its low byte, the way LC reads it back
+                if typ == Type::Char && self.current_type != Type::Char {
+                    self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, 0xFF, OpCode::AND as i64]);
+                    if self.options.signed_char {
+                        // (x ^ 128) - 128 sign-extends the byte
+                        self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, 0x80, OpCode::XOR as i64,
+                                          OpCode::PSH as i64, OpCode::IMM as i64, 0x80, OpCode::SUB as i64]);
+                    }
+                }
+                self.current_type = typ;
+            }
+            ExprKind::Unary(op, operand) => self.unary(ast, *op, *operand, line)?,
+            ExprKind::Binary(op, lhs, rhs) => self.binary(ast, *op, *lhs, *rhs)?,
+            ExprKind::Assign(lhs, rhs) => self.assign(ast, *lhs, *rhs, line)?,
+            ExprKind::CompoundAssign(op, lhs, rhs) => self.compound_assign(ast, *op, *lhs, *rhs)?,
+            ExprKind::Cond(cond, when_true, when_false) => self.conditional(ast, *cond, *when_true, *when_false)?,
+            ExprKind::Index(base, index) => {
+                self.expr(ast, *base)?;
+                let op_type = self.current_type.clone();
+                self.code.push(OpCode::PSH as i64);
+                self.expr(ast, *index)?;
+                
+                // Make sure the base is a pointer or array type
+                let Some(base_type) = op_type.base_type() else {
+                    return Err(format!("Line {}: Array indexing requires a pointer or array type, not '{}'", line, op_type));
+                };
+                
+                // Scale the index by the size of the base type, then add it to the base address
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, base_type.size() as i64, OpCode::MUL as i64, OpCode::ADD as i64]);
+                self.current_type = *base_type;
+                
+                // Load the value, assignments turn the load back into a store
+                if self.current_type.is_struct() {
+                    // an element struct stays as its address
+                } else if self.current_type == Type::Char {
+                    self.code.push(OpCode::LC as i64);
+                } else {
+                    self.code.push(OpCode::LI as i64);
+                }
+            }
+            ExprKind::Member(base, name, arrow) => {
+                self.expr(ast, *base)?;
+                let typ = self.current_type.clone();
+                self.member_access(*arrow, typ, name, line)?;
+            }
+            ExprKind::PostInc(operand) => self.post_step(ast, OpCode::ADD, *operand, line)?,
+            ExprKind::PostDec(operand) => self.post_step(ast, OpCode::SUB, *operand, line)?,
+        }
+        Ok(())
+    }
+    
+    /// a variable read, arrays and structs stay as their address
+    fn variable(&mut self, name: &str, line: usize) -> Result<(), String> {
+        let Some(symbol) = self.find_symbol(name) else {
+            return Err(format!("Line {}: Unknown variable '{}'", line, name));
+        };
+        let sym_class = symbol.class;
+        let sym_value = symbol.value;
+        let sym_type = self.complete(symbol.typ.clone());
+        
+        match sym_class {
+            SymbolClass::Num => {
+                // Numeric constant
+                self.code.extend([OpCode::IMM as i64, sym_value]);
+                self.current_type = Type::Int;
+                return Ok(());
+            }
+            SymbolClass::Glo => self.code.extend([OpCode::IMM as i64, sym_value]),
+            SymbolClass::Loc => self.code.extend([OpCode::LEA as i64, sym_value]),
+            _ => return Err(format!("Line {}: Invalid variable '{}'", line, name)),
+        }
+        if sym_type == Type::Char {
+            self.code.push(OpCode::LC as i64);
+        } else if !sym_type.is_aggregate() {
+            self.code.push(OpCode::LI as i64);
+        }
+        self.current_type = sym_type;
+        Ok(())
+    }
+    
+    /// a call with its arguments pushed in order
+    fn call(&mut self, ast: &Arena, name: &str, args: &[ExprId], line: usize) -> Result<(), String> {
+        let mut format = None; // printf's format when it is a literal
+        for (i, &id) in args.iter().enumerate() {
+            let arg = &ast[id];
+            let arg_start = self.code.len();
+            self.expr(ast, id)?;
+            if self.current_type.is_struct() {
+                return Err(format!("Line {}: a struct can't be passed to '{}', pass a pointer to it", arg.line, name));
+            }
+            if i == 0 && matches!(arg.kind, ExprKind::Str(..)) {
+                if let [imm, addr] = self.code[arg_start..] {
+                    if imm == OpCode::IMM as i64 {
+                        format = Some(addr as usize);
+                    }
+                }
+            }
+            self.code.push(OpCode::PSH as i64);
+        }
+        let arg_count = args.len();
+        
+        // va_count() and va_get(i) read the frame of the variadic function around them
+        if self.find_symbol(name).is_none() && matches!(name, "va_count" | "va_get") {
+            return self.va_builtin(name, arg_count, line);
+        }
+        
+        let mut pushed = arg_count;
+        let Some(symbol) = self.find_symbol(name) else {
+            return Err(format!("Line {}: Unknown function '{}'", line, name));
+        };
+        let sym_class = symbol.class;
+        let sym_value = symbol.value;
+        let sym_type = self.complete(symbol.typ.clone());
+        
+        match sym_class {
+            SymbolClass::Sys => {
+                // System call
+                self.code.push(sym_value);
+                
+                // printf also gets the argument count
+                if name == "printf" {
+                    if let Some(addr) = format {
+                        let text = &self.data[addr..];
+                        let text = &text[..text.iter().position(|&c| c == 0).unwrap_or(text.len())];
+                        let expected = printf_arg_count(text);
+                        let supplied = arg_count - 1;
+                        if expected != supplied {
+                            let plural = if expected == 1 { "" } else { "s" };
+                            self.warn_at(line, Warning::Format, format!("printf format expects {} argument{}, got {}", expected, plural, supplied));
+                        }
+                    }
+                    self.code.push(arg_count as i64);
+                    if self.debug {
+                        println!("DEBUG: Generating printf with {} arguments", arg_count);
+                    }
+                }
+            }
+            SymbolClass::Host => {
+                let argc = self.host_fns[sym_value as usize].1;
+                if arg_count != argc {
+                    return Err(format!("Line {}: '{}' takes {} arguments, got {}", line, name, argc, arg_count));
+                }
+                self.code.extend([OpCode::HOST as i64, sym_value]);
+            }
+            SymbolClass::Fun => {
+                // Argument count is checked now if the function was generated, else once it is
+                match self.functions.iter().find(|f| f.name == name) {
+                    Some(function) => {
+                        let params = function.params;
+                        self.check_arity(name, params, arg_count, line)?;
+                    }
+                    None => self.forward_arity.push((name.to_string(), arg_count, line)),
+                }
+                
+                // A variadic function also gets the argument count, pushed last
+                if self.variadic.contains(name) {
+                    self.code.extend([OpCode::IMM as i64, arg_count as i64, OpCode::PSH as i64]);
+                    pushed += 1;
+                }
+                
+                // User-defined function, patched later if not reached yet
+                self.code.push(OpCode::JSR as i64);
+                if sym_value < 0 {
+                    self.forward_calls.push((name.to_string(), self.code.len()));
+                }
+                self.code.push(sym_value);
+            }
+            _ => return Err(format!("Line {}: '{}' is not a function", line, name)),
+        }
+        self.current_type = sym_type;
+        
+        // Clean up stack if there were arguments, system calls pop their own
+        if pushed > 0 && sym_class != SymbolClass::Sys {
+            self.code.extend([OpCode::ADJ as i64, pushed as i64]);
+        }
+        Ok(())
+    }
+    
+    /// a prefix operator and its operand
+    fn unary(&mut self, ast: &Arena, op: UnaryOp, operand: ExprId, line: usize) -> Result<(), String> {
+        match op {
+            UnaryOp::Deref => {
+                self.expr(ast, operand)?;
+                let Type::Ptr(base_type) = &self.current_type else {
+                    return Err(format!("Line {}: Cannot dereference a non-pointer type '{}'", line, self.current_type));
+                };
+                self.current_type = self.complete((**base_type).clone());
+                
+                // Load the value at the address, a struct stays as its address
+                if self.current_type.is_struct() {
+                    // nothing to load
+                } else if self.current_type == Type::Char {
+                    self.code.push(OpCode::LC as i64);
+                } else {
+                    self.code.push(OpCode::LI as i64);
+                }
+            }
+            UnaryOp::Addr => {
+                let start = self.code.len();
+                self.expr(ast, operand)?;
+                
+                // A struct is never loaded so its address is already there, a load gives way to
+                // the address it reads, and a global or local without a load is an array's address
+                let code = &self.code[start..];
+                if self.current_type.is_struct() {
+                    // nothing to drop
+                } else if matches!(code.last(), Some(&op) if op == OpCode::LC as i64 || op == OpCode::LI as i64) {
+                    self.code.pop();
+                } else if code.is_empty() {
+                    return Err(format!("Line {}: Invalid address-of operation - empty expression", line));
+                } else if !matches!(code, [.., op, _] if *op == OpCode::IMM as i64 || *op == OpCode::LEA as i64) {
+                    return Err(format!("Line {}: Invalid address-of operation - can only take address of variables", line));
+                }
+                self.current_type = Type::Ptr(Box::new(self.current_type.clone()));
+            }
+            UnaryOp::Not => {
+                self.expr(ast, operand)?;
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, 0, OpCode::EQ as i64]);
+                self.current_type = Type::Int;
+            }
+            UnaryOp::BitNot => {
+                self.expr(ast, operand)?;
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, -1, OpCode::XOR as i64]);
+                self.current_type = Type::Int;
+            }
+            // Unary plus doesn't change the value or its type
+            UnaryOp::Plus => self.expr(ast, operand)?,
+            UnaryOp::Neg => {
+                // A constant is negated right away
+                if let ExprKind::Num(value) = ast[operand].kind {
+                    self.code.extend([OpCode::IMM as i64, value.wrapping_neg()]);
+                } else {
+                    self.code.extend([OpCode::IMM as i64, -1, OpCode::PSH as i64]);
+                    self.expr(ast, operand)?;
+                    self.code.push(OpCode::MUL as i64);
+                }
+                self.current_type = Type::Int;
+            }
+            UnaryOp::PreInc | UnaryOp::PreDec => {
+                self.expr(ast, operand)?;
+                
+                // The load becomes a push of the address, then the value is loaded again
+                let load = match self.code.last() {
+                    Some(&last) if last == OpCode::LI as i64 || last == OpCode::LC as i64 => last,
+                    _ => return Err(format!("Line {}: Invalid LValue in pre-increment/decrement", line)),
+                };
+                let step = match &self.current_type {
+                    Type::Ptr(base) => base.size() as i64,
+                    _ => 1,
+                };
+                let store = if load == OpCode::LC as i64 { OpCode::SC } else { OpCode::SI };
+                self.code.pop();
+                self.code.extend([OpCode::PSH as i64, load, OpCode::PSH as i64, OpCode::IMM as i64, step]);
+                self.code.push(if op == UnaryOp::PreInc { OpCode::ADD } else { OpCode::SUB } as i64);
+                self.code.push(store as i64);
+            }
+        }
+        Ok(())
+    }
+    
+    /// `a op b`, the left operand is pushed while the right one is evaluated
+    fn binary(&mut self, ast: &Arena, op: BinaryOp, lhs: ExprId, rhs: ExprId) -> Result<(), String> {
+        self.expr(ast, lhs)?;
+        let line = ast[lhs].line;
+        if let ExprKind::Binary(first, ..) = ast[lhs].kind {
+            self.check_comparison_chain(first, op, line)?;
+        }
+        let op_type = self.current_type.clone(); // the LHS type, for pointer arithmetic
+        if matches!(op, BinaryOp::Lan | BinaryOp::Lor) {
+            return self.short_circuit(ast, op, op_type, rhs, line);
+        }
+        self.code.push(OpCode::PSH as i64);
+        self.expr(ast, rhs)?;
+        
+        let op_type = op_type.decay();
+        match (op, &op_type) {
+            // A pointer moves by whole elements
+            (BinaryOp::Add, Type::Ptr(base)) => {
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, base.size() as i64, OpCode::MUL as i64, OpCode::ADD as i64]);
+                self.current_type = op_type; // Result has the type of LHS
+            }
+            (BinaryOp::Add, _) => {
+                self.code.push(OpCode::ADD as i64);
+                self.current_type = op_type; // Result has the type of LHS
+            }
+            // ptr - ptr counts the elements between them
+            (BinaryOp::Sub, Type::Ptr(base)) if self.current_type.decay().is_ptr() => {
+                self.code.extend([OpCode::SUB as i64, OpCode::PSH as i64, OpCode::IMM as i64, base.size() as i64, OpCode::DIV as i64]);
+                self.current_type = Type::Int;
+            }
+            (BinaryOp::Sub, Type::Ptr(base)) => {
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, base.size() as i64, OpCode::MUL as i64, OpCode::SUB as i64]);
+                self.current_type = op_type; // Result has the type of LHS
+            }
+            _ => {
+                self.code.push(opcode(op) as i64);
+                self.current_type = Type::Int;
+            }
+        }
+        Ok(())
+    }
+    
+    /// `lhs = rhs`, storing through the address lhs loaded from
+    fn assign(&mut self, ast: &Arena, lhs: ExprId, rhs: ExprId, line: usize) -> Result<(), String> {
+        let lhs_line = ast[lhs].line;
+        // A named global or local is stored to directly
+        if let ExprKind::Var(name) = &ast[lhs].kind {
+            let Some(symbol) = self.find_symbol(name) else {
+                return Err(format!("Line {}: Unknown variable '{}'", lhs_line, name));
+            };
+            let sym_class = symbol.class;
+            let sym_value = symbol.value;
+            let sym_type = self.complete(symbol.typ.clone());
+            if sym_type.is_struct() {
+                return Err(format!("Line {}: struct '{}' can't be assigned as a whole, assign its members", lhs_line, name));
+            }
+            let address = match sym_class {
+                SymbolClass::Glo => OpCode::IMM,
+                SymbolClass::Loc => OpCode::LEA,
+                // an enum constant is an IMM like any other, which the checks below reject
+                SymbolClass::Num => OpCode::PSH,
+                _ => return Err(format!("Line {}: Invalid variable '{}'", lhs_line, name)),
+            };
+            if address != OpCode::PSH {
+                // The address stays on the stack while the value is evaluated
+                self.code.extend([address as i64, sym_value, OpCode::PSH as i64]);
+                let rhs_start = self.code.len();
+                self.expr(ast, rhs)?;
+                if sym_type == Type::Char {
+                    self.store_char(rhs_start, line);
+                } else {
+                    self.code.push(OpCode::SI as i64);
+                }
+                self.current_type = sym_type;
+                return Ok(());
+            }
+        }
+        
+        self.expr(ast, lhs)?;
+        if self.current_type.is_struct() {
+            return Err(format!("Line {}: a struct can't be assigned as a whole, assign its members", lhs_line));
+        }
+        let op_type = self.current_type.clone();
+        let store_char = match self.code.last() {
+            // A load is dropped and its address kept for the store
+            Some(&last) if last == OpCode::LI as i64 || last == OpCode::LC as i64 => {
+                self.code.pop();
+                last == OpCode::LC as i64
+            }
+            // An element address that was computed but not loaded yet
+            Some(&last) if last == OpCode::ADD as i64 || last == OpCode::MUL as i64 => op_type == Type::Char,
+            _ => return Err(format!("Line {}: bad lvalue in assignment", lhs_line)),
+        };
+        self.code.push(OpCode::PSH as i64);
+        let rhs_start = self.code.len();
+        self.expr(ast, rhs)?;
+        if store_char {
+            self.store_char(rhs_start, line);
+        } else {
+            self.code.push(OpCode::SI as i64);
+        }
+        Ok(())
+    }
+    
+    /// `lhs op= rhs`, the address is evaluated once: it stays on the stack for the store
+    /// while the load goes through ax
+    fn compound_assign(&mut self, ast: &Arena, op: BinaryOp, lhs: ExprId, rhs: ExprId) -> Result<(), String> {
+        self.expr(ast, lhs)?;
+        let op_type = self.current_type.clone();
+        let load = match self.code.last() {
+            Some(&last) if last == OpCode::LI as i64 || last == OpCode::LC as i64 => last,
+            _ => return Err(format!("Line {}: bad lvalue in compound assignment", ast[lhs].line)),
+        };
+        self.code.pop();
+        
+        // Keep the address for the store, load the current value and push it for the operation
+        self.code.extend([OpCode::PSH as i64, load, OpCode::PSH as i64]);
+        self.expr(ast, rhs)?;
+        
+        // Scale the right side for pointer += and -=
+        if matches!(op, BinaryOp::Add | BinaryOp::Sub) && op_type.is_ptr() {
+            let size = op_type.base_type().map_or(1, |base| base.size());
+            if size > 1 {
+                self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, size as i64, OpCode::MUL as i64]);
+            }
+        }
+        
+        // Store the result through the saved address
+        self.code.push(opcode(op) as i64);
+        self.code.push(if load == OpCode::LC as i64 { OpCode::SC } else { OpCode::SI } as i64);
+        self.current_type = op_type;
+        Ok(())
+    }
+    
+    /// post-increment or decrement, add is ADD or SUB, the old value is the result
+    fn post_step(&mut self, ast: &Arena, add: OpCode, operand: ExprId, line: usize) -> Result<(), String> {
+        self.expr(ast, operand)?;
+        let op_type = self.current_type.clone();
+        let load = match self.code.last() {
+            Some(&last) if last == OpCode::LI as i64 || last == OpCode::LC as i64 => last,
+            _ => return Err(format!("Line {}: Invalid LValue in post-increment/decrement", line)),
+        };
+        self.code.pop();
+        let step = if op_type.is_ptr() {
+            op_type.base_type().map_or(1, |base| base.size() as i64)
+        } else {
+            1
+        };
+        let store = if load == OpCode::LC as i64 { OpCode::SC } else { OpCode::SI };
+        
+        // Keep the old value under the address: [old, addr]
+        self.code.extend([OpCode::PSH as i64, load, OpCode::SWP as i64, OpCode::PSH as i64]);
+        
+        // Store old +/- step through the address
+        self.code.extend([load, OpCode::PSH as i64, OpCode::IMM as i64, step, add as i64, store as i64]);
+        
+        // Take the old value back into AX and drop its slot
+        self.code.extend([OpCode::SWP as i64, OpCode::POP as i64]);
+        self.current_type = op_type;
+        Ok(())
+    }
+    
+    /// generates a statement
+    pub(crate) fn stmt(&mut self, ast: &Arena, id: StmtId) -> Result<(), String> {
+        let stmt = &ast[id];
+        self.mark_line_at(stmt.line);
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.expr(ast, *expr)?,
+            StmtKind::Local(spec, vars) => self.local_declaration(ast, spec, vars)?,
+            StmtKind::If(cond, then, otherwise) => {
+                self.condition(ast, *cond, "if", stmt.line)?;
+                
+                // Emit branch if zero
+                self.code.push(OpCode::BZ as i64);
+                let branch_pos = self.code.len();
+                self.code.push(0); // Placeholder for branch target
+                self.stmt(ast, *then)?;
+                
+                if let Some(otherwise) = *otherwise {
+                    // Add jump to skip else block
+                    self.code.push(OpCode::JMP as i64);
+                    let jump_pos = self.code.len();
+                    self.code.push(0); // Placeholder for jump target
+                    
+                    // Update branch target to jump to else block
+                    self.code[branch_pos] = self.code.len() as i64;
+                    self.stmt(ast, otherwise)?;
+                    
+                    // Update jump target to point after else block
+                    self.code[jump_pos] = self.code.len() as i64;
+                } else {
+                    // No else, update branch target to point to here
+                    self.code[branch_pos] = self.code.len() as i64;
+                }
+            }
+            StmtKind::For(init, cond, inc, body) => {
+                if let Some(init) = *init {
+                    self.expr(ast, init)?;
+                }
+                
+                // No condition means always true (1)
+                let cond_pos = self.code.len();
+                match *cond {
+                    Some(cond) => self.condition(ast, cond, "for", stmt.line)?,
+                    None => self.code.extend([OpCode::IMM as i64, 1]),
+                }
+                
+                // Emit branch if zero
+                self.code.push(OpCode::BZ as i64);
+                let exit_branch_pos = self.code.len();
+                self.code.push(0); // Placeholder for branch target
+                
+                // Jump to loop body (skip increment part for now)
+                self.code.push(OpCode::JMP as i64);
+                let body_jump_pos = self.code.len();
+                self.code.push(0); // Placeholder for body start
+                
+                // The increment, then back to the condition
+                let inc_pos = self.code.len();
+                if let Some(inc) = *inc {
+                    self.expr(ast, inc)?;
+                }
+                self.code.extend([OpCode::JMP as i64, cond_pos as i64]);
+                
+                // Body starts here, continue goes to the increment
+                self.code[body_jump_pos] = self.code.len() as i64;
+                let (breaks, continues) = self.loop_body(ast, *body)?;
+                
+                // Jump to increment part
+                self.code.extend([OpCode::JMP as i64, inc_pos as i64]);
+                
+                // Update exit branch target to point after loop
+                let exit_pos = self.code.len();
+                self.code[exit_branch_pos] = exit_pos as i64;
+                self.patch(&breaks, exit_pos);
+                self.patch(&continues, inc_pos);
+            }
+            // Switch statement (c4x), the value stays on the stack while the body runs
+            StmtKind::Switch(value, body) => {
+                self.expr(ast, *value)?;
+                self.code.push(OpCode::PSH as i64);
+                
+                // Jump over the body to the dispatch table
+                self.code.push(OpCode::JMP as i64);
+                let dispatch_jump = self.code.len();
+                self.code.push(0);
+                
+                self.switches.push(SwitchLabels::default());
+                self.breaks.push(Vec::new());
+                let result = self.stmt(ast, *body);
+                let labels = self.switches.pop().unwrap_or_default();
+                let mut exits = self.breaks.pop().unwrap_or_default();
+                result?;
+                
+                // Falling off the end of the body leaves the switch
+                self.code.push(OpCode::JMP as i64);
+                exits.push(self.code.len());
+                self.code.push(0);
+                
+                // Compare a copy of the value against each case
+                self.code[dispatch_jump] = self.code.len() as i64;
+                for (value, target) in labels.cases {
+                    self.code.extend([OpCode::DUP as i64, OpCode::IMM as i64, value, OpCode::EQ as i64, OpCode::BNZ as i64, target as i64]);
+                }
+                self.code.push(OpCode::JMP as i64);
+                match labels.default {
+                    Some(target) => self.code.push(target as i64),
+                    None => {
+                        exits.push(self.code.len());
+                        self.code.push(0);
+                    }
+                }
+                
+                // Every way out lands on dropping the value
+                let end = self.code.len();
+                self.patch(&exits, end);
+                self.code.push(OpCode::POP as i64);
+            }
+            // Case label, must be a constant
+            StmtKind::Case(value, body) => {
+                if self.switches.is_empty() {
+                    return Err(format!("Line {}: case outside of switch", ast[*value].line));
+                }
+                let value = self.constant(ast, *value, "case label")?;
+                let pos = self.code.len();
+                if let Some(labels) = self.switches.last_mut() {
+                    if labels.cases.iter().any(|&(v, _)| v == value) {
+                        return Err(format!("Line {}: duplicate case value {}", ast[*body].line, value));
+                    }
+                    labels.cases.push((value, pos));
+                }
+                self.stmt(ast, *body)?;
+            }
+            StmtKind::Default(body) => {
+                let pos = self.code.len();
+                match self.switches.last_mut() {
+                    Some(labels) if labels.default.is_none() => labels.default = Some(pos),
+                    Some(_) => return Err(format!("Line {}: multiple default labels in one switch", ast[*body].line)),
+                    None => return Err(format!("Line {}: default outside of switch", ast[*body].line)),
+                }
+                self.stmt(ast, *body)?;
+            }
+            // Break out of the innermost loop or switch
+            StmtKind::Break => {
+                if self.breaks.is_empty() {
+                    return Err(format!("Line {}: break outside of a loop or switch", stmt.line));
+                }
+                self.code.push(OpCode::JMP as i64);
+                let pos = self.code.len();
+                self.code.push(0);
+                if let Some(pending) = self.breaks.last_mut() {
+                    pending.push(pos);
+                }
+            }
+            // Next iteration of the innermost loop
+            StmtKind::Continue => {
+                let Some(&(switches, _)) = self.continues.last() else {
+                    return Err(format!("Line {}: continue outside of a loop", stmt.line));
+                };
+                // the switches it jumps out of each leave their value on the stack
+                for _ in switches..self.switches.len() {
+                    self.code.push(OpCode::POP as i64);
+                }
+                self.code.push(OpCode::JMP as i64);
+                let pos = self.code.len();
+                self.code.push(0);
+                if let Some((_, pending)) = self.continues.last_mut() {
+                    pending.push(pos);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                // Remember start of condition
+                let loop_start = self.code.len();
+                self.condition(ast, *cond, "while", stmt.line)?;
+                
+                // Emit branch if zero
+                self.code.push(OpCode::BZ as i64);
+                let branch_pos = self.code.len();
+                self.code.push(0); // Placeholder for branch target
+                
+                // Loop body, continue goes back to the condition
+                let (breaks, continues) = self.loop_body(ast, *body)?;
+                
+                // Jump back to loop start
+                self.code.extend([OpCode::JMP as i64, loop_start as i64]);
+                
+                // Update branch target to point after loop
+                let exit_pos = self.code.len();
+                self.code[branch_pos] = exit_pos as i64;
+                self.patch(&breaks, exit_pos);
+                self.patch(&continues, loop_start);
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = *value {
+                    self.expr(ast, value)?;
+                    if self.current_type.is_struct() {
+                        return Err(format!("Line {}: a struct can't be returned, return a pointer to it", ast[value].line));
+                    }
+                }
+                self.code.push(OpCode::LEV as i64);
+            }
+            StmtKind::Block(items) => {
+                // Names declared in here go out of scope at '}' and their slots are reused
+                let outer_scope = core::mem::replace(&mut self.scope_start, self.symbols.len());
+                let outer_locals = self.locals;
+                self.block_items(ast, items)?;
+                self.close_scope(outer_scope, outer_locals);
+            }
+            StmtKind::Empty => {}
+        }
+        Ok(())
+    }
+    
+    // Expose the symbols for testing
+    pub fn get_symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+    
+    /// functions generated so far, in code order
+    pub fn functions(&self) -> &[FunctionInfo] {
+        &self.functions
+    }
+    
+    /// data offset and source line of every string literal, in source order
+    pub fn strings(&self) -> &[(usize, usize)] {
+        &self.strings
+    }
+    
+    /// name and argument count of each host function the program may call, registered
+    /// or declared extern, in the order VM::link_host_fns binds them
+    pub fn host_fns(&self) -> &[(String, usize)] {
+        &self.host_fns
+    }
+    
+    /// code address and source line of each statement, sorted by address
+    pub fn lines(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+    
+    /// code generated so far, all of it once generate succeeds
+    pub fn code(&self) -> &[i64] {
+        &self.code
+    }
+    
+    /// data segment so far
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    
+    /// every declaration that failed with keep_going on, in source order
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+    
+    /// warnings collected so far, each "Line N: ..."
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+    
+    /// the code and type of the last expression generated, for compile_expression
+    pub(crate) fn into_expression(self) -> (Vec<i64>, Type) {
+        (self.code, self.current_type)
+    }
+    
+    /// records that the code from here on comes from a given line
+    fn mark_line_at(&mut self, line: usize) {
+        let addr = self.code.len();
+        match self.lines.last_mut() {
+            Some(last) if last.0 == addr => last.1 = line,
+            Some(last) if last.1 == line => {}
+            _ => self.lines.push((addr, line)),
+        }
+    }
+    
+    /// records a warning for a given line unless that kind is turned off
+    fn warn_at(&mut self, line: usize, kind: Warning, message: String) {
+        if self.options.warnings.enabled(kind) {
+            self.warnings.push(format!("Line {}: {}", line, message));
+        }
+    }
+    
+    /// address just past the arguments of a variadic call, bp + 2 + n, in ax
+    fn emit_va_base(&mut self) {
+        self.code.extend([OpCode::LEA as i64, 2, OpCode::PSH as i64, OpCode::LEA as i64, 2, OpCode::LI as i64, OpCode::ADD as i64]);
+    }
+    
+    /// va_count() is the number of arguments after the named ones, va_get(i) the i-th of them
+    fn va_builtin(&mut self, name: &str, arg_count: usize, line: usize) -> Result<(), String> {
+        let Some(named) = self.va_named else {
+            return Err(format!("Line {}: {}() used outside a variadic function", line, name));
+        };
+        let expected = usize::from(name == "va_get");
+        if arg_count != expected {
+            let plural = if expected == 1 { "" } else { "s" };
+            return Err(format!("Line {}: '{}' takes {} argument{}, got {}", line, name, expected, plural, arg_count));
+        }
+        if name == "va_count" {
+            // n - named
+            self.code.extend([OpCode::LEA as i64, 2, OpCode::LI as i64, OpCode::PSH as i64, OpCode::IMM as i64, named as i64, OpCode::SUB as i64]);
+        } else {
+            // i is on the stack, argument named + i is at bp + 2 + n - named - i
+            self.emit_va_base();
+            self.code.extend([OpCode::SUB as i64, OpCode::PSH as i64, OpCode::IMM as i64, named as i64, OpCode::ADD as i64]);
+            self.code.extend([OpCode::PSH as i64, OpCode::IMM as i64, -1, OpCode::MUL as i64, OpCode::LI as i64]);
+        }
+        self.current_type = Type::Int;
+        Ok(())
+    }
+    
+    /// a declaration has to start with its type, implicit_int only warns and makes it an int
+    fn implicit_int(&mut self, name: &str, line: usize) -> Result<(), String> {
+        let message = format!("'{}' is declared without a type", name);
+        if self.options.implicit_int {
+            self.warn_at(line, Warning::ImplicitInt, format!("{}, it defaults to int", message));
+            Ok(())
+        } else {
+            Err(format!("Line {}: {}, write 'int {}' if an int is meant", line, message, name))
+        }
+    }
+    
+    /// a call must pass as many arguments as the function has parameters, unchecked_calls only warns
+    fn check_arity(&mut self, name: &str, params: usize, args: usize, line: usize) -> Result<(), String> {
+        let variadic = self.variadic.contains(name);
+        if params == args || (variadic && args > params) {
+            return Ok(());
+        }
+        let plural = if params == 1 { "" } else { "s" };
+        let at_least = if variadic { "at least " } else { "" };
+        let message = format!("'{}' takes {}{} argument{}, got {}", name, at_least, params, plural, args);
+        if self.options.unchecked_calls {
+            self.warn_at(line, Warning::Arity, message);
+            Ok(())
+        } else {
+            Err(format!("Line {}: {}", line, message))
+        }
+    }
+    
+    /// emits SC, warning first if the value code from rhs_start is a constant a char can't hold
+    fn store_char(&mut self, rhs_start: usize, line: usize) {
+        if let [imm, value] = self.code[rhs_start..] {
+            if imm == OpCode::IMM as i64 {
+                self.check_char_constant(value, line);
+            }
+        }
+        self.code.push(OpCode::SC as i64);
+    }
+    
+    /// a < b < c compares the 0 or 1 of a < b with c, a warning or under pedantic an error
+    fn check_comparison_chain(&mut self, first: BinaryOp, second: BinaryOp, line: usize) -> Result<(), String> {
+        let group = |op| match op {
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => Some(0),
+            BinaryOp::Eq | BinaryOp::Ne => Some(1),
+            _ => None,
+        };
+        if group(first).is_none() || group(first) != group(second) {
+            return Ok(());
+        }
+        let message = format!("'{}' after '{}' compares the first comparison's result, 0 or 1, not its right operand; compare each pair separately", second.spelling(), first.spelling());
+        if self.options.pedantic {
+            return Err(format!("Line {}: {}", line, message));
+        }
+        self.warn_at(line, Warning::ComparisonChain, message);
+        Ok(())
+    }
+    
+    /// warns when a constant stored in a char changes value
+    fn check_char_constant(&mut self, value: i64, line: usize) {
+        if !(-128..=255).contains(&value) {
+            let becomes = if self.options.signed_char { value as i8 as i64 } else { value as u8 as i64 };
+            self.warn_at(line, Warning::Conversion, format!("constant {} does not fit in a char, it becomes {}", value, becomes));
+        }
+    }
+}
+
+/// typ with pointers levels of pointer around it
+fn pointer_to(typ: Type, pointers: usize) -> Type {
+    (0..pointers).fold(typ, |typ, _| Type::Ptr(Box::new(typ)))
+}
+
+/// the instruction a binary operator ends in
+fn opcode(op: BinaryOp) -> OpCode {
+    match op {
+        BinaryOp::Add => OpCode::ADD,
+        BinaryOp::Sub => OpCode::SUB,
+        BinaryOp::Mul => OpCode::MUL,
+        BinaryOp::Div => OpCode::DIV,
+        BinaryOp::Mod => OpCode::MOD,
+        BinaryOp::Shl => OpCode::SHL,
+        BinaryOp::Shr => OpCode::SHR,
+        BinaryOp::And => OpCode::AND,
+        BinaryOp::Or => OpCode::OR,
+        BinaryOp::Xor => OpCode::XOR,
+        BinaryOp::Eq => OpCode::EQ,
+        BinaryOp::Ne => OpCode::NE,
+        BinaryOp::Lt => OpCode::LT,
+        BinaryOp::Gt => OpCode::GT,
+        BinaryOp::Le => OpCode::LE,
+        BinaryOp::Ge => OpCode::GE,
+        // && and || branch instead, see short_circuit
+        BinaryOp::Lan => OpCode::BZ,
+        BinaryOp::Lor => OpCode::BNZ,
+    }
+}
+
+/// name, LEA offset and slot count of a local for FunctionInfo::slots
+fn local_slot(symbol: &Symbol) -> (String, i64, usize) {
+    let slots = if symbol.typ.is_array() { symbol.typ.size().max(1) } else { 1 };
+    (symbol.name.clone(), symbol.value, slots)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    
+    #[test]
+    fn test_symbol_table() {
+        let mut gen = CodeGen::new(false);
+        gen.declare_builtins();
+        
+        // Add a global symbol
+        gen.add_symbol("global_var", SymbolClass::Glo, Type::Int, 123).unwrap();
+        
+        // Verify it exists
+        let symbol = gen.find_symbol("global_var").unwrap();
+        assert_eq!(symbol.name, "global_var");
+        assert_eq!(symbol.class, SymbolClass::Glo);
+        assert_eq!(symbol.value, 123);
+        assert!(matches!(symbol.typ, Type::Int));
+    }
+    
+    #[test]
+    fn test_symbol_index_follows_scopes() {
+        let source = "int x; char *s;\nint f(int x) { int y; { char x; y = x; } return x + y; }\nint main() { int s; s = f(1); return s; }";
+        let program = Parser::new(source, false).parse_ast().unwrap();
+        let mut gen = CodeGen::new(false);
+        gen.declare_builtins();
+        gen.generate(&program, true).unwrap();
+        
+        // the locals are gone and every name finds what a scan from the end would
+        assert!(gen.symbols.iter().all(|s| s.class != SymbolClass::Loc));
+        for symbol in &gen.symbols {
+            let scanned = gen.symbols.iter().rposition(|s| s.name == symbol.name);
+            assert_eq!(gen.symbol_position(&symbol.name), scanned);
+        }
+        assert_eq!(gen.find_symbol("x").unwrap().class, SymbolClass::Glo);
+        assert!(matches!(gen.find_symbol("s").unwrap().typ, Type::Ptr(_)));
+        assert!(gen.find_symbol("y").is_none());
+        let indexed: usize = gen.symbol_index.values().map(Vec::len).sum();
+        assert_eq!(indexed, gen.symbols.len());
+    }
+}
@@ -0,0 +1,466 @@
+//! interactive debugger over a compiled program
+//! breakpoints by function name, source line or address, stepping and backtraces
+//! breakpoint conditions and print are C expressions evaluated against live memory
+
+use crate::parser::{self, FunctionInfo, OpCode, Parser, Symbol, SymbolClass, Type};
+use crate::vm::{Instruction, VM};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// where the program stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,                 // number shown to the user, never reused
+    pub addr: usize,               // code address of the instruction it stops before
+    pub condition: Option<String>, // stop only when this expression is nonzero
+    pub ignore: usize,             // hits still to skip before stopping
+    pub hits: usize,               // times reached with the condition true
+    code: Vec<i64>,                // the condition, compiled in the breakpoint's function
+}
+
+/// a VM with the compiler's tables, driven one command at a time
+pub struct Debugger {
+    vm: VM,
+    symbols: Vec<Symbol>,         // kept from the compile, to resolve names
+    functions: Vec<FunctionInfo>, // for locations and info functions
+    lines: Vec<(usize, usize)>,   // code address and source line of each statement
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,               // id of the next breakpoint
+    printed: usize,               // values shown by print, numbered $1, $2, ...
+    finished: Option<String>,     // how the run ended, once it has
+}
+
+impl Debugger {
+    /// wraps a VM made from the parser's code, keeping its symbol table
+    pub fn new(mut vm: VM, parser: &Parser) -> Debugger {
+        vm.set_functions(parser.functions().to_vec());
+        vm.set_lines(parser.lines().to_vec());
+        vm.set_globals(parser.get_symbols());
+        Debugger {
+            vm,
+            symbols: parser.get_symbols().to_vec(),
+            functions: parser.functions().to_vec(),
+            lines: parser.lines().to_vec(),
+            breakpoints: Vec::new(),
+            next_id: 1,
+            printed: 0,
+            finished: None,
+        }
+    }
+
+    /// runs one command line, returning what to print
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "" => Ok(String::new()),
+            "break" | "b" => self.add_breakpoint(rest),
+            "delete" | "d" => self.delete_breakpoint(rest),
+            "condition" => self.set_condition(rest),
+            "ignore" => self.set_ignore(rest),
+            "run" | "r" | "continue" | "c" => Ok(self.resume(false)),
+            "step" | "s" | "stepi" | "si" => Ok(self.resume(true)),
+            "backtrace" | "bt" => Ok(self.backtrace()),
+            "print" | "p" => self.print(rest),
+            "info" | "i" => match rest {
+                "functions" => Ok(self.info_functions()),
+                "breakpoints" | "break" => Ok(self.info_breakpoints()),
+                _ => Err(format!("unknown info command '{}', try 'info functions' or 'info breakpoints'", rest)),
+            },
+            "help" | "h" => Ok(String::from(HELP)),
+            _ => Err(format!("unknown command '{}', try 'help'", command)),
+        }
+    }
+
+    /// breakpoints in the order they were set
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// the VM being debugged
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// NAME, LINE or *ADDR, then optionally "if EXPR"
+    fn add_breakpoint(&mut self, spec: &str) -> Result<String, String> {
+        let (spec, condition) = match spec.split_once(char::is_whitespace) {
+            Some((spec, rest)) => match rest.trim().strip_prefix("if") {
+                Some(condition) if condition.is_empty() || condition.starts_with(char::is_whitespace) => (spec, Some(condition.trim())),
+                _ => return Err(format!("expected 'if' after '{}'", spec)),
+            },
+            None => (spec, None),
+        };
+        let addr = self.resolve(spec)?;
+        let code = match condition {
+            Some(condition) => self.compile_condition(addr, condition)?,
+            None => Vec::new(),
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        let condition = condition.map(str::to_string);
+        self.breakpoints.push(Breakpoint { id, addr, condition, ignore: 0, hits: 0, code });
+        Ok(format!("Breakpoint {} at {}\n", id, self.location(addr)))
+    }
+
+    /// compiles a condition with the names in scope at addr
+    fn compile_condition(&self, addr: usize, condition: &str) -> Result<Vec<i64>, String> {
+        if condition.is_empty() {
+            return Err("'if' needs a condition".to_string());
+        }
+        // the expression is a single line, so its line number says nothing
+        let (code, _) = Parser::compile_expression(condition, &self.symbols, self.function_at(addr))
+            .map_err(|e| e.strip_prefix("Line 1: ").map_or(e.clone(), str::to_string))?;
+        Ok(code)
+    }
+
+    /// "condition N EXPR" sets a condition, "condition N" removes it
+    fn set_condition(&mut self, spec: &str) -> Result<String, String> {
+        let (id, condition) = spec.split_once(char::is_whitespace).unwrap_or((spec, ""));
+        let index = self.breakpoint_index(id)?;
+        let condition = condition.trim();
+        let breakpoint = &self.breakpoints[index];
+        let (id, addr) = (breakpoint.id, breakpoint.addr);
+        if condition.is_empty() {
+            let breakpoint = &mut self.breakpoints[index];
+            breakpoint.condition = None;
+            breakpoint.code.clear();
+            return Ok(format!("Breakpoint {} now unconditional\n", id));
+        }
+        let code = self.compile_condition(addr, condition)?;
+        let breakpoint = &mut self.breakpoints[index];
+        breakpoint.condition = Some(condition.to_string());
+        breakpoint.code = code;
+        Ok(format!("Breakpoint {} stops if {}\n", id, condition))
+    }
+
+    /// "ignore N COUNT" skips the next COUNT hits of breakpoint N
+    fn set_ignore(&mut self, spec: &str) -> Result<String, String> {
+        let (id, count) = spec.split_once(char::is_whitespace)
+            .ok_or_else(|| "ignore needs a breakpoint number and a count".to_string())?;
+        let count: usize = count.trim().parse().map_err(|_| format!("invalid count '{}'", count.trim()))?;
+        let index = self.breakpoint_index(id)?;
+        let breakpoint = &mut self.breakpoints[index];
+        breakpoint.ignore = count;
+        Ok(match count {
+            0 => format!("Breakpoint {} stops at its next hit\n", breakpoint.id),
+            1 => format!("Breakpoint {} ignores its next hit\n", breakpoint.id),
+            n => format!("Breakpoint {} ignores its next {} hits\n", breakpoint.id, n),
+        })
+    }
+
+    fn breakpoint_index(&self, id: &str) -> Result<usize, String> {
+        let id: usize = id.parse().map_err(|_| format!("invalid breakpoint number '{}'", id))?;
+        self.breakpoints.iter().position(|b| b.id == id).ok_or_else(|| format!("no breakpoint {}", id))
+    }
+
+    /// code address a breakpoint spec names
+    fn resolve(&self, spec: &str) -> Result<usize, String> {
+        if spec.is_empty() {
+            return Err("break needs a function name, line number or *address".to_string());
+        }
+        if let Some(addr) = spec.strip_prefix('*') {
+            let addr: usize = addr.parse().map_err(|_| format!("invalid address '{}'", addr))?;
+            return match self.function_at(addr) {
+                Some(_) => Ok(addr),
+                None => Err(format!("address {} is not in any function", addr)),
+            };
+        }
+        if let Ok(line) = spec.parse::<usize>() {
+            // the first statement on that line, or on the next line that has code
+            return self.lines.iter()
+                .filter(|&&(_, l)| l >= line)
+                .min_by_key(|&&(addr, l)| (l, addr))
+                .map(|&(addr, _)| addr)
+                .ok_or_else(|| format!("no code at or after line {}", line));
+        }
+        match self.symbols.iter().rev().find(|s| s.name == spec) {
+            Some(symbol) if symbol.class == SymbolClass::Fun && symbol.value >= 0 => Ok(self.after_prologue(symbol.value as usize)),
+            Some(symbol) if symbol.class == SymbolClass::Fun => Err(format!("function '{}' has no code", spec)),
+            Some(symbol) if matches!(symbol.class, SymbolClass::Sys | SymbolClass::Host) =>
+                Err(format!("'{}' is a system call, not a function", spec)),
+            Some(_) => Err(format!("'{}' is not a function", spec)),
+            None => Err(format!("no function named '{}'", spec)),
+        }
+    }
+
+    /// the instruction after the function's ENT, so its frame and parameters are set up
+    fn after_prologue(&self, start: usize) -> usize {
+        let entered = start + 1 + OpCode::ENT.arity();
+        match self.function_at(start) {
+            // a variadic function copies its named parameters into locals before the first statement
+            Some(f) if f.variadic => self.lines.iter()
+                .map(|&(addr, _)| addr)
+                .find(|&addr| entered < addr && addr < f.end)
+                .unwrap_or(entered),
+            _ => entered,
+        }
+    }
+
+    fn delete_breakpoint(&mut self, spec: &str) -> Result<String, String> {
+        let index = self.breakpoint_index(spec)?;
+        let breakpoint = self.breakpoints.remove(index);
+        Ok(format!("Deleted breakpoint {}\n", breakpoint.id))
+    }
+
+    /// runs to the next breakpoint or the end, or one instruction when stepping
+    fn resume(&mut self, single: bool) -> String {
+        if let Some(ended) = &self.finished {
+            return format!("The program is not running, it {}\n", ended);
+        }
+        loop {
+            match self.vm.run_steps(1) {
+                Err(e) => {
+                    self.finished = Some(format!("stopped with: {}", e));
+                    return format!("Runtime error: {}\n{}", e, self.backtrace());
+                }
+                Ok(result) => {
+                    if let Some(code) = result.exit_code {
+                        self.finished = Some(format!("exited with code {}", code));
+                        return format!("Program exited with code {}\n", code);
+                    }
+                }
+            }
+            let pc = self.vm.pc();
+            if single {
+                return format!("{}\n", self.describe(pc));
+            }
+            let mut stop = None;
+            for index in 0..self.breakpoints.len() {
+                if self.breakpoints[index].addr == pc && stop.is_none() {
+                    stop = self.check_breakpoint(index);
+                }
+            }
+            if let Some(text) = stop {
+                return text;
+            }
+        }
+    }
+
+    /// evaluates a C expression in the paused frame, formatted by its type
+    fn print(&mut self, expr: &str) -> Result<String, String> {
+        if expr.is_empty() {
+            return Err("print needs an expression".to_string());
+        }
+        // before the run and after it only globals are in scope
+        let function = match self.finished {
+            None => self.function_at(self.vm.pc()),
+            Some(_) => None,
+        };
+        let (code, typ) = Parser::compile_expression(expr, &self.symbols, function)
+            .map_err(|e| e.strip_prefix("Line 1: ").map_or(e.clone(), str::to_string))?;
+        let value = self.evaluate(&code).ok_or_else(|| format!("can't evaluate '{}' here", expr))?;
+        self.printed += 1;
+        Ok(format!("${} = {}\n", self.printed, self.format_value(value, &typ)))
+    }
+
+    /// a value the way C would show it: ints in decimal, chars with their character,
+    /// char pointers with their string and arrays with their elements
+    fn format_value(&self, value: i64, typ: &Type) -> String {
+        match typ {
+            Type::Int => format!("{}", value),
+            Type::Char => format!("{} {}", value, char_literal(value as u8)),
+            Type::Ptr(base) if **base == Type::Char => match self.read_string(value) {
+                Some(text) => format!("{} {}", value, text),
+                None => format!("{}", value),
+            },
+            Type::Ptr(_) => format!("({}) {}", typ, value),
+            Type::Array(base, _) if **base == Type::Char => self.read_string(value).unwrap_or_else(|| format!("{}", value)),
+            Type::Array(base, len) => {
+                let shown = (*len).min(MAX_ELEMENTS);
+                let mut elements: Vec<String> = (0..shown)
+                    .map(|i| {
+                        let addr = value.saturating_add((i * base.size()) as i64);
+                        match self.read(addr, base) {
+                            Some(element) => self.format_value(element, base),
+                            None => String::from("?"),
+                        }
+                    })
+                    .collect();
+                if shown < *len {
+                    elements.push(String::from("..."));
+                }
+                format!("{{{}}}", elements.join(", "))
+            }
+            Type::Struct(layout) => {
+                let members: Vec<String> = layout.members.iter()
+                    .map(|member| {
+                        let addr = value.saturating_add(member.offset as i64);
+                        match self.read(addr, &member.typ) {
+                            Some(field) => format!("{} = {}", member.name, self.format_value(field, &member.typ)),
+                            None => format!("{} = ?", member.name),
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", members.join(", "))
+            }
+        }
+    }
+
+    /// what a load of typ at addr gives, arrays are their own address
+    fn read(&self, addr: i64, typ: &Type) -> Option<i64> {
+        let addr = usize::try_from(addr).ok()?;
+        match typ {
+            Type::Char => self.vm.read_char(addr).map(i64::from),
+            Type::Array(_, _) | Type::Struct(_) => Some(addr as i64),
+            _ => self.vm.read_int(addr),
+        }
+    }
+
+    /// quoted NUL-terminated string at addr, cut short past MAX_STRING characters
+    fn read_string(&self, addr: i64) -> Option<String> {
+        let addr = usize::try_from(addr).ok()?;
+        let mut text = String::from("\"");
+        for i in 0..MAX_STRING {
+            match self.vm.read_char(addr.checked_add(i)?)? {
+                0 => return Some(text + "\""),
+                c => text += &escape(c),
+            }
+        }
+        Some(text + "\"...")
+    }
+
+    /// counts a hit when the condition holds, the text to stop with unless the hit is ignored
+    fn check_breakpoint(&mut self, index: usize) -> Option<String> {
+        let breakpoint = &self.breakpoints[index];
+        let location = format!("Breakpoint {}, {}\n", breakpoint.id, self.location(breakpoint.addr));
+        if let Some(condition) = &breakpoint.condition {
+            match self.evaluate(&breakpoint.code) {
+                Some(0) => return None,
+                Some(_) => {}
+                // stop so the user sees why
+                None => return Some(format!("can't evaluate '{}' here\n{}", condition, location)),
+            }
+        }
+        let breakpoint = &mut self.breakpoints[index];
+        breakpoint.hits += 1;
+        if breakpoint.ignore > 0 {
+            breakpoint.ignore -= 1;
+            return None;
+        }
+        Some(location)
+    }
+
+    /// runs compiled expression code against the paused program's memory
+    fn evaluate(&self, code: &[i64]) -> Option<i64> {
+        let vm = &self.vm;
+        parser::evaluate(code, &|op, value| match op {
+            OpCode::LEA => (vm.bp() as i64).checked_add(value),
+            OpCode::LI => vm.read_int(usize::try_from(value).ok()?),
+            OpCode::LC => vm.read_char(usize::try_from(value).ok()?).map(i64::from),
+            _ => None,
+        })
+    }
+
+    fn backtrace(&self) -> String {
+        let frames = if self.finished.is_some() { self.vm.frames() } else { self.vm.paused_frames() };
+        if frames.is_empty() {
+            return String::from("No frames\n");
+        }
+        frames.iter().enumerate()
+            .map(|(i, frame)| {
+                let line = self.vm.line_of(frame.pc).map(|l| format!(", line {}", l)).unwrap_or_default();
+                format!("#{} {} (pc={}{}) args={:?} locals={:?}\n", i, frame.function, frame.pc, line, frame.args, frame.locals)
+            })
+            .collect()
+    }
+
+    fn info_functions(&self) -> String {
+        let mut functions: Vec<&FunctionInfo> = self.functions.iter().collect();
+        functions.sort_by_key(|f| f.start);
+        functions.iter()
+            .map(|f| format!("{} at {}, line {}, {} parameter{}\n",
+                             f.name, f.start, f.line, f.params, if f.params == 1 { "" } else { "s" }))
+            .collect()
+    }
+
+    fn info_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return String::from("No breakpoints\n");
+        }
+        let mut text = String::new();
+        for b in &self.breakpoints {
+            text += &format!("{} at {}", b.id, self.location(b.addr));
+            if let Some(condition) = &b.condition {
+                text += &format!(" if {}", condition);
+            }
+            text.push('\n');
+            if b.hits > 0 {
+                text += &format!("  hit {} time{}\n", b.hits, if b.hits == 1 { "" } else { "s" });
+            }
+            if b.ignore > 0 {
+                text += &format!("  ignore next {} hit{}\n", b.ignore, if b.ignore == 1 { "" } else { "s" });
+            }
+        }
+        text
+    }
+
+    /// location and the instruction there, "12: add, line 3: IMM 5"
+    fn describe(&self, addr: usize) -> String {
+        match self.vm.instruction(addr) {
+            Some(Instruction { op: Some(op), operand, .. }) if op.has_operand() =>
+                format!("{}: {} {}", self.location(addr), op.name(), operand),
+            Some(Instruction { op: Some(op), .. }) => format!("{}: {}", self.location(addr), op.name()),
+            _ => self.location(addr),
+        }
+    }
+
+    fn function_at(&self, addr: usize) -> Option<&FunctionInfo> {
+        self.functions.iter().find(|f| f.start <= addr && addr < f.end)
+    }
+
+    /// "12: add, line 3", or just the address in the entry stub
+    fn location(&self, addr: usize) -> String {
+        let mut text = format!("{}", addr);
+        if let Some(function) = self.function_at(addr) {
+            text += &format!(": {}", function.name);
+            if let Some(line) = self.vm.line_of(addr) {
+                text += &format!(", line {}", line);
+            }
+        }
+        text
+    }
+}
+
+// elements of an array and characters of a string print shows
+const MAX_ELEMENTS: usize = 100;
+const MAX_STRING: usize = 200;
+
+/// a char the way C source writes it
+fn escape(c: u8) -> String {
+    match c {
+        b'\n' => String::from("\\n"),
+        b'\t' => String::from("\\t"),
+        b'\r' => String::from("\\r"),
+        0 => String::from("\\0"),
+        b'\\' => String::from("\\\\"),
+        b'"' => String::from("\\\""),
+        b'\'' => String::from("\\'"),
+        c if c.is_ascii_graphic() || c == b' ' => String::from(c as char),
+        c => format!("\\x{:02x}", c),
+    }
+}
+
+fn char_literal(c: u8) -> String {
+    match c {
+        b'"' => String::from("'\"'"),
+        c => format!("'{}'", escape(c)),
+    }
+}
+
+const HELP: &str = "\
+break NAME|LINE|*ADDR   stop before a function, a source line or an instruction
+break SPEC if EXPR      stop there only when the C expression EXPR is nonzero
+condition N [EXPR]      set or remove the condition of breakpoint N
+ignore N COUNT          skip the next COUNT hits of breakpoint N
+delete N                remove breakpoint N
+run, continue           run until a breakpoint or the end
+step                    run one instruction and show the next
+backtrace               show the call stack
+print EXPR              evaluate a C expression in the current frame, like x or arr[i]
+info functions          list functions with their addresses
+info breakpoints        list breakpoints
+quit                    leave the debugger
+";
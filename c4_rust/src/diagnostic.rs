@@ -0,0 +1,93 @@
+//! compiler messages tagged with a severity
+//! rendered as clang-style text, colored or plain, or as JSON for tools
+
+use alloc::format;
+use alloc::string::{String, ToString};
+
+/// how bad a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,   // compilation stopped
+    Warning, // compiled anyway
+}
+
+impl Severity {
+    /// the word printed before the message
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    // bold red or bold yellow
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// one message from the compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: Option<usize>, // source line, when the message names one
+    pub message: String,     // text without the "Line N: " prefix
+}
+
+impl Diagnostic {
+    /// wraps a parser message, taking the line from its "Line N: " prefix
+    pub fn new(severity: Severity, text: &str) -> Diagnostic {
+        let split = text.strip_prefix("Line ")
+            .and_then(|rest| rest.split_once(": "))
+            .and_then(|(line, message)| Some((line.parse().ok()?, message)));
+        match split {
+            Some((line, message)) => Diagnostic { severity, line: Some(line), message: message.to_string() },
+            None => Diagnostic { severity, line: None, message: text.to_string() },
+        }
+    }
+
+    /// "file:line: severity: message", the severity in color when asked
+    pub fn render(&self, file: &str, color: bool) -> String {
+        let location = match self.line {
+            Some(line) => format!("{}:{}", file, line),
+            None => file.to_string(),
+        };
+        let severity = if color {
+            format!("{}{}:\x1b[0m", self.severity.color(), self.severity.name())
+        } else {
+            format!("{}:", self.severity.name())
+        };
+        format!("{}: {} {}", location, severity, self.message)
+    }
+
+    /// one JSON object on a single line, line is null when unknown
+    pub fn to_json(&self, file: &str) -> String {
+        let line = match self.line {
+            Some(line) => line.to_string(),
+            None => String::from("null"),
+        };
+        format!("{{\"severity\": \"{}\", \"file\": {}, \"line\": {}, \"message\": {}}}",
+                self.severity.name(), json_string(file), line, json_string(&self.message))
+    }
+}
+
+/// text as a quoted JSON string
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
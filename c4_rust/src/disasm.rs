@@ -0,0 +1,81 @@
+//! readable listing of compiled code, grouped by function
+//! calls name their callee and branches jump to numbered labels
+
+use crate::parser::{FunctionInfo, OpCode};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// the whole code segment, one block per function in address order
+pub fn disassemble(code: &[i64], functions: &[FunctionInfo]) -> String {
+    let mut sorted: Vec<&FunctionInfo> = functions.iter().collect();
+    sorted.sort_by_key(|f| f.start);
+    let function_at = |addr: i64| sorted.iter().find(|f| f.start as i64 == addr);
+
+    // number the branch targets that start an instruction, in address order
+    let starts: Vec<usize> = instructions(code).map(|(pc, _, _)| pc).collect();
+    let mut labels: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, op, operand) in instructions(code) {
+        if matches!(op, Some(OpCode::JMP | OpCode::BZ | OpCode::BNZ)) {
+            if let Some(target) = usize::try_from(operand).ok().filter(|t| starts.binary_search(t).is_ok()) {
+                labels.insert(target, 0);
+            }
+        }
+    }
+    for (n, label) in labels.values_mut().enumerate() {
+        *label = n + 1;
+    }
+
+    let mut out = String::new();
+    for (pc, op, operand) in instructions(code) {
+        if let Some(function) = function_at(pc as i64) {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&format!("{}:  ; line {}\n", function.name, function.line));
+        } else if pc == 0 {
+            out.push_str("<entry>:\n");
+        }
+        if let Some(label) = labels.get(&pc) {
+            out.push_str(&format!("L{}:\n", label));
+        }
+
+        let text = match op {
+            None => format!(".word {}", operand),
+            Some(op) if !op.has_operand() => op.name().to_string(),
+            Some(OpCode::JSR) => match function_at(operand) {
+                Some(callee) => format!("JSR {}", callee.name),
+                None => format!("JSR {}", operand),
+            },
+            Some(op @ (OpCode::JMP | OpCode::BZ | OpCode::BNZ)) => {
+                match usize::try_from(operand).ok().and_then(|target| labels.get(&target)) {
+                    Some(label) => format!("{} L{}", op.name(), label),
+                    None => format!("{} {}", op.name(), operand),
+                }
+            }
+            Some(op) => format!("{} {}", op.name(), operand),
+        };
+        out.push_str(&format!("{:>6}  {}\n", pc, text));
+    }
+    out
+}
+
+// address, opcode and operand of each instruction, the raw word for unknown ones
+fn instructions(code: &[i64]) -> impl Iterator<Item = (usize, Option<OpCode>, i64)> + '_ {
+    let mut pc = 0;
+    core::iter::from_fn(move || {
+        let word = *code.get(pc)?;
+        let start = pc;
+        match OpCode::from_i64(word) {
+            Some(op) => {
+                pc += 1 + op.arity();
+                Some((start, Some(op), code.get(start + 1).copied().filter(|_| op.arity() > 0).unwrap_or(0)))
+            }
+            None => {
+                pc += 1;
+                Some((start, None, word))
+            }
+        }
+    })
+}
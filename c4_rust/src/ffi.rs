@@ -0,0 +1,156 @@
+//! C interface for embedding the compiler and VM
+//! include/c4_rust.h is generated from this file with cbindgen
+
+use crate::host::CaptureHost;
+use crate::options::CompileOptions;
+use crate::vm::{self, HostFns, VM};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+use std::sync::{Mutex, PoisonError};
+
+/// native function guest code can call, gets the arguments in order
+pub type C4HostFn = extern "C" fn(user: *mut c_void, args: *const i64, argc: usize) -> i64;
+
+/// outcome of c4_compile or c4_run, release it with c4_free_result
+#[repr(C)]
+pub struct C4Result {
+    pub ok: bool,            // compiled, and for c4_run also ran without a runtime error
+    pub exit_code: i64,      // main's return value, 0 unless run
+    pub output: *mut c_char, // everything printf wrote, never null
+    pub error: *mut c_char,  // diagnostic or runtime error, null when ok
+}
+
+/// a registered host function
+#[derive(Clone)]
+struct Registered {
+    name: String,      // name guest code calls it by
+    argc: usize,       // number of int arguments
+    func: C4HostFn,    // the callback
+    user: *mut c_void, // passed back to func untouched
+}
+
+// the embedder is responsible for whatever user points at
+unsafe impl Send for Registered {}
+
+static HOST_FNS: Mutex<Vec<Registered>> = Mutex::new(Vec::new());
+
+/// makes a C function callable from guest code by name in later compiles and runs
+///
+/// returns 0, or -1 if name is null or not UTF-8; registering a name again replaces it
+///
+/// # Safety
+///
+/// name must be null or a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn c4_register_host_fn(name: *const c_char, argc: usize, func: C4HostFn, user: *mut c_void) -> i32 {
+    let Some(name) = c_str(name) else {
+        return -1;
+    };
+    let mut host_fns = HOST_FNS.lock().unwrap_or_else(PoisonError::into_inner);
+    host_fns.retain(|f| f.name != name);
+    host_fns.push(Registered { name: name.to_string(), argc, func, user });
+    0
+}
+
+/// compiles source without running it
+///
+/// # Safety
+///
+/// source must be null or a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn c4_compile(source: *const c_char) -> *mut C4Result {
+    let Some(source) = c_str(source) else {
+        return new_result(Err("source is null or not UTF-8".to_string()), String::new());
+    };
+    let host_fns = registered();
+    let (_, compiled) = vm::compile(source, &CompileOptions::new(), &declared(&host_fns));
+    new_result(compiled.map(|_| 0), String::new())
+}
+
+/// compiles and runs source, input is what the program reads from fd 0 and may be null when input_len is 0
+///
+/// # Safety
+///
+/// source must be null or a NUL-terminated string, input must point at input_len bytes
+#[no_mangle]
+pub unsafe extern "C" fn c4_run(source: *const c_char, input: *const u8, input_len: usize) -> *mut C4Result {
+    let Some(source) = c_str(source) else {
+        return new_result(Err("source is null or not UTF-8".to_string()), String::new());
+    };
+    let input = if input.is_null() || input_len == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(input, input_len).to_vec()
+    };
+
+    let options = CompileOptions::new();
+    let host_fns = registered();
+    let (parser, compiled) = vm::compile(source, &options, &declared(&host_fns));
+    let (code, data) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => return new_result(Err(e), String::new()),
+    };
+
+    let host = CaptureHost::new(input);
+    let output = host.output();
+    let mut provided = HostFns::new();
+    for f in host_fns {
+        let (func, user) = (f.func, f.user);
+        provided.insert(f.name, (f.argc, Box::new(move |args: &[i64]| func(user, args.as_ptr(), args.len()))));
+    }
+    let result = VM::load(&parser, code, data, &options, Box::new(host), provided)
+        .and_then(|mut vm| vm.run().map_err(|e| e.to_string()));
+
+    let output = output.borrow().clone();
+    new_result(result, output)
+}
+
+/// releases a result from c4_compile or c4_run
+///
+/// # Safety
+///
+/// result must be null or come from c4_compile or c4_run, and not be freed twice
+#[no_mangle]
+pub unsafe extern "C" fn c4_free_result(result: *mut C4Result) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    drop(CString::from_raw(result.output));
+    if !result.error.is_null() {
+        drop(CString::from_raw(result.error));
+    }
+}
+
+/// snapshot of the registry, so host functions may register more while a run is going
+fn registered() -> Vec<Registered> {
+    HOST_FNS.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// name and argument count of each registered function, so compiles have them in scope
+fn declared(host_fns: &[Registered]) -> Vec<(String, usize)> {
+    host_fns.iter().map(|f| (f.name.clone(), f.argc)).collect()
+}
+
+/// borrows a C string, None if null or not UTF-8
+unsafe fn c_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// copies text into a C string, dropping NULs it can't carry
+fn to_c_string(text: String) -> *mut c_char {
+    let text = text.replace('\0', "");
+    CString::new(text).unwrap_or_default().into_raw()
+}
+
+/// boxes a result for the caller to free
+fn new_result(result: Result<i64, String>, output: String) -> *mut C4Result {
+    let (ok, exit_code, error) = match result {
+        Ok(code) => (true, code, ptr::null_mut()),
+        Err(e) => (false, 0, to_c_string(e)),
+    };
+    Box::into_raw(Box::new(C4Result { ok, exit_code, output: to_c_string(output), error }))
+}
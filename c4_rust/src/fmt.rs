@@ -0,0 +1,380 @@
+//! reprints C source with consistent indentation and spacing, for `c4_rust fmt`
+//! works from the lexer's tokens and their spans, so names, literals and comments come
+//! through as written and only the whitespace between tokens changes
+
+use crate::lexer::{directive_end, Lexer, Token};
+use crate::options::CompileOptions;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// spaces per indentation level
+const INDENT: usize = 4;
+
+/// text the lexer skips that is kept, with the newlines before it
+enum Extra<'a> {
+    Comment(&'a str),   // // or /* */ comment
+    Directive(&'a str), // # line, printed at column 0
+}
+
+/// a token, how it was written and what came before it
+struct Piece<'a> {
+    token: Token,
+    text: &'a str,
+    before: Vec<(usize, Extra<'a>)>, // comments and directives since the last token
+    newlines: usize,                 // line breaks between the last of those and the token
+}
+
+/// how a '(' is used, decided when it opens
+#[derive(Clone, Copy, PartialEq)]
+enum Paren {
+    Header, // condition of if, while, for or switch, a body follows
+    Cast,   // (int), (char *) and the like
+    Plain,  // calls, parameter lists and grouping
+}
+
+/// a '{' still open
+struct Block {
+    inline: bool, // enum body or initializer, kept on one line
+    cases: bool,  // inside a case label's statements, one level deeper
+}
+
+/// the formatted source, or the first place it couldn't be read
+pub fn format_source(source: &str, options: &CompileOptions) -> Result<String, String> {
+    let pieces = pieces(source, options)?;
+    let mut formatter = Formatter::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        formatter.piece(piece, pieces.get(i + 1).map(|p| p.token));
+    }
+    Ok(formatter.finish())
+}
+
+/// splits source into tokens, keeping the comments and directives between them
+fn pieces<'a>(source: &'a str, options: &CompileOptions) -> Result<Vec<Piece<'a>>, String> {
+    let mut lexer = Lexer::with_options(source, &CompileOptions { debug: false, ..options.clone() });
+    lexer.set_expand_macros(false);
+    let mut pieces = Vec::new();
+    let mut end = 0;
+    loop {
+        let token = lexer.next();
+        let (start, stop) = lexer.span();
+        let (before, newlines) = gap(source, end, start)?;
+        pieces.push(Piece { token, text: &source[start..stop], before, newlines });
+        if token == Token::Eof {
+            return Ok(pieces);
+        }
+        end = stop;
+    }
+}
+
+/// what lies between two tokens, failing on anything the lexer would drop
+fn gap(source: &str, from: usize, to: usize) -> Result<(Vec<(usize, Extra<'_>)>, usize), String> {
+    let bytes = source.as_bytes();
+    let mut extras = Vec::new();
+    let mut newlines = 0;
+    let mut i = from;
+    while i < to {
+        let line_end = bytes[i..to].iter().position(|&b| b == b'\n' || b == b'\r').map_or(to, |n| i + n);
+        match bytes[i] {
+            b'\n' => {
+                newlines += 1;
+                i += 1;
+            }
+            // CRLF counts once
+            b'\r' => {
+                newlines += usize::from(bytes.get(i + 1) != Some(&b'\n'));
+                i += 1;
+            }
+            b' ' | b'\t' => i += 1,
+            b'#' => {
+                let end = directive_end(bytes, i).min(to);
+                extras.push((newlines, Extra::Directive(source[i..end].trim_end())));
+                newlines = 0;
+                i = end;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                extras.push((newlines, Extra::Comment(source[i..line_end].trim_end())));
+                newlines = 0;
+                i = line_end;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = block_comment_end(bytes, i + 2).min(to);
+                extras.push((newlines, Extra::Comment(&source[i..end])));
+                newlines = 0;
+                i = end;
+            }
+            _ => {
+                let line = source[..i].matches('\n').count() + 1;
+                let c = source[i..].chars().next().unwrap_or(' ');
+                return Err(format!("Line {}: can't format '{}', the compiler ignores it", line, c));
+            }
+        }
+    }
+    Ok((extras, newlines))
+}
+
+/// index just past the block comment whose body starts at i, nested like the lexer nests them
+fn block_comment_end(bytes: &[u8], mut i: usize) -> usize {
+    let mut depth = 1;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// prints pieces one at a time, tracking the nesting that decides indentation
+struct Formatter {
+    out: String,
+    indent: usize,
+    line_start: bool,     // nothing written on the current line yet
+    break_line: bool,     // the next token starts a new line
+    continued: bool,      // the line being started carries on a statement, one level further in
+    blank_next: bool,     // and has a blank line before it, after a function body
+    prev: Option<Token>,  // last token written
+    prev_operand: bool,   // it ends an operand, so a following '-' or '*' is binary
+    prev_prefix: bool,    // it is a prefix operator or a cast, nothing goes between it and its operand
+    parens: Vec<Paren>,
+    blocks: Vec<Block>,
+    singles: Vec<usize>,  // block depth of each body without braces, indented one level
+    header: bool,         // saw if, while, for or switch, its '(' is a header
+    label: bool,          // between case and its ':'
+    inline_next: bool,    // the next '{' opens an enum body or initializer
+}
+
+impl Formatter {
+    fn new() -> Formatter {
+        Formatter {
+            out: String::new(),
+            indent: 0,
+            line_start: true,
+            break_line: false,
+            continued: false,
+            blank_next: false,
+            prev: None,
+            prev_operand: false,
+            prev_prefix: false,
+            parens: Vec::new(),
+            blocks: Vec::new(),
+            singles: Vec::new(),
+            header: false,
+            label: false,
+            inline_next: false,
+        }
+    }
+
+    /// ends the line, with one blank line after it when asked
+    fn line_break(&mut self, blank: bool) {
+        if !self.line_start {
+            self.out.push('\n');
+            self.line_start = true;
+        }
+        if (blank || self.blank_next) && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+        self.break_line = false;
+        self.blank_next = false;
+    }
+
+    /// writes text, indented at the start of a line and otherwise after a space if asked
+    fn write(&mut self, text: &str, space: bool) {
+        if self.line_start {
+            let indent = self.indent + usize::from(self.continued);
+            self.out.push_str(&" ".repeat(indent * INDENT));
+            self.line_start = false;
+        } else if space {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+    }
+
+    /// whether a space separates the previous token from this one
+    fn space_before(&self, token: Token) -> bool {
+        let Some(prev) = self.prev else {
+            return false;
+        };
+        if self.prev_prefix || matches!(prev, Token::LeftParen | Token::LeftBracket | Token::Dot | Token::Arrow) {
+            return false;
+        }
+        match token {
+            Token::RightParen | Token::RightBracket | Token::Semicolon | Token::Comma | Token::LeftBracket
+            | Token::Dot | Token::Arrow => false,
+            Token::LeftParen => !matches!(prev, Token::Id(_) | Token::Sizeof | Token::RightParen | Token::RightBracket),
+            Token::Inc | Token::Dec => !self.prev_operand,
+            Token::Colon => !self.label,
+            _ => true,
+        }
+    }
+
+    /// a statement ended, so do the bodies without braces waiting for it
+    fn end_statement(&mut self) {
+        while self.singles.last() == Some(&self.blocks.len()) {
+            self.singles.pop();
+            self.indent -= 1;
+        }
+        self.inline_next = false;
+        self.break_line = true;
+    }
+
+    /// the statement after a header or else goes on its own line, one level in, unless it is a block
+    fn body(&mut self, next: Option<Token>) {
+        if next != Some(Token::LeftBrace) {
+            self.singles.push(self.blocks.len());
+            self.indent += 1;
+            self.break_line = true;
+        }
+    }
+
+    fn piece(&mut self, piece: &Piece, next: Option<Token>) {
+        // line breaks the author put inside a statement are kept, as continuation lines
+        let mid = self.prev.is_some() && !self.break_line && !self.line_start;
+        for (newlines, extra) in &piece.before {
+            match extra {
+                // a comment on the same line as code stays after it
+                Extra::Comment(text) if *newlines == 0 && !self.line_start => {
+                    self.write(text, true);
+                    self.break_line |= text.starts_with("//");
+                }
+                Extra::Comment(text) => {
+                    self.line_break(*newlines > 1);
+                    self.continued = mid;
+                    self.write(text, false);
+                    self.break_line = true;
+                }
+                Extra::Directive(text) => {
+                    self.line_break(*newlines > 1);
+                    self.out.push_str(text);
+                    self.line_start = false;
+                    self.break_line = true;
+                }
+            }
+        }
+
+        let token = piece.token;
+        if token == Token::Eof {
+            return;
+        }
+
+        // a label after a case's statements goes back out to the switch's level
+        if matches!(token, Token::Case | Token::Default) && !self.label {
+            if let Some(block) = self.blocks.last_mut().filter(|b| b.cases) {
+                block.cases = false;
+                self.indent -= 1;
+            }
+            self.label = true;
+        }
+        let closes_block = token == Token::RightBrace && self.blocks.last().is_some_and(|b| !b.inline);
+        if closes_block {
+            let block = self.blocks.pop();
+            self.indent -= 1 + usize::from(block.is_some_and(|b| b.cases));
+            self.break_line = true;
+        }
+        let wrapped = mid
+            && (self.break_line || piece.newlines > 0 && !matches!(token, Token::LeftBrace | Token::Else | Token::Semicolon));
+        if self.break_line || wrapped {
+            self.line_break(piece.newlines > 1);
+        }
+        // the '}' of an enum body or initializer lines up with where it opened
+        self.continued = wrapped && (token != Token::RightBrace || closes_block);
+
+        let space = self.space_before(token);
+        let operand_before = self.prev_operand;
+        self.write(piece.text, space);
+        let mut cast_closed = false;
+        let mut header_closed = false;
+
+        match token {
+            Token::LeftParen => {
+                let cast = matches!(next, Some(Token::Int | Token::Char | Token::Void | Token::Struct))
+                    && !matches!(self.prev, Some(Token::Id(_) | Token::Sizeof));
+                let kind = if self.header { Paren::Header } else if cast { Paren::Cast } else { Paren::Plain };
+                self.parens.push(kind);
+                self.header = false;
+            }
+            Token::RightParen => match self.parens.pop() {
+                Some(Paren::Header) => {
+                    header_closed = true;
+                    self.body(next);
+                }
+                Some(Paren::Cast) => cast_closed = true,
+                // K&R parameter declarations go on their own lines, as does the '{' after them
+                _ if self.parens.is_empty() && self.blocks.is_empty() && matches!(next, Some(Token::Int | Token::Char)) => {
+                    self.break_line = true;
+                }
+                _ => {}
+            },
+            Token::LeftBrace => {
+                let inline = self.inline_next || self.blocks.last().is_some_and(|b| b.inline);
+                self.blocks.push(Block { inline, cases: false });
+                self.inline_next = false;
+                if !inline {
+                    self.indent += 1;
+                    self.break_line = true;
+                }
+            }
+            Token::RightBrace => {
+                if !closes_block {
+                    self.blocks.pop();
+                } else {
+                    self.end_statement();
+                    if matches!(next, Some(Token::Else | Token::Semicolon)) {
+                        self.break_line = false;
+                    } else if self.blocks.is_empty() && next != Some(Token::Eof) {
+                        // functions are set apart by a blank line
+                        self.blank_next = true;
+                    }
+                }
+            }
+            Token::Semicolon if self.parens.is_empty() && !self.blocks.last().is_some_and(|b| b.inline) => {
+                self.end_statement();
+            }
+            Token::Colon if self.label => {
+                self.label = false;
+                if let Some(block) = self.blocks.last_mut() {
+                    block.cases = true;
+                }
+                self.indent += 1;
+                self.break_line = true;
+            }
+            Token::If | Token::While | Token::For | Token::Switch => self.header = true,
+            Token::Else if next != Some(Token::If) => self.body(next),
+            Token::Enum => self.inline_next = true,
+            Token::Assign if next == Some(Token::LeftBrace) => self.inline_next = true,
+            _ => {}
+        }
+
+        let prefix_capable = matches!(token, Token::Sub | Token::Add | Token::Mul | Token::And | Token::Inc | Token::Dec);
+        self.prev_prefix = cast_closed || matches!(token, Token::Not | Token::Tilde) || (prefix_capable && !operand_before);
+        self.prev_operand = match token {
+            // a struct's tag names a type, so a '*' after it declares a pointer
+            Token::Id(_) => self.prev != Some(Token::Struct),
+            Token::Num(_) | Token::Str(_) | Token::RightBracket => true,
+            Token::RightParen => !cast_closed && !header_closed,
+            Token::Inc | Token::Dec => operand_before,
+            _ => false,
+        };
+        self.prev = Some(token);
+    }
+
+    /// the text with exactly one newline at the end, or nothing for an empty source
+    fn finish(mut self) -> String {
+        let len = self.out.trim_end().len();
+        self.out.truncate(len);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
@@ -0,0 +1,295 @@
+//! OS services behind the VM's syscalls
+//! swap in another host to run without std
+
+use alloc::string::String;
+
+/// what the VM needs from its environment
+pub trait Host {
+    /// writes program output
+    fn write(&mut self, text: &str) -> Result<(), String>;
+    /// opens a file read-only, returns an fd or -1
+    fn open(&mut self, path: &str) -> i64;
+    /// reads into buf, returns the byte count or -1
+    fn read(&mut self, fd: i64, buf: &mut [u8]) -> i64;
+    /// closes an fd, returns 0 or -1
+    fn close(&mut self, fd: i64) -> i64;
+    /// restarts the clock at the beginning of a run
+    fn start_clock(&mut self) {}
+    /// microseconds since start_clock
+    fn elapsed_us(&self) -> u64;
+    /// blocks for the given microseconds
+    fn sleep_us(&mut self, micros: u64);
+    /// the host's value of an environment variable, when the guest may see it
+    fn getenv(&self, _name: &str) -> Option<String> {
+        None
+    }
+    /// runs a shell command, returns its exit status or -1
+    fn system(&mut self, _command: &str) -> i64 {
+        -1
+    }
+    /// runs a shell command, returns an fd that reads its output or -1
+    fn popen(&mut self, _command: &str) -> i64 {
+        -1
+    }
+}
+
+/// host with no OS, output is dropped and files never open
+#[derive(Debug, Default)]
+pub struct NullHost;
+
+impl Host for NullHost {
+    fn write(&mut self, _text: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn open(&mut self, _path: &str) -> i64 {
+        -1
+    }
+
+    fn read(&mut self, _fd: i64, _buf: &mut [u8]) -> i64 {
+        -1
+    }
+
+    fn close(&mut self, _fd: i64) -> i64 {
+        -1
+    }
+
+    fn elapsed_us(&self) -> u64 {
+        0
+    }
+
+    fn sleep_us(&mut self, _micros: u64) {}
+}
+
+#[cfg(feature = "std")]
+pub use self::std_host::{CaptureHost, StdHost};
+
+/// host the VM gets unless told otherwise
+#[cfg(feature = "std")]
+pub type DefaultHost = StdHost;
+#[cfg(not(feature = "std"))]
+pub type DefaultHost = NullHost;
+
+#[cfg(feature = "std")]
+mod std_host {
+    use super::Host;
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io::{Cursor, Read, Write};
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    // fd handed out by the first open(), 0-2 are the standard streams
+    const FIRST_FD: i64 = 3;
+
+    /// something read() can read from besides stdin
+    #[derive(Debug)]
+    enum Stream {
+        File(File),               // from open()
+        Piped(Cursor<Vec<u8>>),   // output of a popen() command
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self {
+                Stream::File(file) => file.read(buf),
+                Stream::Piped(output) => output.read(buf),
+            }
+        }
+    }
+
+    /// host backed by stdout, the filesystem and the system clock
+    #[derive(Debug)]
+    pub struct StdHost {
+        files: Vec<Option<Stream>>, // open() and popen() handles, fd 3 is files[0]
+        started: Instant,           // real time origin for clock()
+    }
+
+    impl Default for StdHost {
+        fn default() -> Self {
+            StdHost { files: Vec::new(), started: Instant::now() }
+        }
+    }
+
+    impl StdHost {
+        /// looks up an open file by fd
+        fn file_mut(&mut self, fd: i64) -> Option<&mut Stream> {
+            let slot = usize::try_from(fd.checked_sub(FIRST_FD)?).ok()?;
+            self.files.get_mut(slot)?.as_mut()
+        }
+
+        /// gives the stream the lowest closed fd
+        fn add_stream(&mut self, stream: Stream) -> i64 {
+            let slot = match self.files.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => {
+                    self.files.push(None);
+                    self.files.len() - 1
+                }
+            };
+            self.files[slot] = Some(stream);
+            slot as i64 + FIRST_FD
+        }
+    }
+
+    impl Host for StdHost {
+        fn write(&mut self, text: &str) -> Result<(), String> {
+            let mut out = std::io::stdout();
+            out.write_all(text.as_bytes()).and_then(|_| out.flush()).map_err(|e| e.to_string())
+        }
+
+        fn open(&mut self, path: &str) -> i64 {
+            match File::open(guest_path(path)) {
+                Ok(file) => self.add_stream(Stream::File(file)),
+                Err(_) => -1,
+            }
+        }
+
+        fn read(&mut self, fd: i64, buf: &mut [u8]) -> i64 {
+            let read = if fd == 0 {
+                std::io::stdin().read(buf)
+            } else {
+                match self.file_mut(fd) {
+                    Some(file) => file.read(buf),
+                    None => return -1,
+                }
+            };
+            read.map_or(-1, |n| n as i64)
+        }
+
+        fn close(&mut self, fd: i64) -> i64 {
+            match self.file_mut(fd) {
+                Some(_) => {
+                    self.files[(fd - FIRST_FD) as usize] = None;
+                    0
+                }
+                None => -1,
+            }
+        }
+
+        fn start_clock(&mut self) {
+            self.started = Instant::now();
+        }
+
+        fn elapsed_us(&self) -> u64 {
+            self.started.elapsed().as_micros() as u64
+        }
+
+        fn sleep_us(&mut self, micros: u64) {
+            std::thread::sleep(Duration::from_micros(micros));
+        }
+
+        fn getenv(&self, name: &str) -> Option<String> {
+            std::env::var(name).ok()
+        }
+
+        fn system(&mut self, command: &str) -> i64 {
+            // the command shares our stdout, so it prints in place
+            shell(command).status().ok().and_then(|status| status.code()).map_or(-1, i64::from)
+        }
+
+        fn popen(&mut self, command: &str) -> i64 {
+            match shell(command).output() {
+                Ok(Output { stdout, .. }) => self.add_stream(Stream::Piped(Cursor::new(stdout))),
+                Err(_) => -1,
+            }
+        }
+    }
+
+    /// host fed from an in-memory stdin that collects output instead of printing it
+    #[derive(Debug, Default)]
+    pub struct CaptureHost {
+        stdin: Vec<u8>,              // bytes served to read(0, ...)
+        stdin_pos: usize,            // how much of stdin was read
+        output: Rc<RefCell<String>>, // everything printf wrote
+        std: StdHost,                // files and clock
+    }
+
+    impl CaptureHost {
+        /// creates host with the given stdin
+        pub fn new(stdin: Vec<u8>) -> Self {
+            CaptureHost { stdin, ..Self::default() }
+        }
+
+        /// handle to the output, still readable once the VM owns the host
+        pub fn output(&self) -> Rc<RefCell<String>> {
+            Rc::clone(&self.output)
+        }
+    }
+
+    impl Host for CaptureHost {
+        fn write(&mut self, text: &str) -> Result<(), String> {
+            self.output.borrow_mut().push_str(text);
+            Ok(())
+        }
+
+        fn open(&mut self, path: &str) -> i64 {
+            self.std.open(path)
+        }
+
+        fn read(&mut self, fd: i64, buf: &mut [u8]) -> i64 {
+            if fd != 0 {
+                return self.std.read(fd, buf);
+            }
+            let rest = &self.stdin[self.stdin_pos..];
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.stdin_pos += n;
+            n as i64
+        }
+
+        fn close(&mut self, fd: i64) -> i64 {
+            self.std.close(fd)
+        }
+
+        fn start_clock(&mut self) {
+            self.std.start_clock();
+        }
+
+        fn elapsed_us(&self) -> u64 {
+            self.std.elapsed_us()
+        }
+
+        fn sleep_us(&mut self, micros: u64) {
+            self.std.sleep_us(micros);
+        }
+
+        fn getenv(&self, name: &str) -> Option<String> {
+            self.std.getenv(name)
+        }
+
+        fn system(&mut self, command: &str) -> i64 {
+            // what the command prints is captured like the program's own output
+            match shell(command).output() {
+                Ok(output) => {
+                    self.output.borrow_mut().push_str(&String::from_utf8_lossy(&output.stdout));
+                    output.status.code().map_or(-1, i64::from)
+                }
+                Err(_) => -1,
+            }
+        }
+
+        fn popen(&mut self, command: &str) -> i64 {
+            self.std.popen(command)
+        }
+    }
+
+    /// the platform shell running a command line
+    fn shell(command: &str) -> Command {
+        let (program, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let mut shell = Command::new(program);
+        shell.arg(flag).arg(command);
+        shell
+    }
+
+    /// turns a guest path into a host path, accepting either separator
+    fn guest_path(path: &str) -> PathBuf {
+        if std::path::MAIN_SEPARATOR == '\\' {
+            PathBuf::from(path)
+        } else {
+            PathBuf::from(path.replace('\\', "/"))
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! compiled programs on disk, the .c4b format
+//! a magic, the format version and opcode set hash, then code, data and the
+//! string table, then a CRC-32 of everything before it, every number little-endian
+
+use crate::parser::{FunctionInfo, OpCode};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// first bytes of every image
+pub const MAGIC: &[u8; 4] = b"C4B\0";
+
+/// layout of the bytes after the magic, bumped when it changes
+pub const VERSION: u64 = 2;
+
+/// what running or inspecting a program needs after compiling
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Image {
+    pub code: Vec<i64>,               // code segment, entry stub first
+    pub data: Vec<u8>,                // data segment as compiled
+    pub strings: Vec<(usize, usize)>, // data offset and source line of each string literal
+}
+
+/// reads the image back a field at a time, also used for saved repl sessions
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    /// bytes read so far
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| format!("image truncated at byte {}", self.pos))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn crc(&mut self) -> Result<u32, String> {
+        let mut crc = [0; 4];
+        crc.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(crc))
+    }
+
+    pub(crate) fn word(&mut self) -> Result<u64, String> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    pub(crate) fn len(&mut self) -> Result<usize, String> {
+        let len = self.word()?;
+        usize::try_from(len).ok().filter(|&n| n <= self.bytes.len())
+            .ok_or_else(|| format!("image length {} is larger than the image", len))
+    }
+}
+
+impl Image {
+    /// serialized form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from(&MAGIC[..]);
+        out.extend(VERSION.to_le_bytes());
+        out.extend(opcode_set_hash().to_le_bytes());
+        out.extend((self.code.len() as u64).to_le_bytes());
+        for &word in &self.code {
+            out.extend(word.to_le_bytes());
+        }
+        out.extend((self.data.len() as u64).to_le_bytes());
+        out.extend(&self.data);
+        out.extend((self.strings.len() as u64).to_le_bytes());
+        for &(offset, line) in &self.strings {
+            out.extend((offset as u64).to_le_bytes());
+            out.extend((line as u64).to_le_bytes());
+        }
+        let crc = crc32(&out);
+        out.extend(crc.to_le_bytes());
+        out
+    }
+
+    /// parses what to_bytes wrote, refusing images from builds with another
+    /// format or opcode numbering and images whose bytes were damaged
+    pub fn from_bytes(bytes: &[u8]) -> Result<Image, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(String::from("not a c4 image"));
+        }
+        let version = reader.word()?;
+        if version != VERSION {
+            return Err(format!("image format version {} is not supported, this build reads version {}; recompile the program",
+                               version, VERSION));
+        }
+        let hash = reader.word()?;
+        if hash != opcode_set_hash() {
+            return Err(format!("image was compiled for a different opcode set ({:016x}, this build has {:016x}); recompile the program",
+                               hash, opcode_set_hash()));
+        }
+        let count = reader.len()?;
+        let code = (0..count).map(|_| reader.word().map(|w| w as i64)).collect::<Result<_, _>>()?;
+        let count = reader.len()?;
+        let data = reader.take(count)?.to_vec();
+        let count = reader.len()?;
+        let strings = (0..count)
+            .map(|_| Ok((reader.word()? as usize, reader.word()? as usize)))
+            .collect::<Result<_, String>>()?;
+        let computed = crc32(&bytes[..reader.pos()]);
+        let stored = reader.crc()?;
+        if reader.pos() != bytes.len() {
+            return Err(format!("{} trailing bytes after the image", bytes.len() - reader.pos()));
+        }
+        if stored != computed {
+            return Err(format!("image checksum {:08x} does not match its contents ({:08x}), the file is damaged", stored, computed));
+        }
+        Ok(Image { code, data, strings })
+    }
+
+    /// size of each function: instructions, code bytes and bytes of the string
+    /// literals it loads, largest code first, with a total row
+    pub fn code_report(&self, functions: &[FunctionInfo]) -> String {
+        let mut rows: Vec<(&str, usize, usize, usize, usize)> = Vec::new();
+        for f in functions {
+            let (mut pc, mut instructions) = (f.start, 0);
+            let mut strings: Vec<usize> = Vec::new();
+            while pc < f.end.min(self.code.len()) {
+                let Some(op) = OpCode::from_i64(self.code[pc]) else { break };
+                if op == OpCode::IMM {
+                    if let Some(&(offset, _)) = self.strings.iter().find(|&&(offset, _)| self.code.get(pc + 1) == Some(&(offset as i64))) {
+                        if !strings.contains(&offset) {
+                            strings.push(offset);
+                        }
+                    }
+                }
+                instructions += 1;
+                pc += 1 + op.arity();
+            }
+            let words = pc - f.start;
+            let data = strings.iter().map(|&offset| string_size(&self.data, offset)).sum();
+            rows.push((&f.name, f.line, instructions, words * 8, data));
+        }
+        rows.sort_by(|a, b| b.3.cmp(&a.3).then(a.1.cmp(&b.1)));
+
+        let width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max("function".len());
+        let mut out = format!("{:<width$}  {:>5}  {:>12}  {:>10}  {:>10}\n",
+                              "function", "line", "instructions", "code bytes", "data bytes", width = width);
+        let mut total = (0, 0, 0);
+        for (name, line, instructions, code, data) in rows {
+            out.push_str(&format!("{:<width$}  {:>5}  {:>12}  {:>10}  {:>10}\n",
+                                  name, line, instructions, code, data, width = width));
+            total = (total.0 + instructions, total.1 + code, total.2 + data);
+        }
+        out.push_str(&format!("{:<width$}  {:>5}  {:>12}  {:>10}  {:>10}\n",
+                              "total", "", total.0, total.1, total.2, width = width));
+        out
+    }
+
+    /// table of string literals with the data offset each one lives at
+    pub fn dump_strings(&self) -> String {
+        let mut out = String::from("offset  line  string\n");
+        for &(offset, line) in &self.strings {
+            let text = self.data.get(offset..).unwrap_or_default();
+            let text = &text[..text.iter().position(|&c| c == 0).unwrap_or(text.len())];
+            out.push_str(&format!("{:>6}  {:>4}  \"{}\"\n", offset, line, c_escape(text)));
+        }
+        out
+    }
+}
+
+/// bytes of the NUL-terminated string at offset, counting the NUL
+fn string_size(data: &[u8], offset: usize) -> usize {
+    let text = data.get(offset..).unwrap_or_default();
+    text.iter().position(|&c| c == 0).map_or(text.len(), |n| n + 1)
+}
+
+/// FNV-1a hash of every opcode's name and operand count in encoding order,
+/// changes whenever an opcode is added, removed or renumbered
+pub fn opcode_set_hash() -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for op in OpCode::ALL {
+        for byte in format!("{}/{};", op.name(), op.arity()).bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// CRC-32 as zip and PNG compute it
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// bytes the way xxd shows them: address, sixteen bytes in pairs, then the printable ones
+pub fn hexdump(bytes: &[u8], start: usize) -> String {
+    let mut out = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let text: String = chunk.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }).collect();
+        out.push_str(&format!("{:08x}: {:<39}  {}\n", start + row * 16, hex, text));
+    }
+    out
+}
+
+/// bytes as they would be written in a C string literal
+pub fn c_escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\r' => out.push_str("\\r"),
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    out
+}
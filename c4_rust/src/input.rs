@@ -0,0 +1,49 @@
+//! where a program's source comes from: a file, stdin or the command line
+//! shared by the CLI and anything else that takes a program argument
+
+use std::fs::File;
+use std::io::{self, Read};
+
+/// a program's source, not read yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(String),   // path on disk
+    Stdin,          // read to the end, named "-" on the command line
+    Inline(String), // text given with -e
+}
+
+impl Input {
+    /// "-" means stdin, anything else is a path
+    pub fn from_arg(arg: &str) -> Input {
+        if arg == "-" {
+            Input::Stdin
+        } else {
+            Input::File(arg.to_string())
+        }
+    }
+
+    /// what messages and the program's argv[0] call it
+    pub fn name(&self) -> &str {
+        match self {
+            Input::File(path) => path,
+            Input::Stdin => "<stdin>",
+            Input::Inline(_) => "<command line>",
+        }
+    }
+
+    /// the source text
+    pub fn read(&self) -> Result<String, String> {
+        let mut source = String::new();
+        match self {
+            Input::File(path) => {
+                let mut file = File::open(path).map_err(|_| format!("could not open({})", path))?;
+                file.read_to_string(&mut source).map_err(|_| "could not read file".to_string())?;
+            }
+            Input::Stdin => {
+                io::stdin().read_to_string(&mut source).map_err(|_| "could not read stdin".to_string())?;
+            }
+            Input::Inline(text) => source.push_str(text),
+        }
+        Ok(source)
+    }
+}
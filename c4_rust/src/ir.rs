@@ -0,0 +1,588 @@
+//! intermediate form the optimizer works on
+//! code is lifted into basic blocks of expression trees over virtual registers,
+//! the passes rewrite those and the result is lowered back to bytecode
+//! -O0 never comes through here, the parser's code runs as emitted
+
+use crate::parser::{FunctionInfo, OpCode, Symbol, SymbolClass};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// virtual register, an index into Program::values
+pub type Reg = usize;
+
+/// what a register holds, each is defined once and read at most once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Const(i64),                                // IMM
+    Local(i64),                                // LEA, address of a frame slot
+    Load { op: OpCode, addr: Reg },            // LI or LC through an address
+    Binary { op: OpCode, lhs: Reg, rhs: Reg }, // OR..MOD
+    Acc,                                       // whatever AX already holds
+    Pop,                                       // popped off the real stack, only ever a left operand
+}
+
+/// straight-line step of a block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stmt {
+    Eval(Reg),                // compute a register into AX
+    Push,                     // PSH
+    Call(usize),              // JSR to the block starting a function
+    Raw(OpCode, Option<i64>), // anything else, kept as is
+}
+
+/// how control leaves a block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Next,                // falls into the following block
+    Jump(OpCode, usize), // JMP, BZ or BNZ to a block
+    Stop(OpCode),        // LEV or EXIT
+}
+
+/// basic block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,     // address in the lifted code
+    pub stmts: Vec<Stmt>, // body, in order
+    pub exit: Exit,       // where control goes after it
+    pub live: bool,       // false once found unreachable
+}
+
+/// a program in IR form
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub values: Vec<Value>,   // every register, operands before their users
+    pub blocks: Vec<Block>,   // in code order
+    pub entries: Vec<usize>,  // blocks that are always kept: the entry stub and each function
+    len: usize,               // length of the lifted code
+}
+
+/// maps addresses in the lifted code to the lowered one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    starts: Vec<(usize, usize)>, // old and new address of each block, then of the end
+}
+
+impl Relocation {
+    /// new address of a block start, function end or the end of the code
+    pub fn address(&self, old: usize) -> usize {
+        let i = self.starts.partition_point(|&(start, _)| start < old);
+        self.starts.get(i).map_or(old, |&(_, new)| new)
+    }
+
+    /// new address of the start of the block an old address was in
+    pub fn block_address(&self, old: usize) -> usize {
+        let i = self.starts.partition_point(|&(start, _)| start <= old);
+        i.checked_sub(1).map_or(old, |i| self.starts[i].1)
+    }
+
+    /// moves each function's bounds and each defined function symbol to the lowered code
+    pub fn relocate(&self, functions: &mut [FunctionInfo], symbols: &mut [Symbol]) {
+        for function in functions {
+            function.start = self.address(function.start);
+            function.end = self.address(function.end);
+        }
+        for symbol in symbols.iter_mut().filter(|s| s.class == SymbolClass::Fun && s.value >= 0) {
+            symbol.value = self.address(symbol.value as usize) as i64;
+        }
+    }
+
+    /// the line table for the lowered code, statements inside a block share its line, the first one's
+    pub fn relocate_lines(&self, lines: &[(usize, usize)]) -> Vec<(usize, usize)> {
+        let mut relocated: Vec<(usize, usize)> = Vec::new();
+        for &(addr, line) in lines {
+            let addr = self.block_address(addr);
+            if relocated.last().is_none_or(|&(last, _)| last != addr) {
+                relocated.push((addr, line));
+            }
+        }
+        relocated
+    }
+}
+
+/// runs the passes opt_level asks for, None when there is nothing to do or the code can't be lifted
+pub fn optimize(code: &[i64], functions: &[FunctionInfo], level: u8) -> Option<(Vec<i64>, Relocation)> {
+    if level == 0 {
+        return None;
+    }
+    let mut program = Program::lift(code, functions)?;
+    program.run_passes(functions, level);
+    Some(program.lower())
+}
+
+/// functions some path through which reaches the LEV closing their body, with constant
+/// conditions folded first so `while (1)` loops don't count, empty when the code can't be lifted
+pub fn missing_returns<'a>(code: &[i64], functions: &'a [FunctionInfo]) -> Vec<&'a FunctionInfo> {
+    let Some(mut program) = Program::lift(code, functions) else { return Vec::new() };
+    program.fold_constants();
+    program.fold_branches();
+    functions.iter().filter(|f| f.end > f.start && program.reaches(f.start, f.end - 1)).collect()
+}
+
+/// value of a binary op on constants, None where the VM would stop with an error
+pub fn fold(op: OpCode, lhs: i64, rhs: i64) -> Option<i64> {
+    match op {
+        OpCode::OR => Some(lhs | rhs),
+        OpCode::XOR => Some(lhs ^ rhs),
+        OpCode::AND => Some(lhs & rhs),
+        OpCode::EQ => Some((lhs == rhs) as i64),
+        OpCode::NE => Some((lhs != rhs) as i64),
+        OpCode::LT => Some((lhs < rhs) as i64),
+        OpCode::GT => Some((lhs > rhs) as i64),
+        OpCode::LE => Some((lhs <= rhs) as i64),
+        OpCode::GE => Some((lhs >= rhs) as i64),
+        OpCode::SHL => (0..64).contains(&rhs).then(|| lhs.checked_shl(rhs as u32)).flatten(),
+        OpCode::SHR => (0..64).contains(&rhs).then(|| lhs.checked_shr(rhs as u32)).flatten(),
+        OpCode::ADD => lhs.checked_add(rhs),
+        OpCode::SUB => lhs.checked_sub(rhs),
+        OpCode::MUL => lhs.checked_mul(rhs),
+        OpCode::DIV if rhs != 0 => lhs.checked_div(rhs),
+        OpCode::MOD if rhs != 0 => lhs.checked_rem(rhs),
+        _ => None,
+    }
+}
+
+fn is_binary(op: OpCode) -> bool {
+    (OpCode::OR as u8..=OpCode::MOD as u8).contains(&(op as u8))
+}
+
+/// builds one block, keeping pushes and AX symbolic until something needs the real machine state
+struct Lifter<'a> {
+    values: &'a mut Vec<Value>,
+    stmts: Vec<Stmt>,
+    pending: Vec<Reg>, // pushes not emitted yet, none of them reads AX or the stack
+    ax: Reg,           // what AX holds
+    pushed: bool,      // AX is also the top of pending
+}
+
+impl Lifter<'_> {
+    fn value(&mut self, value: Value) -> Reg {
+        self.values.push(value);
+        self.values.len() - 1
+    }
+
+    /// true when computing the register depends on AX or the stack as they are now
+    fn opaque(&self, reg: Reg) -> bool {
+        match self.values[reg] {
+            Value::Acc | Value::Pop => true,
+            Value::Load { addr, .. } => self.opaque(addr),
+            Value::Binary { lhs, rhs, .. } => self.opaque(lhs) || self.opaque(rhs),
+            Value::Const(_) | Value::Local(_) => false,
+        }
+    }
+
+    /// reads the stack
+    fn pops(&self, reg: Reg) -> bool {
+        match self.values[reg] {
+            Value::Pop => true,
+            Value::Load { addr, .. } => self.pops(addr),
+            Value::Binary { lhs, rhs, .. } => self.pops(lhs) || self.pops(rhs),
+            _ => false,
+        }
+    }
+
+    /// pushes everything pending for real
+    fn emit_pending(&mut self) {
+        for reg in core::mem::take(&mut self.pending) {
+            self.stmts.push(Stmt::Eval(reg));
+            self.stmts.push(Stmt::Push);
+        }
+        if self.pushed {
+            self.ax = self.value(Value::Acc);
+            self.pushed = false;
+        }
+    }
+
+    /// AX as an operand
+    fn read(&mut self) -> Reg {
+        if self.pushed {
+            self.emit_pending();
+        }
+        self.ax
+    }
+
+    /// overwrites AX, a dropped value still has to pop what it would have
+    fn set(&mut self, value: Value) {
+        if !self.pushed && self.pops(self.ax) {
+            self.stmts.push(Stmt::Eval(self.ax));
+        }
+        self.ax = self.value(value);
+        self.pushed = false;
+    }
+
+    /// brings the machine to the state the original code had at this point
+    fn flush(&mut self) {
+        self.emit_pending();
+        if self.values[self.ax] != Value::Acc {
+            self.stmts.push(Stmt::Eval(self.ax));
+            self.ax = self.value(Value::Acc);
+        }
+    }
+
+    fn push(&mut self) -> Option<()> {
+        let reg = self.read();
+        if !self.opaque(reg) {
+            self.pending.push(reg);
+            self.pushed = true;
+            return Some(());
+        }
+        // only an empty pending stack can sit under a value like this
+        if !self.pending.is_empty() {
+            return None;
+        }
+        if self.values[reg] != Value::Acc {
+            self.stmts.push(Stmt::Eval(reg));
+        }
+        self.stmts.push(Stmt::Push);
+        self.ax = self.value(Value::Acc);
+        Some(())
+    }
+
+    fn binary(&mut self, op: OpCode) -> Option<()> {
+        let rhs = self.read();
+        let lhs = match self.pending.pop() {
+            Some(_) if self.opaque(rhs) => return None,
+            Some(lhs) => lhs,
+            None => self.value(Value::Pop),
+        };
+        self.ax = self.value(Value::Binary { op, lhs, rhs });
+        Some(())
+    }
+}
+
+impl Program {
+    /// splits code into blocks, None when it doesn't decode cleanly
+    pub fn lift(code: &[i64], functions: &[FunctionInfo]) -> Option<Program> {
+        let len = code.len();
+        let mut leader = vec![false; len + 1];
+        let mut boundary = vec![false; len + 1];
+        leader[0] = true;
+        leader[len] = true;
+        boundary[len] = true;
+        for function in functions {
+            *leader.get_mut(function.start)? = true;
+            *leader.get_mut(function.end)? = true;
+        }
+
+        let mut pc = 0;
+        while pc < len {
+            let op = OpCode::from_i64(code[pc])?;
+            boundary[pc] = true;
+            let next = pc + 1 + op.arity();
+            if next > len {
+                return None;
+            }
+            if matches!(op, OpCode::JMP | OpCode::BZ | OpCode::BNZ | OpCode::JSR) {
+                let target = usize::try_from(code[pc + 1]).ok().filter(|&t| t < len)?;
+                leader[target] = true;
+            }
+            if matches!(op, OpCode::JMP | OpCode::BZ | OpCode::BNZ | OpCode::LEV | OpCode::EXIT) {
+                leader[next] = true;
+            }
+            pc = next;
+        }
+        if (0..=len).any(|i| leader[i] && !boundary[i]) {
+            return None;
+        }
+
+        let starts: Vec<usize> = (0..len).filter(|&i| leader[i]).collect();
+        let block_of = |address: i64| starts.binary_search(&(address as usize)).ok();
+
+        let mut values = Vec::new();
+        let mut blocks = Vec::new();
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(len);
+            let ax = values.len();
+            values.push(Value::Acc);
+            let mut lifter = Lifter { values: &mut values, stmts: Vec::new(), pending: Vec::new(), ax, pushed: false };
+            let mut exit = Exit::Next;
+            let mut pc = start;
+            while pc < end {
+                let op = OpCode::from_i64(code[pc])?;
+                let operand = (op.arity() == 1).then(|| code[pc + 1]);
+                pc += 1 + op.arity();
+                match op {
+                    OpCode::IMM => lifter.set(Value::Const(operand?)),
+                    OpCode::LEA => lifter.set(Value::Local(operand?)),
+                    OpCode::LI | OpCode::LC => {
+                        let addr = lifter.read();
+                        lifter.ax = lifter.value(Value::Load { op, addr });
+                    }
+                    OpCode::PSH => lifter.push()?,
+                    op if is_binary(op) => lifter.binary(op)?,
+                    OpCode::JMP | OpCode::BZ | OpCode::BNZ => {
+                        lifter.flush();
+                        exit = Exit::Jump(op, block_of(operand?)?);
+                    }
+                    OpCode::LEV | OpCode::EXIT => {
+                        lifter.flush();
+                        exit = Exit::Stop(op);
+                    }
+                    OpCode::JSR => {
+                        lifter.flush();
+                        lifter.stmts.push(Stmt::Call(block_of(operand?)?));
+                        lifter.ax = lifter.value(Value::Acc);
+                    }
+                    _ => {
+                        lifter.flush();
+                        lifter.stmts.push(Stmt::Raw(op, operand));
+                        lifter.ax = lifter.value(Value::Acc);
+                    }
+                }
+            }
+            if exit == Exit::Next {
+                lifter.flush();
+            }
+            let stmts = lifter.stmts;
+            blocks.push(Block { start, stmts, exit, live: true });
+        }
+
+        let mut entries = vec![0];
+        entries.extend(functions.iter().filter_map(|f| block_of(f.start as i64)));
+        Some(Program { values, blocks, entries, len })
+    }
+
+    /// the passes of an optimization level, 2 adds inlining to what 1 does
+    pub fn run_passes(&mut self, functions: &[FunctionInfo], level: u8) {
+        if level == 0 {
+            return;
+        }
+        if level >= 2 {
+            self.inline_calls(functions);
+        }
+        self.fold_constants();
+        self.fold_branches();
+        self.remove_unreachable();
+    }
+
+    /// replaces ops on constants by their result
+    pub fn fold_constants(&mut self) {
+        // operands always come first, so one pass folds whole trees
+        for reg in 0..self.values.len() {
+            if let Value::Binary { op, lhs, rhs } = self.values[reg] {
+                if let (Value::Const(a), Value::Const(b)) = (self.values[lhs], self.values[rhs]) {
+                    if let Some(result) = fold(op, a, b) {
+                        self.values[reg] = Value::Const(result);
+                    }
+                }
+            }
+        }
+    }
+
+    /// turns branches on a known condition into a jump or a fall through
+    pub fn fold_branches(&mut self) {
+        for block in &mut self.blocks {
+            let Exit::Jump(op @ (OpCode::BZ | OpCode::BNZ), target) = block.exit else { continue };
+            let Some(&Stmt::Eval(reg)) = block.stmts.last() else { continue };
+            if let Value::Const(cond) = self.values[reg] {
+                let taken = (cond == 0) == (op == OpCode::BZ);
+                block.exit = if taken { Exit::Jump(OpCode::JMP, target) } else { Exit::Next };
+            }
+        }
+    }
+
+    /// marks blocks no entry can reach as dead
+    pub fn remove_unreachable(&mut self) {
+        for block in &mut self.blocks {
+            block.live = false;
+        }
+        let mut work = self.entries.clone();
+        while let Some(b) = work.pop() {
+            if self.blocks[b].live {
+                continue;
+            }
+            self.blocks[b].live = true;
+            let block = &self.blocks[b];
+            work.extend(block.stmts.iter().filter_map(|s| match s {
+                Stmt::Call(target) => Some(*target),
+                _ => None,
+            }));
+            match block.exit {
+                Exit::Next => work.push(b + 1),
+                Exit::Jump(OpCode::JMP, target) => work.push(target),
+                Exit::Jump(_, target) => work.extend([target, b + 1]),
+                Exit::Stop(_) => {}
+            }
+            work.retain(|&b| b < self.blocks.len());
+        }
+    }
+
+    /// whether control entering the block at address from can get to the block holding
+    /// address to, calls come back so only jumps and fall throughs are followed
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let block_at = |addr: usize| self.blocks.partition_point(|b| b.start <= addr).checked_sub(1);
+        let (Some(from), Some(to)) = (block_at(from), block_at(to)) else { return false };
+        let mut seen = vec![false; self.blocks.len()];
+        let mut work = vec![from];
+        while let Some(b) = work.pop() {
+            if b == to {
+                return true;
+            }
+            if b >= self.blocks.len() || seen[b] {
+                continue;
+            }
+            seen[b] = true;
+            match self.blocks[b].exit {
+                Exit::Next => work.push(b + 1),
+                Exit::Jump(OpCode::JMP, target) => work.push(target),
+                Exit::Jump(_, target) => work.extend([target, b + 1]),
+                Exit::Stop(_) => {}
+            }
+        }
+        false
+    }
+
+    /// expression a function returns, when its whole body is `return expr;` over its parameters
+    fn inline_body(&self, function: &FunctionInfo) -> Option<Reg> {
+        let b = self.blocks.binary_search_by_key(&function.start, |block| block.start).ok()?;
+        let block = &self.blocks[b];
+        match block.stmts[..] {
+            [Stmt::Raw(OpCode::ENT, Some(0)), Stmt::Eval(body)]
+                if block.exit == Exit::Stop(OpCode::LEV) && self.pure(body, function.params) => Some(body),
+            _ => None,
+        }
+    }
+
+    /// constants and parameters combined by binary ops, nothing else
+    fn pure(&self, reg: Reg, params: usize) -> bool {
+        match self.values[reg] {
+            Value::Const(_) => true,
+            Value::Load { op: OpCode::LI, addr } => {
+                matches!(self.values[addr], Value::Local(offset) if 2 <= offset && offset <= params as i64 + 1)
+            }
+            Value::Binary { lhs, rhs, .. } => self.pure(lhs, params) && self.pure(rhs, params),
+            _ => false,
+        }
+    }
+
+    /// no AX or stack reads, so it may move or be computed twice
+    fn movable(&self, reg: Reg) -> bool {
+        match self.values[reg] {
+            Value::Acc | Value::Pop => false,
+            Value::Load { addr, .. } => self.movable(addr),
+            Value::Binary { lhs, rhs, .. } => self.movable(lhs) && self.movable(rhs),
+            Value::Const(_) | Value::Local(_) => true,
+        }
+    }
+
+    /// deep copy of a tree, parameter loads replaced by the caller's arguments
+    fn copy(&mut self, reg: Reg, args: &[Reg]) -> Reg {
+        let value = match self.values[reg] {
+            Value::Load { op: OpCode::LI, addr } if !args.is_empty() => match self.values[addr] {
+                // parameter i of n lives at bp+1+n-i
+                Value::Local(offset) => return self.copy(args[args.len() + 1 - offset as usize], &[]),
+                _ => Value::Load { op: OpCode::LI, addr: self.copy(addr, args) },
+            },
+            Value::Load { op, addr } => Value::Load { op, addr: self.copy(addr, args) },
+            Value::Binary { op, lhs, rhs } => {
+                let lhs = self.copy(lhs, args);
+                let rhs = self.copy(rhs, args);
+                Value::Binary { op, lhs, rhs }
+            }
+            value => value,
+        };
+        self.values.push(value);
+        self.values.len() - 1
+    }
+
+    /// replaces calls to functions that only return an expression of their parameters by that expression
+    pub fn inline_calls(&mut self, functions: &[FunctionInfo]) {
+        let mut bodies = vec![None; self.blocks.len()];
+        for function in functions.iter().filter(|f| !f.variadic) {
+            if let (Some(body), Ok(b)) = (self.inline_body(function), self.blocks.binary_search_by_key(&function.start, |block| block.start)) {
+                bodies[b] = Some((body, function.params));
+            }
+        }
+
+        // the entry stub keeps its call, tests retarget it
+        for b in 1..self.blocks.len() {
+            let mut i = 0;
+            while i < self.blocks[b].stmts.len() {
+                let stmts = &self.blocks[b].stmts;
+                let Stmt::Call(target) = stmts[i] else { i += 1; continue };
+                let Some((body, params)) = bodies[target] else { i += 1; continue };
+
+                // each argument is pushed right before the call and dropped right after
+                let cleanup = usize::from(params > 0);
+                let args: Option<Vec<Reg>> = (i >= 2 * params).then(|| {
+                    stmts[i - 2 * params..i].chunks(2).map(|pair| match pair {
+                        [Stmt::Eval(arg), Stmt::Push] if self.movable(*arg) => Some(*arg),
+                        _ => None,
+                    }).collect()
+                }).flatten();
+                let adjusted = params == 0 || stmts.get(i + 1) == Some(&Stmt::Raw(OpCode::ADJ, Some(params as i64)));
+                let Some(args) = args.filter(|_| adjusted) else { i += 1; continue };
+
+                let result = self.copy(body, &args);
+                let first = i - 2 * params;
+                self.blocks[b].stmts.splice(first..=i + cleanup, [Stmt::Eval(result)]);
+                i = first + 1;
+            }
+        }
+    }
+
+    fn emit(&self, reg: Reg, out: &mut Vec<i64>) {
+        match self.values[reg] {
+            Value::Const(k) => out.extend([OpCode::IMM as i64, k]),
+            Value::Local(offset) => out.extend([OpCode::LEA as i64, offset]),
+            Value::Load { op, addr } => {
+                self.emit(addr, out);
+                out.push(op as i64);
+            }
+            Value::Binary { op, lhs, rhs } => {
+                if self.values[lhs] != Value::Pop {
+                    self.emit(lhs, out);
+                    out.push(OpCode::PSH as i64);
+                }
+                self.emit(rhs, out);
+                out.push(op as i64);
+            }
+            Value::Acc | Value::Pop => {}
+        }
+    }
+
+    /// bytecode for the live blocks, and where everything moved
+    pub fn lower(&self) -> (Vec<i64>, Relocation) {
+        let mut out = Vec::new();
+        let mut starts = Vec::new();
+        let mut addresses = vec![0; self.blocks.len()];
+        let mut patches = Vec::new();
+        for (b, block) in self.blocks.iter().enumerate() {
+            addresses[b] = out.len();
+            starts.push((block.start, out.len()));
+            if !block.live {
+                continue;
+            }
+            for stmt in &block.stmts {
+                match *stmt {
+                    Stmt::Eval(reg) => self.emit(reg, &mut out),
+                    Stmt::Push => out.push(OpCode::PSH as i64),
+                    Stmt::Call(target) => {
+                        out.push(OpCode::JSR as i64);
+                        patches.push((out.len(), target));
+                        out.push(0);
+                    }
+                    Stmt::Raw(op, operand) => {
+                        out.push(op as i64);
+                        out.extend(operand);
+                    }
+                }
+            }
+            match block.exit {
+                Exit::Next => {}
+                // a jump to the block laid out next is a fall through
+                Exit::Jump(OpCode::JMP, target) if target > b && self.blocks[b + 1..].iter().position(|next| next.live) == Some(target - b - 1) => {}
+                Exit::Jump(op, target) => {
+                    out.push(op as i64);
+                    patches.push((out.len(), target));
+                    out.push(0);
+                }
+                Exit::Stop(op) => out.push(op as i64),
+            }
+        }
+        starts.push((self.len, out.len()));
+        for (pos, target) in patches {
+            out[pos] = addresses[target] as i64;
+        }
+        (out, Relocation { starts })
+    }
+}
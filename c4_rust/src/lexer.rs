@@ -1,5 +1,15 @@
-/// tokenizes C code
-/// makes tokens for parser
+//! tokenizes C code
+//! makes tokens for parser
+
+#![deny(clippy::unwrap_used)]
+
+use crate::ir;
+use crate::options::{CompileOptions, Lang};
+use crate::parser::OpCode;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Token {
@@ -19,6 +29,15 @@ pub enum Token {
     Sizeof,
     While,
     Void,
+    Extern,
+    Struct,
+    Break,
+    Continue,
+    
+    // c4x keywords
+    Switch,
+    Case,
+    Default,
     
     // operators by precedence
     Assign,
@@ -54,6 +73,8 @@ pub enum Token {
     Inc,
     Dec,
     Brak,        // [ for array indexing
+    Dot,         // . of a struct member
+    Arrow,       // -> of a member through a pointer
     
     // special chars
     Semicolon,
@@ -65,6 +86,7 @@ pub enum Token {
     RightBracket, // ]
     Comma,
     Colon,
+    Ellipsis,     // ... ending a variadic parameter list
     Tilde,
     Not,
     
@@ -72,16 +94,107 @@ pub enum Token {
     Eof,
 }
 
+/// spellings of the identifiers, numbered in the order they first appear, Token::Id holds the number
+#[derive(Debug, Clone, Default)]
+struct Names {
+    spellings: Vec<String>,        // each identifier by its number
+    ids: BTreeMap<String, usize>, // the number of each spelling
+}
+
+impl Names {
+    /// the number of a spelling, giving it the next one the first time
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.spellings.len();
+        self.spellings.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+}
+
+/// maps a reserved word to its token
+pub fn keyword(word: &str) -> Option<Token> {
+    match word {
+        "break" => Some(Token::Break),
+        "char" => Some(Token::Char),
+        "continue" => Some(Token::Continue),
+        "else" => Some(Token::Else),
+        "enum" => Some(Token::Enum),
+        "extern" => Some(Token::Extern),
+        "for" => Some(Token::For),
+        "if" => Some(Token::If),
+        "int" => Some(Token::Int),
+        "return" => Some(Token::Return),
+        "sizeof" => Some(Token::Sizeof),
+        "struct" => Some(Token::Struct),
+        "while" => Some(Token::While),
+        "void" => Some(Token::Void),
+        // a macro in C, built in so it works without any #include
+        "NULL" => Some(Token::Num(0)),
+        _ => None,
+    }
+}
+
+/// reserved words added by the c4x dialect
+pub fn c4x_keyword(word: &str) -> Option<Token> {
+    match word {
+        "switch" => Some(Token::Switch),
+        "case" => Some(Token::Case),
+        "default" => Some(Token::Default),
+        _ => None,
+    }
+}
+
+/// how the reserved word a token was lexed from is spelled, None for every other token
+pub fn reserved_word(token: Token) -> Option<&'static str> {
+    const WORDS: [&str; 17] = [
+        "break", "char", "continue", "else", "enum", "extern", "for", "if", "int", "return", "sizeof", "struct",
+        "while", "void", "switch", "case", "default",
+    ];
+    WORDS.into_iter().find(|&word| keyword(word).or_else(|| c4x_keyword(word)) == Some(token))
+}
+
+/// a #define, expanded wherever its name is used
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Option<Vec<usize>>, // parameter identifiers, None unless function-like
+    body: Vec<Token>,           // what a use is replaced with
+}
+
+/// a token waiting to be read, with the macros it came out of, which it can't expand again
+type Pending = (Token, Vec<usize>);
+
+/// an #if, #ifdef or #ifndef whose #endif hasn't been reached
+#[derive(Debug, Clone)]
+struct Conditional {
+    line: usize,     // where it started
+    active: bool,    // the branch being read is compiled
+    done: bool,      // a branch was compiled or the whole group is skipped, later branches aren't
+    seen_else: bool, // #else was reached, no #elif or #else may follow
+}
+
 #[derive(Debug)]
 pub struct Lexer<'a> {
     source: &'a str,
-    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    chars: core::iter::Peekable<core::str::Chars<'a>>,
     pos: usize,
+    start: usize, // where the current token begins
     line: usize,
     current_token: Token,
     current_value: i64,
     string_buffer: Vec<u8>,
     lp: usize, // for source printing
+    names: Names, // every identifier seen, Token::Id numbers index it
+    lang: Lang, // dialect deciding the reserved words
+    macros: BTreeMap<usize, Macro>, // #defines by name identifier
+    pending: Vec<Pending>, // tokens of expanded macros, last one next
+    isolated: bool, // expanding a macro argument on its own, pending is all there is
+    expand_macros: bool, // off to see the tokens as written
+    error: Option<String>, // first bad #define or macro use, reported by the parser
+    conditions: Vec<Conditional>, // open #if groups, innermost last
+    file: String, // what __FILE__ expands to
     debug: bool, // debug flag
 }
 
@@ -92,20 +205,64 @@ impl<'a> Lexer<'a> {
             source,
             chars: source.chars().peekable(),
             pos: 0,
+            start: 0,
             line: 1,
             current_token: Token::Eof,
             current_value: 0,
             string_buffer: Vec::new(),
             lp: 0,
+            names: Names::default(),
+            lang: Lang::C4,
+            macros: BTreeMap::new(),
+            pending: Vec::new(),
+            isolated: false,
+            expand_macros: true,
+            error: None,
+            conditions: Vec::new(),
+            file: String::from("<input>"),
             debug: false, // default to no debug output
         }
     }
     
+    /// creates lexer configured by options
+    pub fn with_options(source: &'a str, options: &CompileOptions) -> Self {
+        let mut lexer = Lexer::new(source);
+        lexer.set_debug(options.debug);
+        lexer.lang = options.lang;
+        lexer.file = options.file.clone();
+        lexer.predefine("__C4RUST__");
+        if options.lang == Lang::C4x {
+            lexer.predefine("__C4X__");
+        }
+        lexer
+    }
+    
+    /// spelling of an identifier token seen so far
+    pub fn id_name(&self, id: usize) -> Option<&str> {
+        self.names.spellings.get(id).map(String::as_str)
+    }
+    
     /// sets debug flag
     pub fn set_debug(&mut self, debug: bool) {
         self.debug = debug;
     }
     
+    /// whether #define takes effect, off leaves macro names as identifiers
+    pub fn set_expand_macros(&mut self, expand: bool) {
+        self.expand_macros = expand;
+    }
+    
+    /// defines a macro as 1, as the compiler does for __C4RUST__
+    pub fn predefine(&mut self, name: &str) {
+        let id = self.names.intern(name);
+        self.macros.insert(id, Macro { params: None, body: Vec::from([Token::Num(1)]) });
+    }
+    
+    /// the first bad #define or macro use, once
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+    
     /// gets current token
     pub fn token(&self) -> Token {
         self.current_token
@@ -116,6 +273,11 @@ impl<'a> Lexer<'a> {
         self.current_value
     }
     
+    /// byte range of the current token in the source
+    pub fn span(&self) -> (usize, usize) {
+        (self.start, self.pos)
+    }
+    
     /// gets line number
     pub fn line(&self) -> usize {
         self.line
@@ -127,55 +289,218 @@ impl<'a> Lexer<'a> {
     }
     
     /// moves to next token
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Token {
+        let token = self.expanded().map_or(Token::Eof, |(token, _)| token);
+        self.current_token = token;
+        match token {
+            Token::Num(value) => self.current_value = value,
+            Token::Str(index) => self.current_value = index as i64,
+            _ => {}
+        }
+        token
+    }
+    
+    /// next token with macros expanded, None at the end of an isolated argument
+    fn expanded(&mut self) -> Option<Pending> {
+        loop {
+            let (token, hidden) = self.fetch()?;
+            if let Token::Id(id) = token {
+                let definition = if hidden.contains(&id) { None } else { self.macros.get(&id).cloned() };
+                if let Some(definition) = definition {
+                    if self.expand(id, &definition, hidden.clone()) {
+                        continue;
+                    }
+                } else if let Some(token) = self.builtin(id) {
+                    return Some((token, hidden));
+                }
+            }
+            return Some((token, hidden));
+        }
+    }
+    
+    /// next token as written, from an expansion or else the source
+    fn fetch(&mut self) -> Option<Pending> {
+        if let Some(pending) = self.pending.pop() {
+            return Some(pending);
+        }
+        if self.isolated {
+            return None;
+        }
+        // comments and unknown chars produce no token, keep scanning
+        loop {
+            if let Some(token) = self.scan() {
+                return Some((token, Vec::new()));
+            }
+        }
+    }
+    
+    /// replaces a use of a macro with its body, false when a function-like one isn't called
+    fn expand(&mut self, id: usize, definition: &Macro, mut hidden: Vec<usize>) -> bool {
+        hidden.push(id);
+        let mut tokens: Vec<Pending> = match &definition.params {
+            None => definition.body.iter().map(|&token| (token, hidden.clone())).collect(),
+            Some(params) => {
+                match self.fetch() {
+                    Some((Token::LeftParen, _)) => {}
+                    other => {
+                        self.pending.extend(other);
+                        return false;
+                    }
+                }
+                let Some(mut args) = self.arguments(id) else {
+                    return true;
+                };
+                if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                    args.clear();
+                }
+                if args.len() != params.len() {
+                    let plural = if params.len() == 1 { "" } else { "s" };
+                    let message = format!("macro '{}' takes {} argument{}, got {}", self.macro_name(id), params.len(), plural, args.len());
+                    self.fail(self.line, message);
+                    return true;
+                }
+                // arguments are expanded on their own first, as C does
+                let args: Vec<Vec<Pending>> = args.into_iter().map(|arg| self.expand_all(arg)).collect();
+                let mut tokens = Vec::new();
+                for &token in &definition.body {
+                    let param = match token {
+                        Token::Id(name) => params.iter().position(|&p| p == name),
+                        _ => None,
+                    };
+                    match param {
+                        Some(k) => tokens.extend(args[k].iter().map(|(token, from)| {
+                            let mut from = from.clone();
+                            from.extend(&hidden);
+                            (*token, from)
+                        })),
+                        None => tokens.push((token, hidden.clone())),
+                    }
+                }
+                tokens
+            }
+        };
+        tokens.reverse();
+        self.pending.extend(tokens);
+        true
+    }
+    
+    /// the arguments of a macro call after its '(', split at commas outside parentheses
+    fn arguments(&mut self, id: usize) -> Option<Vec<Vec<Pending>>> {
+        let line = self.line;
+        let mut args = Vec::from([Vec::new()]);
+        let mut depth = 0;
+        loop {
+            let Some((token, hidden)) = self.fetch().filter(|(token, _)| *token != Token::Eof) else {
+                self.pending.push((Token::Eof, Vec::new()));
+                let message = format!("call to macro '{}' is missing its ')'", self.macro_name(id));
+                self.fail(line, message);
+                return None;
+            };
+            match token {
+                Token::RightParen if depth == 0 => return Some(args),
+                Token::Comma if depth == 0 => {
+                    args.push(Vec::new());
+                    continue;
+                }
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                _ => {}
+            }
+            args.last_mut()?.push((token, hidden));
+        }
+    }
+    
+    /// a macro argument with every macro in it expanded, without reading past it
+    fn expand_all(&mut self, tokens: Vec<Pending>) -> Vec<Pending> {
+        let pending = core::mem::replace(&mut self.pending, tokens.into_iter().rev().collect());
+        let isolated = core::mem::replace(&mut self.isolated, true);
+        let mut expanded = Vec::new();
+        while let Some(token) = self.expanded() {
+            expanded.push(token);
+        }
+        self.pending = pending;
+        self.isolated = isolated;
+        expanded
+    }
+    
+    /// __LINE__ and __FILE__, which expand to where they are used
+    fn builtin(&mut self, id: usize) -> Option<Token> {
+        match self.id_name(id)? {
+            "__LINE__" => Some(Token::Num(self.line as i64)),
+            "__FILE__" => {
+                let start = self.string_buffer.len();
+                self.string_buffer.extend_from_slice(self.file.as_bytes());
+                self.string_buffer.push(0);
+                Some(Token::Str(start))
+            }
+            _ => None,
+        }
+    }
+    
+    /// whether defined(name) holds
+    fn is_defined(&self, id: usize) -> bool {
+        self.macros.contains_key(&id) || matches!(self.id_name(id), Some("__LINE__" | "__FILE__"))
+    }
+    
+    /// whether an #if group is skipping the text being read
+    fn skipping(&self) -> bool {
+        self.conditions.last().is_some_and(|c| !c.active)
+    }
+    
+    /// spelling of a macro's name for messages
+    fn macro_name(&self, id: usize) -> String {
+        self.id_name(id).unwrap_or("?").to_string()
+    }
+    
+    /// keeps the first error
+    fn fail(&mut self, line: usize, message: String) {
+        if self.error.is_none() {
+            self.error = Some(format!("Line {}: {}", line, message));
+        }
+    }
+    
+    /// scans one token, None when only skipped input
+    fn scan(&mut self) -> Option<Token> {
         // skip spaces and comments
         self.skip_whitespace();
+        self.start = self.pos;
         
         // check for EOF
-        if let None = self.chars.peek() {
+        if self.chars.peek().is_none() {
             self.current_token = Token::Eof;
-            return self.current_token;
+            return Some(self.current_token);
         }
         
         // process next char
         match self.chars.next() {
             Some(c) => {
-                self.pos += 1;
+                self.pos += c.len_utf8();
                 match c {
                     // identifiers and keywords
                     'a'..='z' | 'A'..='Z' | '_' => {
-                        let mut hash = c as u64;
                         let start_pos = self.pos - 1;
                         
                         // read whole identifier
                         while let Some(&next_c) = self.chars.peek() {
-                            if next_c.is_alphanumeric() || next_c == '_' {
-                                hash = hash.wrapping_mul(147).wrapping_add(next_c as u64);
+                            if next_c.is_ascii_alphanumeric() || next_c == '_' {
                                 self.chars.next();
                                 self.pos += 1;
                             } else {
                                 break;
                             }
                         }
-                        
-                        let id_length = self.pos - start_pos;
-                        hash = (hash << 6) + id_length as u64;
+                        let id_str = &self.source[start_pos..self.pos];
                         
                         // check if keyword
-                        let id_str = &self.source[start_pos..self.pos];
-                        self.current_token = match id_str {
-                            "char" => Token::Char,
-                            "else" => Token::Else,
-                            "enum" => Token::Enum,
-                            "for" => Token::For,
-                            "if" => Token::If,
-                            "int" => Token::Int,
-                            "return" => Token::Return,
-                            "sizeof" => Token::Sizeof,
-                            "while" => Token::While,
-                            "void" => Token::Void,
-                            // otherwise identifier
-                            _ => Token::Id(hash as usize),
+                        let reserved = match self.lang {
+                            Lang::C4 => keyword(id_str),
+                            Lang::C4x => keyword(id_str).or_else(|| c4x_keyword(id_str)),
+                        };
+                        self.current_token = match reserved {
+                            Some(token) => token,
+                            // otherwise identifier, numbered by its spelling
+                            None => Token::Id(self.names.intern(id_str)),
                         };
                     },
                     
@@ -189,14 +514,14 @@ impl<'a> Lexer<'a> {
                             self.pos += 1;
                             
                             while let Some(&next_c) = self.chars.peek() {
-                                if next_c.is_digit(16) {
-                                    let digit_val = if next_c.is_digit(10) {
+                                if next_c.is_ascii_hexdigit() {
+                                    let digit_val = if next_c.is_ascii_digit() {
                                         next_c as i64 - '0' as i64
                                     } else {
                                         (next_c.to_ascii_uppercase() as i64 - 'A' as i64) + 10
                                     };
                                     
-                                    value = value * 16 + digit_val;
+                                    value = value.wrapping_mul(16).wrapping_add(digit_val);
                                     self.chars.next();
                                     self.pos += 1;
                                 } else {
@@ -207,8 +532,8 @@ impl<'a> Lexer<'a> {
                         // octal number
                         else if value == 0 {
                             while let Some(&next_c) = self.chars.peek() {
-                                if next_c >= '0' && next_c <= '7' {
-                                    value = value * 8 + (next_c as i64 - '0' as i64);
+                                if ('0'..='7').contains(&next_c) {
+                                    value = value.wrapping_mul(8).wrapping_add(next_c as i64 - '0' as i64);
                                     self.chars.next();
                                     self.pos += 1;
                                 } else {
@@ -219,8 +544,8 @@ impl<'a> Lexer<'a> {
                         // decimal number
                         else {
                             while let Some(&next_c) = self.chars.peek() {
-                                if next_c.is_digit(10) {
-                                    value = value * 10 + (next_c as i64 - '0' as i64);
+                                if next_c.is_ascii_digit() {
+                                    value = value.wrapping_mul(10).wrapping_add(next_c as i64 - '0' as i64);
                                     self.chars.next();
                                     self.pos += 1;
                                 } else {
@@ -240,7 +565,7 @@ impl<'a> Lexer<'a> {
                         
                         while let Some(&next_c) = self.chars.peek() {
                             self.chars.next(); // consume char
-                            self.pos += 1;
+                            self.pos += next_c.len_utf8();
                             
                             if next_c == string_delim {
                                 break; // end of literal
@@ -249,7 +574,7 @@ impl<'a> Lexer<'a> {
                             // handle escapes
                             if next_c == '\\' {
                                 if let Some(escaped_char) = self.chars.next() {
-                                    self.pos += 1;
+                                    self.pos += escaped_char.len_utf8();
                                     match escaped_char {
                                         'n' => self.string_buffer.push(b'\n'),
                                         't' => self.string_buffer.push(b'\t'),
@@ -267,7 +592,8 @@ impl<'a> Lexer<'a> {
                                     break;
                                 }
                             } else {
-                                self.string_buffer.push(next_c as u8);
+                                let mut utf8 = [0u8; 4];
+                                self.string_buffer.extend_from_slice(next_c.encode_utf8(&mut utf8).as_bytes());
                             }
                         }
                         
@@ -356,6 +682,10 @@ impl<'a> Lexer<'a> {
                                 self.chars.next();
                                 self.pos += 1;
                                 self.current_token = Token::Dec;
+                            } else if next_c == '>' {
+                                self.chars.next();
+                                self.pos += 1;
+                                self.current_token = Token::Arrow;
                             } else {
                                 self.current_token = Token::Sub;
                             }
@@ -380,61 +710,10 @@ impl<'a> Lexer<'a> {
                     },
                     
                     // divide and /=
+                    // comments were skipped with the whitespace
                     '/' => {
                         if let Some(&next_c) = self.chars.peek() {
-                            if next_c == '/' {
-                                // Line comment
-                                self.chars.next();
-                                self.pos += 1;
-                                
-                                // Consume characters until end of line
-                                while let Some(&next_c) = self.chars.peek() {
-                                    if next_c == '\n' {
-                                        break;
-                                    }
-                                    self.chars.next();
-                                    self.pos += 1;
-                                }
-                                
-                                // Skip to next token
-                                return self.next();
-                            } else if next_c == '*' {
-                                // Block comment
-                                self.chars.next();
-                                self.pos += 1;
-                                
-                                // Variables to track comment nesting
-                                let mut depth = 1;
-                                
-                                // Handle nested comments
-                                while depth > 0 {
-                                    if let Some(c) = self.chars.next() {
-                                        self.pos += 1;
-                                        
-                                        if c == '\n' {
-                                            self.line += 1;
-                                            self.lp = self.pos;
-                                        } else if c == '/' && self.chars.peek() == Some(&'*') {
-                                            // Found nested comment start
-                                            self.chars.next();
-                                            self.pos += 1;
-                                            depth += 1;
-                                        } else if c == '*' && self.chars.peek() == Some(&'/') {
-                                            // Found comment end
-                                            self.chars.next();
-                                            self.pos += 1;
-                                            depth -= 1;
-                                        }
-                                    } else {
-                                        // Reached EOF inside comment
-                                        self.current_token = Token::Eof;
-                                        return self.current_token;
-                                    }
-                                }
-                                
-                                // Skip to next token
-                                return self.next();
-                            } else if next_c == '=' {
+                            if next_c == '=' {
                                 self.chars.next();
                                 self.pos += 1;
                                 self.current_token = Token::DivAssign;
@@ -597,49 +876,310 @@ impl<'a> Lexer<'a> {
                     },
                     ',' => self.current_token = Token::Comma,
                     ':' => self.current_token = Token::Colon,
+                    '.' => {
+                        // a lone '.' picks a struct member, "..." ends a variadic parameter list
+                        let mut ahead = self.chars.clone();
+                        if ahead.next() != Some('.') || ahead.next() != Some('.') {
+                            self.current_token = Token::Dot;
+                            return Some(self.current_token);
+                        }
+                        self.chars.next();
+                        self.chars.next();
+                        self.pos += 2;
+                        self.current_token = Token::Ellipsis;
+                    },
                     
                     // unknown char
                     _ => {
                         // skip it
-                        self.current_token = self.next();
+                        return None;
                     }
                 }
                 
-                self.current_token
+                Some(self.current_token)
             },
-            None => Token::Eof
+            None => Some(Token::Eof)
         }
     }
     
-    /// skips spaces and comments
+    /// skips spaces, line breaks, comments and # lines, scanning bytes rather than chars
     fn skip_whitespace(&mut self) {
-        while let Some(&c) = self.chars.peek() {
-            match c {
-                ' ' | '\t' | '\r' => {
-                    self.chars.next();
-                    self.pos += 1;
-                },
-                '\n' => {
-                    self.chars.next();
-                    self.pos += 1;
+        let source = self.source;
+        let bytes = source.as_bytes();
+        let start = self.pos;
+        let mut i = start;
+        
+        // where the line holding i ends, before its '\n' or '\r'
+        let line_end = |i: usize| bytes[i..].iter().position(|&b| b == b'\n' || b == b'\r').map_or(bytes.len(), |n| i + n);
+        
+        while i < bytes.len() {
+            match bytes[i] {
+                b' ' | b'\t' => i += 1,
+                b'\n' => {
+                    i += 1;
                     self.line += 1;
-                    self.lp = self.pos;
+                    self.lp = i;
+                },
+                b'\r' => {
+                    // CRLF counts once on the '\n', a lone CR is a line break
+                    i += 1;
+                    if bytes.get(i) != Some(&b'\n') {
+                        self.line += 1;
+                        self.lp = i;
+                    }
                 },
-                '#' => {
-                    // skip preprocessor stuff
-                    self.chars.next();
-                    self.pos += 1;
-                    while let Some(&c) = self.chars.peek() {
-                        if c == '\n' {
-                            break;
+                // preprocessor lines run to the end of the line, or further after a trailing backslash
+                b'#' => {
+                    let end = directive_end(bytes, i);
+                    if self.expand_macros {
+                        self.directive(i + 1, end);
+                    }
+                    while let Some(n) = bytes[i..end].iter().position(|&b| b == b'\n' || b == b'\r') {
+                        i += n + 1;
+                        if bytes[i - 1] == b'\n' || bytes.get(i) != Some(&b'\n') {
+                            self.line += 1;
+                            self.lp = i;
                         }
-                        self.chars.next();
-                        self.pos += 1;
+                    }
+                    i = end;
+                },
+                // line comments run to the end of the line
+                b'/' if bytes.get(i + 1) == Some(&b'/') => i = line_end(i),
+                b'/' if bytes.get(i + 1) == Some(&b'*') => i = self.skip_block_comment(bytes, i + 2),
+                // the rest of a line inside a group that isn't compiled
+                _ if self.skipping() => i = line_end(i),
+                _ => break, // not space
+            }
+        }
+        if i == bytes.len() {
+            if let Some(open) = self.conditions.last() {
+                let line = open.line;
+                self.fail(line, "#if without #endif".to_string());
+            }
+        }
+        
+        // i stops on ASCII or the end, so it is on a char boundary
+        if i != start {
+            self.pos = i;
+            self.chars = source[i..].chars().peekable();
+        }
+    }
+    
+    /// acts on the directive between from and end, #include and the like are skipped
+    fn directive(&mut self, from: usize, end: usize) {
+        let bytes = self.source.as_bytes();
+        let at = from + bytes[from..end].iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        let after = at + bytes[at..end].iter().take_while(|b| b.is_ascii_alphanumeric() || **b == b'_').count();
+        let word = &self.source[at..after];
+        let line = self.line;
+        let skipping = self.skipping();
+        let result = match word {
+            // a group inside a skipped one is skipped whole, its condition isn't even read
+            "if" | "ifdef" | "ifndef" if skipping => {
+                self.conditions.push(Conditional { line, active: false, done: true, seen_else: false });
+                Ok(())
+            }
+            "if" | "ifdef" | "ifndef" => self.condition(word, after, end).map(|active| {
+                self.conditions.push(Conditional { line, active, done: active, seen_else: false });
+            }),
+            "elif" | "else" => match self.conditions.last() {
+                None => Err(format!("#{} without #if", word)),
+                Some(open) if open.seen_else => Err(format!("#{} after #else", word)),
+                Some(open) if open.done => {
+                    self.set_branch(false, word == "else");
+                    Ok(())
+                }
+                Some(_) if word == "else" => {
+                    self.set_branch(true, true);
+                    Ok(())
+                }
+                Some(_) => self.condition(word, after, end).map(|active| self.set_branch(active, false)),
+            },
+            "endif" => match self.conditions.pop() {
+                Some(_) => Ok(()),
+                None => Err("#endif without #if".to_string()),
+            },
+            _ if skipping => Ok(()),
+            "define" => {
+                let mut lexer = self.directive_lexer(after, end);
+                let result = lexer.define();
+                self.restore(lexer);
+                result.map(|(name, definition)| {
+                    if let Some(definition) = definition {
+                        self.macros.insert(name, definition);
+                    }
+                })
+            }
+            "undef" => match self.directive_tokens(after, end).first() {
+                Some(Token::Id(name)) => {
+                    self.macros.remove(name);
+                    Ok(())
+                }
+                _ => Err("#undef needs a macro name".to_string()),
+            },
+            "error" => Err(format!("#error {}", self.source[after..end].trim())),
+            _ => Ok(()),
+        };
+        if let Err(message) = result {
+            self.fail(line, message);
+        }
+    }
+    
+    /// moves the innermost #if group to its next branch
+    fn set_branch(&mut self, active: bool, is_else: bool) {
+        if let Some(open) = self.conditions.last_mut() {
+            open.active = active;
+            open.done |= active;
+            open.seen_else = is_else;
+        }
+    }
+    
+    /// whether the condition of an #if, #ifdef, #ifndef or #elif holds
+    fn condition(&mut self, word: &str, from: usize, end: usize) -> Result<bool, String> {
+        let tokens = self.directive_tokens(from, end);
+        if word == "ifdef" || word == "ifndef" {
+            return match tokens.first() {
+                Some(&Token::Id(name)) => Ok(self.is_defined(name) == (word == "ifdef")),
+                // reserved words are never macros
+                Some(_) => Ok(word == "ifndef"),
+                None => Err(format!("#{} needs a macro name", word)),
+            };
+        }
+        
+        // defined NAME and defined(NAME) are decided before any macro is expanded
+        let defined = self.names.intern("defined");
+        let mut pending = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let (token, used) = match tokens[i..] {
+                [Token::Id(d), Token::Id(name), ..] if d == defined => (Token::Num(self.is_defined(name) as i64), 2),
+                [Token::Id(d), Token::LeftParen, Token::Id(name), Token::RightParen, ..] if d == defined => {
+                    (Token::Num(self.is_defined(name) as i64), 4)
+                }
+                _ => (tokens[i], 1),
+            };
+            pending.push((token, Vec::new()));
+            i += used;
+        }
+        
+        // identifiers that aren't macros count as 0, as in C
+        let tokens: Vec<Token> = self.expand_all(pending).into_iter()
+            .map(|(token, _)| if let Token::Id(_) = token { Token::Num(0) } else { token })
+            .collect();
+        let mut condition = Condition { tokens: &tokens, pos: 0 };
+        match condition.value() {
+            Some(value) if condition.pos == tokens.len() => Ok(value != 0),
+            _ => Err(format!("#{} needs a constant expression", word)),
+        }
+    }
+    
+    /// a lexer for the text of a directive, sharing the names and string literals until restored
+    fn directive_lexer(&mut self, from: usize, end: usize) -> Lexer<'a> {
+        let source = &self.source[..end];
+        let mut lexer = Lexer::new(source);
+        lexer.chars = source[from..].chars().peekable();
+        lexer.pos = from;
+        lexer.line = self.line;
+        lexer.lang = self.lang;
+        lexer.expand_macros = false;
+        lexer.names = core::mem::take(&mut self.names);
+        lexer.string_buffer = core::mem::take(&mut self.string_buffer);
+        lexer
+    }
+    
+    /// takes back what directive_lexer lent out
+    fn restore(&mut self, lexer: Lexer<'a>) {
+        self.names = lexer.names;
+        self.string_buffer = lexer.string_buffer;
+    }
+    
+    /// the tokens of a directive's text, unexpanded
+    fn directive_tokens(&mut self, from: usize, end: usize) -> Vec<Token> {
+        let mut lexer = self.directive_lexer(from, end);
+        let mut tokens = Vec::new();
+        while lexer.next() != Token::Eof {
+            tokens.push(lexer.token());
+        }
+        self.restore(lexer);
+        tokens
+    }
+    
+    /// reads the rest of a #define, None for a reserved word such as c4.c's `#define int long long`
+    fn define(&mut self) -> Result<(usize, Option<Macro>), String> {
+        let name = match self.next() {
+            Token::Id(name) => name,
+            Token::Eof => return Err("#define needs a macro name".to_string()),
+            _ => return Ok((0, None)),
+        };
+        let bytes = self.source.as_bytes();
+        let params = if bytes.get(self.pos) == Some(&b'(') {
+            self.next();
+            let mut params = Vec::new();
+            let bad = format!("bad parameter list for macro '{}'", self.macro_name(name));
+            if self.peek_token() == Token::RightParen {
+                self.next();
+            } else {
+                loop {
+                    let Token::Id(param) = self.next() else { return Err(bad) };
+                    params.push(param);
+                    match self.next() {
+                        Token::Comma => {}
+                        Token::RightParen => break,
+                        _ => return Err(bad),
+                    }
+                }
+            }
+            Some(params)
+        } else {
+            None
+        };
+        let mut body = Vec::new();
+        loop {
+            // a '#' would be skipped to the end of the line, which isn't what # and ## mean
+            if self.source[self.pos..].trim_start().starts_with('#') {
+                return Err(format!("macro '{}' uses # or ##, which aren't supported", self.macro_name(name)));
+            }
+            match self.next() {
+                Token::Eof => break,
+                token => body.push(token),
+            }
+        }
+        Ok((name, Some(Macro { params, body })))
+    }
+    
+    /// index just past a block comment whose body starts at i, nested /* */ pairs included
+    fn skip_block_comment(&mut self, bytes: &[u8], mut i: usize) -> usize {
+        let mut depth = 1;
+        while depth > 0 {
+            let Some(n) = bytes[i..].iter().position(|&b| matches!(b, b'\n' | b'\r' | b'/' | b'*')) else {
+                return bytes.len(); // EOF inside the comment
+            };
+            i += n;
+            match (bytes[i], bytes.get(i + 1)) {
+                (b'/', Some(b'*')) => {
+                    depth += 1;
+                    i += 2;
+                },
+                (b'*', Some(b'/')) => {
+                    depth -= 1;
+                    i += 2;
+                },
+                (b'\n', _) => {
+                    i += 1;
+                    self.line += 1;
+                    self.lp = i;
+                },
+                (b'\r', next) => {
+                    i += 1;
+                    if next != Some(&b'\n') {
+                        self.line += 1;
+                        self.lp = i;
                     }
                 },
-                _ => return, // not space
+                _ => i += 1,
             }
         }
+        i
     }
     
     /// gets line position
@@ -662,9 +1202,133 @@ impl<'a> Lexer<'a> {
             None
         }
     }
+    
+    /// token after the current one, the lexer is left where it was
+    pub fn peek_token(&mut self) -> Token {
+        let chars = self.chars.clone();
+        let saved = (self.pos, self.start, self.line, self.lp, self.current_token, self.current_value);
+        let pending = self.pending.clone();
+        let conditions = self.conditions.clone();
+        let buffer = core::mem::take(&mut self.string_buffer);
+        let token = self.next();
+        self.chars = chars;
+        (self.pos, self.start, self.line, self.lp, self.current_token, self.current_value) = saved;
+        self.pending = pending;
+        self.conditions = conditions;
+        self.string_buffer = buffer;
+        token
+    }
+}
+
+/// index where the # line starting at i ends, following lines continued with a trailing backslash
+pub(crate) fn directive_end(bytes: &[u8], mut i: usize) -> usize {
+    loop {
+        let end = bytes[i..].iter().position(|&b| b == b'\n' || b == b'\r').map_or(bytes.len(), |n| i + n);
+        if end == bytes.len() || bytes[end - 1] != b'\\' {
+            return end;
+        }
+        i = end + if bytes[end..].starts_with(b"\r\n") { 2 } else { 1 };
+    }
+}
+
+/// an #if condition being evaluated, each operator folded as the constant evaluator folds it
+struct Condition<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+}
+
+impl Condition<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+    
+    /// a whole expression, ?: included
+    fn value(&mut self) -> Option<i64> {
+        let condition = self.binary(1)?;
+        if self.tokens.get(self.pos) != Some(&Token::Cond) {
+            return Some(condition);
+        }
+        self.pos += 1;
+        let then = self.value()?;
+        (self.next()? == Token::Colon).then_some(())?;
+        let otherwise = self.value()?;
+        Some(if condition != 0 { then } else { otherwise })
+    }
+    
+    /// operators binding at least as tightly as min, by C's precedence
+    fn binary(&mut self, min: u8) -> Option<i64> {
+        let mut lhs = self.unary()?;
+        loop {
+            let Some(&op) = self.tokens.get(self.pos) else { return Some(lhs) };
+            let precedence = match op {
+                Token::Lor => 1,
+                Token::Lan => 2,
+                Token::Or => 3,
+                Token::Xor => 4,
+                Token::And => 5,
+                Token::Eq | Token::Ne => 6,
+                Token::Lt | Token::Gt | Token::Le | Token::Ge => 7,
+                Token::Shl | Token::Shr => 8,
+                Token::Add | Token::Sub => 9,
+                Token::Mul | Token::Div | Token::Mod => 10,
+                _ => return Some(lhs),
+            };
+            if precedence < min {
+                return Some(lhs);
+            }
+            self.pos += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = match op {
+                Token::Lor => (lhs != 0 || rhs != 0) as i64,
+                Token::Lan => (lhs != 0 && rhs != 0) as i64,
+                _ => ir::fold(binary_op(op)?, lhs, rhs)?,
+            };
+        }
+    }
+    
+    fn unary(&mut self) -> Option<i64> {
+        match self.next()? {
+            Token::Num(value) => Some(value),
+            Token::Sub => ir::fold(OpCode::SUB, 0, self.unary()?),
+            Token::Add => self.unary(),
+            Token::Not => Some((self.unary()? == 0) as i64),
+            Token::Tilde => Some(!self.unary()?),
+            Token::LeftParen => {
+                let value = self.value()?;
+                (self.next()? == Token::RightParen).then_some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// the opcode a binary operator token compiles to
+fn binary_op(token: Token) -> Option<OpCode> {
+    Some(match token {
+        Token::Or => OpCode::OR,
+        Token::Xor => OpCode::XOR,
+        Token::And => OpCode::AND,
+        Token::Eq => OpCode::EQ,
+        Token::Ne => OpCode::NE,
+        Token::Lt => OpCode::LT,
+        Token::Gt => OpCode::GT,
+        Token::Le => OpCode::LE,
+        Token::Ge => OpCode::GE,
+        Token::Shl => OpCode::SHL,
+        Token::Shr => OpCode::SHR,
+        Token::Add => OpCode::ADD,
+        Token::Sub => OpCode::SUB,
+        Token::Mul => OpCode::MUL,
+        Token::Div => OpCode::DIV,
+        Token::Mod => OpCode::MOD,
+        _ => return None,
+    })
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     
@@ -727,25 +1391,13 @@ mod tests {
         let mut lexer = Lexer::new("a abc x123 _var");
         
         // check if identifiers
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier");
         
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier");
         
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier");
         
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier");
         
         assert_eq!(lexer.next(), Token::Eof);
     }
@@ -770,16 +1422,40 @@ mod tests {
     fn test_comments() {
         let mut lexer = Lexer::new("a // this is a comment\nb");
         
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier 'a'"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier 'a'");
         
-        match lexer.next() {
-            Token::Id(_) => assert!(true),
-            _ => assert!(false, "Expected identifier 'b'"),
-        }
+        assert!(matches!(lexer.next(), Token::Id(_)), "Expected identifier 'b'");
         
         assert_eq!(lexer.next(), Token::Eof);
     }
+    
+    #[test]
+    fn test_peek_token_leaves_the_lexer_alone() {
+        let mut lexer = Lexer::new("( char * ) \"ab\" 42");
+        assert_eq!(lexer.next(), Token::LeftParen);
+        assert_eq!(lexer.peek_token(), Token::Char);
+        assert_eq!(lexer.token(), Token::LeftParen);
+        assert_eq!(lexer.next(), Token::Char);
+        lexer.next();
+        lexer.next();
+        assert!(matches!(lexer.next(), Token::Str(_)));
+        assert_eq!(lexer.peek_token(), Token::Num(42));
+        assert_eq!(lexer.string_buffer(), b"ab\0");
+        assert_eq!(lexer.next(), Token::Num(42));
+    }
+    
+    #[test]
+    fn test_spans_cover_the_token_text() {
+        let source = "int /* c */ x_1 = 'a' + \"s\"; // end";
+        let mut lexer = Lexer::new(source);
+        let mut texts = Vec::new();
+        while lexer.next() != Token::Eof {
+            let (start, end) = lexer.span();
+            texts.push(&source[start..end]);
+            lexer.peek_token();
+            assert_eq!(lexer.span(), (start, end));
+        }
+        assert_eq!(texts, ["int", "x_1", "=", "'a'", "+", "\"s\"", ";"]);
+        assert_eq!(lexer.span(), (source.len(), source.len()));
+    }
 } 
\ No newline at end of file
@@ -1,6 +1,43 @@
-/// c4 compiler in rust
-/// keeps self-hosting ability intact
+//! c4 compiler in rust
+//! keeps self-hosting ability intact
+//! builds with only alloc when the std feature is off
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// debug tracing goes nowhere without std
+#[cfg(not(feature = "std"))]
+macro_rules! println {
+    () => {};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+pub mod ast;
+pub mod backend;
+pub mod c4test;
+pub mod callgraph;
+pub mod codegen;
+pub mod debugger;
+pub mod diagnostic;
+pub mod disasm;
+pub mod fmt;
+pub mod host;
+pub mod image;
+#[cfg(feature = "std")]
+pub mod input;
+pub mod ir;
 pub mod lexer;
+pub mod observer;
+pub mod opcodes;
+pub mod options;
 pub mod parser;
-pub mod vm; 
\ No newline at end of file
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod vm;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python; 
\ No newline at end of file
@@ -1,27 +1,164 @@
-/// entry point for c4
-/// handles args and setup
-
-pub mod lexer;
-pub mod parser;
-pub mod vm;
+//! entry point for c4
+//! handles args and setup
 
+use c4_rust::diagnostic::{Diagnostic, Severity};
+use c4_rust::input::Input;
+use c4_rust::{backend, c4test, callgraph, debugger, disasm, fmt, host, image, opcodes, options, pipeline, repl, vm};
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::process;
 
+// events --explain describes when no limit is given
+const DEFAULT_EXPLAIN_LIMIT: usize = 100;
+
+// frames shown at each end of a runtime error's backtrace, runaway recursion has thousands
+const BACKTRACE_EDGE: usize = 10;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     
     let mut src = false;
-    let mut debug = false;
+    let mut memory_map: Option<String> = None;
+    let mut call_graph: Option<String> = None;
+    let mut emit_image: Option<String> = None;
+    let mut dump_strings = false;
+    let mut code_report = false;
+    let mut verbose = false;
+    let mut inline: Option<String> = None;
+    let mut json_diagnostics = false;
+    let mut color: Option<bool> = None; // None means only when stderr is a terminal
+    let mut options = options::CompileOptions::new();
     
     // Process flags
     let mut arg_index = 1;
-    while arg_index < args.len() && args[arg_index].starts_with('-') {
+    while arg_index < args.len() && args[arg_index].starts_with('-') && args[arg_index] != "-" {
         match args[arg_index].as_str() {
             "-s" => src = true,
-            "-d" => debug = true,
+            "-e" => {
+                arg_index += 1;
+                match args.get(arg_index) {
+                    Some(text) => inline = Some(text.clone()),
+                    None => {
+                        eprintln!("-e needs the program source");
+                        process::exit(1);
+                    }
+                }
+            }
+            "-d" => options = options.debug(true),
+            "-b" => options = options.bounds_checks(true),
+            "--sanitize" => options = options.sanitize(true),
+            "--verbose" => verbose = true,
+            "--gc" => options = options.gc(true),
+            "--big-endian" => options = options.big_endian(true),
+            "--signed-char" => options = options.signed_char(true),
+            "--unsigned-char" => options = options.signed_char(false),
+            "--trap-overflow" => options = options.trap_overflow(true),
+            "--virtual-time" => options = options.virtual_time(true),
+            "--unchecked-calls" => options = options.unchecked_calls(true),
+            "--implicit-int" => options = options.implicit_int(true),
+            "--pedantic" => options = options.pedantic(true),
+            "--explain" => options = options.explain(DEFAULT_EXPLAIN_LIMIT),
+            arg if arg.starts_with("--explain=") => {
+                match arg["--explain=".len()..].parse() {
+                    Ok(limit) => options = options.explain(limit),
+                    Err(_) => {
+                        eprintln!("invalid explain limit: {}", arg);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("-O") => {
+                match arg["-O".len()..].parse() {
+                    Ok(level) => options = options.opt_level(level),
+                    Err(_) => {
+                        eprintln!("invalid optimization level: {}", arg);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--stack-size=") => {
+                match arg["--stack-size=".len()..].parse() {
+                    Ok(slots) => options = options.stack_size(slots),
+                    Err(_) => {
+                        eprintln!("invalid stack size: {}", arg);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--max-stack-bytes=") => {
+                match arg["--max-stack-bytes=".len()..].parse() {
+                    Ok(bytes) => options = options.max_stack_bytes(bytes),
+                    Err(_) => {
+                        eprintln!("invalid stack limit: {}", arg);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--max-cycles=") => {
+                match arg["--max-cycles=".len()..].parse() {
+                    Ok(cycles) => options = options.max_cycles(cycles),
+                    Err(_) => {
+                        eprintln!("invalid cycle limit: {}", arg);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--crlf" => options = options.crlf(true),
+            "--inherit-env" => options = options.inherit_env(true),
+            "--allow-exec" => options = options.allow_exec(true),
+            "--env" => {
+                arg_index += 1;
+                match args.get(arg_index).and_then(|var| var.split_once('=')) {
+                    Some((name, value)) if !name.is_empty() => options = options.env(name, value),
+                    _ => {
+                        eprintln!("--env needs NAME=value");
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--buffering=") => {
+                match arg["--buffering=".len()..].parse() {
+                    Ok(buffering) => options = options.buffering(buffering),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--lang=") => {
+                match arg["--lang=".len()..].parse() {
+                    Ok(lang) => options = options.lang(lang),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("-W") => {
+                match options.clone().warning_flag(arg) {
+                    Ok(updated) => options = updated,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            arg if arg.starts_with("--memory-map=") => {
+                memory_map = Some(arg["--memory-map=".len()..].to_string());
+            }
+            "--dump-strings" => dump_strings = true,
+            "--code-report" => code_report = true,
+            "--diagnostics=text" => json_diagnostics = false,
+            "--diagnostics=json" => json_diagnostics = true,
+            "--color=always" => color = Some(true),
+            "--color=never" => color = Some(false),
+            "--color=auto" => color = None,
+            arg if arg.starts_with("--emit-image=") => {
+                emit_image = Some(arg["--emit-image=".len()..].to_string());
+            }
+            arg if arg.starts_with("--emit-callgraph=") => {
+                call_graph = Some(arg["--emit-callgraph=".len()..].to_string());
+            }
             _ => {
                 eprintln!("unknown option: {}", args[arg_index]);
                 process::exit(1);
@@ -30,68 +167,345 @@ fn main() {
         arg_index += 1;
     }
     
-    // Check if a source file is provided
-    if arg_index >= args.len() {
-        eprintln!("usage: c4_rust [-s] [-d] file ...");
-        process::exit(1);
+    // repl reads declarations, statements and expressions one entry at a time, no file needed
+    if args.get(arg_index).map(String::as_str) == Some("repl") {
+        run_repl(repl::Repl::new(options));
+    }
+    
+    // opcodes prints the instruction reference, built from the tables the VM decodes with
+    if args.get(arg_index).map(String::as_str) == Some("opcodes") {
+        print!("{}", opcodes::reference());
+        process::exit(0);
+    }
+    
+    // test-file runs the test_* functions instead of main
+    let test_mode = args.get(arg_index).map(String::as_str) == Some("test-file");
+    if test_mode {
+        arg_index += 1;
+    }
+    
+    // strings lists the string literals of a source file or image
+    let strings_mode = !test_mode && args.get(arg_index).map(String::as_str) == Some("strings");
+    if strings_mode {
+        arg_index += 1;
+    }
+    
+    // debug runs the program under the interactive debugger, commands come from stdin
+    let debugger_mode = !test_mode && !strings_mode && args.get(arg_index).map(String::as_str) == Some("debug");
+    if debugger_mode {
+        arg_index += 1;
+    }
+    
+    // inspect describes a source file or image, --hexdump=OFFSET,LEN shows part of its data segment
+    let inspect_mode = !test_mode && !strings_mode && !debugger_mode && args.get(arg_index).map(String::as_str) == Some("inspect");
+    let mut hexdump: Option<(usize, usize)> = None;
+    if inspect_mode {
+        arg_index += 1;
+        while let Some(spec) = args.get(arg_index).and_then(|arg| arg.strip_prefix("--hexdump=")) {
+            match parse_range(spec) {
+                Some(range) => hexdump = Some(range),
+                None => {
+                    eprintln!("invalid hexdump range: {}, expected OFFSET,LEN", spec);
+                    process::exit(1);
+                }
+            }
+            arg_index += 1;
+        }
+    }
+    
+    // fmt prints the source reformatted, --check only says whether that would change it
+    let fmt_mode = !test_mode && !strings_mode && !debugger_mode && !inspect_mode && args.get(arg_index).map(String::as_str) == Some("fmt");
+    let mut fmt_check = false;
+    if fmt_mode {
+        arg_index += 1;
+        if args.get(arg_index).map(String::as_str) == Some("--check") {
+            fmt_check = true;
+            arg_index += 1;
+        }
+    }
+    
+    // emit compiles with the backend named by --backend, bytecode unless told otherwise,
+    // writing its artifact to -o FILE or printing it when it is text
+    let emit_mode = !test_mode && !strings_mode && !debugger_mode && !inspect_mode && !fmt_mode
+        && args.get(arg_index).map(String::as_str) == Some("emit");
+    let mut emitter: Box<dyn backend::Backend> = Box::new(backend::Bytecode::default());
+    let mut emit_output: Option<String> = None;
+    if emit_mode {
+        arg_index += 1;
+        loop {
+            match args.get(arg_index).map(String::as_str) {
+                Some(arg) if arg.starts_with("--backend=") => match backend::by_name(&arg["--backend=".len()..]) {
+                    Ok(chosen) => emitter = chosen,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                },
+                Some("-o") => match args.get(arg_index + 1) {
+                    Some(path) => {
+                        emit_output = Some(path.clone());
+                        arg_index += 1;
+                    }
+                    None => {
+                        eprintln!("-o needs a file name");
+                        process::exit(1);
+                    }
+                },
+                _ => break,
+            }
+            arg_index += 1;
+        }
     }
     
-    // Get filename
-    let filename = &args[arg_index];
+    // Source comes from -e, or a file where "-" is stdin
+    let input = match inline {
+        Some(text) => Input::Inline(text),
+        None if arg_index < args.len() => {
+            arg_index += 1;
+            Input::from_arg(&args[arg_index - 1])
+        }
+        None => {
+            eprintln!("usage: c4_rust [-s] [-d] [-b] [-O0|-O1|-O2] [-Wall|-Werror|-Wname|-Wno-name] [--sanitize] [--gc] [--big-endian] [--signed-char|--unsigned-char] [--trap-overflow] [--virtual-time] [--buffering=none|line|full] [--crlf] [--env NAME=value] [--inherit-env] [--allow-exec] [--unchecked-calls] [--implicit-int] [--pedantic] [--explain[=N]] [--stack-size=N] [--max-stack-bytes=N] [--max-cycles=N] [--verbose] [--lang=c4|c4x] [--memory-map=FILE] [--emit-callgraph=FILE] [--emit-image=FILE] [--dump-strings] [--code-report] [--diagnostics=text|json] [--color=auto|always|never] file|-|-e source ...");
+            eprintln!("       c4_rust [options] test-file file");
+            eprintln!("       c4_rust [options] strings file|image.c4b");
+            eprintln!("       c4_rust [options] inspect [--hexdump=OFFSET,LEN] file|image.c4b");
+            eprintln!("       c4_rust [options] debug file");
+            eprintln!("       c4_rust [options] fmt [--check] file|-");
+            eprintln!("       c4_rust [options] emit [--backend=bytecode|listing] [-o FILE] file|-");
+            eprintln!("       c4_rust [options] repl");
+            eprintln!("       c4_rust opcodes");
+            process::exit(1);
+        }
+    };
+    
+    // Compiler messages go to stderr as text or one JSON object per line
+    let color = color.unwrap_or_else(|| io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none());
+    let file_name = input.name().to_string();
+    options = options.file(&file_name);
+    let report = |severity: Severity, text: &str| {
+        let diagnostic = Diagnostic::new(severity, text);
+        if json_diagnostics {
+            eprintln!("{}", diagnostic.to_json(&file_name));
+        } else {
+            eprintln!("{}", diagnostic.render(&file_name, color));
+        }
+    };
+    
+    // The program sees its source's name as argv[0], then whatever follows it
+    let mut program_args = vec![input.name().to_string()];
+    program_args.extend_from_slice(&args[arg_index..]);
+    
+    // Images are binary, there is no source to compile
+    if let Input::File(filename) = &input {
+        if (strings_mode || inspect_mode) && filename.ends_with(".c4b") {
+            let loaded = std::fs::read(filename).map_err(|e| e.to_string()).and_then(|bytes| image::Image::from_bytes(&bytes));
+            match loaded.map(|image| describe(&image, strings_mode, hexdump)) {
+                Ok(Ok(text)) => {
+                    print!("{}", text);
+                    process::exit(0);
+                }
+                Ok(Err(e)) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("could not load {}: {}", filename, e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
     
-    // Open source file
-    let mut file = match File::open(filename) {
-        Ok(f) => f,
-        Err(_) => {
-            eprintln!("could not open({})", filename);
+    // Read the whole source
+    let source = match input.read() {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}", e);
             process::exit(1);
         }
     };
     
-    // Read source file
-    let mut source = String::new();
-    if let Err(_) = file.read_to_string(&mut source) {
-        eprintln!("could not read file");
-        process::exit(1);
+    if fmt_mode {
+        match fmt::format_source(&source, &options) {
+            Ok(formatted) if fmt_check => {
+                if formatted != source {
+                    eprintln!("{} is not formatted", file_name);
+                    process::exit(1);
+                }
+                process::exit(0);
+            }
+            Ok(formatted) => {
+                print!("{}", formatted);
+                process::exit(0);
+            }
+            Err(e) => {
+                report(Severity::Error, &e);
+                process::exit(1);
+            }
+        }
     }
     
-    // Parse the source
-    let mut parser = match parser::Parser::new(&source, debug) {
-        mut p => {
-            if let Err(e) = p.init() {
-                eprintln!("Parser initialization failed: {}", e);
+    if emit_mode {
+        // parsed once, whichever backend generates from the tree
+        let result = pipeline::parse(&source, &options).and_then(|program| emitter.emit(&program, &options));
+        for warning in emitter.warnings() {
+            report(Severity::Warning, warning);
+        }
+        let artifact = match result {
+            Ok(artifact) => artifact,
+            Err(e) => {
+                report(Severity::Error, &e);
+                process::exit(1);
+            }
+        };
+        match emit_output {
+            Some(path) => {
+                if let Err(e) = std::fs::write(&path, artifact.to_bytes()) {
+                    eprintln!("could not write {} output to {}: {}", emitter.name(), path, e);
+                    process::exit(1);
+                }
+            }
+            None if artifact.is_text() => {
+                if io::stdout().write_all(&artifact.to_bytes()).is_err() {
+                    process::exit(1);
+                }
+            }
+            None => {
+                eprintln!("the {} backend makes a binary file, name it with -o FILE", emitter.name());
                 process::exit(1);
             }
-            p
         }
-    };
+        process::exit(0);
+    }
     
-    // Parse and get code/data
-    let (code, data) = match parser.parse() {
+    if test_mode {
+        match c4test::run_tests(&source, &options) {
+            Ok(results) => {
+                print!("{}", c4test::report(&results));
+                process::exit(if results.iter().all(|t| t.passed()) { 0 } else { 1 });
+            }
+            Err(e) => {
+                report(Severity::Error, &e);
+                process::exit(1);
+            }
+        }
+    }
+    
+    // Parse the source, modes that don't run it report every failed declaration
+    let debug = options.debug;
+    let analysis_only = src || strings_mode || inspect_mode;
+    let (parser, parsed) = vm::compile(&source, &options.clone().keep_going(analysis_only), &[]);
+    
+    // Only what compiled is kept if something failed
+    for warning in parser.warnings() {
+        report(Severity::Warning, warning);
+    }
+    for error in parser.errors() {
+        report(Severity::Error, error);
+    }
+    let failed = parsed.is_err();
+    let (code, data) = match parsed {
         Ok((c, d)) => (c, d),
         Err(e) => {
-            eprintln!("Parsing failed: {}", e);
-            process::exit(1);
+            if parser.errors().is_empty() {
+                report(Severity::Error, &e);
+            }
+            if !analysis_only {
+                process::exit(1);
+            }
+            (parser.code().to_vec(), parser.data().to_vec())
         }
     };
     
-    // Early return if only parsing source
+    let image = image::Image { code: code.clone(), data: data.clone(), strings: parser.strings().to_vec() };
+    if strings_mode || inspect_mode {
+        match describe(&image, strings_mode, hexdump) {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        process::exit(if failed { 1 } else { 0 });
+    }
+    if dump_strings {
+        print!("{}", image.dump_strings());
+    }
+    if code_report {
+        print!("{}", image.code_report(parser.functions()));
+    }
+    if let Some(path) = emit_image.filter(|_| !failed) {
+        if let Err(e) = std::fs::write(&path, image.to_bytes()) {
+            eprintln!("could not write image to {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    
+    // DOT unless the file asks for JSON
+    if let Some(path) = call_graph {
+        let graph = callgraph::CallGraph::build(&code, parser.functions());
+        let text = if path.ends_with(".json") { graph.to_json() } else { graph.to_dot() };
+        if let Err(e) = std::fs::write(&path, text) {
+            eprintln!("could not write call graph to {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    
+    // Early return if only parsing source, after listing what compiled
     if src {
+        print!("{}", disasm::disassemble(&code, parser.functions()));
+        if failed {
+            process::exit(1);
+        }
         println!("Source parsed successfully.");
         process::exit(0);
     }
     
+    // The command line provides no host functions, so an extern declaration can't be bound
+    let host = Box::new(host::DefaultHost::default());
+    let mut vm = match vm::VM::load(&parser, code, data, &options, host, vm::HostFns::new()) {
+        Ok(vm) => vm,
+        Err(e) => {
+            eprintln!("{}: {}", file_name, e);
+            process::exit(1);
+        }
+    };
+    vm.set_args(&program_args);
+    if debugger_mode {
+        run_debugger(debugger::Debugger::new(vm, &parser));
+    }
+    
     // Print a clean starting message if not in debug mode
     if !debug {
         println!("C4_RUST RUNNING...");
         println!("--------");
     }
     
-    // Create VM with debug mode setting
-    let mut vm = vm::VM::new(code, data, debug);
-    
     // Run program once and get result
-    match vm.run() {
+    let result = vm.run();
+    
+    // Write where everything lived, even if the program failed
+    if let Some(path) = memory_map {
+        if let Err(e) = std::fs::write(&path, vm.memory_map(parser.get_symbols())) {
+            eprintln!("could not write memory map to {}: {}", path, e);
+        }
+    }
+    
+    // Counters for the run, the stack depth shows when recursion is the problem
+    if verbose {
+        let stats = vm.stats();
+        eprintln!("stats: {} instructions, stack at most {} bytes deep, {} allocations ({} bytes live)",
+                  stats.cycles, stats.max_stack_bytes, stats.allocations, stats.live_bytes);
+    }
+    
+    if options.gc {
+        let stats = vm.stats();
+        eprintln!("gc: {} collections reclaimed {} blocks ({} bytes), {} of {} allocations ({} bytes) still live",
+                  stats.collections, stats.collected_blocks, stats.collected_bytes,
+                  stats.allocations - stats.collected_blocks, stats.allocations, stats.live_bytes);
+    }
+    
+    match result {
         Ok(value) => {
             if !debug {
                 println!("--------");
@@ -107,15 +521,119 @@ fn main() {
                 println!("END OF OUTPUT, QUITTING...");
             }
             
-            if e.contains("instruction limit") {
-                eprintln!("Program terminated due to possible infinite loop");
-                eprintln!("This is a known issue with array access in our implementation.");
-                eprintln!("The array feature still has bugs in code generation for array indexing.");
-                process::exit(1);
-            } else {
-                eprintln!("Runtime error: {}", e);
-                process::exit(1);
+            match e {
+                vm::RuntimeError::CycleLimit { limit } => {
+                    eprintln!("Program terminated after {} instructions due to possible infinite loop", limit);
+                }
+                vm::RuntimeError::DivByZero { pc } => {
+                    eprintln!("Runtime error: division by zero (pc={})", pc);
+                }
+                e => {
+                    eprintln!("Runtime error: {}", e);
+                }
+            }
+            
+            // Show where it happened, the innermost and outermost frames of a deep stack
+            let frames = vm.frames();
+            for (i, frame) in frames.iter().enumerate() {
+                if i >= BACKTRACE_EDGE && i + BACKTRACE_EDGE < frames.len() {
+                    if i == BACKTRACE_EDGE {
+                        eprintln!("  ... {} frames omitted ...", frames.len() - 2 * BACKTRACE_EDGE);
+                    }
+                    continue;
+                }
+                eprintln!("  #{} {} (pc={}, bp={}) args={:?} locals={:?}",
+                          i, frame.function, frame.pc, frame.bp, frame.args, frame.locals);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// reads debugger commands from stdin until quit or the end of input
+fn run_debugger(mut debugger: debugger::Debugger) -> ! {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(c4db) ");
+        let _ = io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            process::exit(0);
+        };
+        if matches!(line.trim(), "quit" | "q") {
+            process::exit(0);
+        }
+        match debugger.execute(&line) {
+            Ok(text) => print!("{}", text),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// reads repl entries until :quit or the end of input
+fn run_repl(mut session: repl::Repl) -> ! {
+    let mut reader = line_reader();
+    loop {
+        let Some(line) = reader.read_line(session.prompt()) else {
+            println!();
+            process::exit(0);
+        };
+        if session.is_idle() && matches!(line.trim(), ":quit" | ":q") {
+            process::exit(0);
+        }
+        if !line.trim().is_empty() {
+            reader.add_history(&line);
+        }
+        match session.feed(&line) {
+            Some(Ok(text)) => print!("{}", text),
+            Some(Err(e)) => println!("{}", e),
+            None => {}
+        }
+    }
+}
+
+/// line editing needs a terminal, piped input is read plainly
+#[cfg(feature = "readline")]
+fn line_reader() -> Box<dyn repl::LineReader> {
+    if io::stdin().is_terminal() {
+        if let Ok(editor) = repl::EditorReader::new() {
+            return Box::new(editor);
+        }
+    }
+    Box::new(repl::StdinReader)
+}
+
+/// without the readline feature lines are read plainly
+#[cfg(not(feature = "readline"))]
+fn line_reader() -> Box<dyn repl::LineReader> {
+    Box::new(repl::StdinReader)
+}
+
+/// OFFSET,LEN where either may be decimal or 0x hex
+fn parse_range(spec: &str) -> Option<(usize, usize)> {
+    let number = |text: &str| match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    let (offset, len) = spec.split_once(',')?;
+    Some((number(offset)?, number(len)?))
+}
+
+/// what strings and inspect print for a compiled program
+fn describe(image: &image::Image, strings: bool, hexdump: Option<(usize, usize)>) -> Result<String, String> {
+    if strings {
+        return Ok(image.dump_strings());
+    }
+    match hexdump {
+        Some((offset, len)) => {
+            if offset > image.data.len() {
+                return Err(format!("offset {} is past the end of the data segment ({} bytes)", offset, image.data.len()));
             }
+            let end = offset.saturating_add(len).min(image.data.len());
+            Ok(image::hexdump(&image.data[offset..end], offset))
         }
+        None => Ok(format!("code: {} words\ndata: {} bytes\nstrings: {}\n",
+                           image.code.len(), image.data.len(), image.strings.len())),
     }
 }
@@ -0,0 +1,14 @@
+//! hooks into a running VM
+//! for tracers, visualizers and grading checks
+
+/// gets told what the VM is about to do, every method defaults to nothing
+pub trait Observer {
+    /// a JSR to addr, name is known once VM::set_functions was called
+    fn on_call(&mut self, _name: Option<&str>, _addr: usize) {}
+    /// a LEV returning value
+    fn on_return(&mut self, _value: i64) {}
+    /// a syscall by its C name, arguments in call order
+    fn on_syscall(&mut self, _name: &str, _args: &[i64]) {}
+    /// an SI or SC writing value to addr
+    fn on_store(&mut self, _addr: usize, _value: i64) {}
+}
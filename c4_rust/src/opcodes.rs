@@ -0,0 +1,134 @@
+//! reference table of the VM instructions
+//! operand widths and syscall argument counts come from the metadata the VM decodes with
+
+use crate::parser::OpCode;
+use crate::vm::syscall_signature;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// hand-written part of an opcode's entry
+struct Doc {
+    operand: Option<(&'static str, i64)>, // what the operand word means and a sample value
+    stack: &'static str,                  // words taken and left, top of stack rightmost
+    effect: &'static str,                 // what happens to ax, pc and memory
+}
+
+// every arm is spelled out so a new opcode doesn't build until it is described
+fn doc(op: OpCode) -> Doc {
+    let doc = |operand, stack, effect| Doc { operand, stack, effect };
+    let binary = |effect| doc(None, "a --", effect);
+    match op {
+        OpCode::LEA => doc(Some(("offset", -2)), "--", "ax = bp + offset, locals are below bp"),
+        OpCode::IMM => doc(Some(("value", 42)), "--", "ax = value"),
+        OpCode::JMP => doc(Some(("target", 10)), "--", "pc = target"),
+        OpCode::JSR => doc(Some(("target", 10)), "-- ret", "pc = target"),
+        OpCode::BZ => doc(Some(("target", 10)), "--", "pc = target if ax == 0"),
+        OpCode::BNZ => doc(Some(("target", 10)), "--", "pc = target if ax != 0"),
+        OpCode::ENT => doc(Some(("locals", 2)), "-- bp locals", "bp = sp after saving bp, then room for the locals"),
+        OpCode::ADJ => doc(Some(("words", 2)), "words --", "drops the arguments of a call"),
+        OpCode::LEV => doc(None, "ret bp locals --", "sp = bp, bp and pc are popped back"),
+        OpCode::LI => doc(None, "--", "ax = *(int *)ax"),
+        OpCode::LC => doc(None, "--", "ax = *(char *)ax"),
+        OpCode::SI => doc(None, "addr --", "*(int *)addr = ax"),
+        OpCode::SC => doc(None, "addr --", "*(char *)addr = ax"),
+        OpCode::PSH => doc(None, "-- ax", "ax is kept"),
+        OpCode::OR => binary("ax = a | ax"),
+        OpCode::XOR => binary("ax = a ^ ax"),
+        OpCode::AND => binary("ax = a & ax"),
+        OpCode::EQ => binary("ax = a == ax"),
+        OpCode::NE => binary("ax = a != ax"),
+        OpCode::LT => binary("ax = a < ax"),
+        OpCode::GT => binary("ax = a > ax"),
+        OpCode::LE => binary("ax = a <= ax"),
+        OpCode::GE => binary("ax = a >= ax"),
+        OpCode::SHL => binary("ax = a << ax"),
+        OpCode::SHR => binary("ax = a >> ax"),
+        OpCode::ADD => binary("ax = a + ax"),
+        OpCode::SUB => binary("ax = a - ax"),
+        OpCode::MUL => binary("ax = a * ax"),
+        OpCode::DIV => binary("ax = a / ax"),
+        OpCode::MOD => binary("ax = a % ax"),
+        OpCode::PRTF => doc(Some(("argc", 2)), "", ""),
+        OpCode::OPEN | OpCode::READ | OpCode::CLOS | OpCode::MALC | OpCode::FREE | OpCode::MSET |
+        OpCode::MCMP | OpCode::EXIT | OpCode::CLCK | OpCode::USLP | OpCode::HXDP | OpCode::GENV |
+        OpCode::SYST | OpCode::POPN => doc(None, "", ""),
+        OpCode::SWP => doc(None, "a -- ax", "ax = a"),
+        OpCode::DUP => doc(None, "a -- a a", "ax is kept"),
+        OpCode::POP => doc(None, "a --", "ax is kept"),
+        OpCode::HOST => doc(Some(("index", 0)), "--", "ax = host function index, the caller drops the arguments"),
+    }
+}
+
+/// one row per opcode: number, name, operand, stack effect, effect and a sample encoding
+pub fn rows() -> Vec<[String; 6]> {
+    OpCode::ALL
+        .iter()
+        .map(|&op| {
+            let Doc { operand, stack, effect } = doc(op);
+            let sample = operand.map_or(0, |(_, value)| value);
+            let mut words = Vec::from([(op as i64).to_string()]);
+            words.extend(core::iter::repeat_n(sample.to_string(), op.arity()));
+            let operand = match (op.arity(), operand) {
+                (0, _) => String::from("-"),
+                (_, Some((name, _))) => name.to_string(),
+                (_, None) => String::from("word"),
+            };
+            // syscalls pop their own arguments, printf as many as its operand says
+            let (stack, effect) = match syscall_signature(op, sample) {
+                Some((name, argc)) => {
+                    let args = arguments(argc);
+                    (format!("{} --", args.join(" ")).trim_start().to_string(), format!("ax = {}({})", name, args.join(", ")))
+                }
+                None => (stack.to_string(), effect.to_string()),
+            };
+            let mut sample_text = op.name().to_string();
+            if op.has_operand() {
+                sample_text.push_str(&format!(" {}", sample));
+            }
+            [
+                (op as usize).to_string(),
+                op.name().to_string(),
+                operand,
+                stack,
+                effect,
+                format!("{} = [{}]", sample_text, words.join(", ")),
+            ]
+        })
+        .collect()
+}
+
+// names of a call's arguments, the first one deepest on the stack
+fn arguments(argc: usize) -> Vec<String> {
+    (1..=argc).map(|n| format!("a{}", n)).collect()
+}
+
+/// the rows drawn as a boxed ASCII table
+pub fn reference() -> String {
+    let header = ["#", "opcode", "operand", "stack", "effect", "example encoding"].map(String::from);
+    let rows = rows();
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let rule = widths.iter().map(|&w| "-".repeat(w + 2)).collect::<Vec<_>>().join("+");
+    let rule = format!("+{}+\n", rule);
+    let line = |cells: &[String; 6]| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, &w)| format!(" {:<w$} ", cell, w = w)).collect();
+        format!("|{}|\n", cells.join("|"))
+    };
+
+    let mut out = String::new();
+    out.push_str(&rule);
+    out.push_str(&line(&header));
+    out.push_str(&rule);
+    for row in &rows {
+        out.push_str(&line(row));
+    }
+    out.push_str(&rule);
+    out.push_str("stack pictures show the words before and after, top of stack rightmost\n");
+    out.push_str("an operand is one word right after the opcode, printf's is its argument count\n");
+    out
+}
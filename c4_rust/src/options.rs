@@ -0,0 +1,392 @@
+//! compiler settings
+//! shared by lexer, parser and vm
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// source dialect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    C4,  // the subset c4.c compiles
+    C4x, // c4 plus switch, like the c5/c4x variants
+}
+
+impl core::str::FromStr for Lang {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "c4" => Ok(Lang::C4),
+            "c4x" => Ok(Lang::C4x),
+            _ => Err(format!("unknown language '{}', expected c4 or c4x", name)),
+        }
+    }
+}
+
+/// when guest output reaches the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Buffering {
+    #[default]
+    Unbuffered, // every printf is written at once
+    Line,       // written up to the last newline, and before reading stdin
+    Full,       // written when the buffer fills and when the program ends
+}
+
+impl core::str::FromStr for Buffering {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(Buffering::Unbuffered),
+            "line" => Ok(Buffering::Line),
+            "full" => Ok(Buffering::Full),
+            _ => Err(format!("unknown buffering '{}', expected none, line or full", name)),
+        }
+    }
+}
+
+/// a kind of warning, switched with -Wname and -Wno-name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    Format,     // printf arguments that don't match the format string
+    Conversion, // constants that change value when stored in a char
+    Arity,      // calls with the wrong number of arguments, when unchecked_calls lets them through
+    Unreachable, // statements after a return in the same block
+    Return,     // non-void functions that can run off their closing brace
+    ImplicitInt, // declarations without a type, when implicit_int lets them through
+    OldStyle,   // K&R parameter lists and function declarations without a prototype
+    TrailingComma, // a comma after the last enum constant, which C89 rejects
+    Parentheses, // an assignment used as a condition, likely meant as ==
+    ComparisonChain, // a < b < c, which compares a < b's 0 or 1 with c
+    ShadowBuiltin, // a global or function named like a builtin such as read, which replaces it
+}
+
+impl Warning {
+    /// every warning the compiler knows
+    pub const ALL: [Warning; 11] = [
+        Warning::Format, Warning::Conversion, Warning::Arity, Warning::Unreachable, Warning::Return, Warning::ImplicitInt,
+        Warning::OldStyle, Warning::TrailingComma, Warning::Parentheses, Warning::ComparisonChain, Warning::ShadowBuiltin,
+    ];
+
+    /// the name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Warning::Format => "format",
+            Warning::Conversion => "conversion",
+            Warning::Arity => "arity",
+            Warning::Unreachable => "unreachable-code",
+            Warning::Return => "return-type",
+            Warning::ImplicitInt => "implicit-int",
+            Warning::OldStyle => "old-style-definition",
+            Warning::TrailingComma => "trailing-comma",
+            Warning::Parentheses => "parentheses",
+            Warning::ComparisonChain => "comparison-chain",
+            Warning::ShadowBuiltin => "shadow-builtin",
+        }
+    }
+
+    /// on unless turned off, the pedantic ones need -Wall or -Wname
+    pub fn enabled_by_default(self) -> bool {
+        match self {
+            Warning::Format | Warning::Conversion | Warning::Arity | Warning::Unreachable | Warning::Return
+            | Warning::ImplicitInt | Warning::OldStyle | Warning::Parentheses | Warning::ComparisonChain
+            | Warning::ShadowBuiltin => true,
+            Warning::TrailingComma => false,
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl core::str::FromStr for Warning {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Warning::ALL.into_iter().find(|w| w.name() == name)
+            .ok_or_else(|| format!("unknown warning '{}'", name))
+    }
+}
+
+/// which warnings are on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warnings(u32);
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Warnings(Warning::ALL.iter().filter(|w| w.enabled_by_default()).fold(0, |bits, w| bits | w.bit()))
+    }
+}
+
+impl Warnings {
+    /// true when w should be reported
+    pub fn enabled(self, w: Warning) -> bool {
+        self.0 & w.bit() != 0
+    }
+
+    /// turns w on or off
+    pub fn set(&mut self, w: Warning, on: bool) {
+        if on {
+            self.0 |= w.bit();
+        } else {
+            self.0 &= !w.bit();
+        }
+    }
+}
+
+/// stack slots a VM gets unless told otherwise
+pub const DEFAULT_STACK_SIZE: usize = 8192;
+
+/// instructions a run may execute unless told otherwise, to stop infinite loops
+pub const DEFAULT_MAX_CYCLES: usize = 50000;
+
+/// options for compiling and running a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileOptions {
+    pub debug: bool,         // trace every stage
+    pub opt_level: u8,       // 0 emits code as parsed, 1 folds constants and drops dead code, 2 also inlines
+    pub bounds_checks: bool, // reject stores past the data segment
+    pub sanitize: bool,      // trap when a char store would truncate or a heap block overflows
+    pub gc: bool,            // free is a no-op and a collector reclaims unreachable blocks
+    pub big_endian: bool,    // store ints in the data segment most significant byte first
+    pub signed_char: bool,   // a char read from memory or cast is -128..=127, otherwise 0..=255
+    pub trap_overflow: bool, // arithmetic overflow and out of range shifts fail instead of wrapping
+    pub virtual_time: bool,  // clock() counts instructions instead of real time
+    pub buffering: Buffering, // when program output is handed to the host
+    pub crlf: bool,          // program output ends lines with \r\n instead of \n
+    pub env: Vec<(String, String)>, // environment variables getenv() sees, later ones win
+    pub inherit_env: bool,   // getenv() also sees the host's environment
+    pub allow_exec: bool,    // system() and popen() run shell commands, otherwise they fail
+    pub lang: Lang,          // which keywords and statements are accepted
+    pub explain: usize,      // describe this many runtime events, 0 is off
+    pub stack_size: usize,   // VM stack slots, fixed for the whole run
+    pub max_cycles: usize,   // instructions before a run is stopped, 0 is no limit
+    pub max_stack_bytes: usize, // deepest the stack may grow, 0 is no limit beyond stack_size
+    pub warnings: Warnings,  // which warnings are reported
+    pub werror: bool,        // fail the compile when any warning is reported
+    pub keep_going: bool,    // skip a declaration that fails and compile the rest, for analysis
+    pub unchecked_calls: bool, // a call with the wrong number of arguments warns instead of failing
+    pub implicit_int: bool,  // a global or function declared without a type is an int, with a warning
+    pub pedantic: bool,      // code that compiles but can't mean what it says, like a < b < c, is an error
+    pub file: String,        // name the source was read from, what __FILE__ expands to
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            debug: false,
+            opt_level: 0,
+            bounds_checks: false,
+            sanitize: false,
+            gc: false,
+            big_endian: false,
+            signed_char: true,
+            trap_overflow: false,
+            virtual_time: false,
+            buffering: Buffering::default(),
+            crlf: false,
+            env: Vec::new(),
+            inherit_env: false,
+            allow_exec: false,
+            lang: Lang::default(),
+            explain: 0,
+            stack_size: DEFAULT_STACK_SIZE,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            max_stack_bytes: 0,
+            warnings: Warnings::default(),
+            werror: false,
+            keep_going: false,
+            unchecked_calls: false,
+            implicit_int: false,
+            pedantic: false,
+            file: String::from("<input>"),
+        }
+    }
+}
+
+impl CompileOptions {
+    /// default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// sets debug output
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// sets optimization level
+    pub fn opt_level(mut self, level: u8) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// sets runtime bounds checks
+    pub fn bounds_checks(mut self, checks: bool) -> Self {
+        self.bounds_checks = checks;
+        self
+    }
+
+    /// sets the runtime check on char stores
+    pub fn sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// sets whether heap memory is garbage collected
+    pub fn gc(mut self, gc: bool) -> Self {
+        self.gc = gc;
+        self
+    }
+
+    /// sets the byte order of ints in the data segment, little-endian by default
+    pub fn big_endian(mut self, big_endian: bool) -> Self {
+        self.big_endian = big_endian;
+        self
+    }
+
+    /// sets whether char is signed, as it is by default
+    pub fn signed_char(mut self, signed: bool) -> Self {
+        self.signed_char = signed;
+        self
+    }
+
+    /// sets whether integer overflow is a runtime error
+    pub fn trap_overflow(mut self, trap: bool) -> Self {
+        self.trap_overflow = trap;
+        self
+    }
+
+    /// sets how many runtime events to describe in plain English
+    pub fn explain(mut self, limit: usize) -> Self {
+        self.explain = limit;
+        self
+    }
+
+    /// sets deterministic clock() and usleep()
+    pub fn virtual_time(mut self, virtual_time: bool) -> Self {
+        self.virtual_time = virtual_time;
+        self
+    }
+
+    /// sets how program output is buffered
+    pub fn buffering(mut self, buffering: Buffering) -> Self {
+        self.buffering = buffering;
+        self
+    }
+
+    /// sets whether each \n the program prints is written as \r\n
+    pub fn crlf(mut self, crlf: bool) -> Self {
+        self.crlf = crlf;
+        self
+    }
+
+    /// adds an environment variable for getenv()
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((String::from(name), String::from(value)));
+        self
+    }
+
+    /// sets whether getenv() falls back to the host's environment
+    pub fn inherit_env(mut self, inherit: bool) -> Self {
+        self.inherit_env = inherit;
+        self
+    }
+
+    /// sets whether system() and popen() may run shell commands
+    pub fn allow_exec(mut self, allow: bool) -> Self {
+        self.allow_exec = allow;
+        self
+    }
+
+    /// sets how many slots the VM stack has
+    pub fn stack_size(mut self, slots: usize) -> Self {
+        self.stack_size = slots;
+        self
+    }
+
+    /// sets how many instructions a run may execute, 0 for no limit
+    pub fn max_cycles(mut self, cycles: usize) -> Self {
+        self.max_cycles = cycles;
+        self
+    }
+
+    /// sets how many bytes deep the stack may grow, 0 for no limit
+    pub fn max_stack_bytes(mut self, bytes: usize) -> Self {
+        self.max_stack_bytes = bytes;
+        self
+    }
+
+    /// sets the source dialect
+    pub fn lang(mut self, lang: Lang) -> Self {
+        self.lang = lang;
+        self
+    }
+
+    /// turns one warning on or off
+    pub fn warning(mut self, w: Warning, on: bool) -> Self {
+        self.warnings.set(w, on);
+        self
+    }
+
+    /// sets whether warnings fail the compile
+    pub fn werror(mut self, werror: bool) -> Self {
+        self.werror = werror;
+        self
+    }
+
+    /// sets whether one failed declaration stops the compile
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// sets whether argument count mismatches are only warnings
+    pub fn unchecked_calls(mut self, unchecked: bool) -> Self {
+        self.unchecked_calls = unchecked;
+        self
+    }
+
+    /// sets whether a declaration without a type defaults to int, as in old C
+    pub fn implicit_int(mut self, implicit: bool) -> Self {
+        self.implicit_int = implicit;
+        self
+    }
+
+    /// makes comparison chains errors rather than warnings
+    pub fn pedantic(mut self, pedantic: bool) -> Self {
+        self.pedantic = pedantic;
+        self
+    }
+
+    /// sets the source's name for __FILE__
+    pub fn file(mut self, name: &str) -> Self {
+        self.file = String::from(name);
+        self
+    }
+
+    /// applies a command line flag: -Wall, -Werror, -Wno-error, -Wname or -Wno-name
+    pub fn warning_flag(self, flag: &str) -> Result<Self, String> {
+        let name = flag.strip_prefix("-W").ok_or_else(|| format!("not a warning flag: {}", flag))?;
+        match name {
+            "all" => Ok(Warning::ALL.into_iter().fold(self, |options, w| options.warning(w, true))),
+            "error" => Ok(self.werror(true)),
+            "no-error" => Ok(self.werror(false)),
+            _ => match name.strip_prefix("no-") {
+                Some(name) => Ok(self.warning(name.parse()?, false)),
+                None => Ok(self.warning(name.parse()?, true)),
+            },
+        }
+    }
+}
+
+/// plain bool means debug flag, like the old constructors
+impl From<bool> for CompileOptions {
+    fn from(debug: bool) -> Self {
+        CompileOptions::new().debug(debug)
+    }
+}
@@ -1,7 +1,25 @@
-/// parses C code
-/// generates VM code
+//! parses C code into a syntax tree
+//! the code generator in codegen turns the tree into VM code
 
-use crate::lexer::{Lexer, Token};
+#![deny(clippy::unwrap_used)]
+
+use crate::ast::{
+    Arena, BinaryOp, Decl, Declarator, Enumerator, Expr, ExprId, ExprKind, Forward, Function, Initializer, MemberDecl,
+    Param, Params, Program, Stmt, StmtId, StmtKind, StructSpec, TypeName, TypeSpec, UnaryOp,
+};
+use crate::codegen::CodeGen;
+use crate::lexer::{reserved_word, Lexer, Token};
+use crate::ir;
+use crate::options::CompileOptions;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+// Deepest expression/statement nesting accepted before bailing out
+// (C99 only guarantees 63 levels of nested parentheses)
+const MAX_NESTING: usize = 64;
 
 /// type identifiers
 #[derive(Debug, Clone, PartialEq)]
@@ -10,6 +28,54 @@ pub enum Type {
     Int,
     Ptr(Box<Type>),
     Array(Box<Type>, usize),
+    Struct(Box<StructType>),
+}
+
+/// a struct's tag and member layout, members stay empty while it is only declared,
+/// as in a pointer to the struct inside its own definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub name: String,
+    pub members: Vec<Member>,
+    pub size: usize,  // bytes, padded to a multiple of the alignment
+    pub align: usize, // the strictest member's alignment
+}
+
+/// one member of a struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub typ: Type,
+    pub offset: usize, // bytes from the start of the struct
+}
+
+impl StructType {
+    /// a struct known only by its tag
+    pub fn declared(name: &str) -> Self {
+        StructType { name: String::from(name), members: Vec::new(), size: 0, align: 1 }
+    }
+    
+    /// lays the members out in order, each at the next offset its alignment allows
+    pub fn new(name: &str, members: Vec<(String, Type)>) -> Self {
+        let mut layout = StructType::declared(name);
+        for (name, typ) in members {
+            let align = typ.align();
+            let offset = layout.size.next_multiple_of(align);
+            layout.size = offset + typ.size();
+            layout.align = layout.align.max(align);
+            layout.members.push(Member { name, typ, offset });
+        }
+        layout.size = layout.size.next_multiple_of(layout.align);
+        layout
+    }
+    
+    pub fn is_complete(&self) -> bool {
+        !self.members.is_empty()
+    }
+    
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|member| member.name == name)
+    }
 }
 
 impl Type {
@@ -21,6 +87,23 @@ impl Type {
         matches!(self, Type::Array(_, _))
     }
     
+    pub fn is_struct(&self) -> bool {
+        matches!(self, Type::Struct(_))
+    }
+    
+    /// arrays and structs are used through their address, nothing loads them whole
+    pub fn is_aggregate(&self) -> bool {
+        self.is_array() || self.is_struct()
+    }
+    
+    /// the type an expression of this type has as an operand, arrays become pointers to their first element
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(base, _) => Type::Ptr(base.clone()),
+            other => other.clone(),
+        }
+    }
+    
     pub fn base_type(&self) -> Option<Box<Type>> {
         match self {
             Type::Ptr(base) => Some(base.clone()),
@@ -32,17 +115,53 @@ impl Type {
     pub fn size(&self) -> usize {
         match self {
             Type::Char => 1,
-            Type::Int | Type::Ptr(_) => std::mem::size_of::<i64>(), // Assuming 64-bit pointers/ints
-            Type::Array(base, size) => base.size() * size,
+            Type::Int | Type::Ptr(_) => core::mem::size_of::<i64>(), // Assuming 64-bit pointers/ints
+            Type::Array(base, size) => base.size().saturating_mul(*size),
+            Type::Struct(layout) => layout.size,
+        }
+    }
+    
+    /// the address multiple a value of this type starts at inside a struct
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(base, _) => base.align(),
+            Type::Struct(layout) => layout.align,
+            other => other.size(),
+        }
+    }
+    
+    /// how `name` is declared with this type, "char *argv[2]" or "int (*rows)[3]",
+    /// an empty name gives the type alone
+    pub fn declare(&self, name: &str) -> String {
+        match self {
+            Type::Char => join_declarator("char", name),
+            Type::Int => join_declarator("int", name),
+            Type::Struct(layout) => join_declarator(&format!("struct {}", layout.name), name),
+            Type::Ptr(base) => base.declare(&format!("*{}", name)),
+            // [] binds tighter than *, so a pointer to an array needs parentheses
+            Type::Array(base, len) if name.starts_with('*') => base.declare(&format!("({})[{}]", name, len)),
+            Type::Array(base, len) => base.declare(&format!("{}[{}]", name, len)),
         }
     }
 }
 
+/// C spelling of a type, like "int *", "char [5]" or "int (*)[3]"
+impl core::fmt::Display for Type {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(&self.declare(""))
+    }
+}
+
+fn join_declarator(specifier: &str, declarator: &str) -> String {
+    if declarator.is_empty() { specifier.to_string() } else { format!("{} {}", specifier, declarator) }
+}
+
 /// symbol classes
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SymbolClass {
     Fun, // function
     Sys, // system call
+    Host, // function registered by the embedder
     Glo, // global
     Loc, // local
     Num, // number
@@ -61,6 +180,21 @@ pub struct Symbol {
     pub prev_value: Option<i64>,
 }
 
+/// where a function lives in the code segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub start: usize,  // address of ENT
+    pub end: usize,    // one past the final LEV
+    pub params: usize,
+    pub locals: usize,
+    pub line: usize,   // line of the definition
+    pub slots: Vec<(String, i64, usize)>, // name, LEA offset and slot count of each parameter and local
+    pub types: Vec<Type>, // type of each entry in slots
+    pub variadic: bool, // ends in '...', params counts only the named ones
+    pub returns_value: bool, // false for void functions
+}
+
 /// VM instructions
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
@@ -68,77 +202,126 @@ pub enum OpCode {
     OR, XOR, AND, EQ, NE, LT, GT, LE, GE, SHL, SHR, ADD, SUB, MUL, DIV, MOD,
     OPEN, READ, CLOS, PRTF, MALC, FREE, MSET, MCMP, EXIT,
     SWP,
+    CLCK, USLP,
+    DUP, POP,
+    HOST,
+    HXDP,
+    GENV,
+    SYST, POPN,
+}
+
+impl OpCode {
+    /// every opcode, in encoding order
+    pub const ALL: [OpCode; 49] = [
+        OpCode::LEA, OpCode::IMM, OpCode::JMP, OpCode::JSR, OpCode::BZ, OpCode::BNZ, OpCode::ENT,
+        OpCode::ADJ, OpCode::LEV, OpCode::LI, OpCode::LC, OpCode::SI, OpCode::SC, OpCode::PSH,
+        OpCode::OR, OpCode::XOR, OpCode::AND, OpCode::EQ, OpCode::NE, OpCode::LT, OpCode::GT,
+        OpCode::LE, OpCode::GE, OpCode::SHL, OpCode::SHR, OpCode::ADD, OpCode::SUB, OpCode::MUL,
+        OpCode::DIV, OpCode::MOD,
+        OpCode::OPEN, OpCode::READ, OpCode::CLOS, OpCode::PRTF, OpCode::MALC, OpCode::FREE,
+        OpCode::MSET, OpCode::MCMP, OpCode::EXIT,
+        OpCode::SWP,
+        OpCode::CLCK, OpCode::USLP,
+        OpCode::DUP, OpCode::POP,
+        OpCode::HOST,
+        OpCode::HXDP,
+        OpCode::GENV,
+        OpCode::SYST, OpCode::POPN,
+    ];
+
+    /// decodes an instruction word
+    pub fn from_i64(word: i64) -> Option<OpCode> {
+        usize::try_from(word).ok().and_then(|i| Self::ALL.get(i)).copied()
+    }
+
+    /// mnemonic the listings, traces and the opcode table print
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::LEA => "LEA", OpCode::IMM => "IMM", OpCode::JMP => "JMP", OpCode::JSR => "JSR",
+            OpCode::BZ => "BZ", OpCode::BNZ => "BNZ", OpCode::ENT => "ENT", OpCode::ADJ => "ADJ",
+            OpCode::LEV => "LEV", OpCode::LI => "LI", OpCode::LC => "LC", OpCode::SI => "SI",
+            OpCode::SC => "SC", OpCode::PSH => "PSH",
+            OpCode::OR => "OR", OpCode::XOR => "XOR", OpCode::AND => "AND", OpCode::EQ => "EQ",
+            OpCode::NE => "NE", OpCode::LT => "LT", OpCode::GT => "GT", OpCode::LE => "LE",
+            OpCode::GE => "GE", OpCode::SHL => "SHL", OpCode::SHR => "SHR", OpCode::ADD => "ADD",
+            OpCode::SUB => "SUB", OpCode::MUL => "MUL", OpCode::DIV => "DIV", OpCode::MOD => "MOD",
+            OpCode::OPEN => "OPEN", OpCode::READ => "READ", OpCode::CLOS => "CLOS", OpCode::PRTF => "PRTF",
+            OpCode::MALC => "MALC", OpCode::FREE => "FREE", OpCode::MSET => "MSET", OpCode::MCMP => "MCMP",
+            OpCode::EXIT => "EXIT",
+            OpCode::SWP => "SWP",
+            OpCode::CLCK => "CLCK", OpCode::USLP => "USLP",
+            OpCode::DUP => "DUP", OpCode::POP => "POP",
+            OpCode::HOST => "HOST",
+            OpCode::HXDP => "HXDP",
+            OpCode::GENV => "GENV",
+            OpCode::SYST => "SYST", OpCode::POPN => "POPN",
+        }
+    }
+
+    /// whether one operand word follows the opcode
+    pub fn has_operand(self) -> bool {
+        match self {
+            OpCode::LEA | OpCode::IMM | OpCode::JMP | OpCode::JSR | OpCode::BZ | OpCode::BNZ |
+            OpCode::ENT | OpCode::ADJ => true,
+            // printf carries its argument count inline, host calls their index
+            OpCode::PRTF | OpCode::HOST => true,
+            _ => false,
+        }
+    }
+
+    /// number of operand words after the opcode
+    pub fn arity(self) -> usize {
+        usize::from(self.has_operand())
+    }
 }
 
-/// generates code
+/// parses C code into a syntax tree, then hands it to the code generator
 pub struct Parser<'a> {
+    source: &'a str, // kept for the declaration pre-pass
     lexer: Lexer<'a>,
-    symbols: Vec<Symbol>,
-    code: Vec<i64>,
-    data: Vec<u8>,
-    current_type: Type,
-    locals: usize,
-    _src: bool, // source printing flag (renamed with underscore to indicate unused)
-    debug: bool, // debug flag
+    gen: CodeGen, // checks types and emits the code for the parsed tree
+    ast: Arena, // nodes parsed so far, moved into the Program at the end
+    initialized: bool, // init has declared the builtins and read the first token
+    depth: usize, // current expression/statement nesting
+    braces: usize, // '{' consumed minus '}' consumed, to find the end of a failed declaration
+    options: CompileOptions, // settings for this compile
+    debug: bool, // debug flag, copied from options
 }
 
 impl<'a> Parser<'a> {
     /// create a new parser
-    pub fn new(source: &'a str, src_or_debug: bool) -> Self {
-        let mut data = Vec::new();
-        
-        // Add a test string at the beginning of the data segment
-        // This ensures we have at least one valid string in the data segment
-        let test_str = "Hello world!\n";
-        for byte in test_str.bytes() {
-            data.push(byte);
-        }
-        data.push(0); // null terminator
-        
-        // Align to 8-byte boundary
-        while data.len() % 8 != 0 {
-            data.push(0);
-        }
-        
-        let mut lexer = Lexer::new(source);
-        lexer.set_debug(src_or_debug); // Pass debug flag to lexer
+    pub fn new(source: &'a str, options: impl Into<CompileOptions>) -> Self {
+        let options = options.into();
+        let lexer = Lexer::with_options(source, &options);
         
         Parser {
+            source,
             lexer,
-            symbols: Vec::new(),
-            code: Vec::new(),
-            data,
-            current_type: Type::Int,
-            locals: 0,
-            _src: src_or_debug,
-            debug: src_or_debug, // Pass the src flag as debug flag too
+            gen: CodeGen::new(options.clone()),
+            ast: Arena::default(),
+            initialized: false,
+            depth: 0,
+            braces: 0,
+            debug: options.debug,
+            options,
         }
     }
     
-    /// initialize the parser with keywords and system calls
+    /// options this parser was created with
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+    
+    /// initialize the parser with system calls, keywords are the lexer's job
+    /// parse() calls it too, so only the first call does anything
     pub fn init(&mut self) -> Result<(), String> {
-        // Add keywords to symbol table
-        self.add_keyword("char", 134)?;  // Token::Char
-        self.add_keyword("else", 135)?;  // Token::Else
-        self.add_keyword("enum", 136)?;  // Token::Enum
-        self.add_keyword("for", 137)?;   // Token::For
-        self.add_keyword("if", 138)?;    // Token::If
-        self.add_keyword("int", 139)?;   // Token::Int
-        self.add_keyword("return", 140)?; // Token::Return
-        self.add_keyword("sizeof", 141)?; // Token::Sizeof
-        self.add_keyword("while", 142)?;  // Token::While
-        self.add_keyword("void", 146)?;   // Token::Void
+        if self.initialized {
+            return Ok(());
+        }
+        self.initialized = true;
         
         // Add system calls
-        self.add_syscall("open", OpCode::OPEN as i64)?;
-        self.add_syscall("read", OpCode::READ as i64)?;
-        self.add_syscall("close", OpCode::CLOS as i64)?;
-        self.add_syscall("printf", OpCode::PRTF as i64)?;
-        self.add_syscall("malloc", OpCode::MALC as i64)?;
-        self.add_syscall("free", OpCode::FREE as i64)?;
-        self.add_syscall("memset", OpCode::MSET as i64)?;
-        self.add_syscall("memcmp", OpCode::MCMP as i64)?;
-        self.add_syscall("exit", OpCode::EXIT as i64)?;
+        self.gen.declare_builtins();
         
         // Start tokenizing
         self.lexer.next();
@@ -146,63 +329,17 @@ impl<'a> Parser<'a> {
         Ok(())
     }
     
-    /// add a keyword to the symbol table
-    fn add_keyword(&mut self, name: &str, token_value: i64) -> Result<(), String> {
-        let symbol = Symbol {
-            name: name.to_string(),
-            class: SymbolClass::Num,
-            typ: Type::Int,
-            value: token_value,
-            prev_class: None,
-            prev_type: None,
-            prev_value: None,
-        };
-        
-        self.symbols.push(symbol);
-        Ok(())
-    }
-    
-    /// add a system call to the symbol table
-    fn add_syscall(&mut self, name: &str, id: i64) -> Result<(), String> {
-        let symbol = Symbol {
-            name: name.to_string(),
-            class: SymbolClass::Sys,
-            typ: Type::Int,
-            value: id,
-            prev_class: None,
-            prev_type: None,
-            prev_value: None,
-        };
-        
-        self.symbols.push(symbol);
-        Ok(())
-    }
-    
-    /// find a symbol in the symbol table by name
-    fn find_symbol(&self, name: &str) -> Option<&Symbol> {
-        self.symbols.iter().find(|s| s.name == name)
+    /// makes a host function callable by name, the index must match VM::add_host_fn
+    pub fn add_host_fn(&mut self, name: &str, argc: usize) -> usize {
+        self.gen.add_host_fn(name, argc)
     }
     
-    /// add a new symbol to the symbol table
-    fn add_symbol(&mut self, name: &str, class: SymbolClass, typ: Type, value: i64) -> Result<&mut Symbol, String> {
-        // Check if symbol already exists
-        if let Some(_) = self.find_symbol(name) {
-            return Err(format!("Symbol '{}' already defined", name));
+    /// the error for a missing name, saying so when a reserved word is in its place
+    fn name_expected(&self, message: &str) -> String {
+        match reserved_word(self.token()) {
+            Some(word) => format!("Line {}: '{}' is a reserved word and can't be used as a name", self.lexer.line(), word),
+            None => format!("Line {}: {}", self.lexer.line(), message),
         }
-        
-        // Add new symbol
-        let symbol = Symbol {
-            name: name.to_string(),
-            class,
-            typ,
-            value,
-            prev_class: None,
-            prev_type: None,
-            prev_value: None,
-        };
-        
-        self.symbols.push(symbol);
-        Ok(self.symbols.last_mut().unwrap())
     }
     
     /// get the current token
@@ -212,6 +349,11 @@ impl<'a> Parser<'a> {
     
     /// advance to next token
     fn next(&mut self) -> Token {
+        match self.token() {
+            Token::LeftBrace => self.braces += 1,
+            Token::RightBrace => self.braces = self.braces.saturating_sub(1),
+            _ => {}
+        }
         self.lexer.next()
     }
     
@@ -227,142 +369,119 @@ impl<'a> Parser<'a> {
     
     /// parse all declarations and return the generated code
     pub fn parse(&mut self) -> Result<(Vec<i64>, Vec<u8>), String> {
+        self.parse_program(true)
+    }
+    
+    /// like parse, but main may be missing, the entry stub then calls address 0 until patched
+    pub fn parse_library(&mut self) -> Result<(Vec<i64>, Vec<u8>), String> {
+        self.parse_program(false)
+    }
+    
+    /// parses everything, then generates code for it
+    fn parse_program(&mut self, need_main: bool) -> Result<(Vec<i64>, Vec<u8>), String> {
         self.init()?;
+        let program = self.program();
+        self.gen.generate(&program, need_main)
+    }
+    
+    /// parses everything into a syntax tree without generating any code,
+    /// the first declaration that doesn't parse is the error
+    pub fn parse_ast(&mut self) -> Result<Program, String> {
+        self.init()?;
+        let program = self.program();
+        match program.first_error() {
+            Some(error) => Err(error.to_string()),
+            None => Ok(program),
+        }
+    }
+    
+    /// parses every top-level declaration, a failed one becomes Decl::Error
+    /// and keep_going moves on to the next
+    fn program(&mut self) -> Program {
+        // First pass: find every global and function so order doesn't matter
+        let forward = self.collect_declarations();
+        let mut decls = Vec::new();
         
         // Main parsing loop
         while self.token() != Token::Eof {
-            // Handle special cases for problematic sections of c4.c
-            let line = self.lexer.line();
-            
-            // Special case for printf statements in C4.c that use complex expressions
-            if (line >= 55 && line <= 61) || line == 73 {
-                // These lines contain complex printf with string indexing or bit shifts in c4.c
-                // We'll skip them for self-hosting compatibility
-                if self.debug {
-                    println!("Warning: Line {}: Special handling for complex code in c4.c - skipping", line);
-                }
-                
-                // Skip to the next statement or line
-                while self.token() != Token::Eof {
-                    if self.token() == Token::Semicolon {
-                        self.next();
-                        if self.lexer.line() > line {
-                            break;
-                        }
-                    } else if self.lexer.line() > line {
-                        break;
-                    }
-                    self.next();
-                }
-                continue;
+            let before = decls.len();
+            let mut result = self.declaration(&mut decls);
+            // a bad macro is behind whatever the declaration made of its expansion
+            if let Some(error) = self.lexer.take_error() {
+                decls.truncate(before);
+                result = Err(error);
             }
-            
-            // Normal parsing continues here
-            self.declaration()?;
-        }
-        
-        // Debug: Print all symbols in the table
-        if self.debug {
-            println!("Symbol table contents:");
-            for sym in &self.symbols {
-                println!("Symbol: {}, Class: {:?}, Type: {:?}, Value: {}", 
-                        sym.name, sym.class, sym.typ, sym.value);
+            if let Err(e) = result {
+                decls.push(Decl::Error(e));
+                if !self.options.keep_going {
+                    break;
+                }
+                self.skip_failed_declaration();
             }
         }
         
-        // Find main function
-        let main_sym = self.find_symbol("main").ok_or("main() not defined")?;
-        if main_sym.class != SymbolClass::Fun {
-            return Err("main is not a function".to_string());
-        }
-        
-        // Return the generated code and data segments
-        Ok((self.code.clone(), self.data.clone()))
+        Program { forward, decls, arena: core::mem::take(&mut self.ast) }
     }
     
-    /// parse a declaration (variable or function)
-    fn declaration(&mut self) -> Result<(), String> {
-        let mut base_type = Type::Int; // default to int
+    /// skips to the end of a declaration that failed to parse
+    fn skip_failed_declaration(&mut self) {
+        self.depth = 0;
         
-        // Parse type
-        if self.token() == Token::Int {
-            base_type = Type::Int;
-            self.next();
-        } else if self.token() == Token::Char {
-            base_type = Type::Char;
-            self.next();
-        } else if self.token() == Token::Void {
-            base_type = Type::Int; // Use Int for void return type in symbol table
+        // the declaration ends at a top-level ';' or the '}' closing its body
+        while self.token() != Token::Eof {
+            let token = self.token();
             self.next();
-        } else if self.token() == Token::Enum {
-            self.parse_enum()?;
-            return Ok(());
+            if self.braces == 0 && matches!(token, Token::Semicolon | Token::RightBrace) {
+                break;
+            }
         }
-        
-        // Store the original base type for use in multiple declarations
-        let original_base_type = base_type.clone();
+    }
+    
+    /// parse a declaration (variable or function), pushing what it declares
+    fn declaration(&mut self, decls: &mut Vec<Decl>) -> Result<(), String> {
+        let typed = matches!(self.token(), Token::Int | Token::Char | Token::Void | Token::Struct);
+        match self.token() {
+            Token::Enum => {
+                let decl = self.parse_enum()?;
+                decls.push(decl);
+                return Ok(());
+            }
+            Token::Extern => {
+                let decl = self.extern_declaration()?;
+                decls.push(decl);
+                return Ok(());
+            }
+            _ => {}
+        }
+        let spec = self.type_spec()?;
         
         // Parse declarator list
+        let mut vars = Vec::new();
         while self.token() != Token::Semicolon && self.token() != Token::RightBrace {
-            // Reset to the original base type for each declarator
-            let mut typ = original_base_type.clone();
+            let declarator = self.declarator("Expected identifier in declaration")?;
             
-            // Parse pointer levels for this specific declarator
-            while self.token() == Token::Mul {
-                self.next();
-                typ = Type::Ptr(Box::new(typ.clone()));
+            // Function definition, the variables before it define any struct in the type
+            if self.token() == Token::LeftParen {
+                let mut function_spec = spec.clone();
+                let function_typed = typed || !vars.is_empty();
+                if !vars.is_empty() {
+                    if let TypeSpec::Struct(spec) = &mut function_spec {
+                        spec.members = None;
+                    }
+                    decls.push(Decl::Variables { spec, typed, vars });
+                }
+                let function = self.parse_function(function_spec, function_typed, declarator)?;
+                decls.push(Decl::Function(function));
+                return Ok(());
             }
             
-            // Expect identifier
-            if let Token::Id(id) = self.token() {
-                let name = self.get_id_name(id);
+            let init = if self.token() == Token::Assign {
                 self.next();
-                
-                // Check for array declaration
-                if self.token() == Token::LeftBracket {
-                    self.next(); // Skip '['
-                    
-                    // Get array size
-                    if let Token::Num(size) = self.token() {
-                        if self.debug {
-                            println!("DEBUG PARSER: Found array declaration with size {}", size);
-                        }
-                        typ = Type::Array(Box::new(typ.clone()), size as usize);
-                        self.next(); // Skip size
-                    } else {
-                        return Err(format!("Line {}: Expected numeric array size", self.lexer.line()));
-                    }
-                    
-                    // Expect closing bracket
-                    self.expect(Token::RightBracket, "Expected ']' after array size")?;
-                }
-                
-                // Function definition
-                if self.token() == Token::LeftParen {
-                    self.parse_function(name, typ)?;
-                    return Ok(());
-                }
-                
-                // Variable declaration
-                let _data_len = self.data.len(); // Mark as unused
-                // Align data segment before adding global variables
-                while self.data.len() % std::mem::size_of::<i64>() != 0 {
-                    self.data.push(0);
-                }
-                let aligned_data_len = self.data.len();
-                let type_size = typ.size();
-                
-                // Add symbol to table with proper type
-                self.add_symbol(&name, SymbolClass::Glo, typ, aligned_data_len as i64)?;
-                if self.debug {
-                    println!("DEBUG PARSER: Added global var '{}' of type {:?} at data address {}", name, self.symbols.last().unwrap().typ, aligned_data_len);
-                }
-                
-                // Add space in data segment
-                self.data.resize(aligned_data_len + type_size, 0);
+                Some(self.initializer()?)
             } else {
-                return Err(format!("Line {}: Expected identifier in declaration", self.lexer.line()));
-            }
+                None
+            };
+            vars.push((declarator, init));
             
             // Handle multiple declarations separated by commas
             if self.token() == Token::Comma {
@@ -375,12 +494,327 @@ impl<'a> Parser<'a> {
         
         // End of declaration
         self.expect(Token::Semicolon, "Expected semicolon after variable declaration")?;
+        decls.push(Decl::Variables { spec, typed, vars });
         
         Ok(())
     }
     
+    /// the type a declaration starts with, int when it is left out
+    fn type_spec(&mut self) -> Result<TypeSpec, String> {
+        let spec = match self.token() {
+            Token::Struct => return self.struct_spec().map(TypeSpec::Struct),
+            Token::Int => TypeSpec::Int,
+            Token::Char => TypeSpec::Char,
+            Token::Void => TypeSpec::Void,
+            _ => return Ok(TypeSpec::Int),
+        };
+        self.next();
+        Ok(spec)
+    }
+    
+    /// the number of '*' before a name or after a type
+    fn pointers(&mut self) -> usize {
+        let mut count = 0;
+        while self.token() == Token::Mul {
+            self.next();
+            count += 1;
+        }
+        count
+    }
+    
+    /// `*name[size]` after the type of a declaration
+    fn declarator(&mut self, message: &str) -> Result<Declarator, String> {
+        let pointers = self.pointers();
+        let Token::Id(id) = self.token() else {
+            return Err(self.name_expected(message));
+        };
+        let name = self.get_id_name(id);
+        self.next();
+        let size = self.array_suffix()?;
+        Ok(Declarator { name, pointers, size, line: self.lexer.line() })
+    }
+    
+    /// `[size]` after a name being declared, any constant expression
+    fn array_suffix(&mut self) -> Result<Option<ExprId>, String> {
+        if self.token() != Token::LeftBracket {
+            return Ok(None);
+        }
+        self.next(); // Skip '['
+        let size = self.expr(0)?;
+        self.expect(Token::RightBracket, "Expected ']' after array size")?;
+        Ok(Some(size))
+    }
+    
+    /// `extern int name(int a, char *b);` declares a host function, bound by name when the VM is linked
+    fn extern_declaration(&mut self) -> Result<Decl, String> {
+        self.next(); // Skip 'extern'
+        if !matches!(self.token(), Token::Int | Token::Char | Token::Void) {
+            return Err(format!("Line {}: Expected a type after 'extern'", self.lexer.line()));
+        }
+        self.next();
+        while self.token() == Token::Mul {
+            self.next();
+        }
+        let Token::Id(id) = self.token() else {
+            return Err(self.name_expected("Expected identifier in declaration"));
+        };
+        let name = self.get_id_name(id);
+        let line = self.lexer.line();
+        self.next();
+        self.expect(Token::LeftParen, "only functions can be extern, expected '(' after the name")?;
+        
+        // Parameter names are optional, only the count matters
+        let mut argc = 0;
+        while self.token() != Token::RightParen {
+            let bare_void = self.token() == Token::Void;
+            if !matches!(self.token(), Token::Int | Token::Char | Token::Void) {
+                return Err(format!("Line {}: Expected parameter type", self.lexer.line()));
+            }
+            self.next();
+            if bare_void && argc == 0 && self.token() == Token::RightParen {
+                break; // (void)
+            }
+            while self.token() == Token::Mul {
+                self.next();
+            }
+            if let Token::Id(_) = self.token() {
+                self.next();
+            }
+            argc += 1;
+            if self.token() != Token::Comma {
+                break;
+            }
+            self.next();
+        }
+        self.expect(Token::RightParen, "Expected ')' after parameters")?;
+        self.expect(Token::Semicolon, "Expected ';' after extern declaration")?;
+        Ok(Decl::Extern { name, argc, line })
+    }
+    
+    /// the string literal at the current token, joined with any right after it
+    fn string_literal(&mut self) -> ExprId {
+        let line = self.lexer.line();
+        let bytes = self.literal_bytes();
+        self.ast.push_expr(Expr { kind: ExprKind::Str(bytes, line), line: self.lexer.line() })
+    }
+    
+    /// the text of adjacent string literals, as C joins them, without a terminating 0
+    fn literal_bytes(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Token::Str(start) = self.token() {
+            let content = &self.lexer.string_buffer()[start..];
+            let len = content.iter().position(|&c| c == 0).unwrap_or(content.len());
+            bytes.extend_from_slice(&content[..len]);
+            self.next();
+        }
+        bytes
+    }
+    
+    /// what follows '=' in a global declaration: one value, or a list in braces
+    /// for an array or struct, a comma may follow the last one
+    fn initializer(&mut self) -> Result<Initializer, String> {
+        if self.token() != Token::LeftBrace {
+            return Ok(Initializer::Expr(self.initializer_value()?));
+        }
+        let line = self.lexer.line();
+        self.next();
+        let mut values = Vec::new();
+        while self.token() != Token::RightBrace {
+            values.push(self.initializer_value()?);
+            if self.token() != Token::Comma {
+                break;
+            }
+            self.next();
+        }
+        self.expect(Token::RightBrace, "Expected '}' after the initializer list")?;
+        Ok(Initializer::List(values, line))
+    }
+    
+    /// a constant expression, or a string which is stored rather than evaluated
+    fn initializer_value(&mut self) -> Result<ExprId, String> {
+        if matches!(self.token(), Token::Str(_)) {
+            Ok(self.string_literal())
+        } else {
+            self.expr(0)
+        }
+    }
+    
+    /// first pass: finds the top-level globals and functions before any body is parsed
+    fn collect_declarations(&mut self) -> Vec<Forward> {
+        let options = self.options.clone().debug(false);
+        let mut lexer = Lexer::with_options(self.source, &options);
+        let mut depth = 0usize; // brace nesting, only depth 0 declares
+        let mut forward = Vec::new();
+        lexer.next();
+        
+        while lexer.token() != Token::Eof {
+            let base_type = match lexer.token() {
+                Token::LeftBrace => {
+                    depth += 1;
+                    lexer.next();
+                    continue;
+                }
+                Token::RightBrace => {
+                    depth = depth.saturating_sub(1);
+                    lexer.next();
+                    continue;
+                }
+                // extern functions have no code or storage, the second pass declares them
+                Token::Extern if depth == 0 => {
+                    while !matches!(lexer.token(), Token::Semicolon | Token::Eof) {
+                        lexer.next();
+                    }
+                    continue;
+                }
+                Token::Int | Token::Void if depth == 0 => Type::Int,
+                Token::Char if depth == 0 => Type::Char,
+                // only the tag matters here, the second pass lays the members out
+                Token::Struct if depth == 0 => {
+                    lexer.next();
+                    let Token::Id(id) = lexer.token() else { continue };
+                    let tag = lexer.id_name(id).unwrap_or_default().to_string();
+                    if lexer.peek_token() == Token::LeftBrace {
+                        lexer.next();
+                        let mut braces = 0usize;
+                        loop {
+                            match lexer.token() {
+                                Token::LeftBrace => braces += 1,
+                                Token::RightBrace => braces -= 1,
+                                Token::Eof => break,
+                                _ => {}
+                            }
+                            if braces == 0 {
+                                break;
+                            }
+                            lexer.next();
+                        }
+                    }
+                    Type::Struct(Box::new(StructType::declared(&tag)))
+                }
+                _ => {
+                    lexer.next();
+                    continue;
+                }
+            };
+            lexer.next();
+            
+            // Declarator list
+            loop {
+                let mut typ = base_type.clone();
+                while lexer.token() == Token::Mul {
+                    typ = Type::Ptr(Box::new(typ));
+                    lexer.next();
+                }
+                let Token::Id(id) = lexer.token() else { break };
+                let name = lexer.id_name(id).unwrap_or_default().to_string();
+                lexer.next();
+                
+                if lexer.token() == Token::LeftBracket {
+                    lexer.next();
+                    let Token::Num(size) = lexer.token() else { break };
+                    typ = Type::Array(Box::new(typ), size as usize);
+                    lexer.next();
+                    if lexer.token() != Token::RightBracket {
+                        break;
+                    }
+                    lexer.next();
+                }
+                
+                // the second pass reports duplicates with line numbers
+                if lexer.token() == Token::LeftParen {
+                    // calls need to know about '...' before the definition is reached
+                    let mut variadic = false;
+                    while !matches!(lexer.token(), Token::RightParen | Token::LeftBrace | Token::Eof) {
+                        variadic |= lexer.token() == Token::Ellipsis;
+                        lexer.next();
+                    }
+                    // K&R parameter declarations aren't globals
+                    while !matches!(lexer.token(), Token::LeftBrace | Token::Semicolon | Token::Eof) {
+                        lexer.next();
+                    }
+                    let body = lexer.token() == Token::LeftBrace;
+                    forward.push(Forward::Function { name, typ, variadic, body });
+                    break; // the body is skipped by the brace tracking
+                }
+                // a struct's size isn't known yet, the second pass allocates it
+                if !typ.is_struct() {
+                    forward.push(Forward::Global { name, typ, line: lexer.line() });
+                }
+                // the second pass stores the initializer
+                if lexer.token() == Token::Assign {
+                    let mut parens = 0usize; // parentheses and braces of an initializer list
+                    while !matches!(lexer.token(), Token::Semicolon | Token::Eof) && (parens > 0 || lexer.token() != Token::Comma) {
+                        match lexer.token() {
+                            Token::LeftParen | Token::LeftBrace => parens += 1,
+                            Token::RightParen | Token::RightBrace => parens = parens.saturating_sub(1),
+                            _ => {}
+                        }
+                        lexer.next();
+                    }
+                }
+                if lexer.token() != Token::Comma {
+                    break;
+                }
+                lexer.next();
+            }
+        }
+        
+        forward
+    }
+    
+    /// `struct tag`, or `struct tag { members }` which defines the tag
+    fn struct_spec(&mut self) -> Result<StructSpec, String> {
+        self.next(); // Skip 'struct'
+        let Token::Id(id) = self.token() else {
+            return Err(self.name_expected("Expected a tag name after 'struct'"));
+        };
+        let tag = self.get_id_name(id);
+        self.next();
+        let line = self.lexer.line();
+        if self.token() != Token::LeftBrace {
+            return Ok(StructSpec { tag, members: None, line });
+        }
+        
+        self.next(); // Skip '{'
+        let mut members = Vec::new();
+        while self.token() != Token::RightBrace {
+            if !matches!(self.token(), Token::Struct | Token::Int | Token::Char) {
+                return Err(format!("Line {}: Expected a member type in struct '{}'", self.lexer.line(), tag));
+            }
+            let spec = self.type_spec()?;
+            let mut declarators = Vec::new();
+            loop {
+                let pointers = self.pointers();
+                // a reserved word can name a member, nothing else can follow the type here
+                let declarator_end = matches!(self.lexer.peek_token(), Token::Semicolon | Token::Comma | Token::LeftBracket);
+                let Some(name) = self.member_name(declarator_end) else {
+                    return Err(format!("Line {}: Expected a member name in struct '{}'", self.lexer.line(), tag));
+                };
+                self.next();
+                let size = self.array_suffix()?;
+                declarators.push(Declarator { name, pointers, size, line: self.lexer.line() });
+                if self.token() != Token::Comma {
+                    break;
+                }
+                self.next();
+            }
+            self.expect(Token::Semicolon, "Expected ';' after struct member")?;
+            members.push(MemberDecl { spec, declarators });
+        }
+        self.next(); // Skip '}'
+        Ok(StructSpec { tag, members: Some(members), line })
+    }
+    
+    /// the member name at the current token, a reserved word counts where it can't start anything else
+    fn member_name(&self, unambiguous: bool) -> Option<String> {
+        match self.token() {
+            Token::Id(id) => Some(self.get_id_name(id)),
+            token => reserved_word(token).filter(|_| unambiguous).map(String::from),
+        }
+    }
+    
     /// parse an enum declaration
-    fn parse_enum(&mut self) -> Result<(), String> {
+    fn parse_enum(&mut self) -> Result<Decl, String> {
         self.next(); // Skip 'enum'
         
         // Optional enum name (ignored in C4)
@@ -391,122 +825,93 @@ impl<'a> Parser<'a> {
         // Enum body
         self.expect(Token::LeftBrace, "Expected '{' after enum")?;
         
-        let mut value = 0;
+        let mut members = Vec::new();
+        let mut trailing_comma = None;
         while self.token() != Token::RightBrace {
             // Enum member must be an identifier
-            if let Token::Id(id) = self.token() {
-                let name = self.get_id_name(id);
+            let Token::Id(id) = self.token() else {
+                return Err(self.name_expected("Expected identifier in enum declaration"));
+            };
+            let name = self.get_id_name(id);
+            let line = self.lexer.line();
+            self.next();
+            
+            // Check for explicit value
+            let value = if self.token() == Token::Assign {
                 self.next();
-                
-                // Check for explicit value
-                if self.token() == Token::Assign {
-                    self.next();
-                    if let Token::Num(val) = self.token() {
-                        value = val;
-                        self.next();
-                    } else {
-                        return Err(format!("Line {}: Expected numeric value after '='", self.lexer.line()));
-                    }
-                }
-                
-                // Add enum value to symbol table
-                self.add_symbol(&name, SymbolClass::Num, Type::Int, value)?;
-                
-                // Increment value for next enum member
-                value += 1;
-                
-                // Comma or end of enum
-                if self.token() == Token::Comma {
-                    self.next();
-                }
+                Some(self.expr(0)?)
             } else {
-                return Err(format!("Line {}: Expected identifier in enum declaration", self.lexer.line()));
+                None
+            };
+            members.push(Enumerator { name, value, line });
+            
+            // Comma or end of enum, C99 allows one after the last constant
+            if self.token() == Token::Comma {
+                self.next();
+                if self.token() == Token::RightBrace {
+                    trailing_comma = Some(self.lexer.line());
+                }
             }
         }
         
         self.next(); // Skip closing brace
         self.expect(Token::Semicolon, "Expected semicolon after enum declaration")?;
         
-        Ok(())
+        Ok(Decl::Enum { members, trailing_comma })
     }
     
-    /// parse a function definition
-    fn parse_function(&mut self, name: String, return_type: Type) -> Result<(), String> {
-        // Mark current position in the code segment
-        let fn_pos = self.code.len();
-        
-        // Add function to symbol table
-        let _symbol = self.add_symbol(&name, SymbolClass::Fun, return_type, fn_pos as i64)?;
-        
-        // Save old locals position
-        let old_locals = self.locals;
-        self.locals = 0;
+    /// parse a function definition or prototype, from the '(' after its name
+    fn parse_function(&mut self, spec: TypeSpec, typed: bool, declarator: Declarator) -> Result<Function, String> {
+        let line = self.lexer.line();
         
         // Parse parameter list
         self.next(); // Skip '('
-        let mut param_count = 0i64;
+        let unspecified = self.token() == Token::RightParen;
         
-        if self.token() != Token::RightParen {
+        // (void) is an empty parameter list
+        if self.token() == Token::Void {
+            self.next();
+            if self.token() != Token::RightParen {
+                return Err(format!("Line {}: 'void' must be the only parameter", self.lexer.line()));
+            }
+        }
+        
+        // A K&R list only names the parameters, declarations before the body give their types
+        let old_style = matches!(self.token(), Token::Id(_));
+        let names = if old_style { self.parameter_names() } else { Vec::new() };
+        
+        let mut params = Vec::new();
+        let mut variadic = false;
+        if !old_style && self.token() != Token::RightParen {
             loop {
-                // Parse parameter type
-                let mut _param_type = Type::Int; // default to int
-                
-                if self.token() == Token::Int {
-                    _param_type = Type::Int;
-                    self.next();
-                } else if self.token() == Token::Char { // Handle char parameter type
-                    _param_type = Type::Char;
+                // '...' ends the list, the extra arguments are read with va_get()
+                if self.token() == Token::Ellipsis {
                     self.next();
-                } else {
-                    return Err(format!("Line {}: Parameter type expected", self.lexer.line()));
+                    variadic = true;
+                    break;
                 }
                 
-                // Parse pointer levels
-                while self.token() == Token::Mul {
-                    self.next();
-                    _param_type = Type::Ptr(Box::new(_param_type));
+                // Parse parameter type
+                if !matches!(self.token(), Token::Int | Token::Char | Token::Struct) {
+                    return Err(format!("Line {}: Parameter type expected", self.lexer.line()));
                 }
+                let spec = self.type_spec()?;
+                let pointers = self.pointers();
                 
-                // Parse parameter name
-                if let Token::Id(id) = self.token() {
-                    let param_name = self.get_id_name(id);
-                    
-                    // Check for duplicate parameter
-                    if let Some(existing) = self.find_symbol(&param_name) {
-                        if existing.class == SymbolClass::Loc {
-                            return Err(format!("Line {}: Duplicate parameter '{}'", self.lexer.line(), param_name));
-                        }
-                        
-                        // Save old properties to restore later
-                        let old_class = existing.class;
-                        let old_type = existing.typ.clone();
-                        let old_value = existing.value;
-                        
-                        // Add as local parameter (they're backwards in stack so use negative values)
-                        self.add_symbol_with_history(
-                            &param_name,
-                            SymbolClass::Loc,
-                            _param_type,
-                            param_count,
-                            Some(old_class),
-                            Some(old_type),
-                            Some(old_value),
-                        )?;
-                    } else {
-                        // Add as local parameter
-                        self.add_symbol(
-                            &param_name,
-                            SymbolClass::Loc,
-                            _param_type,
-                            param_count,
-                        )?;
+                // Parse parameter name, only a declaration may leave it out
+                let param = match self.token() {
+                    Token::Id(id) => {
+                        let name = self.get_id_name(id);
+                        self.next();
+                        let (array, size) = self.array_parameter()?;
+                        Param { spec, pointers, name: Some(name), array, size, line: self.lexer.line() }
                     }
-                    
-                    param_count += 1;
-                    self.next();
-                } else {
-                    return Err(format!("Line {}: Parameter name expected", self.lexer.line()));
-                }
+                    Token::Comma | Token::RightParen => {
+                        Param { spec, pointers, name: None, array: false, size: None, line: self.lexer.line() }
+                    }
+                    _ => return Err(self.name_expected("Parameter name expected")),
+                };
+                params.push(param);
                 
                 // Check for more parameters
                 if self.token() == Token::Comma {
@@ -518,1487 +923,338 @@ impl<'a> Parser<'a> {
         }
         
         self.expect(Token::RightParen, "Expected ')' after function parameters")?;
+        let params = if old_style {
+            let decls = self.parameter_declarations()?;
+            Params::OldStyle { names, decls, line: self.lexer.line() }
+        } else if unspecified {
+            Params::Unspecified
+        } else {
+            Params::List { params, variadic }
+        };
         
-        // Store parameter count for local offset calculation
-        self.locals = param_count as usize;
+        // A declaration without a body
+        if self.token() == Token::Semicolon && !old_style {
+            self.next();
+            return Ok(Function { spec, typed, declarator, params, body: None, line });
+        }
         
         // Function body
         self.expect(Token::LeftBrace, "Expected '{' to start function body")?;
+        let body = self.block_items()?;
+        self.expect(Token::RightBrace, "Expected '}' to end function")?;
         
-        // Calculate local stack space needed
-        let local_offset = self.locals as i64 - param_count;
-        
-        // Generate function entry code
-        self.code.push(OpCode::ENT as i64);
-        self.code.push(local_offset);
-        
-        if self.debug {
-            println!("DEBUG PARSER: Function entry - creating stack frame with {} local variables", local_offset);
+        Ok(Function { spec, typed, declarator, params, body: Some(body), line })
+    }
+    
+    /// `[]` or `[size]` after a parameter name, which makes the parameter a pointer
+    fn array_parameter(&mut self) -> Result<(bool, Option<ExprId>), String> {
+        if self.token() != Token::LeftBracket {
+            return Ok((false, None));
         }
-        
-        // Parse local variable declarations and statements
-        
-        // Parse local variable declarations and statements
-        while self.token() != Token::RightBrace && self.token() != Token::Eof {
-            // Check for local variable declarations at the beginning
-            if self.token() == Token::Int || self.token() == Token::Char {
-                let base_type = if self.token() == Token::Int {
-                    self.next();
-                    Type::Int
-                } else {
-                    self.next();
-                    Type::Char // Handle local char variable
+        self.next();
+        let size = if self.token() != Token::RightBracket { Some(self.expr(0)?) } else { None };
+        self.expect(Token::RightBracket, "Expected ']' after array parameter")?;
+        Ok((true, size))
+    }
+    
+    /// the names of a K&R parameter list like `(a, b)`, each with the line after it
+    fn parameter_names(&mut self) -> Vec<(String, usize)> {
+        let mut names = Vec::new();
+        while let Token::Id(id) = self.token() {
+            let name = self.get_id_name(id);
+            self.next();
+            names.push((name, self.lexer.line()));
+            if self.token() != Token::Comma {
+                break;
+            }
+            self.next();
+        }
+        names
+    }
+    
+    /// `int a; char *b;` between a K&R parameter list and the body
+    fn parameter_declarations(&mut self) -> Result<Vec<Param>, String> {
+        let mut decls = Vec::new();
+        while matches!(self.token(), Token::Int | Token::Char) {
+            let spec = self.type_spec()?;
+            loop {
+                let pointers = self.pointers();
+                let Token::Id(id) = self.token() else {
+                    return Err(self.name_expected("Parameter name expected"));
                 };
-                
-                // Parse local variables
-                while self.token() != Token::Semicolon {
-                    let mut var_type = base_type.clone();
-                    
-                    // Parse pointer levels
-                    while self.token() == Token::Mul {
-                        self.next();
-                        var_type = Type::Ptr(Box::new(var_type));
-                    }
-                    
-                    // Parse variable name
-                    if let Token::Id(id) = self.token() {
-                        let var_name = self.get_id_name(id);
-                        self.next();
-                        
-                        // Check for array declaration
-                        if self.token() == Token::LeftBracket {
-                            self.next(); // Skip '['
-                            
-                            // Get array size
-                            if let Token::Num(size) = self.token() {
-                                if self.debug {
-                                    println!("DEBUG PARSER: Found array declaration with size {}", size);
-                                }
-                                var_type = Type::Array(Box::new(var_type.clone()), size as usize);
-                                self.next(); // Skip size
-                            } else {
-                                return Err(format!("Line {}: Expected numeric array size", self.lexer.line()));
-                            }
-                            
-                            // Expect closing bracket
-                            self.expect(Token::RightBracket, "Expected ']' after array size")?;
-                        }
-                        
-                        // Check for duplicate local (except params)
-                        if let Some(existing) = self.find_symbol(&var_name) {
-                            if existing.class == SymbolClass::Loc && existing.value >= param_count {
-                                return Err(format!("Line {}: Duplicate local variable '{}'", self.lexer.line(), var_name));
-                            }
-                            
-                            // Save old properties to restore later
-                            let old_class = existing.class;
-                            let old_type = existing.typ.clone();
-                            let old_value = existing.value;
-                            
-                            // Add as local variable
-                            self.add_symbol_with_history(
-                                &var_name,
-                                SymbolClass::Loc,
-                                var_type.clone(),
-                                self.locals as i64,
-                                Some(old_class),
-                                Some(old_type),
-                                Some(old_value),
-                            )?;
-                        } else {
-                            // Add as local variable
-                            self.add_symbol(
-                                &var_name,
-                                SymbolClass::Loc,
-                                var_type.clone(),
-                                self.locals as i64,
-                            )?;
-                        }
-                        
-                        // Debug output for locals
-                        if self.debug {
-                            println!("DEBUG PARSER: Local variable '{}' at offset {}, generating LEA {}", 
-                                     var_name, self.locals, self.locals);
-                        }
-                                   
-                        self.locals += 1;
-                        
-                        // Check for initialization
-                        if self.token() == Token::Assign {
-                            if self.debug {
-                                println!("DEBUG PARSER: Initializing local variable '{}' at declaration", var_name);
-                            }
-                            self.next(); // Skip '='
-                            
-                            // Generate code to get the address of the local variable
-                            self.code.push(OpCode::LEA as i64);
-                            self.code.push((self.locals - 1) as i64);
-                            
-                            // Step 1: Save variable address for later
-                            self.code.push(OpCode::PSH as i64);
-                            
-                            // Step 2: Evaluate the initializer
-                            self.expr(0)?;
-                            
-                            // Step 3: Store value at the address
-                            if var_type == Type::Char {
-                                self.code.push(OpCode::SC as i64);
-                                if self.debug {
-                                    println!("DEBUG PARSER: Generated SC for local char initialization");
-                                }
-                            } else {
-                                self.code.push(OpCode::SI as i64);
-                                if self.debug {
-                                    println!("DEBUG PARSER: Generated SI for local int initialization");
-                                }
-                            }
-                        }
-                    } else {
-                        return Err(format!("Line {}: Local variable name expected", self.lexer.line()));
-                    }
-                    
-                    // Check for more variables
-                    if self.token() == Token::Comma {
-                        self.next();
-                    } else {
-                        break;
-                    }
+                let name = self.get_id_name(id);
+                self.next();
+                let (array, size) = self.array_parameter()?;
+                decls.push(Param { spec: spec.clone(), pointers, name: Some(name), array, size, line: self.lexer.line() });
+                if self.token() != Token::Comma {
+                    break;
                 }
-                
-                self.expect(Token::Semicolon, "Expected ';' after local variable declaration")?;
-            } else {
-                // Parse statements
-                self.stmt()?;
+                self.next();
             }
+            self.expect(Token::Semicolon, "Expected ';' after parameter declaration")?;
         }
-        
-        // Ensure function has a return statement by adding LEV
-        self.code.push(OpCode::LEV as i64);
-        
-        self.expect(Token::RightBrace, "Expected '}' to end function")?;
-        
-        // Restore symbol table by clearing locals
-        // In real implementation, we'd need to track which symbols to remove
-        // For now, we just keep them all since we're not generating cleanup code
-        self.restore_symbols_after_function()?;
-        
-        // Restore old locals count
-        self.locals = old_locals;
-        
-        Ok(())
+        Ok(decls)
     }
     
-    // Helper method to add a symbol with history for shadowing
-    fn add_symbol_with_history(
-        &mut self,
-        name: &str,
-        class: SymbolClass,
-        typ: Type,
-        value: i64,
-        prev_class: Option<SymbolClass>,
-        prev_type: Option<Type>,
-        prev_value: Option<i64>,
-    ) -> Result<&mut Symbol, String> {
-        // Create the new symbol with history
-        let symbol = Symbol {
-            name: name.to_string(),
-            class,
-            typ,
-            value,
-            prev_class,
-            prev_type,
-            prev_value,
-        };
-        
-        // Add it to the symbols table
-        self.symbols.push(symbol);
-        
-        // Return a mutable reference to the newly added symbol
-        Ok(self.symbols.last_mut().unwrap())
+    /// declarations and statements up to the closing '}'
+    fn block_items(&mut self) -> Result<Vec<StmtId>, String> {
+        let mut items = Vec::new();
+        while self.token() != Token::RightBrace && self.token() != Token::Eof {
+            if matches!(self.token(), Token::Int | Token::Char | Token::Struct) {
+                items.push(self.local_declaration()?);
+            } else {
+                items.push(self.stmt()?);
+            }
+        }
+        Ok(items)
     }
     
-    // Helper method to restore symbols after function scope is exited
-    fn restore_symbols_after_function(&mut self) -> Result<(), String> {
-        // Create a new symbols vector without local variables
-        let mut new_symbols = Vec::new();
-        
-        for symbol in self.symbols.drain(..) {
-            if symbol.class == SymbolClass::Loc {
-                // For parameters and locals, restore any shadowed symbols
-                if let (Some(prev_class), Some(prev_type), Some(prev_value)) = 
-                   (symbol.prev_class, symbol.prev_type, symbol.prev_value) {
-                    // This local shadowed a global, restore it
-                    let restored = Symbol {
-                        name: symbol.name,
-                        class: prev_class,
-                        typ: prev_type,
-                        value: prev_value,
-                        prev_class: None,
-                        prev_type: None,
-                        prev_value: None,
-                    };
-                    new_symbols.push(restored);
+    /// parse a local declaration like `int a, *b, c[4] = ...;`
+    fn local_declaration(&mut self) -> Result<StmtId, String> {
+        let line = self.lexer.line();
+        let spec = self.type_spec()?;
+        
+        // Parse local variables
+        let mut vars = Vec::new();
+        while self.token() != Token::Semicolon {
+            let declarator = self.declarator("Local variable name expected")?;
+            
+            // Check for initialization
+            let init = if self.token() == Token::Assign {
+                self.next(); // Skip '='
+                if self.token() == Token::LeftBrace {
+                    return Err(format!("Line {}: only global arrays and structs can have an initializer list", self.lexer.line()));
                 }
-                // Skip locals that didn't shadow anything
+                Some(self.expr(0)?)
+            } else {
+                None
+            };
+            vars.push((declarator, init));
+            
+            // Check for more variables
+            if self.token() == Token::Comma {
+                self.next();
             } else {
-                // Keep all non-local symbols
-                new_symbols.push(symbol);
+                break;
             }
         }
         
-        // Replace the symbols table
-        self.symbols = new_symbols;
-        
-        Ok(())
+        self.expect(Token::Semicolon, "Expected ';' after local variable declaration")?;
+        Ok(self.ast.push_stmt(Stmt { kind: StmtKind::Local(spec, vars), line }))
     }
     
-    /// get the name of an identifier from its hash
+    /// how an identifier token was spelled, the lexer numbers every one it reads
     fn get_id_name(&self, id: usize) -> String {
-        // In this improved implementation, we treat the id as a simple index into
-        // a naming table that is provided by the lexer
-        // Since our lexer already normalized the handling of identifiers,
-        // we should just use the given hash directly for lookup.
-        
-        // For testing purposes, let's check if it's one of the well-known identifiers
-        if id == 22294568004 || id == 5863476 {
-            return "main".to_string();
-        } else if id == 135095875 || id == 193491849 {
-            return "add".to_string();
-        } else if id == 97264153 || id == 97264 || id == 20261620804 {
-            return "calc".to_string();
-        } else if id == 210871959858 || id == 9871951 {
-            return "process".to_string();
-        } else if id == 8426756478 || id == 210945 {
-            return "complex".to_string();
-        // Standard library functions
-        } else if id == 495450526609734 || id == 24357699 {
-            return "printf".to_string();
-        } else if id == 97 || id == 193499849 {  // 'a'
-            return "a".to_string();
-        } else if id == 98 || id == 193499950 {  // 'b' 
-            return "b".to_string();
-        } else if id == 99 || id == 193500051 {  // 'c'
-            return "c".to_string();
-        } else if id == 120 || id == 193508484 {  // 'x'
-            return "x".to_string();
-        } else if id == 121 || id == 193508585 {  // 'y'
-            return "y".to_string();
-        } else if id == 122 || id == 193508686 {  // 'z'
-            return "z".to_string();
-        } else if id == 112 || id == 193505858 {  // 'p'
-            return "ptr".to_string();
-        } else if id == 118 || id == 193508282 {  // 'v'
-            return "val".to_string();
+        self.lexer.id_name(id).unwrap_or_default().to_string()
+    }
+    
+    /// track nesting so hostile input can't overflow the host stack
+    fn enter_nesting(&mut self) -> Result<(), String> {
+        if self.depth >= MAX_NESTING {
+            return Err(format!("Line {}: Nesting too deep", self.lexer.line()));
         }
-        
-        // Fallback to a generated name
-        format!("id_{}", id)
+        self.depth += 1;
+        Ok(())
     }
     
     /// parse an expression with a given precedence level
-    fn expr(&mut self, precedence: u8) -> Result<(), String> {
+    fn expr(&mut self, precedence: u8) -> Result<ExprId, String> {
+        self.enter_nesting()?;
+        let result = self.parse_expr(precedence);
+        self.depth -= 1;
+        result
+    }
+    
+    /// expression parser body, use expr() for the depth check
+    fn parse_expr(&mut self, precedence: u8) -> Result<ExprId, String> {
         // Debug output to trace expr calls
         if self.debug {
             println!("DEBUG: expr called with precedence {}, token: {:?}, line: {}", 
                      precedence, self.token(), self.lexer.line());
         }
         
-        // Primary expression parsing
-        match self.token() {
-            Token::Num(val) => {
-                // Push immediate value to code
-                self.code.push(OpCode::IMM as i64);
-                self.code.push(val);
-                self.next();
-                self.current_type = Type::Int;
-            },
-            Token::Str(start_pos_in_buffer) => {
-                // Handle string literals
-                let str_start = self.data.len();
-                
-                // Debug output
-                let string_content = &self.lexer.string_buffer()[start_pos_in_buffer..];
-                let string_len = string_content.iter().position(|&c| c == 0).unwrap_or(string_content.len());
-                let string_slice = &string_content[..string_len];
-                if self.debug {
-                    println!(
-                        "DEBUG PARSER: String literal starting at buffer index {}, value: \"{}\"",
-                        start_pos_in_buffer,
-                        String::from_utf8_lossy(string_slice)
-                    );
-                    println!("DEBUG PARSER: Storing string at data segment position: {}", str_start);
-                }
-                
-                // Copy the string data (including null terminator) into the data segment
-                self.data.extend_from_slice(string_slice);
-                self.data.push(0); // Ensure null termination in data segment
-                
-                // Align data segment after string
-                while self.data.len() % std::mem::size_of::<i64>() != 0 {
-                    self.data.push(0);
+        let mut lhs = self.primary(precedence)?;
+        
+        // Handle operators with precedence climbing
+        while self.precedence_of(self.token()) > precedence {
+            let op = self.token();
+            if self.debug {
+                println!("DEBUG PARSER: Found operator {:?} with precedence {}", op, self.precedence_of(op));
+            }
+            self.next();
+            
+            let kind = match op {
+                Token::Assign => ExprKind::Assign(lhs, self.expr(0)?),
+                Token::Cond => {
+                    let when_true = self.expr(0)?;
+                    self.expect(Token::Colon, "Expected ':' in conditional expression")?;
+                    // the else branch stops before an assignment but takes another ?:, so a ? b : c ? d : e nests right
+                    let when_false = self.expr(self.precedence_of(Token::Cond) - 1)?;
+                    if self.precedence_of(self.token()) == self.precedence_of(Token::Assign) {
+                        return Err(format!("Line {}: bad lvalue in assignment", self.lexer.line()));
+                    }
+                    ExprKind::Cond(lhs, when_true, when_false)
                 }
-                
-                // Push immediate value (address of the string in data segment)
-                self.code.push(OpCode::IMM as i64);
-                self.code.push(str_start as i64);
-                if self.debug {
-                    println!("DEBUG PARSER: Generated IMM {} for string address", str_start);
+                Token::LeftBracket => {
+                    let index = self.expr(0)?; // Parse index
+                    self.expect(Token::RightBracket, "Expected ']' after array index")?;
+                    ExprKind::Index(lhs, index)
                 }
-                self.next();
-                
-                // Handle multiple consecutive string literals (C concatenation feature)
-                while let Token::Str(_idx) = self.token() {
-                    // Just consume these tokens, they were already concatenated by the lexer
+                Token::Dot | Token::Arrow => {
+                    let Some(name) = self.member_name(true) else {
+                        let spelling = if op == Token::Dot { "." } else { "->" };
+                        return Err(format!("Line {}: Expected a member name after '{}'", self.lexer.line(), spelling));
+                    };
                     self.next();
+                    ExprKind::Member(lhs, name, op == Token::Arrow)
                 }
-                
-                self.current_type = Type::Ptr(Box::new(Type::Char));
+                // Post-increment/decrement, the old value is the result
+                Token::Inc => ExprKind::PostInc(lhs),
+                Token::Dec => ExprKind::PostDec(lhs),
+                _ => {
+                    let Some((binary, compound)) = binary_operator(op) else {
+                        return Err(format!("Line {}: Unsupported operator", self.lexer.line()));
+                    };
+                    let rhs = self.expr(self.precedence_of(op))?;
+                    if compound {
+                        ExprKind::CompoundAssign(binary, lhs, rhs)
+                    } else {
+                        ExprKind::Binary(binary, lhs, rhs)
+                    }
+                }
+            };
+            lhs = self.ast.push_expr(Expr { kind, line: self.lexer.line() });
+        }
+        
+        Ok(lhs)
+    }
+    
+    /// the operand an expression starts with, with any prefix operators
+    fn primary(&mut self, precedence: u8) -> Result<ExprId, String> {
+        let kind = match self.token() {
+            Token::Num(val) => {
+                self.next();
+                ExprKind::Num(val)
             },
+            Token::Str(_) => return Ok(self.string_literal()),
             Token::Sizeof => {
                 self.next();
                 self.expect(Token::LeftParen, "Expected '(' after sizeof")?;
                 
                 // Parse the type
-                let mut typ = Type::Int;
-                if self.token() == Token::Int {
-                    self.next();
-                } else if self.token() == Token::Char {
-                    self.next();
-                    typ = Type::Char;
-                }
-                
-                // Handle pointer types
-                while self.token() == Token::Mul {
-                    self.next();
-                    typ = Type::Ptr(Box::new(typ));
-                }
+                let spec = if matches!(self.token(), Token::Int | Token::Char | Token::Struct) {
+                    self.type_spec()?
+                } else {
+                    TypeSpec::Int
+                };
+                let pointers = self.pointers();
                 
                 self.expect(Token::RightParen, "Expected ')' after type in sizeof")?;
-                
-                // Add debug check for sizeof output
-                if self.debug {
-                    println!("DEBUG PARSER: sizeof type {:?} resolved to size {}", typ, typ.size());
-                }
-                
-                // Push the size of the type
-                self.code.push(OpCode::IMM as i64);
-                self.code.push(typ.size() as i64);
-                self.current_type = Type::Int;
+                ExprKind::Sizeof(TypeName { spec, pointers })
             },
             Token::Id(id) => {
                 let name = self.get_id_name(id);
                 self.next();
                 
-                // Check for post-increment/decrement
-                let is_post_inc = self.token() == Token::Inc;
-                let is_post_dec = self.token() == Token::Dec;
-                
                 // Function call
                 if self.token() == Token::LeftParen {
-                    self.next(); // Skip '('
-                    
-                    // Push arguments to stack
-                    let mut arg_count = 0;
-                    if self.token() != Token::RightParen {
-                        // Parse argument expressions
-                        loop {
-                            self.expr(0)?; // Parse with lowest precedence
-                            self.code.push(OpCode::PSH as i64); // Push to stack
-                            arg_count += 1;
-                            
-                            if self.token() != Token::Comma {
-                                break;
-                            }
-                            self.next(); // Skip ','
-                        }
-                    }
-                    
-                    // More tolerant handling of missing closing parenthesis
-                    if self.token() == Token::RightParen {
-                        self.next(); // Skip ')'
-                    } else {
-                        // Special handling for complex expressions like printf with nested indexing
-                        // This is a workaround for the specific c4.c pattern with the string indexing in printf
-                        // This is a workaround for the specific c4.c pattern with the string indexing in printf
-                        if name == "printf" && arg_count > 0 {
-                            // In C4.c, there's a complex printf with string indexing at line 61
-                            // We'll tolerate this and assume the closing parenthesis is missing
-                            println!("Warning: Line {}: Missing ')' in printf call - auto-completing", self.lexer.line());
-                        } else {
-                            return Err(format!("Line {}: Expected ')' after function arguments", self.lexer.line()));
-                        }
-                    }
-                    
-                    // Find the function in symbol table - need to get necessary properties before code generation
-                    let sym_class;
-                    let sym_value;
-                    let sym_type;
-                    
-                    if let Some(symbol) = self.find_symbol(&name) {
-                        sym_class = symbol.class;
-                        sym_value = symbol.value;
-                        sym_type = symbol.typ.clone();
-                    } else {
-                        return Err(format!("Line {}: Unknown function '{}'", self.lexer.line(), name));
-                    }
-                    
-                    // Generate code based on the symbol properties we retrieved
-                    match sym_class {
-                        SymbolClass::Sys => {
-                            // System call
-                            self.code.push(sym_value); // Push system call ID
-                            
-                            // If this is printf, also push the argument count
-                            if name == "printf" {
-                                // Push argument count to code
-                                self.code.push(arg_count as i64);
-                                
-                                // For printf, we need to ensure the arguments are pushed correctly
-                                if self.debug {
-                                    println!("DEBUG: Generating printf with {} arguments", arg_count);
-                                }
-                            }
-                        },
-                        SymbolClass::Fun => {
-                            // User-defined function
-                            self.code.push(OpCode::JSR as i64);
-                            self.code.push(sym_value); // Push function address
-                        },
-                        _ => return Err(format!("Line {}: '{}' is not a function", self.lexer.line(), name)),
-                    }
-                    
-                    // Update current type
-                    self.current_type = sym_type;
-                    
-                    // Clean up stack if there were arguments
-                    if arg_count > 0 && name != "printf" { // Printf handles its own stack cleanup
-                        self.code.push(OpCode::ADJ as i64);
-                        self.code.push(arg_count as i64);
-                    }
-                } else {
-                    // Variable access - get properties before generating code
-                    if let Some(symbol) = self.find_symbol(&name) {
-                        let sym_class = symbol.class;
-                        let sym_value = symbol.value;
-                        let sym_type = symbol.typ.clone();
-                        
-                        // Check if this is an assignment
-                        let is_assignment = self.token() == Token::Assign;
-                        
-                        match sym_class {
-                            SymbolClass::Num => {
-                                // Numeric constant
-                                self.code.push(OpCode::IMM as i64);
-                                self.code.push(sym_value);
-                                self.current_type = Type::Int;
-                            },
-                            SymbolClass::Glo => {
-                                if is_assignment {
-                                    // Assignment to global variable
-                                    // Push address to store to
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    // Save the address for later
-                                    self.code.push(OpCode::PSH as i64);
-                                    
-                                    // Skip = token
-                                    self.next();
-                                    
-                                    // Evaluate the expression
-                                    self.expr(0)?;
-                                    
-                                    // Store the value
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::SC as i64);
-                                        println!("DEBUG PARSER: Generated SC (store char)");
-                                    } else {
-                                        self.code.push(OpCode::SI as i64);
-                                        println!("DEBUG PARSER: Generated SI (store int)");
-                                    }
-                                } else if is_post_inc || is_post_dec {
-                                    // Post-increment/decrement for global variable
-                                    // Push address for later use
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(sym_value);
-                                    self.code.push(OpCode::PSH as i64);
-                                    
-                                    // Duplicate address for loading original value
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    // Load original value
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::LC as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading char value with LC");
-                                        }
-                                    } else {
-                                        self.code.push(OpCode::LI as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading int value with LI");
-                                        }
-                                    }
-                                    
-                                    // Save original value (will be our result)
-                                    self.code.push(OpCode::PSH as i64);
-                                    
-                                    // Now load it again for modification
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::LC as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading char value with LC");
-                                        }
-                                    } else {
-                                        self.code.push(OpCode::LI as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading int value with LI");
-                                        }
-                                    }
-                                    
-                                    // Add/subtract 1 (or type size for pointers)
-                                    self.code.push(OpCode::PSH as i64);
-                                    self.code.push(OpCode::IMM as i64);
-                                    
-                                    // Determine increment size
-                                    if sym_type.is_ptr() {
-                                        if let Some(base_type) = sym_type.base_type() {
-                                            self.code.push(base_type.size() as i64);
-                                        } else {
-                                            return Err(format!("Line {}: Invalid pointer type", self.lexer.line()));
-                                        }
-                                    } else {
-                                        self.code.push(1); // Regular int increment
-                                    }
-                                    
-                                    // Add or subtract based on operator
-                                    if is_post_inc {
-                                        self.code.push(OpCode::ADD as i64);
-                                        self.next(); // Consume the Inc token
-                                    } else {
-                                        self.code.push(OpCode::SUB as i64);
-                                        self.next(); // Consume the Dec token
-                                    }
-                                    
-                                    // Store back the modified value
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::SC as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Generated SC for global post-inc/dec");
-                                        }
-                                    } else {
-                                        self.code.push(OpCode::SI as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Generated SI for global post-inc/dec");
-                                        }
-                                    }
-                                    
-                                    // Original value is on stack - pop it as our result
-                                    self.code.push(OpCode::PSH as i64);
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(0); // Add 0 to restore original
-                                    self.code.push(OpCode::ADD as i64);
-                                } else {
-                                    // Global variable access - push address
-                                    self.code.push(OpCode::IMM as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    // Based on type, load value
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::LC as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading char value with LC");
-                                        }
-                                    } else {
-                                        self.code.push(OpCode::LI as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Loading int value with LI");
-                                        }
-                                    }
-                                }
-                                self.current_type = sym_type;
-                                
-                                // Debug after loading a variable
-                                println!("DEBUG: After variable load, next token is: {:?}", self.token());
-                            },
-                            SymbolClass::Loc => {
-                                if is_assignment {
-                                    // Assignment to local variable
-                                    // Generate LEA to get the address
-                                    self.code.push(OpCode::LEA as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    // Save the address for later
-                                    self.code.push(OpCode::PSH as i64);
-                                    
-                                    // Skip = token
-                                    self.next();
-                                    
-                                    // Evaluate the expression
-                                    self.expr(0)?;
-                                    
-                                    if self.debug {
-                                        println!("DEBUG PARSER: Generating store for assignment to local '{}' with value in AX", name);
-                                    }
-                                    
-                                    // Store the value
-                                    if sym_type == Type::Char {
-                                        self.code.push(OpCode::SC as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Generated SC (store char)");
-                                        }
-                                    } else {
-                                        self.code.push(OpCode::SI as i64);
-                                        if self.debug {
-                                            println!("DEBUG PARSER: Generated SI (store int)");
-                                        }
-                                    }
-                                } else {
-                                    // Local variable - calculate address from bp
-                                    self.code.push(OpCode::LEA as i64);
-                                    self.code.push(sym_value);
-                                    
-                                    // Debug output for locals
-                                    if self.debug {
-                                        println!("DEBUG PARSER: Local variable '{}' at offset {}, generating LEA {}", 
-                                                name, sym_value, sym_value);
-                                    }
-                                    
-                                    // If we have post-increment/decrement coming up, we'll need to:
-                                    // 1. Push the address for later use
-                                    // 2. Load the value
-                                    // 3. Push the original value (for the result)
-                                    // 4. Handle post-increment/decrement logic
-                                    if is_post_inc || is_post_dec {
-                                        // Save address for later use
-                                        self.code.push(OpCode::PSH as i64);
-                                        
-                                        // Duplicate address for loading original value
-                                        self.code.push(OpCode::LEA as i64);
-                                        self.code.push(sym_value);
-                                        
-                                        // Load original value
-                                        if sym_type == Type::Char {
-                                            self.code.push(OpCode::LC as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading char value with LC");
-                                            }
-                                        } else {
-                                            self.code.push(OpCode::LI as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading int value with LI");
-                                            }
-                                        }
-                                        
-                                        // Save original value (will be our result)
-                                        self.code.push(OpCode::PSH as i64);
-                                        
-                                        // Now work with the saved address
-                                        self.code.push(OpCode::LEA as i64);
-                                        self.code.push(sym_value);
-                                        
-                                        // Load it again for modification
-                                        if sym_type == Type::Char {
-                                            self.code.push(OpCode::LC as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading char value with LC");
-                                            }
-                                        } else {
-                                            self.code.push(OpCode::LI as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading int value with LI");
-                                            }
-                                        }
-                                        
-                                        // Add/subtract 1 (or type size for pointers)
-                                        self.code.push(OpCode::PSH as i64);
-                                        self.code.push(OpCode::IMM as i64);
-                                        
-                                        // Determine increment size
-                                        if sym_type.is_ptr() {
-                                            if let Some(base_type) = sym_type.base_type() {
-                                                self.code.push(base_type.size() as i64);
-                                            } else {
-                                                return Err(format!("Line {}: Invalid pointer type", self.lexer.line()));
-                                            }
-                                        } else {
-                                            self.code.push(1); // Regular int increment
-                                        }
-                                        
-                                        // Add or subtract based on operator
-                                        if is_post_inc {
-                                            self.code.push(OpCode::ADD as i64);
-                                            self.next(); // Consume the Inc token
-                                        } else {
-                                            self.code.push(OpCode::SUB as i64);
-                                            self.next(); // Consume the Dec token
-                                        }
-                                        
-                                        // Store back the modified value
-                                        if sym_type == Type::Char {
-                                            self.code.push(OpCode::SC as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Generated SC for local post-inc/dec");
-                                            }
-                                        } else {
-                                            self.code.push(OpCode::SI as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Generated SI for local post-inc/dec");
-                                            }
-                                        }
-                                        
-                                        // Original value is on stack - pop it as our result
-                                        self.code.push(OpCode::PSH as i64);
-                                        self.code.push(OpCode::IMM as i64);
-                                        self.code.push(0); // Add 0 to restore original
-                                        self.code.push(OpCode::ADD as i64);
-                                    } else {
-                                        // Regular variable access (no post-increment/decrement)
-                                        // Load value
-                                        if sym_type == Type::Char {
-                                            self.code.push(OpCode::LC as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading char value with LC");
-                                            }
-                                        } else {
-                                            self.code.push(OpCode::LI as i64);
-                                            if self.debug {
-                                                println!("DEBUG PARSER: Loading int value with LI");
-                                            }
-                                        }
-                                    }
-                                }
-                                self.current_type = sym_type;
-                            },
-                            _ => return Err(format!("Line {}: Invalid variable '{}'", self.lexer.line(), name)),
-                        }
-                    } else {
-                        return Err(format!("Line {}: Unknown variable '{}'", self.lexer.line(), name));
-                    }
-                }
-            },
-            Token::Mul => {
-                // Dereferencing operator
-                self.next();
-                self.expr(11)?; // 11 is the precedence of Inc/Dec
-                
-                // Check if we're dereferencing a pointer
-                if let Type::Ptr(base_type) = &self.current_type {
-                    self.current_type = (**base_type).clone();
-                } else {
-                    return Err(format!("Line {}: Cannot dereference a non-pointer type ({:?})", 
-                                          self.lexer.line(), self.current_type));
+                    return self.call(name);
                 }
                 
-                // Generate code to load the value at the address
-                if self.current_type == Type::Char {
-                    self.code.push(OpCode::LC as i64);
-                    if self.debug {
-                        println!("DEBUG PARSER: Generated LC for dereference");
-                    }
+                // Check if this is an assignment, not the operand of a tighter operator like `*p = ...`
+                let var = self.ast.push_expr(Expr { kind: ExprKind::Var(name), line: self.lexer.line() });
+                if self.token() == Token::Assign && precedence < self.precedence_of(Token::Assign) {
+                    self.next(); // Skip =
+                    let value = self.expr(0)?;
+                    ExprKind::Assign(var, value)
                 } else {
-                    self.code.push(OpCode::LI as i64);
-                    if self.debug {
-                        println!("DEBUG PARSER: Generated LI for dereference");
-                    }
+                    return Ok(var);
                 }
             },
-            Token::And => {
-                // Address-of operator
-                self.next();
-                
-                // First, mark the current position in the code
-                let code_pos_before = self.code.len();
-                
-                // Evaluate the expression 
-                self.expr(11)?; // 11 is the precedence of Inc/Dec
-                
-                // Look at the code we just generated
-                let code_len = self.code.len();
-                
-                // Check if we're taking address of a string literal
-                // In C, we can take address of a string literal directly since it's already a pointer
-                if let Token::Str(_) = self.token() {
-                    if self.debug {
-                        println!("DEBUG PARSER: Taking address of string literal (already an address)");
-                    }
-                    // String literal is already an address, just keep the IMM value
-                    self.current_type = Type::Ptr(Box::new(Type::Char));
-                    return Ok(());
-                }
-                
-                // Check what was generated
-                if code_len > code_pos_before {
-                    let last_instr = self.code[code_len - 1] as usize;
-                    
-                    if last_instr == OpCode::LC as usize || last_instr == OpCode::LI as usize {
-                        // Great, it's a load instruction - we can replace it with just the address
-                        self.code.pop();
-                        
-                        if self.debug {
-                            println!("DEBUG PARSER: Address-of removed load instruction ({:?})", 
-                                     if last_instr == OpCode::LC as usize { "LC" } else { "LI" });
-                        }
-                        
-                        // The expression must have resulted in a memory access
-                        // Transform the type of the expression to a pointer to its current type
-                        self.current_type = Type::Ptr(Box::new(self.current_type.clone()));
-                    } else if code_len > code_pos_before + 1 {
-                        // Special case for string literals and array accesses
-                        // Check if we have something like IMM addr or an array indexing operation
-                        let next_to_last = self.code[code_len - 2] as usize;
-                        let value_if_imm = self.code[code_len - 1]; // The potential address
-                        
-                        if next_to_last == OpCode::IMM as usize {
-                            if self.debug {
-                                println!("DEBUG PARSER: Address-of found IMM {}", value_if_imm);
-                            }
-                            // This might be a string literal or a global address
-                            // We'll allow taking the address of these
-                            self.current_type = Type::Ptr(Box::new(self.current_type.clone()));
-                        } else if next_to_last == OpCode::LEA as usize {
-                            if self.debug {
-                                println!("DEBUG PARSER: Address-of found LEA {}", value_if_imm);
-                            }
-                            // This is address of local var, it's already an address
-                            self.current_type = Type::Ptr(Box::new(self.current_type.clone()));
-                        } else {
-                            // For now, report an error if it's not a recognized addressable entity
-                            return Err(format!("Line {}: Invalid address-of operation - can only take address of variables", self.lexer.line()));
-                        }
-                    } else {
-                        // For now, report an error if it's not a recognized addressable entity
-                        return Err(format!("Line {}: Invalid address-of operation - can only take address of variables", self.lexer.line()));
-                    }
-                } else {
-                    return Err(format!("Line {}: Invalid address-of operation - empty expression", self.lexer.line()));
-                }
-            },
-            Token::Not => {
-                // Logical NOT operator
-                self.next();
-                self.expr(11)?;
-                self.code.push(OpCode::PSH as i64);
-                self.code.push(OpCode::IMM as i64);
-                self.code.push(0);  // Push 0 for comparison
-                self.code.push(OpCode::EQ as i64);  // Test if expression == 0
-                self.current_type = Type::Int;
-            },
-            Token::Tilde => {
-                // Bitwise NOT operator
-                self.next();
-                self.expr(11)?;
-                self.code.push(OpCode::PSH as i64);
-                self.code.push(OpCode::IMM as i64);
-                self.code.push(-1);  // Push -1 for XOR
-                self.code.push(OpCode::XOR as i64);  // Bitwise NOT
-                self.current_type = Type::Int;
-            },
-            Token::Add => {
-                // Unary plus - doesn't change value
+            Token::Mul | Token::And | Token::Not | Token::Tilde | Token::Add | Token::Inc | Token::Dec => {
+                let op = match self.token() {
+                    Token::Mul => UnaryOp::Deref,
+                    Token::And => UnaryOp::Addr,
+                    Token::Not => UnaryOp::Not,
+                    Token::Tilde => UnaryOp::BitNot,
+                    Token::Add => UnaryOp::Plus,
+                    Token::Inc => UnaryOp::PreInc,
+                    _ => UnaryOp::PreDec,
+                };
                 self.next();
-                self.expr(11)?;
-                // Type remains the same
+                // only postfix operators bind tighter than a unary one
+                ExprKind::Unary(op, self.expr(12)?)
             },
             Token::Sub => {
-                // Unary minus - negate value
+                // Unary minus, a constant is negated right away
                 self.next();
-                
-                // Check if we have a constant number
-                if self.token() == Token::Num(self.lexer.value()) {
-                    // Negate the constant
-                    let val = -self.lexer.value();
-                    self.code.push(OpCode::IMM as i64);
-                    self.code.push(val);
+                let operand = if let Token::Num(val) = self.token() {
                     self.next();
+                    self.ast.push_expr(Expr { kind: ExprKind::Num(val), line: self.lexer.line() })
                 } else {
-                    // Generate code for -expr
-                    self.code.push(OpCode::IMM as i64);
-                    self.code.push(-1);
-                    self.code.push(OpCode::PSH as i64);
-                    self.expr(11)?;
-                    self.code.push(OpCode::MUL as i64);
-                }
-                self.current_type = Type::Int;
-            },
-            Token::Inc | Token::Dec => {
-                // Pre-increment/decrement
-                let op = self.token();
-                self.next();
-                self.expr(11)?;
-                
-                // Ensure we have a valid LValue
-                let code_len = self.code.len();
-                if code_len >= 1 {
-                    let last_instr = self.code[code_len - 1] as usize;
-                    if last_instr == OpCode::LC as usize || last_instr == OpCode::LI as usize {
-                        // Replace last instruction with a push of the address
-                        self.code[code_len - 1] = OpCode::PSH as i64;
-                        
-                        // Re-load the value after we push the address
-                        if last_instr == OpCode::LC as usize {
-                            self.code.push(OpCode::LC as i64);
-                        } else {
-                            self.code.push(OpCode::LI as i64);
-                        }
-                        
-                        // Push the value
-                        self.code.push(OpCode::PSH as i64);
-                        
-                        // Push the increment size
-                        self.code.push(OpCode::IMM as i64);
-                        if self.current_type.is_ptr() {
-                            // For pointers, increment by the size of the base type
-                            if let Some(base_type) = self.current_type.base_type() {
-                                self.code.push(base_type.size() as i64);
-                            } else {
-                                return Err(format!("Line {}: Invalid pointer type", self.lexer.line()));
-                            }
-                        } else {
-                            // For scalars, increment by 1
-                            self.code.push(1);
-                        }
-                        
-                        // Add or subtract
-                        if op == Token::Inc {
-                            self.code.push(OpCode::ADD as i64);
-                        } else {
-                            self.code.push(OpCode::SUB as i64);
-                        }
-                        
-                        // Store back
-                        if last_instr == OpCode::LC as usize {
-                            self.code.push(OpCode::SC as i64);
-                        } else {
-                            self.code.push(OpCode::SI as i64);
-                        }
-                    } else {
-                        return Err(format!("Line {}: Invalid LValue in pre-increment/decrement", self.lexer.line()));
-                    }
-                } else {
-                    return Err(format!("Line {}: Invalid LValue in pre-increment/decrement", self.lexer.line()));
-                }
+                    self.expr(12)?
+                };
+                ExprKind::Unary(UnaryOp::Neg, operand)
             },
             Token::LeftParen => {
-                self.next();
-                
-                // Type casting
-                if self.token() == Token::Int || self.token() == Token::Char {
-                    let mut typ = if self.token() == Token::Int { 
-                        Type::Int 
-                    } else { 
-                        Type::Char 
-                    };
+                // A type name after '(' makes it a cast, anything else is a parenthesized expression
+                if matches!(self.lexer.peek_token(), Token::Int | Token::Char | Token::Void | Token::Struct) {
                     self.next();
                     
-                    // Handle pointer types
-                    while self.token() == Token::Mul {
-                        self.next();
-                        typ = Type::Ptr(Box::new(typ));
-                    }
-                    
+                    // void is int here, as in declarations
+                    let spec = self.type_spec()?;
+                    let pointers = self.pointers();
                     self.expect(Token::RightParen, "Expected ')' after type cast")?;
                     
-                    // Parse the expression being cast
-                    self.expr(11)?;
-                    
-                    // Update the current type to the cast type
-                    self.current_type = typ;
+                    // Parse the expression being cast, postfix operators bind tighter than the cast
+                    let operand = self.expr(12)?;
+                    ExprKind::Cast(TypeName { spec, pointers }, operand)
                 } else {
+                    self.next();
                     // Regular parenthesized expression
-                    self.expr(0)?;
+                    let inner = self.expr(0)?;
                     self.expect(Token::RightParen, "Expected ')' after expression")?;
-                }
-            },
-            Token::Lt => {
-                println!("DEBUG: Checking Lt token for bit shift or comparison");
-                let next_char = self.lexer.peek_next();
-                if next_char == Some('<') {
-                    // This is a left shift operator
-                    match self.handle_bitwise_operators() {
-                        Ok(()) => {}, // Successfully handled 
-                        Err(_) => {
-                            // Regular less than operator
-                            self.next();
-                            self.code.push(OpCode::PSH as i64);
-                            self.expr(self.precedence_of(Token::Lt))?;
-                            self.code.push(OpCode::LT as i64);
-                            self.current_type = Type::Int;
-                        }
-                    }
-                } else {
-                    // Regular less than operator
-                    self.next();
-                    self.code.push(OpCode::PSH as i64);
-                    self.expr(self.precedence_of(Token::Lt))?;
-                    self.code.push(OpCode::LT as i64);
-                    self.current_type = Type::Int;
-                }
-            },
-            Token::Gt => {
-                println!("DEBUG: Checking Gt token for bit shift or comparison");
-                let next_char = self.lexer.peek_next();
-                if next_char == Some('>') {
-                    // This is a right shift operator
-                    match self.handle_bitwise_operators() {
-                        Ok(()) => {}, // Successfully handled
-                        Err(_) => {
-                            // Regular greater than operator
-                            self.next();
-                            self.code.push(OpCode::PSH as i64);
-                            self.expr(self.precedence_of(Token::Gt))?;
-                            self.code.push(OpCode::GT as i64);
-                            self.current_type = Type::Int;
-                        }
-                    }
-                } else {
-                    // Regular greater than operator
-                    self.next();
-                    self.code.push(OpCode::PSH as i64);
-                    self.expr(self.precedence_of(Token::Gt))?;
-                    self.code.push(OpCode::GT as i64);
-                    self.current_type = Type::Int;
+                    ExprKind::Paren(inner)
                 }
             },
             _ => {
-                println!("DEBUG: Unknown token in expr: {:?}", self.token());
+                if self.debug {
+                    println!("DEBUG: Unknown token in expr: {:?}", self.token());
+                }
                 return Err(format!("Line {}: Expected expression", self.lexer.line()));
             },
-        }
+        };
+        Ok(self.ast.push_expr(Expr { kind, line: self.lexer.line() }))
+    }
+    
+    /// the arguments of a call, from the '(' after the function's name
+    fn call(&mut self, name: String) -> Result<ExprId, String> {
+        self.next(); // Skip '('
         
-        // Handle operators with precedence climbing
-        while self.precedence_of(self.token()) > precedence {
-            let op = self.token();
-            let op_type = self.current_type.clone(); // Save the LHS type for pointer arithmetic
-            if self.debug {
-                println!("DEBUG PARSER: Found operator {:?} with precedence {}", op, self.precedence_of(op));
-            }
-            self.next();
-            
-            // Handle assignment specially
-            if op == Token::Assign {
-                if self.debug {
-                    println!("DEBUG PARSER: Handling assignment operator");
-                }
-                // For assignment, we need the LHS to be a loadable location
-                // Check if the last generated code is appropriate
-                if self.code.len() >= 1 {
-                    let len = self.code.len();
-                    let last_code = self.code[len-1] as usize;
-                    
-                    if self.debug {
-                        println!("DEBUG PARSER: Checking assignment - last opcode: {:?}", last_code);
-                    }
-                    // If the last code is a load instruction (LI or LC), 
-                    // pop it off and push a store instead after evaluating the RHS
-                    if last_code == OpCode::LI as usize || last_code == OpCode::LC as usize {
-                        // Remove the load instruction
-                        self.code.pop();
-                        
-                        if self.debug {
-                            println!("DEBUG PARSER: Assignment detected, removed load instruction ({:?})",
-                                     if last_code == OpCode::LC as usize { "LC" } else { "LI" });
-                        }
-                        
-                        // Evaluate the right side of the assignment
-                        self.expr(0)?;
-                        
-                        if self.debug {
-                            println!("DEBUG PARSER: Finished RHS evaluation, generating store");
-                        }
-                        
-                        // Generate a store instruction
-                        if last_code == OpCode::LC as usize {
-                            self.code.push(OpCode::SC as i64);
-                            println!("DEBUG PARSER: Generated SC for char store");
-                        } else {
-                            self.code.push(OpCode::SI as i64);
-                            println!("DEBUG PARSER: Generated SI for int store");
-                        }
-                        continue;
-                    }
-                    // Array indexing case - the last instruction(s) should have calculated an address
-                    // but we didn't load from it yet because we saw the assignment coming
-                    else if last_code == OpCode::ADD as usize || last_code == OpCode::MUL as usize {
-                        if self.debug {
-                            println!("DEBUG PARSER: Assignment to array element detected");
-                        }
-                        
-                        // Push the calculated address on the stack
-                        self.code.push(OpCode::PSH as i64);
-                        
-                        // Evaluate the right hand side
-                        self.expr(0)?;
-                        
-                        // Store to the calculated address
-                        if op_type == Type::Char {
-                            self.code.push(OpCode::SC as i64);
-                            if self.debug {
-                                println!("DEBUG PARSER: Generated SC for char array element");
-                            }
-                        } else {
-                            self.code.push(OpCode::SI as i64);
-                            if self.debug {
-                                println!("DEBUG PARSER: Generated SI for int array element");
-                            }
-                        }
-                        continue;
-                    }
-                }
-                
-                return Err(format!("Line {}: bad lvalue in assignment", self.lexer.line()));
-            } else if op == Token::AddAssign || op == Token::SubAssign || op == Token::MulAssign || 
-                      op == Token::DivAssign || op == Token::ModAssign || op == Token::ShlAssign || 
-                      op == Token::ShrAssign || op == Token::AndAssign || op == Token::XorAssign || 
-                      op == Token::OrAssign {
-                // For compound assignments like a += b, convert to a = a + b
-                if self.debug {
-                    println!("DEBUG: Converting compound assignment to normal assignment");
-                }
-                
-                // Get the code to load the LHS variable (without the actual load instruction)
-                if self.code.len() < 2 {
-                    return Err(format!("Line {}: bad lvalue in compound assignment", self.lexer.line()));
-                }
-                
-                // Remove the load instruction (it's the last instruction)
-                let load_type = self.code.pop().unwrap() as usize;
-                if load_type != OpCode::LI as usize && load_type != OpCode::LC as usize {
-                    return Err(format!("Line {}: expected load instruction in compound assignment", self.lexer.line()));
-                }
-                
-                // Save the variable address code (e.g., LEA n or IMM n)
-                let variable_code = self.code.clone();
-                
-                // 1. First, get the current value of the variable
-                self.code.extend_from_slice(&variable_code);
-                if load_type == OpCode::LC as usize {
-                    self.code.push(OpCode::LC as i64);
-                } else {
-                    self.code.push(OpCode::LI as i64);
-                }
-                
-                // 2. Push the current value for the binary operation
-                self.code.push(OpCode::PSH as i64);
-                
-                // 3. Parse the right side of the assignment
-                self.expr(self.precedence_of(op))?;
-                
-                // 4. Generate the appropriate operation
-                match op {
-                    Token::AddAssign => self.code.push(OpCode::ADD as i64),
-                    Token::SubAssign => self.code.push(OpCode::SUB as i64),
-                    Token::MulAssign => self.code.push(OpCode::MUL as i64),
-                    Token::DivAssign => self.code.push(OpCode::DIV as i64),
-                    Token::ModAssign => self.code.push(OpCode::MOD as i64),
-                    Token::ShlAssign => self.code.push(OpCode::SHL as i64),
-                    Token::ShrAssign => self.code.push(OpCode::SHR as i64),
-                    Token::AndAssign => self.code.push(OpCode::AND as i64),
-                    Token::XorAssign => self.code.push(OpCode::XOR as i64),
-                    Token::OrAssign => self.code.push(OpCode::OR as i64),
-                    _ => unreachable!(),
-                }
-                
-                // 5. Generate address again and store the result
-                self.code.push(OpCode::PSH as i64);
-                self.code.extend_from_slice(&variable_code);
-                
-                // 6. Store the result back to the variable
-                if load_type == OpCode::LC as usize {
-                    self.code.push(OpCode::SC as i64);
-                } else {
-                    self.code.push(OpCode::SI as i64);
-                }
-            } else {
-                // For other operators, parse the right side of the expression
-                self.code.push(OpCode::PSH as i64); // Push LHS
-                
-                // Special handling for binary operators with pointers
-                match op {
-                    Token::Add => {
-                        if self.debug {
-                            println!("DEBUG: Handling ADD operator");
-                        }
-                        self.expr(self.precedence_of(op))?;
-                        
-                        // If LHS is a pointer, adjust RHS by pointer's base size
-                        if op_type.is_ptr() {
-                            self.code.push(OpCode::PSH as i64);
-                            self.code.push(OpCode::IMM as i64);
-                            
-                            if let Some(base_type) = op_type.base_type() {
-                                self.code.push(base_type.size() as i64);
-                            } else {
-                                return Err(format!("Line {}: Invalid pointer type in addition", self.lexer.line()));
-                            }
-                            
-                            self.code.push(OpCode::MUL as i64);
-                        }
-                        
-                        self.code.push(OpCode::ADD as i64);
-                        self.current_type = op_type; // Result has the type of LHS
-                    },
-                    Token::Sub => {
-                        if self.debug {
-                            println!("DEBUG: Handling SUB operator");
-                        }
-                        self.expr(self.precedence_of(op))?;
-                        
-                        // Three cases:
-                        // 1. ptr - ptr: results in how many elements between them (int)
-                        // 2. ptr - int: adjusted by element size
-                        // 3. int - int: regular subtraction
-
-                        if op_type.is_ptr() && self.current_type.is_ptr() {
-                            // Case 1: ptr - ptr
-                            let base_size = match op_type.base_type() {
-                                Some(base) => base.size() as i64,
-                                None => return Err(format!("Line {}: Invalid pointer type in subtraction", self.lexer.line())),
-                            };
-                            
-                            // Subtract pointers, then divide by element size to get element count
-                            self.code.push(OpCode::SUB as i64);
-                            self.code.push(OpCode::PSH as i64);
-                            self.code.push(OpCode::IMM as i64);
-                            self.code.push(base_size);
-                            self.code.push(OpCode::DIV as i64);
-                            self.current_type = Type::Int; // Result is an integer
-                        } else if op_type.is_ptr() {
-                            // Case 2: ptr - int
-                            self.code.push(OpCode::PSH as i64);
-                            self.code.push(OpCode::IMM as i64);
-                            
-                            if let Some(base_type) = op_type.base_type() {
-                                self.code.push(base_type.size() as i64);
-                            } else {
-                                return Err(format!("Line {}: Invalid pointer type in subtraction", self.lexer.line()));
-                            }
-                            
-                            self.code.push(OpCode::MUL as i64);
-                            self.code.push(OpCode::SUB as i64);
-                            self.current_type = op_type; // Result has the type of LHS
-                        } else {
-                            // Case 3: int - int
-                            self.code.push(OpCode::SUB as i64);
-                            self.current_type = Type::Int;
-                        }
-                    },
-                    // Handle array indexing
-                    Token::LeftBracket => {
-                        if self.debug {
-                            println!("DEBUG PARSER: Handling array indexing with token LeftBracket");
-                            println!("DEBUG PARSER: Current type: {:?}, is_array: {}", op_type, op_type.is_array());
-                        }
-                        self.expr(0)?; // Parse index
-                        self.expect(Token::RightBracket, "Expected ']' after array index")?;
-                        
-                        // Make sure LHS is a pointer or array type
-                        if !op_type.is_ptr() && !op_type.is_array() {
-                            return Err(format!("Line {}: Array indexing requires a pointer or array type", self.lexer.line()));
-                        }
-                        
-                        // Scale the index by the size of the base type
-                        self.code.push(OpCode::PSH as i64);
-                        self.code.push(OpCode::IMM as i64);
-                        
-                        if let Some(base_type) = op_type.base_type() {
-                            self.code.push(base_type.size() as i64);
-                            
-                            // After scaling, add to base address
-                            self.code.push(OpCode::MUL as i64);
-                            self.code.push(OpCode::ADD as i64);
-                            
-                            // Update current type to the element type
-                            self.current_type = (*base_type).clone();
-                            
-                            // Check if this is part of an assignment (don't load)
-                            if self.token() != Token::Assign && 
-                               self.token() != Token::AddAssign && 
-                               self.token() != Token::SubAssign && 
-                               self.token() != Token::MulAssign && 
-                               self.token() != Token::DivAssign && 
-                               self.token() != Token::ModAssign {
-                                // Load the value at the calculated address
-                                if self.current_type == Type::Char {
-                                    self.code.push(OpCode::LC as i64);
-                                } else {
-                                    self.code.push(OpCode::LI as i64);
-                                }
-                            }
-                        } else {
-                            return Err(format!("Line {}: Invalid pointer type in array indexing", self.lexer.line()));
-                        }
-                    },
-                    // For other operators, use standard code generation
-                    Token::Mul => { 
-                        if self.debug {
-                            println!("DEBUG: Handling MUL operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::MUL as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Div => { 
-                        if self.debug {
-                            println!("DEBUG: Handling DIV operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::DIV as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Mod => { 
-                        if self.debug {
-                            println!("DEBUG: Handling MOD operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::MOD as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Eq => { 
-                        if self.debug {
-                            println!("DEBUG: Handling EQ operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::EQ as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Ne => { 
-                        if self.debug {
-                            println!("DEBUG: Handling NE operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::NE as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Le => { 
-                        if self.debug {
-                            println!("DEBUG: Handling LE operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::LE as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Ge => { 
-                        if self.debug {
-                            println!("DEBUG: Handling GE operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::GE as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::And => { self.expr(self.precedence_of(op))?; self.code.push(OpCode::AND as i64); self.current_type = Type::Int; },
-                    Token::Or => { self.expr(self.precedence_of(op))?; self.code.push(OpCode::OR as i64); self.current_type = Type::Int; },
-                    Token::Xor => { self.expr(self.precedence_of(op))?; self.code.push(OpCode::XOR as i64); self.current_type = Type::Int; },
-                    Token::Shl => { self.expr(self.precedence_of(op))?; self.code.push(OpCode::SHL as i64); self.current_type = Type::Int; },
-                    Token::Shr => { self.expr(self.precedence_of(op))?; self.code.push(OpCode::SHR as i64); self.current_type = Type::Int; },
-                    Token::Lt => { 
-                        if self.debug {
-                            println!("DEBUG: Handling LT binary operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::LT as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Gt => { 
-                        if self.debug {
-                            println!("DEBUG: Handling GT binary operator");
-                        }
-                        self.expr(self.precedence_of(op))?; 
-                        self.code.push(OpCode::GT as i64); 
-                        self.current_type = Type::Int; 
-                    },
-                    Token::Inc | Token::Dec => {
-                        // Post-increment/decrement
-                        // Need to handle similarly to pre-increment/decrement
-                        // But value before incrementing is used
-                        let code_len = self.code.len();
-                        if code_len >= 1 {
-                            let last_instr = self.code[code_len - 1] as usize;
-                            if last_instr == OpCode::LC as usize || last_instr == OpCode::LI as usize {
-                                // Replace load with push of the address
-                                self.code[code_len - 1] = OpCode::PSH as i64;
-                                
-                                // Re-load the value
-                                if last_instr == OpCode::LC as usize {
-                                    self.code.push(OpCode::LC as i64);
-                                } else {
-                                    self.code.push(OpCode::LI as i64);
-                                }
-                                
-                                // Save original value to stack
-                                self.code.push(OpCode::PSH as i64);
-                                
-                                // Duplicate the address for later use
-                                self.code.push(OpCode::PSH as i64);
-                                self.code.push(OpCode::IMM as i64);
-                                
-                                // Determine increment size
-                                if op_type.is_ptr() {
-                                    if let Some(base_type) = op_type.base_type() {
-                                        self.code.push(base_type.size() as i64);
-                                    } else {
-                                        return Err(format!("Line {}: Invalid pointer type", self.lexer.line()));
-                                    }
-                                } else {
-                                    self.code.push(1);
-                                }
-                                
-                                // Add or subtract
-                                if op == Token::Inc {
-                                    self.code.push(OpCode::ADD as i64);
-                                } else {
-                                    self.code.push(OpCode::SUB as i64);
-                                }
-                                
-                                // Store the incremented value
-                                if last_instr == OpCode::LC as usize {
-                                    self.code.push(OpCode::SC as i64);
-                                } else {
-                                    self.code.push(OpCode::SI as i64);
-                                }
-                                
-                                // Original value is still on the stack
-                                self.code.push(OpCode::PSH as i64);
-                                self.code.push(OpCode::IMM as i64);
-                                
-                                // For subtracting from the original value to get the original back (if needed)
-                                if op_type.is_ptr() {
-                                    if let Some(base_type) = op_type.base_type() {
-                                        self.code.push(base_type.size() as i64);
-                                    } else {
-                                        return Err(format!("Line {}: Invalid pointer type", self.lexer.line()));
-                                    }
-                                } else {
-                                    self.code.push(1);
-                                }
-                                
-                                // Undo the increment/decrement for the returned value
-                                if op == Token::Inc {
-                                    self.code.push(OpCode::SUB as i64);
-                                } else {
-                                    self.code.push(OpCode::ADD as i64);
-                                }
-                                
-                                // Current type remains unchanged
-                            } else {
-                                return Err(format!("Line {}: Invalid LValue in post-increment/decrement", self.lexer.line()));
-                            }
-                        } else {
-                            return Err(format!("Line {}: Invalid LValue in post-increment/decrement", self.lexer.line()));
-                        }
-                    },
-                    _ => {
-                        println!("DEBUG: Unhandled binary operator: {:?}", op);
-                        return Err(format!("Line {}: Unsupported operator", self.lexer.line()));
-                    }
+        // Parse argument expressions
+        let mut args = Vec::new();
+        if self.token() != Token::RightParen {
+            loop {
+                args.push(self.expr(0)?); // Parse with lowest precedence
+                if self.token() != Token::Comma {
+                    break;
                 }
+                self.next(); // Skip ','
             }
         }
         
-        Ok(())
+        // More tolerant handling of missing closing parenthesis
+        if self.token() == Token::RightParen {
+            self.next(); // Skip ')'
+        } else if name == "printf" && !args.is_empty() {
+            // In C4.c, there's a complex printf with string indexing at line 61
+            // We'll tolerate this and assume the closing parenthesis is missing
+            println!("Warning: Line {}: Missing ')' in printf call - auto-completing", self.lexer.line());
+        } else {
+            return Err(format!("Line {}: Expected ')' after function arguments", self.lexer.line()));
+        }
+        
+        Ok(self.ast.push_expr(Expr { kind: ExprKind::Call(name, args), line: self.lexer.line() }))
     }
     
     /// Helper function to get operator precedence
@@ -2007,24 +1263,36 @@ impl<'a> Parser<'a> {
             Token::Assign | Token::AddAssign | Token::SubAssign | Token::MulAssign | 
             Token::DivAssign | Token::ModAssign | Token::ShlAssign | 
             Token::ShrAssign | Token::AndAssign | Token::XorAssign | Token::OrAssign => 1,
-            Token::Lor => 2,
-            Token::Lan => 3,
-            Token::Or => 4,
-            Token::Xor => 5,
-            Token::And => 6,
-            Token::Eq | Token::Ne => 7,
-            Token::Lt | Token::Gt | Token::Le | Token::Ge => 8,
-            Token::Shl | Token::Shr => 9,
-            Token::Add | Token::Sub => 10,
-            Token::Mul | Token::Div | Token::Mod => 11,
-            Token::LeftBracket => 12,  // Array indexing has higher precedence
+            Token::Cond => 2,
+            Token::Lor => 3,
+            Token::Lan => 4,
+            Token::Or => 5,
+            Token::Xor => 6,
+            Token::And => 7,
+            Token::Eq | Token::Ne => 8,
+            Token::Lt | Token::Gt | Token::Le | Token::Ge => 9,
+            Token::Shl | Token::Shr => 10,
+            Token::Add | Token::Sub => 11,
+            Token::Mul | Token::Div | Token::Mod => 12,
+            Token::LeftBracket => 13,  // Array indexing has higher precedence
+            Token::Inc | Token::Dec => 13, // Postfix, binds like indexing
+            Token::Dot | Token::Arrow => 13, // Member access, also postfix
             _ => 0,
         }
     }
     
     /// parse a statement
-    fn stmt(&mut self) -> Result<(), String> {
-        match self.token() {
+    fn stmt(&mut self) -> Result<StmtId, String> {
+        self.enter_nesting()?;
+        let line = self.lexer.line();
+        let result = self.parse_stmt().map(|kind| self.ast.push_stmt(Stmt { kind, line }));
+        self.depth -= 1;
+        result
+    }
+    
+    /// statement parser body, use stmt() for the depth check
+    fn parse_stmt(&mut self) -> Result<StmtKind, String> {
+        let kind = match self.token() {
             // If statement
             Token::If => {
                 if self.debug {
@@ -2032,147 +1300,78 @@ impl<'a> Parser<'a> {
                 }
                 self.next(); // Skip 'if'
                 self.expect(Token::LeftParen, "Expected '(' after 'if'")?;
-                if self.debug {
-                    println!("DEBUG: Parsing if condition, next token: {:?}", self.token());
-                }
-                self.expr(0)?; // Parse condition
-                if self.debug {
-                    println!("DEBUG: After condition, result in AX, next token: {:?}", self.token());
-                }
+                let cond = self.expr(0)?;
                 self.expect(Token::RightParen, "Expected ')' after condition")?;
-                
-                // Emit branch if zero
-                self.code.push(OpCode::BZ as i64);
-                let branch_pos = self.code.len();
-                self.code.push(0); // Placeholder for branch target
-                if self.debug {
-                    println!("DEBUG: Generated BZ instruction, branch placeholder at position {}", branch_pos);
-                }
-                
-                // Parse if body
-                self.stmt()?;
+                let then = self.stmt()?;
                 
                 // Check for else
-                if self.debug {
-                    println!("DEBUG: Checking for else clause, token: {:?}", self.token());
-                }
-                if self.token() == Token::Else {
-                    if self.debug {
-                        println!("DEBUG: Found else clause");
-                    }
+                let otherwise = if self.token() == Token::Else {
                     self.next(); // Skip 'else'
-                    
-                    // Add jump to skip else block
-                    self.code.push(OpCode::JMP as i64);
-                    let jump_pos = self.code.len();
-                    self.code.push(0); // Placeholder for jump target
-                    
-                    // Update branch target to jump to else block
-                    self.code[branch_pos] = self.code.len() as i64;
-                    
-                    // Parse else body
-                    self.stmt()?;
-                    
-                    // Update jump target to point after else block
-                    self.code[jump_pos] = self.code.len() as i64;
+                    Some(self.stmt()?)
                 } else {
-                    if self.debug {
-                        println!("DEBUG: No else clause");
-                    }
-                    // No else, update branch target to point to here
-                    self.code[branch_pos] = self.code.len() as i64;
-                }
+                    None
+                };
+                StmtKind::If(cond, then, otherwise)
             },
             
-            // For statement - add support for C-style for loops
+            // For statement, each part may be left out
             Token::For => {
                 self.next(); // Skip 'for'
                 self.expect(Token::LeftParen, "Expected '(' after 'for'")?;
-                
-                // Parse initialization (can be expression or empty)
-                if self.token() != Token::Semicolon {
-                    self.expr(0)?;
-                }
+                let init = if self.token() != Token::Semicolon { Some(self.expr(0)?) } else { None };
                 self.expect(Token::Semicolon, "Expected ';' after for initialization")?;
-                
-                // Store the position for condition check
-                let cond_pos = self.code.len();
-                
-                // Parse condition (can be empty, in which case it's treated as always true)
-                if self.token() != Token::Semicolon {
-                    self.expr(0)?;
-                } else {
-                    // No condition means always true (1)
-                    self.code.push(OpCode::IMM as i64);
-                    self.code.push(1);
-                }
+                let cond = if self.token() != Token::Semicolon { Some(self.expr(0)?) } else { None };
                 self.expect(Token::Semicolon, "Expected ';' after for condition")?;
-                
-                // Emit branch if zero
-                self.code.push(OpCode::BZ as i64);
-                let exit_branch_pos = self.code.len();
-                self.code.push(0); // Placeholder for branch target
-                
-                // Jump to loop body (skip increment part for now)
-                self.code.push(OpCode::JMP as i64);
-                let body_jump_pos = self.code.len();
-                self.code.push(0); // Placeholder for body start
-                
-                // Store the position for the increment expression
-                let inc_pos = self.code.len();
-                
-                // Parse increment expression (can be empty)
-                if self.token() != Token::RightParen {
-                    self.expr(0)?;
-                }
-                
-                // Jump back to condition
-                self.code.push(OpCode::JMP as i64);
-                self.code.push(cond_pos as i64);
-                
+                let inc = if self.token() != Token::RightParen { Some(self.expr(0)?) } else { None };
                 self.expect(Token::RightParen, "Expected ')' after for increment")?;
-                
-                // Body starts here
-                let body_pos = self.code.len();
-                self.code[body_jump_pos] = body_pos as i64;
-                
-                // Parse loop body
-                self.stmt()?;
-                
-                // Jump to increment part
-                self.code.push(OpCode::JMP as i64);
-                self.code.push(inc_pos as i64);
-                
-                // Update exit branch target to point after loop
-                let exit_pos = self.code.len();
-                self.code[exit_branch_pos] = exit_pos as i64;
+                StmtKind::For(init, cond, inc, self.stmt()?)
+            },
+            
+            // Switch statement (c4x)
+            Token::Switch => {
+                self.next(); // Skip 'switch'
+                self.expect(Token::LeftParen, "Expected '(' after 'switch'")?;
+                let value = self.expr(0)?;
+                self.expect(Token::RightParen, "Expected ')' after switch value")?;
+                StmtKind::Switch(value, self.stmt()?)
+            },
+            
+            // Case label, the statement after it belongs to the label
+            Token::Case => {
+                self.next(); // Skip 'case'
+                let value = self.expr(0)?;
+                self.expect(Token::Colon, "Expected ':' after case value")?;
+                StmtKind::Case(value, self.stmt()?)
+            },
+            
+            // Default label
+            Token::Default => {
+                self.next(); // Skip 'default'
+                self.expect(Token::Colon, "Expected ':' after 'default'")?;
+                StmtKind::Default(self.stmt()?)
+            },
+            
+            // Break out of the innermost loop or switch
+            Token::Break => {
+                self.next(); // Skip 'break'
+                self.expect(Token::Semicolon, "Expected ';' after 'break'")?;
+                StmtKind::Break
+            },
+            
+            // Next iteration of the innermost loop
+            Token::Continue => {
+                self.next(); // Skip 'continue'
+                self.expect(Token::Semicolon, "Expected ';' after 'continue'")?;
+                StmtKind::Continue
             },
             
             // While statement
             Token::While => {
                 self.next(); // Skip 'while'
-                
-                // Remember start of condition
-                let loop_start = self.code.len();
-                
                 self.expect(Token::LeftParen, "Expected '(' after 'while'")?;
-                self.expr(0)?; // Parse condition
+                let cond = self.expr(0)?;
                 self.expect(Token::RightParen, "Expected ')' after condition")?;
-                
-                // Emit branch if zero
-                self.code.push(OpCode::BZ as i64);
-                let branch_pos = self.code.len();
-                self.code.push(0); // Placeholder for branch target
-                
-                // Parse while body
-                self.stmt()?;
-                
-                // Jump back to loop start
-                self.code.push(OpCode::JMP as i64);
-                self.code.push(loop_start as i64);
-                
-                // Update branch target to point after loop
-                self.code[branch_pos] = self.code.len() as i64;
+                StmtKind::While(cond, self.stmt()?)
             },
             
             // Return statement
@@ -2180,88 +1379,23 @@ impl<'a> Parser<'a> {
                 self.next(); // Skip 'return'
                 
                 // Check if return has a value
-                if self.token() != Token::Semicolon {
-                    self.expr(0)?; // Parse return value expression
-                }
-                
-                // Emit leave function
-                self.code.push(OpCode::LEV as i64);
-                
+                let value = if self.token() != Token::Semicolon { Some(self.expr(0)?) } else { None };
                 self.expect(Token::Semicolon, "Expected ';' after return")?;
+                StmtKind::Return(value)
             },
             
             // Block statement
             Token::LeftBrace => {
                 self.next(); // Skip '{'
-                
-                // Remember old locals count for scope handling
-                let _old_locals = self.locals;
-                
-                // Parse statements until closing brace
-                while self.token() != Token::RightBrace && self.token() != Token::Eof {
-                    // Check for local variable declarations within blocks
-                    if self.token() == Token::Int || self.token() == Token::Char {
-                        let base_type = if self.token() == Token::Int {
-                            self.next();
-                            Type::Int
-                        } else {
-                            self.next();
-                            Type::Char // Handle local char variable
-                        };
-                        
-                        // Parse local variables
-                        while self.token() != Token::Semicolon {
-                            let mut var_type = base_type.clone();
-                            
-                            // Parse pointer levels
-                            while self.token() == Token::Mul {
-                                self.next();
-                                var_type = Type::Ptr(Box::new(var_type));
-                            }
-                            
-                            // Parse variable name
-                            if let Token::Id(id) = self.token() {
-                                let var_name = self.get_id_name(id);
-                                
-                                // Add as local variable
-                                self.add_symbol(
-                                    &var_name,
-                                    SymbolClass::Loc,
-                                    var_type,
-                                    self.locals as i64,
-                                )?;
-                                
-                                self.locals += 1;
-                                self.next();
-                            } else {
-                                return Err(format!("Line {}: Local variable name expected", self.lexer.line()));
-                            }
-                            
-                            // Check for more variables
-                            if self.token() == Token::Comma {
-                                self.next();
-                            } else {
-                                break;
-                            }
-                        }
-                        
-                        self.expect(Token::Semicolon, "Expected ';' after local variable declaration")?;
-                    } else {
-                        // Otherwise, it's a regular statement
-                        self.stmt()?;
-                    }
-                }
-                
+                let items = self.block_items()?;
                 self.expect(Token::RightBrace, "Expected '}' to end block")?;
-                
-                // Clean up local variables declared in this block
-                // In a more advanced compiler, we'd need to track which locals to restore
-                // For now, we just keep them all since we're not generating cleanup code
+                StmtKind::Block(items)
             },
             
             // Empty statement
             Token::Semicolon => {
                 self.next(); // Skip ';'
+                StmtKind::Empty
             },
             
             // Expression statement
@@ -2269,173 +1403,236 @@ impl<'a> Parser<'a> {
                 if self.debug {
                     println!("DEBUG: Expression statement, token: {:?}", self.token());
                 }
-                self.expr(0)?;
+                let expr = self.expr(0)?;
                 
                 // After expression, expect semicolon
-                // But be more tolerant in certain cases (complex printf in c4.c)
-                if self.token() == Token::Semicolon {
-                    self.next();
-                } else {
-                    // Special case: if we're in the main processing loop of the next() function
-                    // in c4.c, printf is used in a complex way without a semicolon
-                    // We'll tolerate this for self-hosting compatibility
-                    let line = self.lexer.line();
-                    if line == 61 && self.current_type == Type::Int {
-                        println!("Warning: Line {}: Missing ';' after printf - auto-completing", line);
-                    } else {
-                        return Err(format!("Line {}: Expected ';' after expression", line));
-                    }
+                if self.token() != Token::Semicolon {
+                    return Err(format!("Line {}: Expected ';' after expression", self.lexer.line()));
                 }
+                self.next();
+                StmtKind::Expr(expr)
             },
-        }
+        };
         
-        Ok(())
+        Ok(kind)
     }
     
     // Expose the symbols for testing
     pub fn get_symbols(&self) -> &[Symbol] {
-        &self.symbols
+        self.gen.get_symbols()
+    }
+    
+    /// functions generated so far, in code order
+    pub fn functions(&self) -> &[FunctionInfo] {
+        self.gen.functions()
     }
 
-    // Add special handling for bit shift operators (<<, >>)
-    fn handle_bitwise_operators(&mut self) -> Result<(), String> {
-        // Only handle actual bit shift operators, not other comparison operators
-        let current_token = self.token();
-        
-        if current_token == Token::Lt {
-            // Handle left shift (<<)
-            if self.lexer.peek_next() == Some('<') {
-                self.next(); // Skip '<'
-                self.next(); // Skip the second '<'
-                
-                // Push LHS (should be on stack already from caller)
-                self.code.push(OpCode::PSH as i64);
-                
-                // Parse RHS
-                self.expr(self.precedence_of(Token::Shl))?;
-                
-                // Generate SHL instruction
-                self.code.push(OpCode::SHL as i64);
-                self.current_type = Type::Int;
-                
-                return Ok(());
-            }
-        } else if current_token == Token::Gt {
-            // Handle right shift (>>)
-            if self.lexer.peek_next() == Some('>') {
-                self.next(); // Skip '>'
-                self.next(); // Skip second '>'
-                
-                // Push LHS (should be on stack already from caller)
-                self.code.push(OpCode::PSH as i64);
-                
-                // Parse RHS
-                self.expr(self.precedence_of(Token::Shr))?;
-                
-                // Generate SHR instruction
-                self.code.push(OpCode::SHR as i64);
-                self.current_type = Type::Int;
-                
-                return Ok(());
+    /// data offset and source line of every string literal, in source order
+    pub fn strings(&self) -> &[(usize, usize)] {
+        self.gen.strings()
+    }
+
+    /// compiles one expression against a finished compile's symbols, for the debugger
+    /// function gives the parameters and locals in scope, their addresses are LEA offsets
+    pub fn compile_expression(source: &str, symbols: &[Symbol], function: Option<&FunctionInfo>) -> Result<(Vec<i64>, Type), String> {
+        let mut parser = Parser::new(source, CompileOptions::new());
+        for symbol in symbols.iter().filter(|s| s.class != SymbolClass::Loc) {
+            parser.gen.push_symbol(symbol.clone());
+        }
+        if let Some(function) = function {
+            for ((name, offset, _), typ) in function.slots.iter().zip(&function.types) {
+                // a name reused by two blocks means the first one
+                if !parser.gen.find_symbol(name).is_some_and(|s| s.class == SymbolClass::Loc) {
+                    parser.gen.push_symbol(Symbol {
+                        name: name.clone(),
+                        class: SymbolClass::Loc,
+                        typ: typ.clone(),
+                        value: *offset,
+                        prev_class: None,
+                        prev_type: None,
+                        prev_value: None,
+                    });
+                }
             }
         }
-        
-        // If we get here, it wasn't actually a bit shift operator
-        Err(format!("Not a bit shift operator"))
+        parser.lexer.next();
+        let expr = parser.expr(0)?;
+        parser.gen.expr(&parser.ast, expr)?;
+        if parser.token() != Token::Eof {
+            return Err(format!("unexpected {:?} after the expression", parser.token()));
+        }
+        Ok(parser.gen.into_expression())
+    }
+
+    /// name and argument count of each host function the program may call, registered
+    /// or declared extern, in the order VM::link_host_fns binds them
+    pub fn host_fns(&self) -> &[(String, usize)] {
+        self.gen.host_fns()
+    }
+
+    /// code address and source line of each statement, sorted by address
+    pub fn lines(&self) -> &[(usize, usize)] {
+        self.gen.lines()
+    }
+
+    /// code generated so far, all of it once parse succeeds
+    pub fn code(&self) -> &[i64] {
+        self.gen.code()
+    }
+    
+    /// data segment so far
+    pub fn data(&self) -> &[u8] {
+        self.gen.data()
+    }
+    
+    /// every declaration that failed with keep_going on, in source order
+    pub fn errors(&self) -> &[String] {
+        self.gen.errors()
+    }
+    
+    /// warnings collected so far, each "Line N: ..."
+    pub fn warnings(&self) -> &[String] {
+        self.gen.warnings()
     }
+}
+
+/// the operation of a binary operator, and whether it is the compound assignment form like +=
+fn binary_operator(token: Token) -> Option<(BinaryOp, bool)> {
+    let op = match token {
+        Token::Add | Token::AddAssign => BinaryOp::Add,
+        Token::Sub | Token::SubAssign => BinaryOp::Sub,
+        Token::Mul | Token::MulAssign => BinaryOp::Mul,
+        Token::Div | Token::DivAssign => BinaryOp::Div,
+        Token::Mod | Token::ModAssign => BinaryOp::Mod,
+        Token::Shl | Token::ShlAssign => BinaryOp::Shl,
+        Token::Shr | Token::ShrAssign => BinaryOp::Shr,
+        Token::And | Token::AndAssign => BinaryOp::And,
+        Token::Or | Token::OrAssign => BinaryOp::Or,
+        Token::Xor | Token::XorAssign => BinaryOp::Xor,
+        Token::Eq => BinaryOp::Eq,
+        Token::Ne => BinaryOp::Ne,
+        Token::Lt => BinaryOp::Lt,
+        Token::Gt => BinaryOp::Gt,
+        Token::Le => BinaryOp::Le,
+        Token::Ge => BinaryOp::Ge,
+        Token::Lan => BinaryOp::Lan,
+        Token::Lor => BinaryOp::Lor,
+        _ => return None,
+    };
+    let compound = matches!(token, Token::AddAssign | Token::SubAssign | Token::MulAssign | Token::DivAssign | Token::ModAssign |
+                                   Token::ShlAssign | Token::ShrAssign | Token::AndAssign | Token::XorAssign | Token::OrAssign);
+    Some((op, compound))
+}
+
+/// value of the code from start on when it only loads constants and combines them,
+/// computed the way the VM would
+pub(crate) fn eval_constant(code: &[i64], start: usize) -> Option<i64> {
+    evaluate_from(code, start, &|_, _| None)
+}
 
-    /// Extract the last variable reference from the code
-    fn extract_last_variable(&self) -> Option<(String, Type, i64, SymbolClass)> {
-        // Check if the code is valid and has at least a load instruction
-        if self.code.len() < 1 {
-            println!("DEBUG: No code to extract variable from");
-            return None;
+/// value of expression code, computed the way the VM would
+/// LEA, LI and LC go through load with their operand or address, so code that reads memory
+/// needs a load that knows it, None for anything else the VM would have to run
+pub fn evaluate(code: &[i64], load: &dyn Fn(OpCode, i64) -> Option<i64>) -> Option<i64> {
+    evaluate_from(code, 0, load)
+}
+
+// jump targets are indices into code, and only forward jumps like those of ?: are followed
+fn evaluate_from(code: &[i64], start: usize, load: &dyn Fn(OpCode, i64) -> Option<i64>) -> Option<i64> {
+    let mut stack = Vec::new();
+    let mut ax = None;
+    let mut pc = start;
+    while pc < code.len() {
+        let op = OpCode::from_i64(code[pc])?;
+        let next = pc + 1 + op.arity();
+        match op {
+            OpCode::IMM => ax = Some(*code.get(pc + 1)?),
+            OpCode::PSH => stack.push(ax?),
+            OpCode::LEA => ax = Some(load(op, *code.get(pc + 1)?)?),
+            OpCode::LI | OpCode::LC => ax = Some(load(op, ax?)?),
+            OpCode::JMP | OpCode::BZ | OpCode::BNZ => {
+                let target = usize::try_from(*code.get(pc + 1)?).ok().filter(|&target| target >= next)?;
+                let taken = match op {
+                    OpCode::JMP => true,
+                    OpCode::BZ => ax? == 0,
+                    _ => ax? != 0,
+                };
+                pc = if taken { target } else { next };
+                continue;
+            }
+            op => ax = Some(ir::fold(op, stack.pop()?, ax?)?),
         }
-        
-        let last_code = self.code[self.code.len() - 1] as usize;
-        println!("DEBUG: Last code is {} ({:?})", last_code, 
-                 if last_code == OpCode::LI as usize { "LI" } 
-                 else if last_code == OpCode::LC as usize { "LC" } 
-                 else { "unknown" });
-        
-        // If the last instruction is LI or LC, check the previous code to find the variable
-        if last_code == OpCode::LI as usize || last_code == OpCode::LC as usize {
-            println!("DEBUG: Last code is LI or LC");
-            
-            // For local variables, we should have LEA with an offset
-            if self.code.len() >= 3 {
-                let prev_inst_index = self.code.len() - 2;
-                let prev_inst = self.code[prev_inst_index];
-                println!("DEBUG: Previous instruction is {}", prev_inst);
-                
-                if prev_inst == OpCode::LEA as i64 {
-                    let offset_index = self.code.len() - 1;
-                    let offset = self.code[offset_index];
-                    println!("DEBUG: Found LEA with offset {}", offset);
-                    
-                    // Find the variable in the symbol table
-                    for sym in &self.symbols {
-                        if sym.class == SymbolClass::Loc && sym.value == offset {
-                            println!("DEBUG: Found local variable {} at offset {}", sym.name, offset);
-                            return Some((sym.name.clone(), sym.typ.clone(), offset, SymbolClass::Loc));
-                        }
-                    }
-                } 
-                else if prev_inst == OpCode::IMM as i64 {
-                    let address_index = self.code.len() - 1;
-                    let address = self.code[address_index];
-                    println!("DEBUG: Found IMM with address {}", address);
-                    
-                    // Find the variable in the symbol table
-                    for sym in &self.symbols {
-                        if sym.class == SymbolClass::Glo && sym.value == address {
-                            println!("DEBUG: Found global variable {} at address {}", sym.name, address);
-                            return Some((sym.name.clone(), sym.typ.clone(), address, SymbolClass::Glo));
-                        }
-                    }
-                }
+        pc = next;
+    }
+    ax
+}
+
+/// checks every BZ, BNZ and JMP inside a function lands on an instruction of that same function
+pub fn verify_jumps(code: &[i64], functions: &[FunctionInfo]) -> Result<(), String> {
+    for function in functions {
+        let mut pc = function.start;
+        let mut boundaries = BTreeSet::new();
+        let mut jumps = Vec::new();
+        while pc < function.end {
+            let op = code.get(pc).copied().and_then(OpCode::from_i64)
+                .ok_or_else(|| format!("'{}': no instruction at {}", function.name, pc))?;
+            boundaries.insert(pc);
+            if matches!(op, OpCode::JMP | OpCode::BZ | OpCode::BNZ) {
+                jumps.push((pc, op, code.get(pc + 1).copied().unwrap_or(-1)));
             }
+            pc += 1 + op.arity();
         }
-        
-        // Special hack: try to get variable from class Loc with offset 0 (common pattern)
-        for sym in &self.symbols {
-            if sym.class == SymbolClass::Loc && sym.value == 0 {
-                println!("DEBUG: Fallback - found local variable {} at offset 0", sym.name);
-                return Some((sym.name.clone(), sym.typ.clone(), 0, SymbolClass::Loc));
+        for (pc, op, target) in jumps {
+            let inside = usize::try_from(target).is_ok_and(|t| boundaries.contains(&t));
+            if !inside {
+                return Err(format!("{} at {} in '{}' targets {}, outside the function ({}..{})",
+                                   op.name(), pc, function.name, target, function.start, function.end));
             }
         }
-        
-        println!("DEBUG: Could not extract variable information");
-        println!("DEBUG: Current code state (last 5 instructions):");
-        let start = if self.code.len() > 5 { self.code.len() - 5 } else { 0 };
-        for i in start..self.code.len() {
-            println!("  code[{}] = {}", i, self.code[i]);
+    }
+    Ok(())
+}
+
+/// number of arguments a printf format consumes, read the way VM::format_printf reads it
+pub fn printf_arg_count(format: &[u8]) -> usize {
+    let mut sequential = 0;
+    let mut highest = 0; // largest %n$ position
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let digits = format[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let mut position = None;
+        if digits > 0 && format.get(i + digits) == Some(&b'$') {
+            position = core::str::from_utf8(&format[i..i + digits]).ok().and_then(|n| n.parse::<usize>().ok());
+            i += digits + 1;
+        }
+        while i < format.len() && matches!(format[i], b'-' | b'0' | b'+' | b' ' | b'#' | b'0'..=b'9' | b'.' | b'h' | b'l' | b'z' | b'j' | b't') {
+            i += 1;
+        }
+        let Some(&conv) = format.get(i) else {
+            break;
+        };
+        i += 1;
+        if !matches!(conv, b'd' | b'i' | b'u' | b'x' | b'X' | b'o' | b'c' | b's' | b'p') {
+            continue;
+        }
+        match position {
+            Some(n) => highest = highest.max(n),
+            None => sequential += 1,
         }
-        
-        None
     }
+    sequential.max(highest)
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     
-    #[test]
-    fn test_symbol_table() {
-        let mut parser = Parser::new("", false);
-        parser.init().unwrap();
-        
-        // Add a global symbol
-        parser.add_symbol("global_var", SymbolClass::Glo, Type::Int, 123).unwrap();
-        
-        // Verify it exists
-        let symbol = parser.find_symbol("global_var").unwrap();
-        assert_eq!(symbol.name, "global_var");
-        assert_eq!(symbol.class, SymbolClass::Glo);
-        assert_eq!(symbol.value, 123);
-        assert!(matches!(symbol.typ, Type::Int));
-    }
-    
     #[test]
     fn test_type_size() {
         assert_eq!(Type::Char.size(), 1);
@@ -2450,8 +1647,9 @@ mod tests {
         let mut parser = Parser::new(source, false);
         parser.init().unwrap();
         
-        // Parse the expression
-        parser.expr(0).unwrap();
+        // Parse the expression, then generate its code
+        let expr = parser.expr(0).unwrap();
+        parser.gen.expr(&parser.ast, expr).unwrap();
         
         // Expected bytecode (pseudo)
         // IMM 1
@@ -2471,7 +1669,7 @@ mod tests {
             OpCode::ADD as i64,
         ];
         
-        assert_eq!(parser.code, expected, "Simple expression code generation failed");
+        assert_eq!(parser.code(), expected, "Simple expression code generation failed");
     }
     
     #[test]
@@ -2480,8 +1678,9 @@ mod tests {
         let mut parser = Parser::new(source, false);
         parser.init().unwrap();
         
-        // Parse the if statement
-        parser.stmt().unwrap();
+        // Parse the if statement, then generate its code
+        let stmt = parser.stmt().unwrap();
+        parser.gen.stmt(&parser.ast, stmt).unwrap();
         
         // Expected bytecode (pseudo)
         // IMM 1
@@ -2499,7 +1698,7 @@ mod tests {
             OpCode::IMM as i64, 3,
         ];
         
-        assert_eq!(parser.code, expected, "If-else statement code generation failed");
+        assert_eq!(parser.code(), expected, "If-else statement code generation failed");
     }
     
     #[test]
@@ -2508,8 +1707,9 @@ mod tests {
         let mut parser = Parser::new(source, false);
         parser.init().unwrap();
         
-        // Parse the while statement
-        parser.stmt().unwrap();
+        // Parse the while statement, then generate its code
+        let stmt = parser.stmt().unwrap();
+        parser.gen.stmt(&parser.ast, stmt).unwrap();
         
         // Expected bytecode (pseudo)
         // loop:
@@ -2525,6 +1725,17 @@ mod tests {
             OpCode::JMP as i64, 0,
         ];
         
-        assert_eq!(parser.code, expected, "While statement code generation failed");
+        assert_eq!(parser.code(), expected, "While statement code generation failed");
     }
-} 
\ No newline at end of file
+    
+    #[test]
+    fn test_options_reach_parser_and_lexer() {
+        let options = CompileOptions::new().debug(false).opt_level(2).bounds_checks(true);
+        let parser = Parser::new("", options.clone());
+        assert_eq!(parser.options(), &options);
+        
+        // bool still works as the debug flag
+        let parser = Parser::new("", false);
+        assert_eq!(parser.options(), &CompileOptions::default());
+    }
+}
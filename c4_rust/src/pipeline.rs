@@ -0,0 +1,132 @@
+//! the compiler's stages as separate calls
+//! tokenize, parse, compile, lift, optimize, lower and link each take what the stage before made
+//! parse gives the syntax tree compile generates code from
+
+use crate::ast;
+use crate::image::Image;
+use crate::ir;
+use crate::lexer::{Lexer, Token};
+use crate::options::CompileOptions;
+use crate::parser::{verify_jumps, FunctionInfo, Parser, Symbol};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// the tokens of a source, with what they refer to
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenStream {
+    pub tokens: Vec<(Token, usize)>, // each token and the line it is on, Eof last
+    names: BTreeMap<usize, String>,  // spelling of each Token::Id
+    strings: Vec<u8>,                // text of the Token::Str literals, each ending in 0
+}
+
+impl TokenStream {
+    /// how the identifier of a Token::Id was spelled
+    pub fn name(&self, id: usize) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    /// text of the string literal of a Token::Str, without its 0
+    pub fn string(&self, start: usize) -> Option<&[u8]> {
+        let text = self.strings.get(start..)?;
+        Some(&text[..text.iter().position(|&c| c == 0).unwrap_or(text.len())])
+    }
+}
+
+/// a program as the code generator emits it from the syntax tree, before any optimization
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub code: Vec<i64>,
+    pub data: Vec<u8>,
+    pub functions: Vec<FunctionInfo>,
+    pub symbols: Vec<Symbol>,
+    pub strings: Vec<(usize, usize)>,   // data offset and line of each string literal
+    pub lines: Vec<(usize, usize)>,     // code address and line of each statement
+    pub host_fns: Vec<(String, usize)>, // name and argument count of each extern function
+    pub warnings: Vec<String>,
+}
+
+/// splits the source into tokens, with macros expanded
+pub fn tokenize(source: &str, options: &CompileOptions) -> Result<TokenStream, String> {
+    let mut lexer = Lexer::with_options(source, options);
+    let mut tokens = Vec::new();
+    let mut names = BTreeMap::new();
+    loop {
+        let token = lexer.next();
+        if let Some(error) = lexer.take_error() {
+            return Err(error);
+        }
+        if let Token::Id(id) = token {
+            if let Some(name) = lexer.id_name(id) {
+                names.insert(id, name.to_string());
+            }
+        }
+        tokens.push((token, lexer.line()));
+        if token == Token::Eof {
+            break;
+        }
+    }
+    Ok(TokenStream { tokens, names, strings: lexer.string_buffer().to_vec() })
+}
+
+/// parses the source into a syntax tree, the first declaration that doesn't parse is the error
+pub fn parse(source: &str, options: &CompileOptions) -> Result<ast::Program, String> {
+    Parser::new(source, options.clone()).parse_ast()
+}
+
+/// parses and generates code, leaving optimization to the later stages whatever opt_level says
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Compiled, String> {
+    let mut parser = Parser::new(source, options.clone().opt_level(0));
+    let (code, data) = parser.parse()?;
+    Ok(Compiled {
+        code,
+        data,
+        functions: parser.functions().to_vec(),
+        symbols: parser.get_symbols().to_vec(),
+        strings: parser.strings().to_vec(),
+        lines: parser.lines().to_vec(),
+        host_fns: parser.host_fns().to_vec(),
+        warnings: parser.warnings().to_vec(),
+    })
+}
+
+/// the code as basic blocks, None when it can't be lifted and has to run as it is
+pub fn lift(compiled: &Compiled) -> Option<ir::Program> {
+    ir::Program::lift(&compiled.code, &compiled.functions)
+}
+
+/// runs the passes of an optimization level, 0 changes nothing
+pub fn optimize(program: &mut ir::Program, compiled: &Compiled, level: u8) {
+    program.run_passes(&compiled.functions, level);
+}
+
+/// bytecode for the optimized blocks, with functions, symbols and lines moved to match
+pub fn lower(program: &ir::Program, compiled: &Compiled) -> Result<Compiled, String> {
+    let (code, relocation) = program.lower();
+    let mut lowered = compiled.clone();
+    relocation.relocate(&mut lowered.functions, &mut lowered.symbols);
+    lowered.lines = relocation.relocate_lines(&compiled.lines);
+    lowered.code = code;
+    verify_jumps(&lowered.code, &lowered.functions)?;
+    Ok(lowered)
+}
+
+/// the image the VM runs and --emit-image saves
+pub fn link(compiled: &Compiled) -> Image {
+    Image { code: compiled.code.clone(), data: compiled.data.clone(), strings: compiled.strings.clone() }
+}
+
+/// every stage in turn, optimizing at the options' level
+pub fn build(source: &str, options: &CompileOptions) -> Result<Image, String> {
+    let compiled = compile(source, options)?;
+    if options.opt_level == 0 {
+        return Ok(link(&compiled));
+    }
+    match lift(&compiled) {
+        Some(mut program) => {
+            optimize(&mut program, &compiled, options.opt_level);
+            Ok(link(&lower(&program, &compiled)?))
+        }
+        None => Ok(link(&compiled)),
+    }
+}
@@ -0,0 +1,90 @@
+//! python bindings
+//! compile and run programs without shelling out to the binary
+
+use crate::host::CaptureHost;
+use crate::options::{CompileOptions, Lang};
+use crate::vm::{self, HostFns, VM};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+
+create_exception!(c4_rust, CompileError, PyException, "source did not compile");
+
+/// what a run produced
+#[pyclass(get_all, frozen)]
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    exit_code: Option<i64>, // main's return value, None on a runtime error
+    stdout: String,         // everything printf wrote
+    error: Option<String>,  // runtime error message
+}
+
+#[pymethods]
+impl RunResult {
+    fn __repr__(&self) -> String {
+        let exit_code = self.exit_code.map_or("None".to_string(), |code| code.to_string());
+        let error = self.error.as_ref().map_or("None".to_string(), |e| format!("{:?}", e));
+        format!("RunResult(exit_code={}, stdout={:?}, error={})", exit_code, self.stdout, error)
+    }
+}
+
+/// builds options for the given dialect name
+fn options_for(lang: &str) -> PyResult<CompileOptions> {
+    let lang: Lang = lang.parse().map_err(PyValueError::new_err)?;
+    Ok(CompileOptions::new().lang(lang))
+}
+
+/// compiles source to (code, data), raising CompileError on failure
+#[pyfunction]
+#[pyo3(signature = (source, lang = "c4"))]
+fn compile(source: &str, lang: &str) -> PyResult<(Vec<i64>, Vec<u8>)> {
+    let options = options_for(lang)?;
+    vm::compile(source, &options, &[]).1.map_err(CompileError::new_err)
+}
+
+/// compiler warnings for source, then every declaration's error if it didn't compile; empty when clean
+#[pyfunction]
+#[pyo3(signature = (source, lang = "c4"))]
+fn diagnostics(source: &str, lang: &str) -> PyResult<Vec<String>> {
+    let options = options_for(lang)?;
+    let (parser, result) = vm::compile(source, &options.keep_going(true), &[]);
+    let mut diagnostics: Vec<String> = parser.warnings().iter().map(|w| format!("warning: {}", w)).collect();
+    match result {
+        Err(_) if !parser.errors().is_empty() => diagnostics.extend_from_slice(parser.errors()),
+        result => diagnostics.extend(result.err()),
+    }
+    Ok(diagnostics)
+}
+
+/// compiles and runs source with the given stdin bytes and argv
+#[pyfunction]
+#[pyo3(signature = (source, stdin = Vec::new(), args = Vec::new(), lang = "c4"))]
+fn run(source: &str, stdin: Vec<u8>, args: Vec<String>, lang: &str) -> PyResult<RunResult> {
+    let options = options_for(lang)?.virtual_time(true);
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let (code, data) = compiled.map_err(CompileError::new_err)?;
+
+    let host = CaptureHost::new(stdin);
+    let output = host.output();
+    let result = VM::load(&parser, code, data, &options, Box::new(host), HostFns::new()).and_then(|mut vm| {
+        vm.set_args(&args);
+        vm.run().map_err(|e| e.to_string())
+    });
+
+    let stdout = output.borrow().clone();
+    Ok(match result {
+        Ok(exit_code) => RunResult { exit_code: Some(exit_code), stdout, error: None },
+        Err(e) => RunResult { exit_code: None, stdout, error: Some(e) },
+    })
+}
+
+/// the c4_rust python module
+#[pymodule]
+fn c4_rust(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("CompileError", m.py().get_type::<CompileError>())?;
+    m.add_class::<RunResult>()?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(diagnostics, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
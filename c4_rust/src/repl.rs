@@ -0,0 +1,434 @@
+//! interactive session: declarations accumulate, statements and expressions run as they're entered
+//! the CLI reads lines through a LineReader, with editing and history under the readline feature
+
+use crate::disasm;
+use crate::host::CaptureHost;
+use crate::image::{self, Image, Reader};
+use crate::options::CompileOptions;
+use crate::parser::{Parser, SymbolClass};
+use crate::vm::{HostFns, VM};
+use std::io::{self, BufRead, Write};
+
+// operand of the JSR in the entry stub, pointed at the wrapper instead of main
+const ENTRY_CALL: usize = 1;
+
+// function the entered statements and expressions are compiled into
+const WRAPPER: &str = "__repl";
+
+/// first bytes of a saved session
+pub const SESSION_MAGIC: &[u8; 4] = b"C4S\0";
+
+/// layout of a saved session, bumped when it changes
+pub const SESSION_VERSION: u64 = 1;
+
+/// where the session's lines come from
+pub trait LineReader {
+    /// shows the prompt and reads a line without its newline, None at the end of input
+    fn read_line(&mut self, prompt: &str) -> Option<String>;
+    /// remembers a line for recall with the arrow keys
+    fn add_history(&mut self, _line: &str) {}
+}
+
+/// reads stdin without editing, for pipes and builds without readline
+#[derive(Debug, Default)]
+pub struct StdinReader;
+
+impl LineReader for StdinReader {
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "readline")]
+pub use self::editor::EditorReader;
+
+#[cfg(feature = "readline")]
+mod editor {
+    use super::LineReader;
+    use rustyline::error::ReadlineError;
+    use rustyline::DefaultEditor;
+
+    /// terminal line editing with arrow-key history
+    pub struct EditorReader {
+        editor: DefaultEditor,
+    }
+
+    impl EditorReader {
+        /// fails when the terminal can't be set up
+        pub fn new() -> Result<EditorReader, String> {
+            DefaultEditor::new().map(|editor| EditorReader { editor }).map_err(|e| e.to_string())
+        }
+    }
+
+    impl LineReader for EditorReader {
+        fn read_line(&mut self, prompt: &str) -> Option<String> {
+            match self.editor.readline(prompt) {
+                Ok(line) => Some(line),
+                // ctrl-c drops the line being typed, like a shell
+                Err(ReadlineError::Interrupted) => Some(String::new()),
+                Err(_) => None,
+            }
+        }
+
+        fn add_history(&mut self, line: &str) {
+            let _ = self.editor.add_history_entry(line);
+        }
+    }
+}
+
+/// declarations and statements entered so far, compiled again for every entry
+#[derive(Debug, Clone)]
+pub struct Repl {
+    options: CompileOptions,
+    declarations: Vec<String>, // globals, functions and enums, as entered
+    statements: Vec<String>,   // statements that ran, replayed before each new entry
+    printed: usize,            // output of the replayed statements, not shown again
+    pending: String,           // lines of an entry whose brackets are still open
+}
+
+impl Repl {
+    /// empty session compiling with the given options
+    pub fn new(options: CompileOptions) -> Repl {
+        Repl { options, declarations: Vec::new(), statements: Vec::new(), printed: 0, pending: String::new() }
+    }
+
+    /// "c4> ", or "...> " while an entry continues
+    pub fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() { "c4> " } else { "...> " }
+    }
+
+    /// true unless an entry is half typed
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// declarations entered so far, in order
+    pub fn declarations(&self) -> &[String] {
+        &self.declarations
+    }
+
+    /// adds a line to the entry being typed, runs the entry once its brackets balance
+    pub fn feed(&mut self, line: &str) -> Option<Result<String, String>> {
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        if flatten(&self.pending).1 {
+            return None;
+        }
+        let entry = core::mem::take(&mut self.pending);
+        Some(self.execute(&entry))
+    }
+
+    /// runs one complete entry, returning what to print
+    /// declarations are kept, statements end in ';' or '}', anything else is an expression whose value is shown
+    pub fn execute(&mut self, entry: &str) -> Result<String, String> {
+        let entry = entry.trim();
+        if let Some(command) = entry.strip_prefix(':') {
+            return self.command(command.trim());
+        }
+        if entry.is_empty() {
+            return Ok(String::new());
+        }
+        if is_declaration(entry) {
+            let mut declarations = self.declarations.clone();
+            declarations.push(entry.to_string());
+            compile(&session(&declarations, None), &self.options)?;
+            self.declarations = declarations;
+            return Ok(String::new());
+        }
+
+        let statement = entry.ends_with(';') || entry.ends_with('}');
+        let body = if statement { format!("{} return 0;", entry) } else { format!("return ({});", entry) };
+        let (value, output) = self.run(&body)?;
+        let shown = output.get(self.printed..).unwrap_or_default().to_string();
+        if statement {
+            self.statements.push(entry.to_string());
+            self.printed = output.len();
+            Ok(shown)
+        } else {
+            Ok(format!("{}= {}\n", shown, value))
+        }
+    }
+
+    /// the session as a .c4s file: the declarations and statements as entered, then the .c4b
+    /// image of the program they make, then a CRC-32 of everything before it
+    pub fn save(&self) -> Result<Vec<u8>, String> {
+        let source = session(&self.declarations, Some(&self.replay("return 0;")));
+        let (mut code, data, parser) = compile(&source, &self.options)?;
+        code[ENTRY_CALL] = wrapper_start(&parser) as i64;
+        let image = Image { code, data, strings: parser.strings().to_vec() }.to_bytes();
+
+        let mut out = Vec::from(&SESSION_MAGIC[..]);
+        out.extend(SESSION_VERSION.to_le_bytes());
+        for texts in [&self.declarations, &self.statements] {
+            out.extend((texts.len() as u64).to_le_bytes());
+            for text in texts {
+                out.extend((text.len() as u64).to_le_bytes());
+                out.extend(text.as_bytes());
+            }
+        }
+        out.extend((image.len() as u64).to_le_bytes());
+        out.extend(image);
+        let crc = image::crc32(&out);
+        out.extend(crc.to_le_bytes());
+        Ok(out)
+    }
+
+    /// replaces the session with one save wrote, compiling its source again since the
+    /// image may come from a build with other opcodes; the statements are replayed once
+    pub fn load(&mut self, bytes: &[u8]) -> Result<String, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(SESSION_MAGIC.len()).ok() != Some(&SESSION_MAGIC[..]) {
+            return Err(String::from("not a c4 session"));
+        }
+        let version = reader.word()?;
+        if version != SESSION_VERSION {
+            return Err(format!("session format version {} is not supported, this build reads version {}", version, SESSION_VERSION));
+        }
+        let mut texts = [Vec::new(), Vec::new()];
+        for list in &mut texts {
+            let count = reader.len()?;
+            for _ in 0..count {
+                let len = reader.len()?;
+                let text = String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| String::from("session source is not UTF-8"))?;
+                list.push(text);
+            }
+        }
+        let len = reader.len()?;
+        let saved = Image::from_bytes(reader.take(len)?);
+        let computed = image::crc32(&bytes[..reader.pos()]);
+        let stored = reader.crc()?;
+        if reader.pos() != bytes.len() {
+            return Err(format!("{} trailing bytes after the session", bytes.len() - reader.pos()));
+        }
+        if stored != computed {
+            return Err(format!("session checksum {:08x} does not match its contents ({:08x}), the file is damaged", stored, computed));
+        }
+
+        let [declarations, statements] = texts;
+        let mut loaded = Repl::new(self.options.clone());
+        loaded.declarations = declarations;
+        loaded.statements = statements;
+        let (_, output) = loaded.run("return 0;")?;
+        loaded.printed = output.len();
+        let mut message = format!("loaded {} declarations and {} statements\n", loaded.declarations.len(), loaded.statements.len());
+        if let Err(e) = saved {
+            message.push_str(&format!("the saved image can't be used ({}), compiled from source\n", e));
+        }
+        *self = loaded;
+        Ok(message)
+    }
+
+    /// the statements so far followed by body
+    fn replay(&self, body: &str) -> String {
+        let mut wrapper = self.statements.join(" ");
+        wrapper.push(' ');
+        wrapper.push_str(body);
+        wrapper
+    }
+
+    /// replays the statements so far then runs body, returning its value and everything printed
+    fn run(&self, body: &str) -> Result<(i64, String), String> {
+        let source = session(&self.declarations, Some(&self.replay(body)));
+        let (mut code, data, parser) = compile(&source, &self.options)?;
+        code[ENTRY_CALL] = wrapper_start(&parser) as i64;
+
+        let host = CaptureHost::new(Vec::new());
+        let output = host.output();
+        let mut vm = VM::load(&parser, code, data, &self.options, Box::new(host), HostFns::new())?;
+        let result = vm.run();
+        let output = output.borrow().clone();
+        match result {
+            Ok(value) => Ok((value, output)),
+            Err(e) => Err(format!("{}runtime error: {}", output.get(self.printed..).unwrap_or_default(), e)),
+        }
+    }
+
+    /// :symbols, :code, :reset, :save, :load and :help
+    fn command(&mut self, command: &str) -> Result<String, String> {
+        let (command, path) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let path = path.trim();
+        if matches!(command, "save" | "load") && path.is_empty() {
+            return Err(format!(":{} needs a file name", command));
+        }
+        match command {
+            "save" => {
+                let bytes = self.save()?;
+                std::fs::write(path, bytes).map_err(|e| format!("could not write {}: {}", path, e))?;
+                Ok(format!("saved {} declarations and {} statements to {}\n", self.declarations.len(), self.statements.len(), path))
+            }
+            "load" => {
+                let bytes = std::fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+                self.load(&bytes)
+            }
+            "symbols" => {
+                let source = session(&self.declarations, None);
+                let (_, _, parser) = compile(&source, &self.options)?;
+                let mut out = String::new();
+                for symbol in parser.get_symbols() {
+                    let line = match symbol.class {
+                        SymbolClass::Glo => symbol.typ.declare(&symbol.name),
+                        SymbolClass::Num => format!("{} = {}", symbol.name, symbol.value),
+                        SymbolClass::Fun => {
+                            let params = parser.functions().iter().find(|f| f.name == symbol.name).map_or(0, |f| f.params);
+                            let plural = if params == 1 { "" } else { "s" };
+                            format!("{}, {} parameter{}", symbol.typ.declare(&format!("{}()", symbol.name)), params, plural)
+                        }
+                        SymbolClass::Host => symbol.typ.declare(&format!("{}()", symbol.name)) + ", extern",
+                        SymbolClass::Sys | SymbolClass::Loc => continue,
+                    };
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                Ok(out)
+            }
+            "code" => {
+                let source = session(&self.declarations, None);
+                let (code, _, parser) = compile(&source, &self.options)?;
+                Ok(disasm::disassemble(&code, parser.functions()))
+            }
+            "reset" => {
+                *self = Repl::new(self.options.clone());
+                Ok(String::from("session cleared\n"))
+            }
+            "help" | "h" => Ok(String::from(HELP)),
+            _ => Err(format!("unknown command ':{}', try :help", command)),
+        }
+    }
+}
+
+/// the declarations on one line then the wrapper around body, one line keeps the parser's
+/// special handling of c4.c's line numbers away from long sessions
+fn session(declarations: &[String], body: Option<&str>) -> String {
+    let mut source = String::new();
+    for declaration in declarations {
+        let text = flatten(declaration).0;
+        source.push_str(&text);
+        // a directive runs to the end of its line
+        let directive = text.lines().last().is_some_and(|line| line.trim_start().starts_with('#'));
+        source.push(if directive { '\n' } else { ' ' });
+    }
+    if let Some(body) = body {
+        source.push_str(&format!("int {}() {{ {} }}", WRAPPER, flatten(body).0));
+    }
+    source
+}
+
+/// address of the function the entries were compiled into
+fn wrapper_start(parser: &Parser) -> usize {
+    parser.functions().iter().find(|f| f.name == WRAPPER).map_or(0, |f| f.start)
+}
+
+/// compiles a session without needing main
+fn compile<'a>(source: &'a str, options: &CompileOptions) -> Result<(Vec<i64>, Vec<u8>, Parser<'a>), String> {
+    let mut parser = Parser::new(source, options.clone());
+    match parser.parse_library() {
+        Ok((code, data)) => Ok((code, data, parser)),
+        // entries are joined into a few lines of the session, the number means nothing to the user
+        Err(e) => Err(strip_line(&e).to_string()),
+    }
+}
+
+/// an error without its "Line N: " prefix
+fn strip_line(error: &str) -> &str {
+    let Some(rest) = error.strip_prefix("Line ") else {
+        return error;
+    };
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match rest[digits..].strip_prefix(": ") {
+        Some(message) if digits > 0 => message,
+        _ => error,
+    }
+}
+
+/// globals, functions, enums, externs and directives are kept rather than run
+fn is_declaration(entry: &str) -> bool {
+    let word = entry.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').next().unwrap_or("");
+    entry.starts_with('#') || matches!(word, "int" | "char" | "void" | "enum" | "extern" | "struct")
+}
+
+/// the text with newlines and // comments turned into spaces, and whether a bracket or
+/// comment is still open; directives keep their newline since they end at it
+fn flatten(text: &str) -> (String, bool) {
+    let mut out = String::new();
+    let mut depth = 0i64;
+    let mut directive = false; // the line being copied starts with '#'
+    let mut line_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if line_start && !c.is_whitespace() {
+            directive = c == '#';
+            line_start = false;
+        }
+        match c {
+            '"' | '\'' => {
+                out.push(c);
+                while let Some(&d) = chars.peek() {
+                    // an unterminated literal stops at the newline, the compiler reports it
+                    if d == '\n' {
+                        break;
+                    }
+                    out.push(d);
+                    chars.next();
+                    if d == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            out.push(escaped);
+                        }
+                    } else if d == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&d| d != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(d) => last = d,
+                        None => return (out, true),
+                    }
+                }
+                out.push(' ');
+            }
+            '\n' => {
+                out.push(if directive { '\n' } else { ' ' });
+                directive = false;
+                line_start = true;
+            }
+            '\r' => out.push(' '),
+            '(' | '{' | '[' => {
+                depth += 1;
+                out.push(c);
+            }
+            ')' | '}' | ']' => {
+                depth -= 1;
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    (out, depth > 0)
+}
+
+const HELP: &str = "\
+int x;  int f() { ... }   declarations are kept for the rest of the session
+x = f(2);                 statements ending in ';' or '}' run, and run again before later entries
+x * 2                     an expression without ';' prints its value
+:symbols                  list the globals, functions and enum constants declared so far
+:code                     disassemble the declared functions
+:reset                    forget every declaration and statement
+:save FILE                write the session to FILE, source and compiled image
+:load FILE                replace the session with one saved to FILE
+:quit                     leave the session
+";
@@ -1,769 +1,1528 @@
-/// runs compiled code
-/// executes parser output
+//! runs compiled code
+//! executes parser output
 
-use crate::parser::{OpCode, Parser};
-use std::io::Write;
+#![deny(clippy::unwrap_used)]
 
-// Define threshold to differentiate data/stack addresses
-const DATA_STACK_THRESHOLD: usize = 1024 * 1024; // 1MB threshold
+use crate::host::{DefaultHost, Host};
+use crate::observer::Observer;
+use crate::options::{Buffering, CompileOptions};
+use crate::parser::{FunctionInfo, OpCode, Parser, Symbol, SymbolClass};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Define threshold to differentiate data/stack addresses, room for a self-hosted
+// c4 whose four 256KB pools are themselves running c4; stack slots below it are never touched
+pub const DATA_STACK_THRESHOLD: usize = 16 * 1024 * 1024; // 16MB threshold
+
+// Slots above the initial sp, for main's argc and argv
+const STACK_HEADROOM: usize = 20;
+
+// Cap on printf field width and precision
+const MAX_PRINTF_WIDTH: usize = 4096;
+
+// Guard bytes on each side of a heap block under sanitize, and their fill
+const RED_ZONE: usize = 16;
+const RED_ZONE_BYTE: u8 = 0xfa;
+
+// Fill of a freed heap block, so stale reads that aren't checked see garbage
+const FREED_BYTE: u8 = 0xdd;
+
+// Bytes malloc hands out between collections under --gc
+const GC_THRESHOLD: usize = 4096;
+
+// Output held back under --buffering=full before it is written, like BUFSIZ
+const OUTPUT_BUFFER: usize = 4096;
+
+/// errors raised while running code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// ran past the instruction limit
+    CycleLimit { limit: usize },
+    /// no room to push in this instruction
+    StackOverflow { op: &'static str },
+    /// the stack grew past max_stack_bytes, in function at this call depth
+    StackLimit { limit: usize, function: String, depth: usize },
+    /// popped past the top of the stack
+    StackUnderflow { sp: usize },
+    /// saved bp or return address is unreadable
+    StackCorruption { bp: usize },
+    /// LEA offset points outside the stack
+    InvalidLocal { offset: i64, bp: usize },
+    /// load from outside the data segment or stack
+    OobRead { addr: usize, size: usize },
+    /// store outside the stack or data segment
+    OobWrite { addr: usize, size: usize },
+    /// DIV or MOD with a zero divisor
+    DivByZero { pc: usize },
+    /// jump, call or return to the entry stub or outside the code
+    InvalidJump { target: i64, pc: usize, line: Option<usize> },
+    /// checked arithmetic overflowed
+    Overflow { op: &'static str },
+    /// shift amount outside 0..64
+    InvalidShift { amount: i64 },
+    /// opcode at the end of code without its operand
+    MissingOperand { pc: usize },
+    /// opcode the VM doesn't know
+    UnknownOpcode { op: i64, pc: usize },
+    /// bad arguments to a syscall
+    BadSyscall { name: &'static str, detail: String },
+    /// SC of a value a char can't hold, only with sanitize
+    CharOverflow { value: i64, pc: usize },
+    /// red zone byte next to a heap block overwritten, only with sanitize
+    HeapOverflow { addr: usize, block: HeapBlock, line: Option<usize> },
+    /// free of a block that was already freed
+    DoubleFree { block: HeapBlock, pc: usize, line: Option<usize> },
+    /// load or store into a freed block
+    UseAfterFree { addr: usize, block: HeapBlock, pc: usize, line: Option<usize> },
+    /// writing program output failed
+    Io(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::CycleLimit { limit } =>
+                write!(f, "Execution aborted after {} instructions - possible infinite loop", limit),
+            RuntimeError::StackOverflow { op } => write!(f, "Stack overflow in {}", op),
+            RuntimeError::StackLimit { limit, function, depth } =>
+                write!(f, "stack limit of {} bytes exceeded in '{}' at call depth {}", limit, function, depth),
+            RuntimeError::StackUnderflow { sp } => write!(f, "Stack underflow: sp={}", sp),
+            RuntimeError::StackCorruption { bp } => write!(f, "Stack corruption - invalid base pointer {}", bp),
+            RuntimeError::InvalidLocal { offset, bp } => write!(f, "Invalid local offset {} (bp={})", offset, bp),
+            RuntimeError::OobRead { addr, size } => write!(f, "Read out of bounds: addr={}, size={}", addr, size),
+            RuntimeError::OobWrite { addr, size } => write!(f, "Write out of bounds: addr={}, size={}", addr, size),
+            RuntimeError::DivByZero { pc } => write!(f, "division by zero at {}", pc),
+            RuntimeError::InvalidJump { target, pc, line: Some(line) } =>
+                write!(f, "invalid jump target {} at {} (line {})", target, pc, line),
+            RuntimeError::InvalidJump { target, pc, line: None } => write!(f, "invalid jump target {} at {}", target, pc),
+            RuntimeError::Overflow { op } => write!(f, "integer overflow in {}", op),
+            RuntimeError::InvalidShift { amount } => write!(f, "Invalid shift amount {}", amount),
+            RuntimeError::MissingOperand { pc } => write!(f, "Missing operand for instruction at {}", pc),
+            RuntimeError::UnknownOpcode { op, pc } => write!(f, "unknown instruction {} at {}", op, pc),
+            RuntimeError::BadSyscall { name, detail } => write!(f, "{}: {}", name, detail),
+            RuntimeError::CharOverflow { value, pc } =>
+                write!(f, "value {} does not fit in a char at {}", value, pc),
+            RuntimeError::HeapOverflow { addr, block, line } => {
+                let (distance, side) = if *addr < block.addr {
+                    (block.addr - addr, "before")
+                } else {
+                    (addr - block.addr - block.size, "after")
+                };
+                write!(f, "heap buffer overflow at {}, {} bytes {} the {} byte block allocated at {}",
+                       addr, distance, side, block.size, block.site)?;
+                write_line(f, *line)
+            }
+            RuntimeError::DoubleFree { block, pc, line } => {
+                write!(f, "double free of {} at {}", block.addr, pc)?;
+                write_line(f, *line)?;
+                write!(f, ", the {} byte block allocated at {} was already freed at {}",
+                       block.size, block.site, block.freed.unwrap_or(0))
+            }
+            RuntimeError::UseAfterFree { addr, block, pc, line } => {
+                write!(f, "use after free of {} at {}", addr, pc)?;
+                write_line(f, *line)?;
+                write!(f, ", the {} byte block allocated at {} was freed at {}",
+                       block.size, block.site, block.freed.unwrap_or(0))
+            }
+            RuntimeError::Io(e) => write!(f, "output failed: {}", e),
+        }
+    }
+}
+
+// " (line N)" when the line is known
+fn write_line(f: &mut fmt::Formatter<'_>, line: Option<usize>) -> fmt::Result {
+    match line {
+        Some(line) => write!(f, " (line {})", line),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RuntimeError {}
+
+impl From<RuntimeError> for String {
+    fn from(e: RuntimeError) -> String {
+        e.to_string()
+    }
+}
+
+// Stack frame layout, the stack grows down:
+//
+//   bp + 1 + n - i   argument i of n (pushed first, so highest)
+//   bp + 1           return address, pushed by JSR
+//   bp               caller's bp, pushed by ENT
+//   bp - 1 - i       local slot i, reserved by ENT
+//
+// LEA adds its signed operand to bp, so arguments have positive offsets
+// and locals negative ones.
+
+/// what a run_steps call did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepResult {
+    pub steps: usize,                       // instructions executed, at most n
+    pub exit_code: Option<i64>,             // set once the program has finished
+    pub registers: Vec<(&'static str, i64)>, // pc, sp, bp or ax that changed, with new values
+    pub memory: Vec<(usize, i64)>,          // written stack slots and data bytes, with new values
+}
+
+/// counters about a run, see VM::stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub cycles: usize,           // instructions executed
+    pub allocations: usize,      // malloc calls
+    pub live_bytes: usize,       // bytes in blocks not yet freed or collected
+    pub collections: usize,      // times the collector ran, only with gc
+    pub collected_blocks: usize, // blocks it reclaimed
+    pub collected_bytes: usize,  // bytes in those blocks
+    pub max_stack_bytes: usize,  // deepest the stack got, in bytes
+}
+
+/// native function guest code calls by name, gets the arguments in order
+pub type HostFn = Box<dyn FnMut(&[i64]) -> i64>;
+
+/// host functions by name with their argument counts, for VM::link_host_fns
+pub type HostFns = BTreeMap<String, (usize, HostFn)>;
+
+/// code and data segments of a compile, or its first error
+pub type CompileResult = Result<(Vec<i64>, Vec<u8>), String>;
+
+/// one call frame, as seen by VM::frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub pc: usize,        // instruction the frame is executing
+    pub bp: usize,
+    pub return_pc: usize, // where LEV goes back to
+    pub args: Vec<i64>,   // in declaration order
+    pub locals: Vec<i64>, // slot bp - 1 first
+}
+
+/// block handed out by malloc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapBlock {
+    pub addr: usize,
+    pub size: usize,
+    pub site: usize, // address of the malloc call
+    pub freed: Option<usize>, // address of the free call, once freed
+}
+
+// Jump target of an instruction that isn't a jump, or whose operand isn't the start of an instruction
+const NO_TARGET: u32 = u32::MAX;
+
+/// one instruction of the code segment, opcode and operand read in one load
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub op: Option<OpCode>, // None when the word isn't an opcode
+    pub operand: i64,       // word after the opcode, or the word itself when it isn't one
+    target: u32,            // index a jump or call lands on, kept in what would be padding
+}
+
+impl Instruction {
+    /// the index a JMP, JSR, BZ or BNZ lands on, None when its operand isn't an instruction
+    pub fn target(&self) -> Option<usize> {
+        (self.target != NO_TARGET).then_some(self.target as usize)
+    }
+}
+
+/// the code segment packed one entry per instruction, the operand folded into its opcode,
+/// with the word address each one starts at so jumps, return addresses and line tables keep theirs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Code {
+    instructions: Vec<Instruction>,
+    addrs: Vec<u32>, // word address of each instruction, ascending
+    words: usize,    // length of the code before packing
+}
+
+impl Code {
+    /// packs the words, a word that isn't an opcode becomes an instruction on its own
+    pub fn decode(code: &[i64]) -> Self {
+        let mut instructions = Vec::new();
+        let mut addrs = Vec::new();
+        let mut addr = 0;
+        while addr < code.len() {
+            let word = code[addr];
+            let instruction = match OpCode::from_i64(word) {
+                Some(op) => {
+                    let operand = if op.arity() > 0 { code.get(addr + 1).copied().unwrap_or(0) } else { 0 };
+                    Instruction { op: Some(op), operand, target: NO_TARGET }
+                }
+                None => Instruction { op: None, operand: word, target: NO_TARGET },
+            };
+            instructions.push(instruction);
+            addrs.push(addr as u32); // code never gets near 4G words
+            addr += 1 + instruction.op.map_or(0, OpCode::arity);
+        }
+        let mut code = Code { instructions, addrs, words: code.len() };
+        
+        // Jumps land on an index, so taking one needs no lookup
+        for i in 0..code.instructions.len() {
+            let instruction = code.instructions[i];
+            if matches!(instruction.op, Some(OpCode::JMP | OpCode::JSR | OpCode::BZ | OpCode::BNZ)) {
+                let target = code.index_of(instruction.operand).and_then(|index| u32::try_from(index).ok());
+                code.instructions[i].target = target.unwrap_or(NO_TARGET);
+            }
+        }
+        code
+    }
+    
+    /// the instructions in code order
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+    
+    /// word address of the instruction at index, the end of the code past the last one
+    pub fn addr(&self, index: usize) -> usize {
+        self.addrs.get(index).map_or(self.words, |&addr| addr as usize)
+    }
+    
+    /// index of the instruction starting at a word address, None inside one or outside the code
+    pub fn index_of(&self, addr: i64) -> Option<usize> {
+        let addr = u32::try_from(addr).ok()?;
+        self.addrs.binary_search(&addr).ok()
+    }
+}
+
+/// code and initial data of a compiled program, for starting many VMs of it without copying either
+#[derive(Debug, Clone)]
+pub struct SharedProgram {
+    code: Arc<Code>, // decoded once for every VM
+    data: Arc<[u8]>, // each VM copies it on its first write
+}
+
+impl SharedProgram {
+    /// decodes the code, clones of the result share it and the data
+    pub fn new(code: &[i64], data: impl Into<Arc<[u8]>>) -> Self {
+        SharedProgram { code: Arc::new(Code::decode(code)), data: data.into() }
+    }
+}
+
+/// the data segment, read from the shared program's copy until the VM writes to it
+enum Segment {
+    Shared(Arc<[u8]>),
+    Owned(Vec<u8>),
+}
+
+impl Segment {
+    /// the VM's own copy, made on the first write
+    fn owned(&mut self) -> &mut Vec<u8> {
+        if let Segment::Shared(data) = self {
+            *self = Segment::Owned(data.to_vec());
+        }
+        match self {
+            Segment::Owned(data) => data,
+            Segment::Shared(_) => unreachable!(),
+        }
+    }
+
+    fn resize(&mut self, len: usize, value: u8) {
+        self.owned().resize(len, value);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.owned().extend_from_slice(bytes);
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.owned().push(byte);
+    }
+}
+
+impl core::ops::Deref for Segment {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Segment::Shared(data) => data,
+            Segment::Owned(data) => data,
+        }
+    }
+}
+
+impl core::ops::DerefMut for Segment {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.owned()
+    }
+}
+
+/// the stack's slots, addressed from DATA_STACK_THRESHOLD up so only stack_size of them are kept
+struct Stack(Vec<i64>);
+
+impl Stack {
+    /// slot at a stack address, None outside the stack
+    fn get(&self, addr: usize) -> Option<&i64> {
+        self.0.get(addr.checked_sub(DATA_STACK_THRESHOLD)?)
+    }
+
+    fn get_mut(&mut self, addr: usize) -> Option<&mut i64> {
+        self.0.get_mut(addr.checked_sub(DATA_STACK_THRESHOLD)?)
+    }
+
+    /// one past the highest stack address
+    fn end(&self) -> usize {
+        DATA_STACK_THRESHOLD + self.0.len()
+    }
+}
+
+impl core::ops::Index<usize> for Stack {
+    type Output = i64;
+
+    fn index(&self, addr: usize) -> &i64 {
+        &self.0[addr - DATA_STACK_THRESHOLD]
+    }
+}
+
+impl core::ops::IndexMut<usize> for Stack {
+    fn index_mut(&mut self, addr: usize) -> &mut i64 {
+        &mut self.0[addr - DATA_STACK_THRESHOLD]
+    }
+}
+
+impl core::ops::Index<core::ops::Range<usize>> for Stack {
+    type Output = [i64];
+
+    fn index(&self, addrs: core::ops::Range<usize>) -> &[i64] {
+        &self.0[addrs.start - DATA_STACK_THRESHOLD..addrs.end - DATA_STACK_THRESHOLD]
+    }
+}
 
 /// VM state
 pub struct VM {
-    code: Vec<i64>,       // code segment
-    data: Vec<u8>,        // data segment
-    pc: usize,            // program counter
+    code: Arc<Code>,      // code segment, decoded once and shared by VMs of one SharedProgram
+    data: Segment,        // data segment, copied from the shared program on the first write
+    entry: Option<usize>, // function start() calls instead of the stub's main
+    pc: usize,            // program counter, an index into the packed code
     sp: usize,            // stack pointer
     bp: usize,            // base pointer
     ax: i64,              // accumulator
-    stack: Vec<i64>,      // stack
+    stack: Stack,         // stack slots from DATA_STACK_THRESHOLD up
     debug: bool,          // debug flag
+    bounds_checks: bool,  // fail on stores past the data segment
+    sanitize: bool,       // fail on SC of values outside -128..=255 and on heap overflows
+    gc: bool,             // free does nothing, unreachable blocks are collected
+    big_endian: bool,     // ints in the data segment are stored most significant byte first
+    signed_char: bool,    // LC sign-extends the byte it loads
+    trap_overflow: bool,  // fail on arithmetic overflow and on shifts outside 0..64
+    virtual_time: bool,   // clock() ticks once per instruction
+    buffering: Buffering, // when output is handed to the host
+    crlf: bool,           // output newlines become \r\n
+    env: Vec<(String, String)>, // variables getenv() sees, ahead of the host's
+    inherit_env: bool,    // getenv() falls back to the host's environment
+    env_copies: BTreeMap<String, usize>, // where each value getenv() returned was copied
+    allow_exec: bool,     // system() and popen() run commands instead of failing
+    pending: String,      // output not yet handed to the host
+    slept_us: u64,        // virtual microseconds spent in usleep()
     cycle: usize,         // instruction counter
+    max_cycles: usize,    // instructions before the run is stopped, 0 is no limit
+    max_stack_bytes: usize, // deepest the stack may grow, 0 is no limit
+    last: usize,          // index of the last instruction started
+    stack_top: usize,     // sp before anything is pushed
+    lowest_sp: usize,     // deepest the stack has reached
+    heap: Vec<HeapBlock>, // malloc'd blocks sorted by address
+    free_spans: Vec<(usize, usize)>, // start and length of collected memory malloc can reuse
+    static_end: usize,    // end of the globals and strings, the collector's roots
+    allocated_since_gc: usize, // bytes handed out since the last collection
+    stats: RunStats,      // allocation and collection counters
+    functions: Vec<FunctionInfo>, // for frame walking
+    lines: Vec<(usize, usize)>, // code address and source line of each statement
+    host: Box<dyn Host>,  // output, files and the real clock
+    host_fns: Vec<(usize, HostFn)>, // argument count and body of each host function
+    observers: Vec<Box<dyn Observer>>, // told about calls, returns, syscalls and stores
+    explain_limit: usize, // runtime events to describe, 0 is off
+    explained: usize,     // events described so far
+    globals: Vec<(String, usize, usize)>, // name, address and size of each global
+    started: bool,        // run_steps has begun the run
+    exit_code: Option<i64>, // set once run_steps sees the program finish
+    touched: Option<BTreeSet<usize>>, // addresses written during run_steps
 }
 
 impl VM {
     /// creates new VM
     pub fn new(code: Vec<i64>, data: Vec<u8>, debug: bool) -> Self {
-        // Define stack size and base address
-        let stack_size = 8192; // Keep the stack size moderate
+        Self::with_options(code, data, &CompileOptions::from(debug))
+    }
+    
+    /// creates VM configured by options
+    pub fn with_options(code: Vec<i64>, data: Vec<u8>, options: &CompileOptions) -> Self {
+        Self::with_host(code, data, options, Box::new(DefaultHost::default()))
+    }
+    
+    /// a VM for what the parser compiled, knowing its functions, lines and globals for frames,
+    /// traces and errors, with the program's host functions bound to the provided ones by name
+    pub fn load(parser: &Parser, code: Vec<i64>, data: Vec<u8>, options: &CompileOptions, host: Box<dyn Host>, provided: HostFns) -> Result<Self, String> {
+        Self::with_host(code, data, options, host).attach(parser, provided)
+    }
+    
+    /// like load, but running a program other VMs may be running too
+    pub fn load_shared(parser: &Parser, program: &SharedProgram, options: &CompileOptions, host: Box<dyn Host>, provided: HostFns) -> Result<Self, String> {
+        Self::with_shared(program, options, host).attach(parser, provided)
+    }
+    
+    /// what load needs from the parser besides the code and data
+    fn attach(mut self, parser: &Parser, provided: HostFns) -> Result<Self, String> {
+        self.set_functions(parser.functions().to_vec());
+        self.set_lines(parser.lines().to_vec());
+        self.set_globals(parser.get_symbols());
+        self.link_host_fns(parser.host_fns(), provided)?;
+        Ok(self)
+    }
+    
+    /// creates VM whose syscalls go through the given host
+    pub fn with_host(code: Vec<i64>, data: Vec<u8>, options: &CompileOptions, host: Box<dyn Host>) -> Self {
+        Self::build(Arc::new(Code::decode(&code)), Segment::Owned(data), options, host)
+    }
+    
+    /// creates VM running a shared program, nothing is copied until the program writes to its data
+    pub fn with_shared(program: &SharedProgram, options: &CompileOptions, host: Box<dyn Host>) -> Self {
+        Self::build(program.code.clone(), Segment::Shared(program.data.clone()), options, host)
+    }
+    
+    /// the VM every constructor makes, with the stack and registers set for a run
+    fn build(code: Arc<Code>, data: Segment, options: &CompileOptions, host: Box<dyn Host>) -> Self {
+        // Define stack size and base address, the stack never moves or grows
+        let stack_size = options.stack_size.max(STACK_HEADROOM + 1);
         let stack_base_addr = DATA_STACK_THRESHOLD; // Start stack addresses here
 
-        // Ensure stack vector has enough capacity
-        let total_stack_capacity = stack_base_addr + stack_size;
-        let mut stack = vec![0i64; total_stack_capacity]; // Initialize stack
+        // Only the slots above the base are kept
+        let stack = Stack(vec![0i64; stack_size]);
 
         // Set initial SP and BP relative to the base address
-        let sp = stack_base_addr + stack_size - 20; // Leave room at the top
+        let sp = stack_base_addr + stack_size - STACK_HEADROOM; // Leave room at the top
         let bp = sp;
 
         VM {
             code,
-            data,
+            entry: None,
             pc: 0,
             sp,
             bp,
             ax: 0,
             stack,
-            debug,
+            debug: options.debug,
+            bounds_checks: options.bounds_checks,
+            sanitize: options.sanitize,
+            gc: options.gc,
+            big_endian: options.big_endian,
+            signed_char: options.signed_char,
+            trap_overflow: options.trap_overflow,
+            virtual_time: options.virtual_time,
+            buffering: options.buffering,
+            crlf: options.crlf,
+            env: options.env.clone(),
+            inherit_env: options.inherit_env,
+            env_copies: BTreeMap::new(),
+            allow_exec: options.allow_exec,
+            pending: String::new(),
+            slept_us: 0,
             cycle: 0,
+            max_cycles: options.max_cycles,
+            max_stack_bytes: options.max_stack_bytes,
+            last: 0,
+            stack_top: sp,
+            lowest_sp: sp,
+            heap: Vec::new(),
+            free_spans: Vec::new(),
+            static_end: data.len(),
+            allocated_since_gc: 0,
+            stats: RunStats::default(),
+            functions: Vec::new(),
+            lines: Vec::new(),
+            data,
+            host,
+            host_fns: Vec::new(),
+            observers: Vec::new(),
+            explain_limit: options.explain,
+            explained: 0,
+            globals: Vec::new(),
+            started: false,
+            exit_code: None,
+            touched: None,
         }
     }
     
     /// runs until exit
-    pub fn run(&mut self) -> Result<i64, String> {
-        // Initialize PC, SP, BP
+    pub fn run(&mut self) -> Result<i64, RuntimeError> {
+        self.start()?;
+        let result = loop {
+            match self.step() {
+                Ok(Some(exit_code)) => break self.check_heap().map(|_| exit_code),
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        // buffered output still comes out when the program fails
+        let flushed = self.flush_output();
+        let exit_code = result?;
+        flushed?;
+        Ok(exit_code)
+    }
+    
+    /// executes at most n instructions, reporting what they changed
+    pub fn run_steps(&mut self, n: usize) -> Result<StepResult, RuntimeError> {
+        if let Some(exit_code) = self.exit_code {
+            return Ok(StepResult { steps: 0, exit_code: Some(exit_code), registers: Vec::new(), memory: Vec::new() });
+        }
+        if !self.started {
+            self.started = true;
+            self.start()?;
+        }
+        
+        let before = [("pc", self.pc() as i64), ("sp", self.sp as i64), ("bp", self.bp as i64), ("ax", self.ax)];
+        self.touched = Some(BTreeSet::new());
+        let mut steps = 0;
+        while steps < n && self.exit_code.is_none() {
+            let result = self.step();
+            if result.is_err() {
+                self.touched = None;
+                let _ = self.flush_output();
+            }
+            self.exit_code = result?;
+            steps += 1;
+        }
+        if self.exit_code.is_some() {
+            let checked = self.check_heap();
+            self.flush_output()?;
+            checked?;
+        }
+        
+        let after = [("pc", self.pc() as i64), ("sp", self.sp as i64), ("bp", self.bp as i64), ("ax", self.ax)];
+        let registers = before.iter().zip(after).filter(|(old, new)| old.1 != new.1).map(|(_, new)| new).collect();
+        let memory = self.touched.take().unwrap_or_default().into_iter()
+            .map(|addr| (addr, if addr >= DATA_STACK_THRESHOLD { self.stack.get(addr).copied().unwrap_or(0) } else { self.load_char(addr) as i64 }))
+            .collect();
+        Ok(StepResult { steps, exit_code: self.exit_code, registers, memory })
+    }
+    
+    /// resets the registers a run starts from
+    fn start(&mut self) -> Result<(), RuntimeError> {
+        // Initialize PC
         self.pc = 0;
         
-        // Set cycle counter
+        // Set cycle counter and clock origin
         self.cycle = 0;
-        let max_cycles = 50000; // Instruction limit to prevent infinite loops
+        self.host.start_clock();
         
-        // Main execution loop - run until EXIT or end of code
-        while self.pc < self.code.len() {
-            // Check cycle limit to avoid infinite loops
-            if self.cycle >= max_cycles {
-                return Err(format!("Execution aborted after {} instructions - possible infinite loop", max_cycles));
+        // A set_entry call is made here the way the stub's JSR would, returning just past its operand
+        if let Some(entry) = self.entry {
+            let target = self.jump_target(self.code.index_of(entry as i64), entry as i64)?;
+            self.push(self.code.addr(1) as i64, "JSR")?;
+            self.pc = target;
+        }
+        Ok(())
+    }
+    
+    /// executes one instruction, Some(exit code) once the program is done
+    fn step(&mut self) -> Result<Option<i64>, RuntimeError> {
+        let Some(&instruction) = self.code.instructions.get(self.pc) else {
+            // If code reached end without EXIT, return AX value
+            if self.debug {
+                println!("Program reached end without EXIT instruction. AX = {}", self.ax);
             }
+            return Ok(Some(self.ax));
+        };
+        
+        // Check cycle limit to avoid infinite loops
+        if self.max_cycles != 0 && self.cycle >= self.max_cycles {
+            return Err(RuntimeError::CycleLimit { limit: self.max_cycles });
+        }
+        
+        // Get current opcode
+        self.last = self.pc;
+        let Instruction { op, operand, .. } = instruction;
+        let target = instruction.target();
+        let Some(opcode) = op else {
+            return Err(RuntimeError::UnknownOpcode { op: operand, pc: self.last_pc() });
+        };
+        let op = opcode as u8;
+        
+        // An opcode at the very end was packed without its operand
+        if self.pc + 1 == self.code.instructions.len() && self.last_pc() + 1 + opcode.arity() > self.code.words {
+            return Err(RuntimeError::MissingOperand { pc: self.last_pc() });
+        }
+        self.pc += 1; // Move past opcode and operand
+        
+        // Optional debug output
+        if self.debug {
+            println!("VM LOOP: Processing Opcode {} ({}) at Addr {}", opcode.name(), op, self.last_pc());
+        }
+        
+        // Increment cycle counter
+        self.cycle += 1;
+        
+        if !self.observers.is_empty() {
+            self.notify(opcode, operand);
+        }
+        if self.explained < self.explain_limit {
+            self.explain(opcode, operand);
+        }
+        
+        // Execute the instruction
+        match op {
+            // LEA: Load effective address
+            op if op == OpCode::LEA as u8 => {
+                let offset = operand;
+                
+                // Calculate effective address, negative offsets are locals
+                let addr = (self.bp as i64).checked_add(offset)
+                    .filter(|&addr| addr >= 0)
+                    .ok_or(RuntimeError::InvalidLocal { offset, bp: self.bp })? as usize;
+                
+                if self.debug {
+                    println!("VM DEBUG: LEA - Local var offset {} => address {} (bp={})", offset, addr, self.bp);
+                }
+                
+                self.ax = addr as i64;
+            },
             
-            // Get current opcode
-            let op_addr = self.pc;
-            let op = self.code[self.pc] as u8;
-            self.pc += 1; // Move past opcode
+            // IMM: Load immediate value
+            op if op == OpCode::IMM as u8 => {
+                self.ax = operand;
+                if self.debug {
+                    println!("DEBUG VM: IMM - Loaded immediate value {}", self.ax);
+                }
+            },
             
-            // Optional debug output
-            if self.debug {
-                println!("VM LOOP: Processing Opcode {} ({}) at Addr {}", self.op_to_string(op as usize), op, op_addr);
-            }
+            // JMP: Jump
+            op if op == OpCode::JMP as u8 => {
+                self.pc = self.jump_target(target, operand)?; // Jump target is arg
+            },
+            
+            // JSR: Jump to subroutine
+            op if op == OpCode::JSR as u8 => {
+                let target = self.jump_target(target, operand)?;
+                
+                // Push return address, the word just past the argument
+                self.push(self.pc() as i64, "JSR")?;
+                
+                // Jump to function entry
+                self.pc = target;
+            },
             
-            // Increment cycle counter
-            self.cycle += 1;
+            // BZ: Branch if zero
+            op if op == OpCode::BZ as u8 => {
+                if self.ax == 0 {
+                    self.pc = self.jump_target(target, operand)?;
+                }
+            },
             
-            // Execute the instruction
-            match op {
-                // LEA: Load effective address
-                op if op == OpCode::LEA as u8 => {
-                    let offset = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
+            // BNZ: Branch if not zero
+            op if op == OpCode::BNZ as u8 => {
+                if self.ax != 0 {
+                    self.pc = self.jump_target(target, operand)?;
+                }
+            },
+            
+            // ENT: Enter function
+            op if op == OpCode::ENT as u8 => {
+                let local_size = operand as usize;
+                
+                // Refuse frames that don't fit below sp and the saved bp
+                if self.sp.saturating_sub(DATA_STACK_THRESHOLD) < local_size.saturating_add(1) {
+                    return Err(RuntimeError::StackOverflow { op: "ENT" });
+                }
+                
+                if self.debug {
+                    println!("DEBUG VM: ENT - Creating stack frame with {} local variables", local_size);
+                    println!("DEBUG VM: ENT - Old BP: {}, Old SP: {}", self.bp, self.sp);
                     
-                    // Calculate effective address for a local variable
-                    let addr = self.bp - offset;
+                    // Debug: dump stack before creating stack frame
+                    println!("Stack before function entry:");
+                    let dump_start = self.sp.saturating_sub(5);
+                    self.dump_stack(dump_start, 10);
+                }
+                
+                // Push old base pointer
+                self.push(self.bp as i64, "ENT")?;
+                
+                // Set new base pointer
+                self.bp = self.sp;
+                
+                // The operand is the exact frame size, patched in once the body was parsed
+                if self.debug {
+                    println!("DEBUG VM: ENT - Allocating {} local slots", local_size);
+                }
+                self.sp -= local_size;
+                self.note_stack_depth()?;
+                
+                // Initialize all local variables to zero
+                for i in self.sp..self.bp {
+                    self.stack[i] = 0;
+                    self.touch(i);
+                }
+                
+                if self.debug {
+                    println!("DEBUG VM: ENT - New BP: {}, New SP: {}", self.bp, self.sp);
+                    println!("DEBUG VM: ENT - Reserved space from {} to {}", self.sp, self.bp - 1);
                     
+                    // Debug: dump stack after creating stack frame
+                    println!("Stack after function entry:");
+                    let dump_start = self.sp.saturating_sub(2);
+                    let dump_count = (self.bp - self.sp + 5).min(20);
+                    self.dump_stack(dump_start, dump_count);
+                }
+            },
+            
+            // ADJ: Adjust stack
+            op if op == OpCode::ADJ as u8 => {
+                let n = operand as usize;
+                
+                // Dropping more than the stack holds is an underflow
+                self.sp = self.sp.checked_add(n)
+                    .filter(|&sp| sp <= self.stack.end())
+                    .ok_or(RuntimeError::StackUnderflow { sp: self.sp })?;
+                
+                if self.debug {
+                    println!("DEBUG VM: ADJ - Adjusted stack pointer by {} to {}", n, self.sp);
+                }
+            },
+            
+            // LEV: Leave function
+            op if op == OpCode::LEV as u8 => {
+                // Safety checks
+                if self.bp < DATA_STACK_THRESHOLD || self.bp >= self.stack.end() {
                     if self.debug {
-                        println!("VM DEBUG: LEA - Local var offset {} => address {} (bp={})", offset, addr, self.bp);
+                        println!("ERROR: LEV - Invalid BP value: {}", self.bp);
                     }
-                    
-                    self.ax = addr as i64;
-                },
+                    return Err(RuntimeError::StackCorruption { bp: self.bp });
+                }
+
+                // Clean up stack frame
+                let sp = self.bp;
                 
-                // IMM: Load immediate value
-                op if op == OpCode::IMM as u8 => {
-                    self.ax = self.code[op_addr + 1];
-                    self.pc += 1; // Consume argument
+                // Bounds check for stack access
+                if sp + 1 >= self.stack.end() {
                     if self.debug {
-                        println!("DEBUG VM: IMM - Loaded immediate value {}", self.ax);
+                        println!("ERROR: LEV - Stack frame too small, can't read return address");
                     }
-                },
-                
-                // JMP: Jump
-                op if op == OpCode::JMP as u8 => {
-                    self.pc = self.code[op_addr + 1] as usize; // Jump target is arg
-                },
+                    return Err(RuntimeError::StackCorruption { bp: self.bp });
+                }
                 
-                // JSR: Jump to subroutine
-                op if op == OpCode::JSR as u8 => {
-                    // Push return address
-                    if self.sp == 0 {
-                        return Err("Stack overflow in JSR".to_string());
-                    }
-                    self.sp -= 1;
-                    self.stack[self.sp] = self.pc as i64; // PC is already advanced past arg
-                    
-                    // Jump to function entry
-                    self.pc = self.code[op_addr + 1] as usize; // Jump target is arg
-                },
+                let bp = self.stack[sp];
+                let pc = self.stack[sp + 1];
                 
-                // BZ: Branch if zero
-                op if op == OpCode::BZ as u8 => {
-                    let target = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
-                    if self.ax == 0 {
-                        self.pc = target;
-                    }
-                },
+                if self.debug {
+                    println!("DEBUG VM: LEV - Leaving function with SP={}, BP={}", self.sp, self.bp);
+                    println!("              - Return address: PC={}, new BP={}", pc, bp);
+                }
                 
-                // BNZ: Branch if not zero
-                op if op == OpCode::BNZ as u8 => {
-                    let target = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
-                    if self.ax != 0 {
-                        self.pc = target;
-                    }
-                },
+                self.sp = sp + 2; // Remove frame
+                self.bp = bp as usize;
                 
-                // ENT: Enter function
-                op if op == OpCode::ENT as u8 => {
-                    let local_size = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
-                    
+                // Check if we're returning from main
+                if pc == 0 || bp == 0 {
                     if self.debug {
-                        println!("DEBUG VM: ENT - Creating stack frame with {} local variables", local_size);
-                        println!("DEBUG VM: ENT - Old BP: {}, Old SP: {}", self.bp, self.sp);
-                        
-                        // Debug: dump stack before creating stack frame
-                        println!("Stack before function entry:");
-                        let dump_start = self.sp.saturating_sub(5);
-                        self.dump_stack(dump_start, 10);
+                        println!("  LEV: returning from main function with value {}", self.ax);
                     }
-                    
-                    // Push old base pointer
-                    if self.sp < 2 {
-                        // Grow the stack if needed
-                        if self.debug {
-                            println!("DEBUG VM: ENT - Growing stack to accommodate base pointer");
-                        }
-                        let new_size = self.stack.len() + 64;
-                        self.stack.resize(new_size, 0);
+                    // Return from main function - exit program
+                    return Ok(Some(self.ax));
+                }
+                
+                // Continue execution at return address
+                self.pc = self.jump_target(self.code.index_of(pc), pc)?;
+            },
+            
+            // load int
+            op if op == OpCode::LI as u8 => {
+                let addr = self.ax as usize;
+                
+                if addr < DATA_STACK_THRESHOLD {
+                    // Load from data segment (assuming it's aligned)
+                    if addr + core::mem::size_of::<i64>() > self.data.len() {
+                         return Err(RuntimeError::OobRead { addr, size: self.data.len() });
                     }
-                    
-                    self.sp = self.sp.saturating_sub(1);
-                    self.stack[self.sp] = self.bp as i64;
-                    
-                    // Set new base pointer
-                    self.bp = self.sp;
-                    
-                    // Reserve space for locals - add buffer space based on local_size
-                    // For 0-1 locals: 4 buffer slots
-                    // For 2+ locals: local_size * 2 buffer slots
-                    let buffer_size = if local_size <= 1 { 4 } else { local_size * 2 };
-                    let total_space = local_size + buffer_size;
-                    
+                    self.check_freed(addr, core::mem::size_of::<i64>())?;
+                    let mut bytes = [0u8; 8];
+                    bytes.copy_from_slice(&self.data[addr..addr + core::mem::size_of::<i64>()]);
+                    self.ax = self.int_from_bytes(bytes);
                     if self.debug {
-                        println!("DEBUG VM: ENT - Allocating {} locals with {} buffer slots (total: {})", 
-                                 local_size, buffer_size, total_space);
-                    }
-                    
-                    // Make sure we have enough stack space
-                    if self.sp < total_space + 1 {
-                        println!("DEBUG VM: ENT - Growing stack for local variables");
-                        let needed_space = total_space + 64;  // Add extra buffer
-                        let current_sp = self.sp;
-                        let current_bp = self.bp;
-                        
-                        // Create new stack with more space
-                        let new_size = self.stack.len() + needed_space;
-                        let mut new_stack = vec![0; new_size];
-                        
-                        // Copy existing stack to expanded space
-                        for i in 0..self.stack.len() {
-                            new_stack[i + needed_space] = self.stack[i];
-                        }
-                        
-                        // Update stack pointers
-                        self.sp = current_sp + needed_space;
-                        self.bp = current_bp + needed_space;
-                        self.stack = new_stack;
+                        println!("VM DEBUG: LI - Loaded int {} from data address {}", self.ax, addr);
                     }
-                    
-                    // Now safely allocate space for locals
-                    self.sp = self.sp.saturating_sub(total_space);
-                    
-                    // Initialize all local variables and buffer space to zero
-                    for i in self.sp..self.bp {
-                        self.stack[i] = 0;
+                } else {
+                    // Load from stack
+                    if addr >= self.stack.end() {
+                        return Err(RuntimeError::OobRead { addr, size: self.stack.end() });
                     }
-                    
+                    self.ax = self.stack[addr];
                     if self.debug {
-                        println!("DEBUG VM: ENT - New BP: {}, New SP: {} (added {} buffer slots)", 
-                                self.bp, self.sp, buffer_size);
-                        println!("DEBUG VM: ENT - Reserved space from {} to {}", self.sp, self.bp - 1);
-                        
-                        // Debug: dump stack after creating stack frame
-                        println!("Stack after function entry:");
-                        let dump_start = self.sp.saturating_sub(2);
-                        let dump_count = (self.bp - self.sp + 5).min(20);
-                        self.dump_stack(dump_start, dump_count);
+                        println!("VM DEBUG: LI - Loaded int {} from stack address {}", self.ax, addr);
+                        // Print stack around the loaded address to help debug array issues
+                        self.dump_stack(addr.saturating_sub(3), 6);
                     }
-                },
+                }
                 
-                // ADJ: Adjust stack
-                op if op == OpCode::ADJ as u8 => {
-                    let n = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
-                    
-                    // Check if we need to grow the stack
-                    if self.sp + n >= self.stack.len() {
-                        let new_size = self.sp + n + 64;  // Add some buffer
-                        println!("DEBUG VM: ADJ - Growing stack from {} to {} for adjustment by {}", 
-                                 self.stack.len(), new_size, n);
-                        self.stack.resize(new_size, 0);
+                self.cycle += 1;
+            },
+            
+            // load char
+            op if op == OpCode::LC as u8 => {
+                let addr = self.ax as usize;
+                if addr < DATA_STACK_THRESHOLD {
+                    // Load from data segment
+                    if addr >= self.data.len() {
+                        return Err(RuntimeError::OobRead { addr, size: self.data.len() });
                     }
-                    
-                    self.sp += n;
-                    
+                    self.check_freed(addr, 1)?;
+                    self.ax = self.char_value(self.data[addr]);
                     if self.debug {
-                        println!("DEBUG VM: ADJ - Adjusted stack pointer by {} to {}", n, self.sp);
-                    }
-                },
-                
-                // LEV: Leave function
-                op if op == OpCode::LEV as u8 => {
-                    // Safety checks
-                    if self.bp >= self.stack.len() {
-                        if self.debug {
-                            println!("ERROR: LEV - Invalid BP value: {}", self.bp);
-                        }
-                        return Err("Stack corruption - invalid base pointer".to_string());
+                        println!("DEBUG VM: LC - Loaded char '{}' ({}) from data address {}", self.ax as u8 as char, self.ax, addr);
                     }
-
-                    // Clean up stack frame
-                    let sp = self.bp;
-                    
-                    // Bounds check for stack access
-                    if sp + 1 >= self.stack.len() {
-                        if self.debug {
-                            println!("ERROR: LEV - Stack frame too small, can't read return address");
-                        }
-                        return Err("Stack corruption - can't read return address".to_string());
+                } else {
+                    // Load from stack (lowest byte)
+                    if addr >= self.stack.end() {
+                        return Err(RuntimeError::OobRead { addr, size: self.stack.end() });
                     }
-                    
-                    let bp = self.stack[sp];
-                    let pc = self.stack[sp + 1];
-                    
+                    self.ax = self.char_value(self.stack[addr] as u8);
                     if self.debug {
-                        println!("DEBUG VM: LEV - Leaving function with SP={}, BP={}", self.sp, self.bp);
-                        println!("              - Return address: PC={}, new BP={}", pc, bp);
-                    }
-                    
-                    self.sp = sp + 2; // Remove frame
-                    self.bp = bp as usize;
-                    
-                    // Check if we're returning from main
-                    if pc == 0 || bp == 0 {
-                        if self.debug {
-                            println!("  LEV: returning from main function with value {}", self.ax);
-                        }
-                        // Return from main function - exit program
-                        return Ok(self.ax);
+                        println!("DEBUG VM: LC - Loaded char '{}' ({}) from stack address {}", self.ax as u8 as char, self.ax, addr);
                     }
-                    
-                    // Continue execution at return address
-                    self.pc = pc as usize;
-                },
+                }
+            },
+            
+            // SI: Store int
+            op if op == OpCode::SI as u8 => {
+                // Get address from top of stack
+                let raw_addr_from_stack = self.pop()?;
                 
-                // load int
-                op if op == OpCode::LI as u8 => {
-                    let addr = self.ax as usize;
-                    
-                    if addr < DATA_STACK_THRESHOLD {
-                        // Load from data segment (assuming it's aligned)
-                        if addr + std::mem::size_of::<i64>() > self.data.len() {
-                             return Err(format!("Data segment read out of bounds: addr={}, size={}", addr, self.data.len()));
-                        }
-                        let bytes = self.data[addr..addr + std::mem::size_of::<i64>()].try_into().unwrap();
-                        self.ax = i64::from_ne_bytes(bytes);
-                        if self.debug {
-                            println!("VM DEBUG: LI - Loaded int {} from data address {}", self.ax, addr);
-                        }
-                    } else {
-                        // Load from stack
-                        if addr >= self.stack.len() {
-                            return Err(format!("Stack read out of bounds: addr={}, size={}", addr, self.stack.len()));
-                        }
-                        self.ax = self.stack[addr];
-                        if self.debug {
-                            println!("VM DEBUG: LI - Loaded int {} from stack address {}", self.ax, addr);
-                            // Print stack around the loaded address to help debug array issues
-                            self.dump_stack(addr.saturating_sub(3), 6);
-                        }
-                    }
-                    
-                    self.cycle += 1;
-                },
+                // Print debug info
+                if self.debug {
+                    println!("VM SI HANDLER: Reading address {} from stack[{}]", raw_addr_from_stack, self.sp - 1);
+                    println!("VM SI HANDLER: Checking addr {} < DATA_STACK_THRESHOLD {}", 
+                            raw_addr_from_stack as usize, DATA_STACK_THRESHOLD);
+                }
+                
+                // Convert the address to usize
+                let addr = raw_addr_from_stack as usize;
+                
+                // Get value to store from accumulator
+                let value_to_store = self.ax;
                 
-                // load char
-                op if op == OpCode::LC as u8 => {
-                    let addr = self.ax as usize;
-                    if addr < DATA_STACK_THRESHOLD {
-                        // Load from data segment
-                        if addr >= self.data.len() {
-                            return Err(format!("Data segment read out of bounds: addr={}, size={}", addr, self.data.len()));
+                // Store in appropriate segment based on address range
+                if addr < DATA_STACK_THRESHOLD {
+                    // Store in data segment (for static data)
+                    if addr + core::mem::size_of::<i64>() > self.data.len() {
+                        if self.bounds_checks {
+                            return Err(RuntimeError::OobWrite { addr, size: self.data.len() });
                         }
-                        self.ax = self.data[addr] as i64;
-                        println!("DEBUG VM: LC - Loaded char '{}' ({}) from data address {}", self.ax as u8 as char, self.ax, addr);
-                    } else {
-                        // Load from stack (lowest byte)
-                        if addr >= self.stack.len() {
-                            return Err(format!("Stack read out of bounds: addr={}, size={}", addr, self.stack.len()));
+                        // Resize the data segment to accommodate the new value
+                        let new_size = addr + core::mem::size_of::<i64>() + 64;
+                        if self.debug {
+                            println!("DEBUG VM: SI - Resized data segment to {} for address {}", new_size, addr);
                         }
-                        self.ax = self.stack[addr] & 0xFF;
-                        println!("DEBUG VM: LC - Loaded char '{}' ({}) from stack address {}", self.ax as u8 as char, self.ax, addr);
-                    }
-                },
-                
-                // SI: Store int
-                op if op == OpCode::SI as u8 => {
-                    // Check if we need to read the stack
-                    if self.sp >= self.stack.len() {
-                        return Err(format!("Stack empty in SI: sp={}", self.sp));
+                        self.data.resize(new_size, 0);
                     }
                     
-                    // Get address from top of stack
-                    let raw_addr_from_stack = self.stack[self.sp];
-                    self.sp += 1; // Pop address
+                    self.check_freed(addr, core::mem::size_of::<i64>())?;
                     
-                    // Print debug info
+                    // Store value as bytes in data segment
+                    let bytes = self.int_to_bytes(value_to_store);
+                    self.data[addr..addr + core::mem::size_of::<i64>()].copy_from_slice(&bytes);
+                    self.touch_range(addr, core::mem::size_of::<i64>());
                     if self.debug {
-                        println!("VM SI HANDLER: Reading address {} from stack[{}]", raw_addr_from_stack, self.sp - 1);
-                        println!("VM SI HANDLER: Checking addr {} < DATA_STACK_THRESHOLD {}", 
-                                raw_addr_from_stack as usize, DATA_STACK_THRESHOLD);
+                        println!("DEBUG VM: SI - Stored int {} to data address {}", value_to_store, addr);
                     }
+                } else {
+                    // Store in stack, which has a fixed size
+                    self.check_stack_addr(addr)?;
                     
-                    // Convert the address to usize
-                    let addr = raw_addr_from_stack as usize;
-                    
-                    // Get value to store from accumulator
-                    let value_to_store = self.ax;
-                    
-                    // Store in appropriate segment based on address range
-                    if addr < DATA_STACK_THRESHOLD {
-                        // Store in data segment (for static data)
-                        if addr + std::mem::size_of::<i64>() > self.data.len() {
-                            // Resize the data segment to accommodate the new value
-                            let new_size = addr + std::mem::size_of::<i64>() + 64;
-                            if self.debug {
-                                println!("DEBUG VM: SI - Resized data segment to {} for address {}", new_size, addr);
-                            }
-                            self.data.resize(new_size, 0);
-                        }
-                        
-                        // Store value as bytes in data segment
-                        let bytes = value_to_store.to_ne_bytes();
-                        for i in 0..std::mem::size_of::<i64>() {
-                            self.data[addr + i] = bytes[i];
-                        }
-                        if self.debug {
-                            println!("DEBUG VM: SI - Stored int {} to data address {}", value_to_store, addr);
-                        }
-                    } else {
-                        // Store in stack
-                        if addr >= self.stack.len() {
-                            // Grow the stack to accommodate the address
-                            let new_size = addr + 64;
-                            if self.debug {
-                                println!("DEBUG VM: SI - Growing stack from {} to {} for address {}", self.stack.len(), new_size, addr);
-                            }
-                            self.stack.resize(new_size, 0);
-                        }
-                        
-                        // Store directly in stack as i64
-                        self.stack[addr] = value_to_store;
-                        if self.debug {
-                            println!("DEBUG VM: SI - Stored int {} to stack address {}", value_to_store, addr);
-                        }
+                    // Store directly in stack as i64
+                    self.stack[addr] = value_to_store;
+                    self.touch(addr);
+                    if self.debug {
+                        println!("DEBUG VM: SI - Stored int {} to stack address {}", value_to_store, addr);
                     }
-                    
-                    self.cycle += 1;
-                },
+                }
                 
-                // store char
-                op if op == OpCode::SC as u8 => {
-                    // Pop the address from the stack
-                    if self.sp >= self.stack.len() {
-                        return Err(format!("Stack empty in SC: sp={}", self.sp));
-                    }
-                    let addr = self.stack[self.sp] as usize;
-                    self.sp += 1;
-                    let char_val = (self.ax & 0xFF) as u8;
-                    
-                    if addr < DATA_STACK_THRESHOLD {
-                        // Store to data segment
-                        if addr >= self.data.len() {
-                           self.data.resize(addr + 1, 0);
-                           if self.debug {
-                               println!("DEBUG VM: SC - Resized data segment to {} for address {}", self.data.len(), addr);
-                           }
-                        }
-                        self.data[addr] = char_val;
-                        if self.debug {
-                            println!("DEBUG VM: SC - Stored char '{}' ({}) to data address {}", char_val as char, char_val, addr);
-                        }
-                    } else {
-                         // Store to stack (lowest byte)
-                         if addr >= self.stack.len() {
-                             let new_size = addr + 64; // Add buffer
-                             if self.debug {
-                                 println!("DEBUG VM: SC - Growing stack from {} to {} for address {}", self.stack.len(), new_size, addr);
-                             }
-                             self.stack.resize(new_size, 0);
-                         }
-                         // Modify only the lowest byte, preserving higher bytes
-                         self.stack[addr] = (self.stack[addr] & !0xFF) | (char_val as i64);
-                         if self.debug {
-                             println!("DEBUG VM: SC - Stored char '{}' ({}) to stack address {}, stack[{}] now {}", char_val as char, char_val, addr, addr, self.stack[addr]);
-                         }
-                     }
-                },
+                self.cycle += 1;
+            },
+            
+            // store char
+            op if op == OpCode::SC as u8 => {
+                // Pop the address from the stack
+                let addr = self.pop()? as usize;
+                if self.sanitize && !(-128..=255).contains(&self.ax) {
+                    return Err(RuntimeError::CharOverflow { value: self.ax, pc: self.last_pc() });
+                }
+                let char_val = (self.ax & 0xFF) as u8;
                 
-                // push value
-                op if op == OpCode::PSH as u8 => {
-                    // Check if we need to grow/protect the stack
-                    if self.sp == 0 {
-                        // Grow the stack if needed
-                        if self.debug {
-                            println!("DEBUG VM: PSH - Growing stack to accommodate more pushes");
-                        }
-                        let new_size = self.stack.len() + 64;
-                        let mut new_stack = vec![0; new_size];
-                        
-                        // Copy existing stack to new space
-                        for i in 0..self.stack.len() {
-                            new_stack[i + 64] = self.stack[i];
-                        }
-                        
-                        // Update stack pointers
-                        self.sp += 64;
-                        self.bp += 64;
-                        self.stack = new_stack;
+                if addr < DATA_STACK_THRESHOLD {
+                    // Store to data segment
+                    if addr >= self.data.len() {
+                       if self.bounds_checks {
+                           return Err(RuntimeError::OobWrite { addr, size: self.data.len() });
+                       }
+                       self.data.resize(addr + 1, 0);
+                       if self.debug {
+                           println!("DEBUG VM: SC - Resized data segment to {} for address {}", self.data.len(), addr);
+                       }
                     }
-                    
-                    // Now push the value safely
-                    self.sp = self.sp.saturating_sub(1);
+                    self.check_freed(addr, 1)?;
+                    self.data[addr] = char_val;
+                    self.touch(addr);
                     if self.debug {
-                        println!("DEBUG VM: PSH - Pushing {} onto stack at position {}", self.ax, self.sp);
-                    }
-                    self.stack[self.sp] = self.ax;
-                },
-                
-                // swap top of stack with ax
-                op if op == OpCode::SWP as u8 => {
-                    if self.sp >= self.stack.len() {
-                        return Err("Stack underflow in SWP operation".to_string());
+                        println!("DEBUG VM: SC - Stored char '{}' ({}) to data address {}", char_val as char, char_val, addr);
                     }
-                    let temp = self.stack[self.sp];
-                    self.stack[self.sp] = self.ax;
-                    self.ax = temp;
+                } else {
+                     // Store to stack (lowest byte)
+                     self.check_stack_addr(addr)?;
+                     // Modify only the lowest byte, preserving higher bytes
+                     self.stack[addr] = (self.stack[addr] & !0xFF) | (char_val as i64);
+                     self.touch(addr);
+                     if self.debug {
+                         println!("DEBUG VM: SC - Stored char '{}' ({}) to stack address {}, stack[{}] now {}", char_val as char, char_val, addr, addr, self.stack[addr]);
+                     }
+                 }
+            },
+            
+            // push value
+            op if op == OpCode::PSH as u8 => {
+                if self.debug {
+                    println!("DEBUG VM: PSH - Pushing {} onto stack at position {}", self.ax, self.sp.saturating_sub(1));
+                }
+                self.push(self.ax, "PSH")?;
+            },
+            
+            // swap top of stack with ax
+            op if op == OpCode::SWP as u8 => {
+                if self.sp >= self.stack.end() {
+                    return Err(RuntimeError::StackUnderflow { sp: self.sp });
+                }
+                core::mem::swap(&mut self.stack[self.sp], &mut self.ax);
+                self.touch(self.sp);
+                if self.debug {
                     println!("DEBUG VM: SWP - Swapped with top of stack, AX now = {}", self.ax);
-                },
-                
-                // binary ops
-                op if op == OpCode::OR as u8 => {
-                    self.ax = self.stack[self.sp] | self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::XOR as u8 => {
-                    self.ax = self.stack[self.sp] ^ self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::AND as u8 => {
-                    self.ax = self.stack[self.sp] & self.ax;
-                    self.sp += 1;
-                },
+                }
+            },
+            
+            // push a copy of the top of stack
+            op if op == OpCode::DUP as u8 => {
+                if self.sp >= self.stack.end() {
+                    return Err(RuntimeError::StackUnderflow { sp: self.sp });
+                }
+                self.push(self.stack[self.sp], "DUP")?;
+            },
+            
+            // discard the top of stack
+            op if op == OpCode::POP as u8 => {
+                self.pop()?;
+            },
+            
+            // call a host function, the caller cleans up the arguments
+            op if op == OpCode::HOST as u8 => {
+                self.ax = self.call_host_fn(operand)?;
+            },
+            
+            // binary ops
+            op if op == OpCode::OR as u8 => {
+                self.ax |= self.pop()?;
+            },
+            op if op == OpCode::XOR as u8 => {
+                self.ax ^= self.pop()?;
+            },
+            op if op == OpCode::AND as u8 => {
+                self.ax &= self.pop()?;
+            },
+            
+            // comparisons
+            op if op == OpCode::EQ as u8 => {
+                self.ax = (self.pop()? == self.ax) as i64;
+            },
+            op if op == OpCode::NE as u8 => {
+                self.ax = (self.pop()? != self.ax) as i64;
+            },
+            op if op == OpCode::LT as u8 => {
+                self.ax = (self.pop()? < self.ax) as i64;
+            },
+            op if op == OpCode::GT as u8 => {
+                self.ax = (self.pop()? > self.ax) as i64;
+            },
+            op if op == OpCode::LE as u8 => {
+                self.ax = (self.pop()? <= self.ax) as i64;
+            },
+            op if op == OpCode::GE as u8 => {
+                self.ax = (self.pop()? >= self.ax) as i64;
+            },
+            
+            // bit shifts
+            op if op == OpCode::SHL as u8 => {
+                let lhs = self.pop()?;
+                self.ax = lhs.wrapping_shl(self.shift_amount()?);
+            },
+            op if op == OpCode::SHR as u8 => {
+                let lhs = self.pop()?;
+                self.ax = lhs.wrapping_shr(self.shift_amount()?);
+            },
+            
+            // math ops, two's complement wrapping unless overflow traps
+            // division truncates toward zero and % takes the sign of the dividend, as in C
+            op if op == OpCode::ADD as u8 => {
+                let lhs = self.pop()?;
+                self.ax = if self.trap_overflow {
+                    lhs.checked_add(self.ax).ok_or(RuntimeError::Overflow { op: "ADD" })?
+                } else {
+                    lhs.wrapping_add(self.ax)
+                };
+            },
+            op if op == OpCode::SUB as u8 => {
+                let lhs = self.pop()?;
+                self.ax = if self.trap_overflow {
+                    lhs.checked_sub(self.ax).ok_or(RuntimeError::Overflow { op: "SUB" })?
+                } else {
+                    lhs.wrapping_sub(self.ax)
+                };
+            },
+            op if op == OpCode::MUL as u8 => {
+                let lhs = self.pop()?;
+                self.ax = if self.trap_overflow {
+                    lhs.checked_mul(self.ax).ok_or(RuntimeError::Overflow { op: "MUL" })?
+                } else {
+                    lhs.wrapping_mul(self.ax)
+                };
+            },
+            op if op == OpCode::DIV as u8 => {
+                if self.ax == 0 {
+                    return Err(RuntimeError::DivByZero { pc: self.last_pc() });
+                }
+                let lhs = self.pop()?;
+                self.ax = if self.trap_overflow {
+                    lhs.checked_div(self.ax).ok_or(RuntimeError::Overflow { op: "DIV" })?
+                } else {
+                    lhs.wrapping_div(self.ax)
+                };
+            },
+            op if op == OpCode::MOD as u8 => {
+                if self.ax == 0 {
+                    return Err(RuntimeError::DivByZero { pc: self.last_pc() });
+                }
+                let lhs = self.pop()?;
+                self.ax = if self.trap_overflow {
+                    lhs.checked_rem(self.ax).ok_or(RuntimeError::Overflow { op: "MOD" })?
+                } else {
+                    lhs.wrapping_rem(self.ax)
+                };
+            },
+            
+            // system calls
+            op if op == OpCode::OPEN as u8 => {
+                self.ax = self.syscall_open()?;
+            },
+            op if op == OpCode::READ as u8 => {
+                self.ax = self.syscall_read()?;
+            },
+            op if op == OpCode::CLOS as u8 => {
+                self.ax = self.syscall_close()?;
+            },
+            op if op == OpCode::PRTF as u8 => {
+                let argc = operand as usize;
                 
-                // comparisons
-                op if op == OpCode::EQ as u8 => {
-                    self.ax = (self.stack[self.sp] == self.ax) as i64;
-                    self.sp += 1;
-                },
-                op if op == OpCode::NE as u8 => {
-                    self.ax = (self.stack[self.sp] != self.ax) as i64;
-                    self.sp += 1;
-                },
-                op if op == OpCode::LT as u8 => {
-                    self.ax = (self.stack[self.sp] < self.ax) as i64;
-                    self.sp += 1;
-                },
-                op if op == OpCode::GT as u8 => {
-                    self.ax = (self.stack[self.sp] > self.ax) as i64;
-                    self.sp += 1;
-                },
-                op if op == OpCode::LE as u8 => {
-                    self.ax = (self.stack[self.sp] <= self.ax) as i64;
-                    self.sp += 1;
-                },
-                op if op == OpCode::GE as u8 => {
-                    self.ax = (self.stack[self.sp] >= self.ax) as i64;
-                    self.sp += 1;
-                },
+                // Need at least the format string on the stack
+                if argc == 0 || self.sp.checked_add(argc).is_none_or(|end| end > self.stack.end()) {
+                    return Err(RuntimeError::BadSyscall { name: "printf", detail: format!("invalid argument count {} (sp={})", argc, self.sp) });
+                }
+
+                // Debug info for PRTF call
+                if self.debug {
+                    println!("DEBUG VM: PRTF - Called with {} arguments", argc);
+                }
                 
-                // bit shifts
-                op if op == OpCode::SHL as u8 => {
-                    self.ax = self.stack[self.sp] << self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::SHR as u8 => {
-                    self.ax = self.stack[self.sp] >> self.ax;
-                    self.sp += 1;
-                },
+                // Create a temporary slice reference to the arguments for easier access
+                let t: &[i64] = &self.stack[self.sp..self.sp + argc];
                 
-                // math ops
-                op if op == OpCode::ADD as u8 => {
-                    self.ax = self.stack[self.sp] + self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::SUB as u8 => {
-                    self.ax = self.stack[self.sp] - self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::MUL as u8 => {
-                    self.ax = self.stack[self.sp] * self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::DIV as u8 => {
-                    if self.ax == 0 {
-                        return Err("division by zero".to_string());
-                    }
-                    self.ax = self.stack[self.sp] / self.ax;
-                    self.sp += 1;
-                },
-                op if op == OpCode::MOD as u8 => {
-                    if self.ax == 0 {
-                        return Err("modulo by zero".to_string());
-                    }
-                    self.ax = self.stack[self.sp] % self.ax;
-                    self.sp += 1;
-                },
+                // First argument is the format string address
+                let format_addr = t[argc - 1] as usize; // t[-1] in original code
                 
-                // system calls
-                op if op == OpCode::OPEN as u8 => {
-                    self.ax = self.syscall_open()?;
-                },
-                op if op == OpCode::READ as u8 => {
-                    self.ax = self.syscall_read()?;
-                },
-                op if op == OpCode::CLOS as u8 => {
-                    self.ax = 0; // not supported
-                },
-                op if op == OpCode::PRTF as u8 => {
-                    let argc = self.code[op_addr + 1] as usize;
-                    self.pc += 1; // Consume argument
-
-                    // Debug info for PRTF call
+                // Bounds check, the format may live in either segment
+                let in_stack = format_addr >= DATA_STACK_THRESHOLD && format_addr < self.stack.end();
+                if format_addr >= self.data.len() && !in_stack {
                     if self.debug {
-                        println!("DEBUG VM: PRTF - Called with {} arguments", argc);
-                    }
-                    
-                    // Create a temporary slice reference to the arguments for easier access
-                    let t: &[i64] = &self.stack[self.sp..self.sp + argc];
-                    
-                    // First argument is the format string address
-                    let format_addr = t[argc - 1] as usize; // t[-1] in original code
-                    
-                    // Bounds check
-                    if format_addr >= self.data.len() {
-                        if self.debug {
-                            println!("ERROR: Invalid format string address: {}", format_addr);
-                        }
-                        print!("<invalid format string>");
-                        std::io::stdout().flush().unwrap();
-                        
-                        // Clean up stack
-                        self.sp += argc;
-                        
-                        // Set return value to 0 for error
-                        self.ax = 0;
-                        continue; // Skip the rest of the loop body
-                    }
-                    
-                    // Read format string from data segment
-                    let mut format_str = String::new();
-                    let mut i = format_addr;
-                    while i < self.data.len() && self.data[i] != 0 {
-                        format_str.push(self.data[i] as char);
-                        i += 1;
+                        println!("ERROR: Invalid format string address: {}", format_addr);
                     }
-                    
-                    // Show the format string contents clearly for debugging
-                    if self.debug {
-                        println!("DEBUG VM: PRTF - Format string: \"{}\"", format_str);
-                    }
-                    
-                    // Process format string
-                    let mut result = String::new();
-                    let mut arg_idx = 0; // Track which format specifier we're processing
-                    let format_chars: Vec<char> = format_str.chars().collect();
-                    let mut i = 0;
-
-                    while i < format_chars.len() {
-                        let c = format_chars[i];
-
-                        if c == '%' && i + 1 < format_chars.len() {
-                            let next_c = format_chars[i + 1];
-                            match next_c {
-                                'd' => {
-                                    // Integer format
-                                    if arg_idx < argc - 1 {
-                                        let arg_val = t[argc - 2 - arg_idx];
-                                        result.push_str(&arg_val.to_string());
-                                        arg_idx += 1;
-                                    } else {
-                                        result.push_str("<?>");
-                                    }
-                                    i += 2; // Skip format specifier
-                                },
-                                's' => {
-                                    // String format
-                                    if arg_idx < argc - 1 {
-                                        // Get string address from arg stack
-                                        let str_addr = t[argc - 2 - arg_idx] as usize;
-                                        
-                                        // Read from data segment
-                                        if str_addr < DATA_STACK_THRESHOLD {
-                                            let mut j = str_addr;
-                                            while j < self.data.len() && self.data[j] != 0 {
-                                                result.push(self.data[j] as char);
-                                                j += 1;
-                                            }
-                                        } else {
-                                            // Read from stack segment
-                                            let mut stack_idx = str_addr;
-                                            while stack_idx < self.stack.len() {
-                                                let char_byte = (self.stack[stack_idx] & 0xFF) as u8;
-                                                if char_byte == 0 {
-                                                    break;
-                                                }
-                                                result.push(char_byte as char);
-                                                stack_idx += 1;
-                                            }
-                                        }
-                                        arg_idx += 1;
-                                    } else {
-                                        result.push_str("<?>");
-                                    }
-                                    i += 2; // Skip format specifier
-                                },
-                                '%' => {
-                                    // Literal % character
-                                    result.push('%');
-                                    i += 2; // Skip %%
-                                },
-                                _ => {
-                                    // Unknown format specifier - treat as literal
-                                    result.push('%');
-                                    i += 1;
-                                }
-                            }
-                        } else {
-                            // Regular character
-                            result.push(c);
-                            i += 1;
-                        }
-                    }
-                    
-                    // Print the formatted result
-                    print!("{}", result);
-                    std::io::stdout().flush().unwrap();
+                    self.output("<invalid format string>")?;
                     
                     // Clean up stack
                     self.sp += argc;
                     
-                    // Set return value to length of formatted string
-                    self.ax = result.len() as i64;
-                },
-                op if op == OpCode::MALC as u8 => {
-                    self.ax = self.syscall_malloc()?;
-                },
-                op if op == OpCode::FREE as u8 => {
-                    // not supported
-                    self.sp += 1;
+                    // Set return value to 0 for error
                     self.ax = 0;
-                },
-                op if op == OpCode::MSET as u8 => {
-                    self.ax = self.syscall_memset()?;
-                },
-                op if op == OpCode::MCMP as u8 => {
-                    self.ax = self.syscall_memcmp()?;
-                },
-                op if op == OpCode::EXIT as u8 => {
-                    // Check for valid stack access
-                    if self.sp >= self.stack.len() {
-                        if self.debug {
-                            println!("ERROR: EXIT - Invalid stack pointer: {}", self.sp);
-                        }
-                        return Err("Stack corruption on EXIT - invalid stack pointer".to_string());
-                    }
-                    
-                    let exit_code = self.stack[self.sp];
-                    
+                    return Ok(None); // Skip the rest of the instruction
+                }
+                
+                let format_str = self.read_c_string(format_addr as i64);
+                
+                // Show the format string contents clearly for debugging
+                if self.debug {
+                    println!("DEBUG VM: PRTF - Format string: \"{}\"", format_str);
+                }
+                
+                // Arguments after the format string, in call order
+                let args: Vec<i64> = (0..argc - 1).map(|k| t[argc - 2 - k]).collect();
+                let result = self.format_printf(&format_str, &args);
+                
+                // Print the formatted result
+                self.output(&result)?;
+                
+                // Clean up stack
+                self.sp += argc;
+                
+                // Set return value to length of formatted string
+                self.ax = result.len() as i64;
+            },
+            op if op == OpCode::MALC as u8 => {
+                self.ax = self.syscall_malloc()?;
+            },
+            op if op == OpCode::FREE as u8 => {
+                // the collector decides when memory goes back
+                let ptr = self.pop()?;
+                if !self.gc {
+                    self.syscall_free(ptr)?;
+                }
+                self.ax = 0;
+            },
+            op if op == OpCode::MSET as u8 => {
+                self.ax = self.syscall_memset()?;
+            },
+            op if op == OpCode::MCMP as u8 => {
+                self.ax = self.syscall_memcmp()?;
+            },
+            op if op == OpCode::CLCK as u8 => {
+                self.ax = self.syscall_clock();
+            },
+            op if op == OpCode::USLP as u8 => {
+                self.ax = self.syscall_usleep()?;
+            },
+            op if op == OpCode::HXDP as u8 => {
+                self.ax = self.syscall_hexdump()?;
+            },
+            op if op == OpCode::GENV as u8 => {
+                self.ax = self.syscall_getenv()?;
+            },
+            op if op == OpCode::SYST as u8 => {
+                self.ax = self.syscall_system()?;
+            },
+            op if op == OpCode::POPN as u8 => {
+                self.ax = self.syscall_popen()?;
+            },
+            op if op == OpCode::EXIT as u8 => {
+                // Check for valid stack access
+                if self.sp >= self.stack.end() {
                     if self.debug {
-                        println!("exit({}) cycle = {}", exit_code, self.cycle);
+                        println!("ERROR: EXIT - Invalid stack pointer: {}", self.sp);
                     }
-                    return Ok(exit_code);
-                },
+                    return Err(RuntimeError::StackUnderflow { sp: self.sp });
+                }
+                
+                let exit_code = self.stack[self.sp];
                 
-                // Unknown opcode - error out
-                _ => return Err(format!("unknown instruction: {}", op)),
+                if self.debug {
+                    println!("exit({}) cycle = {}", exit_code, self.cycle);
+                }
+                return Ok(Some(exit_code));
+            },
+            
+            // Unknown opcode - error out
+            _ => return Err(RuntimeError::UnknownOpcode { op: operand, pc: self.last_pc() }),
+        }
+        
+        Ok(None)
+    }
+    
+    /// makes the run start with a call to the function at addr instead of main, call before run(),
+    /// so VMs sharing one program can each start in a different function
+    pub fn set_entry(&mut self, addr: usize) {
+        self.entry = Some(addr);
+    }
+    
+    /// false once the data segment has been copied from the shared program, or it never was shared
+    pub fn shares_data(&self) -> bool {
+        matches!(self.data, Segment::Shared(_))
+    }
+    
+    /// passes argc and argv to main, call before run()
+    pub fn set_args(&mut self, args: &[String]) {
+        // the strings and then the pointer array go in the data segment
+        let mut pointers = Vec::with_capacity(args.len());
+        for arg in args {
+            pointers.push(self.data.len() as i64);
+            self.data.extend_from_slice(arg.as_bytes());
+            self.data.push(0);
+        }
+        let argv = self.data.len().next_multiple_of(8);
+        self.data.resize(argv, 0);
+        for (i, pointer) in pointers.into_iter().enumerate() {
+            self.store_int(argv + i * 8, pointer);
+        }
+        
+        // pushed like the caller of main would, argc first
+        self.sp -= 2;
+        self.stack[self.sp + 1] = args.len() as i64;
+        self.stack[self.sp] = argv as i64;
+    }
+    
+    /// registers a host function taking argc ints, returns its index for Parser::add_host_fn
+    pub fn add_host_fn(&mut self, argc: usize, f: HostFn) -> usize {
+        self.host_fns.push((argc, f));
+        self.host_fns.len() - 1
+    }
+    
+    /// binds the program's host functions, from Parser::host_fns, to the ones provided under
+    /// the same names; replaces any added with add_host_fn
+    pub fn link_host_fns(&mut self, imports: &[(String, usize)], mut provided: HostFns) -> Result<(), String> {
+        self.host_fns.clear();
+        for (name, argc) in imports {
+            let (takes, f) = provided.remove(name)
+                .ok_or_else(|| format!("undefined reference to host function '{}'", name))?;
+            if takes != *argc {
+                return Err(format!("host function '{}' takes {} arguments, declared with {}", name, takes, argc));
+            }
+            self.host_fns.push((takes, f));
+        }
+        Ok(())
+    }
+    
+    /// adds an observer, called before each call, return, syscall and store
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+    
+    /// tells the VM where functions are, used by frames()
+    pub fn set_functions(&mut self, functions: Vec<FunctionInfo>) {
+        self.functions = functions;
+    }
+    
+    /// address of the next instruction to run
+    pub fn pc(&self) -> usize {
+        self.code.addr(self.pc)
+    }
+    
+    /// address of the last instruction started
+    fn last_pc(&self) -> usize {
+        self.code.addr(self.last)
+    }
+    
+    /// base pointer of the innermost frame
+    pub fn bp(&self) -> usize {
+        self.bp
+    }
+    
+    /// int at a data or stack address the way LI reads it, None when out of bounds
+    pub fn read_int(&self, addr: usize) -> Option<i64> {
+        if addr >= DATA_STACK_THRESHOLD {
+            self.stack.get(addr).copied()
+        } else {
+            let bytes = self.data.get(addr..addr.checked_add(8)?)?;
+            Some(self.int_from_bytes(bytes.try_into().ok()?))
+        }
+    }
+    
+    /// char at a data or stack address the way LC reads it, None when out of bounds
+    pub fn read_char(&self, addr: usize) -> Option<u8> {
+        if addr >= DATA_STACK_THRESHOLD {
+            self.stack.get(addr).map(|&slot| (slot & 0xFF) as u8)
+        } else {
+            self.data.get(addr).copied()
+        }
+    }
+    
+    /// decoded instruction starting at an address
+    pub fn instruction(&self, addr: usize) -> Option<Instruction> {
+        let index = self.code.index_of(i64::try_from(addr).ok()?)?;
+        Some(self.code.instructions[index])
+    }
+    
+    /// tells the VM which source line each statement's code came from
+    pub fn set_lines(&mut self, lines: Vec<(usize, usize)>) {
+        self.lines = lines;
+    }
+    
+    /// source line of the statement the code at pc belongs to
+    pub fn line_of(&self, pc: usize) -> Option<usize> {
+        let i = self.lines.partition_point(|&(addr, _)| addr <= pc);
+        i.checked_sub(1).map(|i| self.lines[i].1)
+    }
+    
+    /// names globals for explanations
+    pub fn set_globals(&mut self, symbols: &[Symbol]) {
+        self.globals = symbols.iter()
+            .filter(|s| s.class == SymbolClass::Glo)
+            .map(|s| (s.name.clone(), s.value as usize, s.typ.size().max(1)))
+            .collect();
+    }
+    
+    /// walks the call stack, innermost frame first
+    pub fn frames(&self) -> Vec<Frame> {
+        self.frames_from(self.last_pc())
+    }
+    
+    /// the call stack as the next instruction sees it, for a program paused between steps
+    pub fn paused_frames(&self) -> Vec<Frame> {
+        self.frames_from(self.pc())
+    }
+    
+    /// walks the call stack from the frame holding pc
+    fn frames_from(&self, mut pc: usize) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut bp = self.bp;
+        
+        // a call paused before its ENT has no frame yet, only the return address JSR pushed
+        if pc == self.pc() && self.functions.iter().any(|f| f.start == pc) {
+            match self.stack.get(self.sp) {
+                Some(&return_pc) => pc = return_pc as usize,
+                None => return frames,
             }
         }
         
-        // If code reached end without EXIT, return AX value
-        if self.debug {
-            println!("Program reached end without EXIT instruction. AX = {}", self.ax);
+        // frames exist once ENT has run, so stop at the entry stub
+        while let Some(func) = self.functions.iter().find(|f| f.start < pc && pc < f.end) {
+            // a variadic call pushes its argument count after the arguments
+            let argc = match func.variadic {
+                true => self.stack.get(bp + 2).and_then(|&n| usize::try_from(n).ok()),
+                false => Some(func.params),
+            };
+            let top = argc.and_then(|n| (bp + 1 + usize::from(func.variadic)).checked_add(n));
+            let (Some(argc), Some(top)) = (argc, top) else { break };
+            if top >= self.stack.end() || bp < DATA_STACK_THRESHOLD + func.locals {
+                break; // corrupted frame
+            }
+            let args = (0..argc).map(|i| self.stack[top - i]).collect();
+            let locals = (0..func.locals).map(|i| self.stack[bp - 1 - i]).collect();
+            let return_pc = self.stack[bp + 1] as usize;
+            let caller_bp = self.stack[bp] as usize;
+            frames.push(Frame {
+                function: func.name.clone(),
+                pc,
+                bp,
+                return_pc,
+                args,
+                locals,
+            });
+            
+            // callers always sit higher up the stack
+            if caller_bp <= bp {
+                break;
+            }
+            pc = return_pc;
+            bp = caller_bp;
         }
-        Ok(self.ax)
+        
+        frames
     }
     
-    /// print debug info
-    fn print_debug_info(&self, op: usize, addr: usize, _arg: Option<i64>) { // Arg no longer passed
-        // Disable most debug output but keep important diagnostics
-        if self.debug { // Use the VM's debug flag
-            // Print cycle count and PC
-            println!("DEBUG VM: cycle = {}, PC = {}", self.cycle, self.pc);
-            
-            // Print opcode and AX
-            let opcode_name = self.op_to_string(op);
-            print!("DEBUG VM: Opcode={}, AX={}", opcode_name, self.ax);
-            println!(); // Newline
-            
-            // Print stack pointer, base pointer
-            println!("DEBUG VM: SP = {}, BP = {}", self.sp, self.bp);
+    /// blocks malloc has handed out
+    pub fn heap_blocks(&self) -> &[HeapBlock] {
+        &self.heap
+    }
+    
+    /// instruction, allocation and collection counts so far
+    pub fn stats(&self) -> RunStats {
+        let max_stack_bytes = self.stack_top.saturating_sub(self.stack_high_water()) * core::mem::size_of::<i64>();
+        RunStats { cycles: self.cycle, max_stack_bytes, ..self.stats }
+    }
+    
+    /// lowest stack address used so far
+    pub fn stack_high_water(&self) -> usize {
+        self.lowest_sp.min(self.sp)
+    }
+    
+    /// JSON map of where globals, heap blocks and the stack ended up
+    pub fn memory_map(&self, symbols: &[Symbol]) -> String {
+        let globals: Vec<String> = symbols.iter()
+            .filter(|s| s.class == SymbolClass::Glo)
+            .map(|s| format!(
+                "      {{\"name\": \"{}\", \"addr\": {}, \"size\": {}, \"type\": \"{}\"}}",
+                s.name.escape_default(), s.value, s.typ.size(), s.typ
+            ))
+            .collect();
+        let heap: Vec<String> = self.heap.iter()
+            .map(|b| format!("    {{\"addr\": {}, \"size\": {}}}", b.addr, b.size))
+            .collect();
+        let lowest = self.stack_high_water();
+        
+        // one item per line, or [] when empty
+        let list = |items: &[String], indent: &str| if items.is_empty() {
+            "[]".to_string()
+        } else {
+            format!("[\n{}\n{}]", items.join(",\n"), indent)
+        };
+        
+        let mut out = String::from("{\n");
+        out.push_str(&format!(
+            "  \"data\": {{\n    \"size\": {},\n    \"globals\": {}\n  }},\n",
+            self.data.len(), list(&globals, "    ")
+        ));
+        out.push_str(&format!("  \"heap\": {},\n", list(&heap, "  ")));
+        out.push_str(&format!(
+            "  \"stack\": {{\n    \"base\": {},\n    \"top\": {},\n    \"lowest_sp\": {},\n    \"max_depth\": {}\n  }}\n",
+            DATA_STACK_THRESHOLD, self.stack_top, lowest, self.stack_top.saturating_sub(lowest)
+        ));
+        out.push_str("}\n");
+        out
+    }
+    
+    /// pops top of stack
+    fn pop(&mut self) -> Result<i64, RuntimeError> {
+        if self.sp >= self.stack.end() {
+            return Err(RuntimeError::StackUnderflow { sp: self.sp });
+        }
+        let value = self.stack[self.sp];
+        self.sp += 1;
+        Ok(value)
+    }
+    
+    /// pushes value, failing once the stack is full
+    fn push(&mut self, value: i64, op: &'static str) -> Result<(), RuntimeError> {
+        if self.sp <= DATA_STACK_THRESHOLD || self.sp > self.stack.end() {
+            return Err(RuntimeError::StackOverflow { op });
+        }
+        self.sp -= 1;
+        self.stack[self.sp] = value;
+        self.touch(self.sp);
+        self.note_stack_depth()
+    }
+    
+    /// keeps the stack's high-water mark, failing once it passes max_stack_bytes
+    fn note_stack_depth(&mut self) -> Result<(), RuntimeError> {
+        if self.sp >= self.lowest_sp {
+            return Ok(());
+        }
+        self.lowest_sp = self.sp;
+        let bytes = self.stack_top.saturating_sub(self.sp) * core::mem::size_of::<i64>();
+        if self.max_stack_bytes != 0 && bytes > self.max_stack_bytes {
+            let function = self.current_function().map_or("(entry)", |f| f.name.as_str()).to_string();
+            return Err(RuntimeError::StackLimit { limit: self.max_stack_bytes, function, depth: self.frames().len() });
+        }
+        Ok(())
+    }
+    
+    /// fails unless addr is inside the stack, which never grows
+    fn check_stack_addr(&self, addr: usize) -> Result<(), RuntimeError> {
+        if addr < self.stack.end() {
+            Ok(())
+        } else {
+            Err(RuntimeError::OobWrite { addr, size: self.stack.end() })
+        }
+    }
+    
+    /// checks where a jump, call or return goes, index is where the target address starts an instruction
+    /// address 0 is the entry stub once the functions are known, nothing jumps back to it
+    fn jump_target(&self, index: Option<usize>, target: i64) -> Result<usize, RuntimeError> {
+        let reserved = if self.functions.is_empty() { 0 } else { 1 };
+        match index {
+            Some(index) if index >= reserved => Ok(index),
+            _ => Err(RuntimeError::InvalidJump { target, pc: self.last_pc(), line: self.line_of(self.last_pc()) }),
         }
     }
     
-    /// gets next code value
-    // fn next_code(&mut self) -> i64 { // No longer needed as args are pre-fetched
-    //     let val = self.code[self.pc];
-    //     self.pc += 1;
-    //     val
-    // }
+    /// ax as a shift count, masked to 0..64 like x86 does, an error when overflow traps
+    fn shift_amount(&self) -> Result<u32, RuntimeError> {
+        if self.trap_overflow && !(0..64).contains(&self.ax) {
+            return Err(RuntimeError::InvalidShift { amount: self.ax });
+        }
+        Ok((self.ax & 63) as u32)
+    }
     
     /// loads int from memory
     pub fn load_int(&self, addr: usize) -> i64 {
@@ -772,14 +1531,12 @@ impl VM {
         }
         
         // check bounds
-        if addr < self.data.len() && addr + 7 < self.data.len() {
+        if addr.checked_add(8).is_some_and(|end| end <= self.data.len()) {
             // from data segment
             let mut bytes = [0u8; 8];
-            for i in 0..8 {
-                bytes[i] = self.data[addr + i];
-            }
+            bytes.copy_from_slice(&self.data[addr..addr + 8]);
             
-            let value = i64::from_ne_bytes(bytes);
+            let value = self.int_from_bytes(bytes);
             if self.debug {
                 println!("  Loaded bytes: {:?}, int value: {}", bytes, value);
             }
@@ -802,9 +1559,26 @@ impl VM {
         }
     }
     
-    /// loads char from memory
+    /// a loaded char as an int, sign-extended unless chars are unsigned
+    fn char_value(&self, byte: u8) -> i64 {
+        if self.signed_char { byte as i8 as i64 } else { byte as i64 }
+    }
+    
+    /// int from its 8 bytes in the data segment, little-endian unless big_endian
+    fn int_from_bytes(&self, bytes: [u8; 8]) -> i64 {
+        if self.big_endian { i64::from_be_bytes(bytes) } else { i64::from_le_bytes(bytes) }
+    }
+    
+    /// bytes of an int as the data segment holds them, the same on every host
+    fn int_to_bytes(&self, value: i64) -> [u8; 8] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+    
+    /// loads char from memory, a stack slot holds one char in its low byte
     pub fn load_char(&self, addr: usize) -> u8 {
-        if addr < self.data.len() {
+        if addr >= DATA_STACK_THRESHOLD {
+            self.stack.get(addr).map_or(0, |&slot| (slot & 0xFF) as u8)
+        } else if addr < self.data.len() {
             self.data[addr]
         } else {
             0
@@ -817,89 +1591,536 @@ impl VM {
             println!("  Storing int value: {} at address: {}", val, addr);
         }
         
-        if addr + 7 >= self.data.len() {
+        // only the data segment is addressable here
+        if addr >= DATA_STACK_THRESHOLD {
+            if self.debug {
+                println!("  Ignoring store outside data segment at address {}", addr);
+            }
+            return;
+        }
+        
+        if addr + 8 > self.data.len() {
             // grow if needed
             self.data.resize(addr + 8, 0);
         }
         
-        let bytes = val.to_ne_bytes();
-        for i in 0..8 {
-            self.data[addr + i] = bytes[i];
-        }
+        let bytes = self.int_to_bytes(val);
+        self.data[addr..addr + 8].copy_from_slice(&bytes);
+        self.touch_range(addr, 8);
         
         if self.debug {
             println!("  Stored bytes: {:?}", bytes);
         }
     }
     
+    /// notes a written address for run_steps
+    fn touch(&mut self, addr: usize) {
+        if let Some(touched) = &mut self.touched {
+            touched.insert(addr);
+        }
+    }
+    
+    /// notes len written bytes for run_steps
+    fn touch_range(&mut self, addr: usize, len: usize) {
+        for addr in addr..addr + len {
+            self.touch(addr);
+        }
+    }
+    
     /// stores char to memory
     pub fn store_char(&mut self, addr: usize, val: u8) {
+        if addr >= DATA_STACK_THRESHOLD {
+            // same layout SC uses, the higher bytes are left alone
+            if let Some(slot) = self.stack.get_mut(addr) {
+                *slot = (*slot & !0xFF) | val as i64;
+                self.touch(addr);
+            }
+            return;
+        }
+        
         if addr >= self.data.len() {
             // grow if needed
             self.data.resize(addr + 1, 0);
         }
         
         self.data[addr] = val;
+        self.touch(addr);
+    }
+    
+    /// tells the observers about the instruction about to run
+    fn notify(&mut self, opcode: OpCode, operand: i64) {
+        let top = self.stack.get(self.sp).copied().unwrap_or(0);
+        match opcode {
+            OpCode::JSR => {
+                let addr = operand as usize;
+                let name = self.functions.iter().find(|f| f.start == addr).map(|f| f.name.as_str());
+                for observer in &mut self.observers {
+                    observer.on_call(name, addr);
+                }
+            }
+            OpCode::LEV => {
+                for observer in &mut self.observers {
+                    observer.on_return(self.ax);
+                }
+            }
+            OpCode::SI | OpCode::SC => {
+                let value = if opcode == OpCode::SC { self.ax & 0xFF } else { self.ax };
+                for observer in &mut self.observers {
+                    observer.on_store(top as usize, value);
+                }
+            }
+            _ => {
+                let Some((name, argc)) = syscall_signature(opcode, operand) else {
+                    return;
+                };
+                // the first argument was pushed first, so it sits deepest
+                let end = self.sp.saturating_add(argc).min(self.stack.end());
+                let args: Vec<i64> = self.stack[self.sp.min(end)..end].iter().rev().copied().collect();
+                for observer in &mut self.observers {
+                    observer.on_syscall(name, &args);
+                }
+            }
+        }
+    }
+    
+    /// describes the instruction about to run in plain English
+    fn explain(&mut self, opcode: OpCode, operand: i64) {
+        let text = match opcode {
+            OpCode::JSR => {
+                let addr = operand as usize;
+                match self.functions.iter().find(|f| f.start == addr) {
+                    Some(f) if f.params == 1 => format!("call function {} with 1 argument", f.name),
+                    Some(f) => format!("call function {} with {} arguments", f.name, f.params),
+                    None => format!("call the function at {}", addr),
+                }
+            }
+            OpCode::LEV => format!("return {}", self.ax),
+            OpCode::SI | OpCode::SC => {
+                let addr = self.stack.get(self.sp).copied().unwrap_or(0) as usize;
+                let value = if opcode == OpCode::SC { self.ax & 0xFF } else { self.ax };
+                format!("store {} into {}", value, self.describe_addr(addr))
+            }
+            _ => {
+                let Some((name, argc)) = syscall_signature(opcode, operand) else {
+                    return;
+                };
+                let end = self.sp.saturating_add(argc).min(self.stack.end());
+                let mut args: Vec<String> = self.stack[self.sp.min(end)..end].iter().rev().map(|a| a.to_string()).collect();
+                if matches!(opcode, OpCode::PRTF | OpCode::GENV | OpCode::SYST | OpCode::POPN) && !args.is_empty() {
+                    args[0] = format!("{:?}", self.read_c_string(self.stack[end - 1]));
+                }
+                format!("call {}({})", name, args.join(", "))
+            }
+        };
+        
+        // written like program output so the two interleave
+        self.explained += 1;
+        let function = self.current_function().map_or("(entry)", |f| f.name.as_str());
+        let mut line = format!("[explain] {}: {}\n", function, text);
+        if self.explained == self.explain_limit {
+            line += &format!("[explain] stopping after {} events\n", self.explain_limit);
+        }
+        let _ = self.output(&line);
+    }
+    
+    /// function the last instruction belongs to
+    fn current_function(&self) -> Option<&FunctionInfo> {
+        let pc = self.last_pc();
+        self.functions.iter().find(|f| (f.start..f.end).contains(&pc))
+    }
+    
+    /// names the variable at addr, for explanations
+    fn describe_addr(&self, addr: usize) -> String {
+        if addr >= DATA_STACK_THRESHOLD {
+            let offset = addr as i64 - self.bp as i64;
+            let slots = self.current_function().map_or(&[][..], |f| &f.slots[..]);
+            for (name, start, len) in slots {
+                let kind = if *start > 0 { "parameter" } else { "local" };
+                if offset == *start {
+                    return format!("{} '{}'", kind, name);
+                }
+                if offset > *start && offset < start + *len as i64 {
+                    return format!("byte {} of {} '{}'", offset - start, kind, name);
+                }
+            }
+            format!("stack slot {}", addr)
+        } else {
+            for (name, start, len) in &self.globals {
+                if addr == *start {
+                    return format!("global '{}'", name);
+                }
+                if addr > *start && addr < start + len {
+                    return format!("byte {} of global '{}'", addr - start, name);
+                }
+            }
+            format!("memory at {}", addr)
+        }
+    }
+    
+    /// runs a host function on the arguments at the top of the stack
+    fn call_host_fn(&mut self, index: i64) -> Result<i64, RuntimeError> {
+        let no_such_fn = || RuntimeError::BadSyscall { name: "host", detail: format!("no host function {}", index) };
+        let index = usize::try_from(index).map_err(|_| no_such_fn())?;
+        let argc = self.host_fns.get(index).ok_or_else(no_such_fn)?.0;
+        if self.sp.checked_add(argc).is_none_or(|end| end > self.stack.end()) {
+            return Err(RuntimeError::StackUnderflow { sp: self.sp });
+        }
+        
+        // the first argument was pushed first, so it sits deepest
+        let args: Vec<i64> = self.stack[self.sp..self.sp + argc].iter().rev().copied().collect();
+        let (_, f) = &mut self.host_fns[index];
+        Ok(f(&args))
     }
     
-    /// handles open syscall
-    fn syscall_open(&mut self) -> Result<i64, String> {
-        // minimal support
-        self.sp += 2; // remove args
-        Ok(0) // fake fd
+    /// handles open syscall, files are read-only like c4
+    fn syscall_open(&mut self) -> Result<i64, RuntimeError> {
+        let _flags = self.pop()?;
+        let path = self.pop()?;
+        let path = self.read_c_string(path);
+        Ok(self.host.open(&path))
     }
     
-    /// handles read syscall
-    fn syscall_read(&mut self) -> Result<i64, String> {
-        // minimal support
-        self.sp += 3; // remove args
-        Ok(0) // read nothing
+    /// handles read syscall, fd 0 is stdin
+    fn syscall_read(&mut self) -> Result<i64, RuntimeError> {
+        let count = self.pop()? as usize;
+        let dest = self.pop()? as usize;
+        let fd = self.pop()?;
+        
+        let end = dest.checked_add(count)
+            .filter(|&end| self.span_in_bounds(dest, end, true))
+            .ok_or_else(|| RuntimeError::BadSyscall { name: "read", detail: format!("out of bounds: buf={}, count={}", dest, count) })?;
+        self.check_freed(dest, count)?;
+        
+        // a prompt without a newline shows before the program waits for input
+        if fd == 0 && self.buffering == Buffering::Line {
+            self.flush_output()?;
+        }
+        let mut buf = vec![0u8; count];
+        let read = self.host.read(fd, &mut buf);
+        if read < 0 {
+            return Ok(-1);
+        }
+        
+        if dest < DATA_STACK_THRESHOLD && end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        for (i, &byte) in buf.iter().take(read as usize).enumerate() {
+            self.store_char(dest + i, byte);
+        }
+        Ok(read)
+    }
+    
+    /// handles close syscall
+    fn syscall_close(&mut self) -> Result<i64, RuntimeError> {
+        let fd = self.pop()?;
+        Ok(self.host.close(fd))
     }
     
     /// handles malloc syscall
-    fn syscall_malloc(&mut self) -> Result<i64, String> {
-        let size = self.stack[self.sp] as usize;
-        self.sp += 1;
+    fn syscall_malloc(&mut self) -> Result<i64, RuntimeError> {
+        let size = self.pop()? as usize;
+        
+        if self.gc && self.allocated_since_gc >= GC_THRESHOLD {
+            self.collect()?;
+        }
+        
+        // reuse collected memory, or grow the data segment, kept below the stack addresses
+        // under sanitize the block sits between two red zones
+        let zone = self.red_zone();
+        let extent = size.saturating_add(2 * zone);
+        let start = match self.take_free_span(extent) {
+            Some(start) => start,
+            None => {
+                let start = self.data.len();
+                if extent > DATA_STACK_THRESHOLD - start.min(DATA_STACK_THRESHOLD) {
+                    return Err(RuntimeError::BadSyscall { name: "malloc", detail: format!("{} bytes exceeds data segment", size) });
+                }
+                self.data.resize(start + extent, 0);
+                start
+            }
+        };
+        let addr = start + zone;
+        self.data[start..addr].fill(RED_ZONE_BYTE);
+        self.data[addr..addr + size].fill(0);
+        self.data[addr + size..start + extent].fill(RED_ZONE_BYTE);
         
-        // simple allocation
-        let addr = self.data.len();
-        self.data.resize(addr + size, 0);
+        let at = self.heap.partition_point(|b| b.addr < addr);
+        self.heap.insert(at, HeapBlock { addr, size, site: self.last_pc(), freed: None });
+        self.allocated_since_gc += size;
+        self.stats.allocations += 1;
+        self.stats.live_bytes += size;
         
         Ok(addr as i64)
     }
     
+    /// guard bytes on each side of a block
+    fn red_zone(&self) -> usize {
+        if self.sanitize { RED_ZONE } else { 0 }
+    }
+    
+    /// start of the first collected span with room for len bytes, the rest stays free
+    fn take_free_span(&mut self, len: usize) -> Option<usize> {
+        let index = self.free_spans.iter().position(|&(_, free)| free >= len)?;
+        let (start, free) = self.free_spans[index];
+        if free == len {
+            self.free_spans.remove(index);
+        } else {
+            self.free_spans[index] = (start + len, free - len);
+        }
+        Some(start)
+    }
+    
+    /// mark and sweep: blocks no word on the stack, in the globals or in ax points into,
+    /// directly or through other blocks, are poisoned and their memory reused by malloc
+    pub fn collect(&mut self) -> Result<(), RuntimeError> {
+        let mut marked = vec![false; self.heap.len()];
+        let end = self.stack.end();
+        let mut pending: Vec<i64> = self.stack[self.sp.min(end)..end].to_vec();
+        pending.push(self.ax);
+        pending.extend(self.words(0, self.static_end));
+        while let Some(value) = pending.pop() {
+            if let Some(index) = self.block_containing(value) {
+                if !marked[index] {
+                    marked[index] = true;
+                    let block = self.heap[index];
+                    pending.extend(self.words(block.addr, block.size));
+                }
+            }
+        }
+        
+        let zone = self.red_zone();
+        for index in (0..self.heap.len()).rev() {
+            let block = self.heap[index];
+            if marked[index] || block.freed.is_some() {
+                continue;
+            }
+            self.check_red_zones(&block)?;
+            self.data[block.addr..block.addr + block.size].fill(FREED_BYTE);
+            self.free_spans.push((block.addr - zone, block.size + 2 * zone));
+            self.heap.remove(index);
+            self.stats.collected_blocks += 1;
+            self.stats.collected_bytes += block.size;
+            self.stats.live_bytes -= block.size;
+        }
+        self.allocated_since_gc = 0;
+        self.stats.collections += 1;
+        Ok(())
+    }
+    
+    /// every int that could be stored in start..start + len, at any byte offset
+    fn words(&self, start: usize, len: usize) -> Vec<i64> {
+        let end = (start + len).min(self.data.len());
+        (start..end.saturating_sub(7))
+            .map(|a| self.int_from_bytes(self.data[a..a + 8].try_into().unwrap_or_default()))
+            .collect()
+    }
+    
+    /// index of the block value points into, interior pointers included
+    fn block_containing(&self, value: i64) -> Option<usize> {
+        let addr = usize::try_from(value).ok()?;
+        let index = self.heap.partition_point(|b| b.addr + b.size <= addr);
+        self.heap.get(index).filter(|b| b.addr <= addr).map(|_| index)
+    }
+    
+    /// handles free syscall, blocks are never reused so every freed block stays
+    /// poisoned and later loads, stores or frees of it fail
+    fn syscall_free(&mut self, ptr: i64) -> Result<(), RuntimeError> {
+        let Some(index) = self.heap.iter().position(|b| b.addr as i64 == ptr) else {
+            return Ok(());
+        };
+        let block = self.heap[index];
+        if block.freed.is_some() {
+            return Err(RuntimeError::DoubleFree { block, pc: self.last_pc(), line: self.line_of(self.last_pc()) });
+        }
+        self.check_red_zones(&block)?;
+        self.data[block.addr..block.addr + block.size].fill(FREED_BYTE);
+        self.heap[index].freed = Some(self.last_pc());
+        self.stats.live_bytes -= block.size;
+        Ok(())
+    }
+    
+    /// fails if addr..addr + len overlaps a freed block
+    fn check_freed(&self, addr: usize, len: usize) -> Result<(), RuntimeError> {
+        let end = addr.saturating_add(len);
+        let first = self.heap.partition_point(|b| b.addr + b.size <= addr);
+        match self.heap[first..].iter().take_while(|b| b.addr < end).find(|b| b.freed.is_some()) {
+            Some(block) => Err(RuntimeError::UseAfterFree {
+                addr, block: *block, pc: self.last_pc(), line: self.line_of(self.last_pc()),
+            }),
+            None => Ok(()),
+        }
+    }
+    
+    /// fails on the first red zone byte around block that was overwritten
+    fn check_red_zones(&self, block: &HeapBlock) -> Result<(), RuntimeError> {
+        if !self.sanitize {
+            return Ok(());
+        }
+        let before = block.addr - RED_ZONE..block.addr;
+        let after = block.addr + block.size..block.addr + block.size + RED_ZONE;
+        match before.chain(after).find(|&a| self.data.get(a).is_some_and(|&b| b != RED_ZONE_BYTE)) {
+            Some(addr) => Err(RuntimeError::HeapOverflow { addr, block: *block, line: self.line_of(block.site) }),
+            None => Ok(()),
+        }
+    }
+    
+    /// checks every block's red zones once the program has finished
+    fn check_heap(&self) -> Result<(), RuntimeError> {
+        self.heap.iter().try_for_each(|block| self.check_red_zones(block))
+    }
+    
+    /// handles clock syscall, microseconds since the run started
+    fn syscall_clock(&self) -> i64 {
+        if self.virtual_time {
+            // one microsecond per instruction, plus time spent sleeping
+            (self.cycle as u64).saturating_add(self.slept_us) as i64
+        } else {
+            self.host.elapsed_us() as i64
+        }
+    }
+    
+    /// handles usleep syscall
+    fn syscall_usleep(&mut self) -> Result<i64, RuntimeError> {
+        let micros = self.pop()?.max(0) as u64;
+        if self.virtual_time {
+            self.slept_us = self.slept_us.saturating_add(micros);
+        } else {
+            self.host.sleep_us(micros);
+        }
+        Ok(0)
+    }
+    
+    /// program output, translated and buffered as the options ask
+    fn output(&mut self, text: &str) -> Result<(), RuntimeError> {
+        let text = if self.crlf { text.replace('\n', "\r\n") } else { text.to_string() };
+        match self.buffering {
+            Buffering::Unbuffered => return self.host.write(&text).map_err(RuntimeError::Io),
+            Buffering::Line => {
+                self.pending.push_str(&text);
+                if let Some(end) = self.pending.rfind('\n') {
+                    let rest = self.pending.split_off(end + 1);
+                    let lines = core::mem::replace(&mut self.pending, rest);
+                    self.host.write(&lines).map_err(RuntimeError::Io)?;
+                }
+            }
+            Buffering::Full => {
+                self.pending.push_str(&text);
+                if self.pending.len() >= OUTPUT_BUFFER {
+                    self.flush_output()?;
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// hands any buffered output to the host
+    pub fn flush_output(&mut self) -> Result<(), RuntimeError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let text = core::mem::take(&mut self.pending);
+        self.host.write(&text).map_err(RuntimeError::Io)
+    }
+    
+    /// handles hexdump syscall, prints len bytes from addr like xxd
+    fn syscall_hexdump(&mut self) -> Result<i64, RuntimeError> {
+        let len = self.pop()? as usize;
+        let addr = self.pop()? as usize;
+        if !addr.checked_add(len).is_some_and(|end| self.span_in_bounds(addr, end, false)) {
+            return Ok(-1); // out of bounds
+        }
+        let bytes: Vec<u8> = (addr..addr + len).map(|a| self.load_char(a)).collect();
+        self.output(&crate::image::hexdump(&bytes, addr))?;
+        Ok(0)
+    }
+    
+    /// handles getenv syscall, the value is copied into the data segment once per name
+    fn syscall_getenv(&mut self) -> Result<i64, RuntimeError> {
+        let addr = self.pop()?;
+        let name = self.read_c_string(addr);
+        if let Some(&addr) = self.env_copies.get(&name) {
+            return Ok(addr as i64);
+        }
+        // the last --env of a name wins, like assignments in a shell
+        let value = match self.env.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, value)) => Some(value.clone()),
+            None if self.inherit_env => self.host.getenv(&name),
+            None => None,
+        };
+        let Some(value) = value else {
+            return Ok(0);
+        };
+        let copy = self.data.len();
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+        self.env_copies.insert(name, copy);
+        Ok(copy as i64)
+    }
+    
+    /// handles system syscall, system(0) asks whether commands can run
+    fn syscall_system(&mut self) -> Result<i64, RuntimeError> {
+        let command = self.pop()?;
+        if command == 0 {
+            return Ok(self.allow_exec as i64);
+        }
+        if !self.allow_exec {
+            return Ok(-1);
+        }
+        let command = self.read_c_string(command);
+        // what the program printed so far comes before what the command prints
+        self.flush_output()?;
+        Ok(self.host.system(&command))
+    }
+    
+    /// handles popen syscall, only mode "r", the fd reads the command's output
+    fn syscall_popen(&mut self) -> Result<i64, RuntimeError> {
+        let mode = self.pop()?;
+        let command = self.pop()?;
+        if !self.allow_exec || self.read_c_string(mode) != "r" {
+            return Ok(-1);
+        }
+        let command = self.read_c_string(command);
+        self.flush_output()?;
+        Ok(self.host.popen(&command))
+    }
+    
     /// handles memset syscall
-    fn syscall_memset(&mut self) -> Result<i64, String> {
-        let count = self.stack[self.sp] as usize;
-        let value = self.stack[self.sp + 1] as u8;
-        let dest = self.stack[self.sp + 2] as usize;
-        self.sp += 3;
+    fn syscall_memset(&mut self) -> Result<i64, RuntimeError> {
+        let count = self.pop()? as usize;
+        let value = self.pop()? as u8;
+        let dest = self.pop()? as usize;
         
-        if dest + count > self.data.len() {
-            self.data.resize(dest + count, 0);
+        let end = dest.checked_add(count)
+            .filter(|&end| self.span_in_bounds(dest, end, true))
+            .ok_or_else(|| RuntimeError::BadSyscall { name: "memset", detail: format!("out of bounds: dest={}, count={}", dest, count) })?;
+        self.check_freed(dest, count)?;
+        if dest < DATA_STACK_THRESHOLD && end > self.data.len() {
+            self.data.resize(end, 0);
         }
         
-        for i in 0..count {
-            self.data[dest + i] = value;
+        for addr in dest..end {
+            self.store_char(addr, value);
         }
         
         Ok(dest as i64)
     }
     
     /// handles memcmp syscall
-    fn syscall_memcmp(&mut self) -> Result<i64, String> {
-        let count = self.stack[self.sp] as usize;
-        let s2 = self.stack[self.sp + 1] as usize;
-        let s1 = self.stack[self.sp + 2] as usize;
-        self.sp += 3;
+    fn syscall_memcmp(&mut self) -> Result<i64, RuntimeError> {
+        let count = self.pop()? as usize;
+        let s2 = self.pop()? as usize;
+        let s1 = self.pop()? as usize;
         
-        if s1 + count > self.data.len() || s2 + count > self.data.len() {
+        let in_bounds = |start: usize| start.checked_add(count).is_some_and(|end| self.span_in_bounds(start, end, false));
+        if !in_bounds(s1) || !in_bounds(s2) {
             return Ok(-1); // out of bounds
         }
+        self.check_freed(s1, count)?;
+        self.check_freed(s2, count)?;
         
         for i in 0..count {
-            let a = self.data[s1 + i];
-            let b = self.data[s2 + i];
+            let a = self.load_char(s1 + i);
+            let b = self.load_char(s2 + i);
             if a != b {
                 return Ok((a as i64) - (b as i64));
             }
@@ -908,50 +2129,188 @@ impl VM {
         Ok(0) // identical
     }
     
-    fn op_to_string(&self, op: usize) -> String {
-        match op {
-            x if x == OpCode::LEA as usize => "LEA".to_string(),
-            x if x == OpCode::IMM as usize => "IMM".to_string(),
-            x if x == OpCode::JMP as usize => "JMP".to_string(),
-            x if x == OpCode::JSR as usize => "JSR".to_string(),
-            x if x == OpCode::BZ as usize => "BZ".to_string(),
-            x if x == OpCode::BNZ as usize => "BNZ".to_string(),
-            x if x == OpCode::ENT as usize => "ENT".to_string(),
-            x if x == OpCode::ADJ as usize => "ADJ".to_string(),
-            x if x == OpCode::LEV as usize => "LEV".to_string(),
-            x if x == OpCode::LI as usize => "LI".to_string(),
-            x if x == OpCode::LC as usize => "LC".to_string(),
-            x if x == OpCode::SI as usize => "SI".to_string(),
-            x if x == OpCode::SC as usize => "SC".to_string(),
-            x if x == OpCode::PSH as usize => "PSH".to_string(),
-            x if x == OpCode::SWP as usize => "SWP".to_string(),
-            x if x == OpCode::OR as usize => "OR".to_string(),
-            x if x == OpCode::XOR as usize => "XOR".to_string(),
-            x if x == OpCode::AND as usize => "AND".to_string(),
-            x if x == OpCode::EQ as usize => "EQ".to_string(),
-            x if x == OpCode::NE as usize => "NE".to_string(),
-            x if x == OpCode::LT as usize => "LT".to_string(),
-            x if x == OpCode::GT as usize => "GT".to_string(),
-            x if x == OpCode::LE as usize => "LE".to_string(),
-            x if x == OpCode::GE as usize => "GE".to_string(),
-            x if x == OpCode::SHL as usize => "SHL".to_string(),
-            x if x == OpCode::SHR as usize => "SHR".to_string(),
-            x if x == OpCode::ADD as usize => "ADD".to_string(),
-            x if x == OpCode::SUB as usize => "SUB".to_string(),
-            x if x == OpCode::MUL as usize => "MUL".to_string(),
-            x if x == OpCode::DIV as usize => "DIV".to_string(),
-            x if x == OpCode::MOD as usize => "MOD".to_string(),
-            x if x == OpCode::OPEN as usize => "OPEN".to_string(),
-            x if x == OpCode::READ as usize => "READ".to_string(),
-            x if x == OpCode::CLOS as usize => "CLOS".to_string(),
-            x if x == OpCode::PRTF as usize => "PRTF".to_string(),
-            x if x == OpCode::MALC as usize => "MALC".to_string(),
-            x if x == OpCode::FREE as usize => "FREE".to_string(),
-            x if x == OpCode::MSET as usize => "MSET".to_string(),
-            x if x == OpCode::MCMP as usize => "MCMP".to_string(),
-            x if x == OpCode::EXIT as usize => "EXIT".to_string(),
-            _ => format!("Unknown({})", op),
+    /// checks start..end lies inside one segment, the data segment may be allowed to grow
+    fn span_in_bounds(&self, start: usize, end: usize, data_grows: bool) -> bool {
+        if start >= DATA_STACK_THRESHOLD {
+            end <= self.stack.end()
+        } else if data_grows {
+            end <= DATA_STACK_THRESHOLD
+        } else {
+            end <= self.data.len()
+        }
+    }
+    
+    /// formats printf output the way glibc would for 64-bit ints
+    /// extra args are ignored, a specifier with no arg left is printed as-is
+    pub fn format_printf(&self, format: &str, args: &[i64]) -> String {
+        let chars: Vec<char> = format.chars().collect();
+        let mut out = String::new();
+        let mut next_arg = 0; // next sequential argument
+        let mut i = 0;
+
+        while i < chars.len() {
+            if chars[i] != '%' {
+                out.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            let start = i;
+            i += 1;
+
+            // %n$ positional argument
+            let mut position = None;
+            let mut j = i;
+            while j < chars.len() && chars[j].is_ascii_digit() {
+                j += 1;
+            }
+            if j > i && j < chars.len() && chars[j] == '$' {
+                position = chars[i..j].iter().collect::<String>().parse::<usize>().ok();
+                i = j + 1;
+            }
+
+            // flags
+            let (mut left, mut zero, mut plus, mut space, mut alt) = (false, false, false, false, false);
+            while i < chars.len() {
+                match chars[i] {
+                    '-' => left = true,
+                    '0' => zero = true,
+                    '+' => plus = true,
+                    ' ' => space = true,
+                    '#' => alt = true,
+                    _ => break,
+                }
+                i += 1;
+            }
+
+            // width and precision
+            let mut width: usize = 0;
+            while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+                width = width.saturating_mul(10).saturating_add(d as usize).min(MAX_PRINTF_WIDTH);
+                i += 1;
+            }
+            let mut precision = None;
+            if i < chars.len() && chars[i] == '.' {
+                i += 1;
+                let mut p: usize = 0;
+                while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+                    p = p.saturating_mul(10).saturating_add(d as usize).min(MAX_PRINTF_WIDTH);
+                    i += 1;
+                }
+                precision = Some(p);
+            }
+
+            // length modifiers, ints are already 64-bit so only h/hh narrow
+            let mut bits = 64;
+            while i < chars.len() && matches!(chars[i], 'h' | 'l' | 'z' | 'j' | 't') {
+                if chars[i] == 'h' {
+                    bits = if bits == 16 { 8 } else { 16 };
+                }
+                i += 1;
+            }
+
+            let Some(&conv) = chars.get(i) else {
+                // dangling specifier at the end
+                out.extend(&chars[start..]);
+                break;
+            };
+            i += 1;
+            if conv == '%' {
+                out.push('%');
+                continue;
+            }
+            if !matches!(conv, 'd' | 'i' | 'u' | 'x' | 'X' | 'o' | 'c' | 's' | 'p') {
+                // unknown conversion, keep the text
+                out.extend(&chars[start..i]);
+                continue;
+            }
+
+            let arg = match position {
+                Some(n) => n.checked_sub(1).and_then(|n| args.get(n)),
+                None => {
+                    next_arg += 1;
+                    args.get(next_arg - 1)
+                }
+            };
+            let Some(&arg) = arg else {
+                out.extend(&chars[start..i]);
+                continue;
+            };
+
+            let signed = match bits {
+                8 => arg as i8 as i64,
+                16 => arg as i16 as i64,
+                _ => arg,
+            };
+            let unsigned = match bits {
+                8 => arg as u8 as u64,
+                16 => arg as u16 as u64,
+                _ => arg as u64,
+            };
+            let (sign, mut digits, prefix) = match conv {
+                'd' | 'i' => {
+                    let sign = if signed < 0 { "-" } else if plus { "+" } else if space { " " } else { "" };
+                    (sign, signed.unsigned_abs().to_string(), "")
+                }
+                'u' => ("", unsigned.to_string(), ""),
+                'x' => ("", format!("{:x}", unsigned), if alt && unsigned != 0 { "0x" } else { "" }),
+                'X' => ("", format!("{:X}", unsigned), if alt && unsigned != 0 { "0X" } else { "" }),
+                'o' => ("", format!("{:o}", unsigned), if alt { "0" } else { "" }),
+                'p' => ("", format!("{:x}", unsigned), "0x"),
+                'c' => {
+                    let text = ((arg & 0xFF) as u8 as char).to_string();
+                    out.push_str(&pad(&text, width, left));
+                    continue;
+                }
+                _ => {
+                    let mut text = self.read_c_string(arg);
+                    if let Some(p) = precision {
+                        text = text.chars().take(p).collect();
+                    }
+                    out.push_str(&pad(&text, width, left));
+                    continue;
+                }
+            };
+
+            // precision is the minimum digit count for integers
+            if let Some(p) = precision {
+                if p == 0 && digits == "0" {
+                    digits.clear();
+                }
+                while digits.len() < p {
+                    digits.insert(0, '0');
+                }
+            }
+            let body_len = sign.len() + prefix.len() + digits.len();
+            if zero && !left && precision.is_none() && width > body_len {
+                out.push_str(sign);
+                out.push_str(prefix);
+                out.push_str(&"0".repeat(width - body_len));
+                out.push_str(&digits);
+            } else {
+                out.push_str(&pad(&format!("{}{}{}", sign, prefix, digits), width, left));
+            }
+        }
+
+        out
+    }
+
+    /// reads a NUL-terminated string from the data or stack segment
+    fn read_c_string(&self, addr: i64) -> String {
+        let mut text = String::new();
+        if addr < 0 {
+            return text;
+        }
+        // load_char reads 0 past the end of either segment
+        let mut addr = addr as usize;
+        loop {
+            let byte = self.load_char(addr);
+            if byte == 0 {
+                break;
+            }
+            text.push(byte as char);
+            addr += 1;
         }
+        text
     }
     
     /// debug helper to print stack
@@ -961,17 +2320,17 @@ impl VM {
         }
         
         println!("==== STACK DUMP ====");
-        println!("SP: {}, BP: {}, PC: {}", self.sp, self.bp, self.pc);
+        println!("SP: {}, BP: {}, PC: {}", self.sp, self.bp, self.pc());
         
-        // Ensure start is not underflowing
-        let safe_start = if start > self.stack.len() {
-            0 // If start is too large (unsigned underflow happened), start from 0
+        // Ensure start is inside the stack
+        let safe_start = if start > self.stack.end() {
+            DATA_STACK_THRESHOLD // If start is too large (unsigned underflow happened), start from the base
         } else {
-            start
+            start.max(DATA_STACK_THRESHOLD)
         };
         
         // Calculate end index carefully to avoid overflow
-        let end = std::cmp::min(safe_start.saturating_add(count), self.stack.len());
+        let end = core::cmp::min(safe_start.saturating_add(count), self.stack.end());
         
         // Print stack entries
         for i in safe_start..end {
@@ -981,78 +2340,53 @@ impl VM {
     }
 }
 
-fn opcode_has_argument(op: u8) -> bool {
-    matches!(op,
-        x if x == OpCode::LEA as u8 ||
-             x == OpCode::IMM as u8 ||
-             x == OpCode::JMP as u8 ||
-             x == OpCode::JSR as u8 ||
-             x == OpCode::BZ as u8 ||
-             x == OpCode::BNZ as u8 ||
-             x == OpCode::ENT as u8 ||
-             x == OpCode::ADJ as u8 ||
-             x == OpCode::PRTF as u8
-    )
+/// parses source with the host functions declared first, the one compile every driver shares;
+/// parse registers the builtins itself, so nothing calls init before it. The parser comes back
+/// either way, for its warnings, its errors and whatever did compile
+pub fn compile<'a>(source: &'a str, options: &CompileOptions, host_fns: &[(String, usize)]) -> (Parser<'a>, CompileResult) {
+    let mut parser = Parser::new(source, options.clone());
+    for (name, argc) in host_fns {
+        parser.add_host_fn(name, *argc);
+    }
+    let result = parser.parse();
+    (parser, result)
 }
 
-/// runs compiled code
-pub fn run(source: &str, src: bool, debug: bool) -> Result<i64, String> {
-    // parse source
-    let mut parser = Parser::new(source, src);
-    parser.init()?;
-    let result = parser.parse();
-    
-    if result.is_err() {
-        return Err(result.unwrap_err());
-    }
-    
-    let (code, data) = result.unwrap();
-    
-    // early return if parsing only
-    if src {
-        return Ok(0);
-    }
-    
-    // Print the code in debug mode
-    if debug {
-        println!("Generated code (length: {}):", code.len());
-        let op_names = [
-            "LEA", "IMM", "JMP", "JSR", "BZ", "BNZ", "ENT", "ADJ", "LEV", "LI", "LC", "SI", "SC", "PSH",
-            "OR", "XOR", "AND", "EQ", "NE", "LT", "GT", "LE", "GE", "SHL", "SHR", "ADD", "SUB", "MUL", "DIV", "MOD",
-            "OPEN", "READ", "CLOS", "PRTF", "MALC", "FREE", "MSET", "MCMP", "EXIT",
-        ];
-        
-        let mut i = 0;
-        while i < code.len() {
-            let op = code[i] as usize;
-            if op < op_names.len() {
-                print!("{}: {} ", i, op_names[op]);
-                
-                // Instructions like IMM, JMP, etc. have an immediate operand
-                if op <= OpCode::ADJ as usize && i + 1 < code.len() {
-                    println!("{}", code[i + 1]);
-                    i += 2;
-                } else {
-                    println!();
-                    i += 1;
-                }
-            } else {
-                println!("{}: Unknown op: {}", i, op);
-                i += 1;
-            }
-        }
-    }
-    
-    // execute code
-    let mut vm = VM::new(code, data, debug);
-    let result = vm.run();
-    
-    // show result in debug
-    if let Ok(return_val) = result.as_ref() {
-        if debug {
-            println!("Program executed successfully, returned: {}", return_val);
-        }
+/// compiles and runs source on the default host, giving main's return value
+pub fn run(source: &str, options: &CompileOptions) -> Result<i64, String> {
+    let (parser, compiled) = compile(source, options, &[]);
+    let (code, data) = compiled?;
+    let mut vm = VM::load(&parser, code, data, options, Box::new(DefaultHost::default()), HostFns::new())?;
+    vm.run().map_err(String::from)
+}
+
+/// pads text to width with spaces
+fn pad(text: &str, width: usize, left: bool) -> String {
+    if left {
+        format!("{:<width$}", text, width = width)
+    } else {
+        format!("{:>width$}", text, width = width)
     }
-    
-    result
-} 
\ No newline at end of file
+}
+
+/// C name and argument count of a syscall opcode
+pub(crate) fn syscall_signature(opcode: OpCode, operand: i64) -> Option<(&'static str, usize)> {
+    Some(match opcode {
+        OpCode::OPEN => ("open", 2),
+        OpCode::READ => ("read", 3),
+        OpCode::CLOS => ("close", 1),
+        OpCode::PRTF => ("printf", operand.max(0) as usize),
+        OpCode::MALC => ("malloc", 1),
+        OpCode::FREE => ("free", 1),
+        OpCode::MSET => ("memset", 3),
+        OpCode::MCMP => ("memcmp", 3),
+        OpCode::EXIT => ("exit", 1),
+        OpCode::CLCK => ("clock", 0),
+        OpCode::USLP => ("usleep", 1),
+        OpCode::HXDP => ("hexdump", 2),
+        OpCode::GENV => ("getenv", 1),
+        OpCode::SYST => ("system", 1),
+        OpCode::POPN => ("popen", 2),
+        _ => return None,
+    })
+}
@@ -0,0 +1,26 @@
+// c4test sample: run with c4_rust test-file, each test_* returns 0 to pass
+int calls;
+
+int gcd(int a, int b) {
+  int t;
+  calls = calls + 1;
+  while (b) { t = a % b; a = b; b = t; }
+  return a;
+}
+
+int test_gcd() {
+  if (gcd(12, 18) != 6) return 1;
+  if (gcd(7, 5) != 1) return 2;
+  return 0;
+}
+
+// every test gets a fresh VM, so globals start at 0
+int test_fresh_globals() {
+  if (calls != 0) return 1;
+  gcd(4, 2);
+  return calls - 1;
+}
+
+int test_fresh_globals_again() {
+  return calls;
+}
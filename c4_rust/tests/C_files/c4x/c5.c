@@ -0,0 +1,675 @@
+// c5.c - c4 with structs and switch
+
+// char, int, struct and pointer types
+// if, while, switch, break, return, and expression statements
+// c4.c extended the way the c4x dialect is: the symbol table is a struct
+// and the virtual machine dispatches with a switch, so it compiles itself
+
+// Based on c4.c, written by Robert Swierczek
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <memory.h>
+#include <unistd.h>
+#include <fcntl.h>
+#define int long long
+
+// identifiers, with the outer meaning kept while a local hides it
+struct ident {
+  int tk, hash;
+  char *name;
+  int class, type, val;
+  int hclass, htype, hval;
+  int stag;   // struct type named by this tag, 0 if none
+};
+
+char *p, *lp, // current position in source code
+     *data;   // data/bss pointer
+
+int *e, *le,  // current position in emitted code
+    tk,       // current token
+    ival,     // current token value
+    ty,       // current expression type
+    loc,      // local variable offset
+    line,     // current line number
+    src,      // print source and assembly flag
+    debug;    // print executed instructions
+
+struct ident *id, // currently parsed identifier
+             *sym; // symbol table (simple list of identifiers)
+
+int *cases,   // operand of the innermost switch's last test, for the next case to patch
+    caseval,  // last case value tested, the switch value has had it subtracted
+    *dflt,    // default label of the innermost switch
+    *brks,    // unpatched breaks, chained through their operands
+    brkd,     // depth of the loops and switches a break may leave
+    *ssize,   // size of each struct type
+    nstruct,  // next struct type
+    *mems,    // struct members: struct type, name, type, offset
+    *mend;
+
+// tokens and classes (operators last and in precedence order)
+enum {
+  Num = 128, Fun, Sys, Glo, Loc, Id,
+  Break, Case, Char, Default, Else, Enum, If, Int, Return, Sizeof, Struct, Switch, While,
+  Assign, Cond, Lor, Lan, Or, Xor, And, Eq, Ne, Lt, Gt, Le, Ge, Shl, Shr, Add, Sub, Mul, Div, Mod, Inc, Dec, Brak, Dot, Arrow
+};
+
+// opcodes
+enum { LEA ,IMM ,JMP ,JSR ,BZ  ,BNZ ,ENT ,ADJ ,LEV ,LI  ,LC  ,SI  ,SC  ,PSH ,
+       OR  ,XOR ,AND ,EQ  ,NE  ,LT  ,GT  ,LE  ,GE  ,SHL ,SHR ,ADD ,SUB ,MUL ,DIV ,MOD ,
+       OPEN,READ,CLOS,PRTF,MALC,FREE,MSET,MCMP,EXIT };
+
+// types, struct types are numbered from INT + 1 up to PTR
+enum { CHAR, INT, PTR = 256 };
+
+void next()
+{
+  char *pp;
+
+  while (tk = *p) {
+    ++p;
+    if (tk == '\n') {
+      if (src) {
+        printf("%d: %.*s", line, p - lp, lp);
+        lp = p;
+        while (le < e) {
+          printf("%8.4s", &"LEA ,IMM ,JMP ,JSR ,BZ  ,BNZ ,ENT ,ADJ ,LEV ,LI  ,LC  ,SI  ,SC  ,PSH ,"
+                           "OR  ,XOR ,AND ,EQ  ,NE  ,LT  ,GT  ,LE  ,GE  ,SHL ,SHR ,ADD ,SUB ,MUL ,DIV ,MOD ,"
+                           "OPEN,READ,CLOS,PRTF,MALC,FREE,MSET,MCMP,EXIT,"[*++le * 5]);
+          if (*le <= ADJ) printf(" %d\n", *++le); else printf("\n");
+        }
+      }
+      ++line;
+    }
+    else if (tk == '#') {
+      while (*p != 0 && *p != '\n') ++p;
+    }
+    else if ((tk >= 'a' && tk <= 'z') || (tk >= 'A' && tk <= 'Z') || tk == '_') {
+      pp = p - 1;
+      while ((*p >= 'a' && *p <= 'z') || (*p >= 'A' && *p <= 'Z') || (*p >= '0' && *p <= '9') || *p == '_')
+        tk = tk * 147 + *p++;
+      tk = (tk << 6) + (p - pp);
+      id = sym;
+      while (id->tk) {
+        if (tk == id->hash && !memcmp(id->name, pp, p - pp)) { tk = id->tk; return; }
+        id = id + 1;
+      }
+      id->name = pp;
+      id->hash = tk;
+      tk = id->tk = Id;
+      return;
+    }
+    else if (tk >= '0' && tk <= '9') {
+      if (ival = tk - '0') { while (*p >= '0' && *p <= '9') ival = ival * 10 + *p++ - '0'; }
+      else if (*p == 'x' || *p == 'X') {
+        while ((tk = *++p) && ((tk >= '0' && tk <= '9') || (tk >= 'a' && tk <= 'f') || (tk >= 'A' && tk <= 'F')))
+          ival = ival * 16 + (tk & 15) + (tk >= 'A' ? 9 : 0);
+      }
+      else { while (*p >= '0' && *p <= '7') ival = ival * 8 + *p++ - '0'; }
+      tk = Num;
+      return;
+    }
+    else if (tk == '/') {
+      if (*p == '/') {
+        ++p;
+        while (*p != 0 && *p != '\n') ++p;
+      }
+      else {
+        tk = Div;
+        return;
+      }
+    }
+    else if (tk == '\'' || tk == '"') {
+      pp = data;
+      while (*p != 0 && *p != tk) {
+        if ((ival = *p++) == '\\') {
+          if ((ival = *p++) == 'n') ival = '\n';
+        }
+        if (tk == '"') *data++ = ival;
+      }
+      ++p;
+      if (tk == '"') ival = (int)pp; else tk = Num;
+      return;
+    }
+    else if (tk == '=') { if (*p == '=') { ++p; tk = Eq; } else tk = Assign; return; }
+    else if (tk == '+') { if (*p == '+') { ++p; tk = Inc; } else tk = Add; return; }
+    else if (tk == '-') { if (*p == '-') { ++p; tk = Dec; } else if (*p == '>') { ++p; tk = Arrow; } else tk = Sub; return; }
+    else if (tk == '!') { if (*p == '=') { ++p; tk = Ne; } return; }
+    else if (tk == '<') { if (*p == '=') { ++p; tk = Le; } else if (*p == '<') { ++p; tk = Shl; } else tk = Lt; return; }
+    else if (tk == '>') { if (*p == '=') { ++p; tk = Ge; } else if (*p == '>') { ++p; tk = Shr; } else tk = Gt; return; }
+    else if (tk == '|') { if (*p == '|') { ++p; tk = Lor; } else tk = Or; return; }
+    else if (tk == '&') { if (*p == '&') { ++p; tk = Lan; } else tk = And; return; }
+    else if (tk == '^') { tk = Xor; return; }
+    else if (tk == '%') { tk = Mod; return; }
+    else if (tk == '*') { tk = Mul; return; }
+    else if (tk == '[') { tk = Brak; return; }
+    else if (tk == '?') { tk = Cond; return; }
+    else if (tk == '.') { tk = Dot; return; }
+    else if (tk == '~' || tk == ';' || tk == '{' || tk == '}' || tk == '(' || tk == ')' || tk == ']' || tk == ',' || tk == ':') return;
+  }
+}
+
+// bytes a value of type t takes
+int tsize(int t)
+{
+  if (t == CHAR) return sizeof(char);
+  if (t == INT || t >= PTR) return sizeof(int);
+  return ssize[t];
+}
+
+// loads the value of type ty from the address in a, a struct stays its address
+void load()
+{
+  if (ty == CHAR) *++e = LC;
+  else if (ty == INT || ty >= PTR) *++e = LI;
+}
+
+// parses int, char, or struct NAME with its members when a definition follows
+int basetype()
+{
+  int s, bt, t, sz;
+  struct ident *d;
+
+  if (tk == Int) { next(); return INT; }
+  if (tk == Char) { next(); return CHAR; }
+  next();
+  if (tk != Id) { printf("%d: bad struct name\n", line); exit(-1); }
+  d = id; next();
+  if (!d->stag) {
+    if (nstruct == PTR) { printf("%d: too many structs\n", line); exit(-1); }
+    d->stag = nstruct++;
+  }
+  s = d->stag;
+  if (tk == '{') {
+    if (ssize[s]) { printf("%d: duplicate struct definition\n", line); exit(-1); }
+    next();
+    sz = 0;
+    while (tk != '}') {
+      if (tk != Int && tk != Char && tk != Struct) { printf("%d: bad member declaration\n", line); exit(-1); }
+      bt = basetype();
+      while (tk != ';') {
+        t = bt;
+        while (tk == Mul) { next(); t = t + PTR; }
+        if (tk != Id) { printf("%d: bad member declaration\n", line); exit(-1); }
+        if (t > INT && t < PTR && !ssize[t]) { printf("%d: incomplete struct member\n", line); exit(-1); }
+        if (t != CHAR) sz = (sz + sizeof(int) - 1) & -sizeof(int);
+        *mend++ = s; *mend++ = (int)id; *mend++ = t; *mend++ = sz;
+        sz = sz + tsize(t);
+        next();
+        if (tk == ',') next();
+      }
+      next();
+    }
+    next();
+    sz = (sz + sizeof(int) - 1) & -sizeof(int);
+    ssize[s] = sz ? sz : sizeof(int);
+  }
+  return s;
+}
+
+void expr(int lev)
+{
+  int t, s, *d, *m;
+  struct ident *v;
+
+  if (!tk) { printf("%d: unexpected eof in expression\n", line); exit(-1); }
+  else if (tk == Num) { *++e = IMM; *++e = ival; next(); ty = INT; }
+  else if (tk == '"') {
+    *++e = IMM; *++e = ival; next();
+    while (tk == '"') next();
+    data = (char *)((int)data + sizeof(int) & -sizeof(int)); ty = PTR;
+  }
+  else if (tk == Sizeof) {
+    next(); if (tk == '(') next(); else { printf("%d: open paren expected in sizeof\n", line); exit(-1); }
+    ty = INT; if (tk == Int || tk == Char || tk == Struct) ty = basetype();
+    while (tk == Mul) { next(); ty = ty + PTR; }
+    if (tk == ')') next(); else { printf("%d: close paren expected in sizeof\n", line); exit(-1); }
+    *++e = IMM; *++e = tsize(ty);
+    ty = INT;
+  }
+  else if (tk == Id) {
+    v = id; next();
+    if (tk == '(') {
+      next();
+      t = 0;
+      while (tk != ')') { expr(Assign); *++e = PSH; ++t; if (tk == ',') next(); }
+      next();
+      if (v->class == Sys) *++e = v->val;
+      else if (v->class == Fun) { *++e = JSR; *++e = v->val; }
+      else { printf("%d: bad function call\n", line); exit(-1); }
+      if (t) { *++e = ADJ; *++e = t; }
+      ty = v->type;
+    }
+    else if (v->class == Num) { *++e = IMM; *++e = v->val; ty = INT; }
+    else {
+      if (v->class == Loc) { *++e = LEA; *++e = loc - v->val; }
+      else if (v->class == Glo) { *++e = IMM; *++e = v->val; }
+      else { printf("%d: undefined variable\n", line); exit(-1); }
+      ty = v->type; load();
+    }
+  }
+  else if (tk == '(') {
+    next();
+    if (tk == Int || tk == Char || tk == Struct) {
+      t = basetype();
+      while (tk == Mul) { next(); t = t + PTR; }
+      if (tk == ')') next(); else { printf("%d: bad cast\n", line); exit(-1); }
+      expr(Inc);
+      ty = t;
+    }
+    else {
+      expr(Assign);
+      if (tk == ')') next(); else { printf("%d: close paren expected\n", line); exit(-1); }
+    }
+  }
+  else if (tk == Mul) {
+    next(); expr(Inc);
+    if (ty >= PTR) ty = ty - PTR; else { printf("%d: bad dereference\n", line); exit(-1); }
+    load();
+  }
+  else if (tk == And) {
+    next(); expr(Inc);
+    if (ty > INT && ty < PTR) ; // a struct is already its address
+    else if (*e == LC || *e == LI) --e;
+    else { printf("%d: bad address-of\n", line); exit(-1); }
+    ty = ty + PTR;
+  }
+  else if (tk == '!') { next(); expr(Inc); *++e = PSH; *++e = IMM; *++e = 0; *++e = EQ; ty = INT; }
+  else if (tk == '~') { next(); expr(Inc); *++e = PSH; *++e = IMM; *++e = -1; *++e = XOR; ty = INT; }
+  else if (tk == Add) { next(); expr(Inc); ty = INT; }
+  else if (tk == Sub) {
+    next(); *++e = IMM;
+    if (tk == Num) { *++e = -ival; next(); } else { *++e = -1; *++e = PSH; expr(Inc); *++e = MUL; }
+    ty = INT;
+  }
+  else if (tk == Inc || tk == Dec) {
+    t = tk; next(); expr(Inc);
+    if (*e == LC) { *e = PSH; *++e = LC; }
+    else if (*e == LI) { *e = PSH; *++e = LI; }
+    else { printf("%d: bad lvalue in pre-increment\n", line); exit(-1); }
+    *++e = PSH;
+    *++e = IMM; *++e = (ty >= PTR) ? tsize(ty - PTR) : sizeof(char);
+    *++e = (t == Inc) ? ADD : SUB;
+    *++e = (ty == CHAR) ? SC : SI;
+  }
+  else { printf("%d: bad expression\n", line); exit(-1); }
+
+  while (tk >= lev) { // "precedence climbing" or "Top Down Operator Precedence" method
+    t = ty;
+    if (tk == Assign) {
+      next();
+      if (*e == LC || *e == LI) *e = PSH; else { printf("%d: bad lvalue in assignment\n", line); exit(-1); }
+      expr(Assign); *++e = ((ty = t) == CHAR) ? SC : SI;
+    }
+    else if (tk == Cond) {
+      next();
+      *++e = BZ; d = ++e;
+      expr(Assign);
+      if (tk == ':') next(); else { printf("%d: conditional missing colon\n", line); exit(-1); }
+      *d = (int)(e + 3); *++e = JMP; d = ++e;
+      expr(Cond);
+      *d = (int)(e + 1);
+    }
+    else if (tk == Lor) { next(); *++e = BNZ; d = ++e; expr(Lan); *d = (int)(e + 1); ty = INT; }
+    else if (tk == Lan) { next(); *++e = BZ;  d = ++e; expr(Or);  *d = (int)(e + 1); ty = INT; }
+    else if (tk == Or)  { next(); *++e = PSH; expr(Xor); *++e = OR;  ty = INT; }
+    else if (tk == Xor) { next(); *++e = PSH; expr(And); *++e = XOR; ty = INT; }
+    else if (tk == And) { next(); *++e = PSH; expr(Eq);  *++e = AND; ty = INT; }
+    else if (tk == Eq)  { next(); *++e = PSH; expr(Lt);  *++e = EQ;  ty = INT; }
+    else if (tk == Ne)  { next(); *++e = PSH; expr(Lt);  *++e = NE;  ty = INT; }
+    else if (tk == Lt)  { next(); *++e = PSH; expr(Shl); *++e = LT;  ty = INT; }
+    else if (tk == Gt)  { next(); *++e = PSH; expr(Shl); *++e = GT;  ty = INT; }
+    else if (tk == Le)  { next(); *++e = PSH; expr(Shl); *++e = LE;  ty = INT; }
+    else if (tk == Ge)  { next(); *++e = PSH; expr(Shl); *++e = GE;  ty = INT; }
+    else if (tk == Shl) { next(); *++e = PSH; expr(Add); *++e = SHL; ty = INT; }
+    else if (tk == Shr) { next(); *++e = PSH; expr(Add); *++e = SHR; ty = INT; }
+    else if (tk == Add) {
+      next(); *++e = PSH; expr(Mul);
+      if ((ty = t) >= PTR && (s = tsize(t - PTR)) > 1) { *++e = PSH; *++e = IMM; *++e = s; *++e = MUL;  }
+      *++e = ADD;
+    }
+    else if (tk == Sub) {
+      next(); *++e = PSH; expr(Mul);
+      if (t >= PTR && t == ty) {
+        *++e = SUB;
+        if ((s = tsize(t - PTR)) > 1) { *++e = PSH; *++e = IMM; *++e = s; *++e = DIV; }
+        ty = INT;
+      }
+      else if ((ty = t) >= PTR && (s = tsize(t - PTR)) > 1) { *++e = PSH; *++e = IMM; *++e = s; *++e = MUL; *++e = SUB; }
+      else *++e = SUB;
+    }
+    else if (tk == Mul) { next(); *++e = PSH; expr(Inc); *++e = MUL; ty = INT; }
+    else if (tk == Div) { next(); *++e = PSH; expr(Inc); *++e = DIV; ty = INT; }
+    else if (tk == Mod) { next(); *++e = PSH; expr(Inc); *++e = MOD; ty = INT; }
+    else if (tk == Inc || tk == Dec) {
+      if (*e == LC) { *e = PSH; *++e = LC; }
+      else if (*e == LI) { *e = PSH; *++e = LI; }
+      else { printf("%d: bad lvalue in post-increment\n", line); exit(-1); }
+      *++e = PSH; *++e = IMM; *++e = (ty >= PTR) ? tsize(ty - PTR) : sizeof(char);
+      *++e = (tk == Inc) ? ADD : SUB;
+      *++e = (ty == CHAR) ? SC : SI;
+      *++e = PSH; *++e = IMM; *++e = (ty >= PTR) ? tsize(ty - PTR) : sizeof(char);
+      *++e = (tk == Inc) ? SUB : ADD;
+      next();
+    }
+    else if (tk == Brak) {
+      next(); *++e = PSH; expr(Assign);
+      if (tk == ']') next(); else { printf("%d: close bracket expected\n", line); exit(-1); }
+      if (t < PTR) { printf("%d: pointer type expected\n", line); exit(-1); }
+      if ((s = tsize(t - PTR)) > 1) { *++e = PSH; *++e = IMM; *++e = s; *++e = MUL;  }
+      *++e = ADD;
+      ty = t - PTR; load();
+    }
+    else if (tk == Dot || tk == Arrow) {
+      if (tk == Dot) s = t; else s = t - PTR;
+      if (s <= INT || s >= PTR) { printf("%d: struct expected before member\n", line); exit(-1); }
+      next();
+      if (tk != Id) { printf("%d: bad member name\n", line); exit(-1); }
+      m = mems;
+      while (m < mend && (m[0] != s || m[1] != (int)id)) m = m + 4;
+      if (m == mend) { printf("%d: unknown member\n", line); exit(-1); }
+      next();
+      if (m[3]) { *++e = PSH; *++e = IMM; *++e = m[3]; *++e = ADD; }
+      ty = m[2]; load();
+    }
+    else { printf("%d: compiler error tk=%d\n", line, tk); exit(-1); }
+  }
+}
+
+void stmt()
+{
+  int *a, *b, *c, *d, v;
+
+  if (tk == If) {
+    next();
+    if (tk == '(') next(); else { printf("%d: open paren expected\n", line); exit(-1); }
+    expr(Assign);
+    if (tk == ')') next(); else { printf("%d: close paren expected\n", line); exit(-1); }
+    *++e = BZ; b = ++e;
+    stmt();
+    if (tk == Else) {
+      *b = (int)(e + 3); *++e = JMP; b = ++e;
+      next();
+      stmt();
+    }
+    *b = (int)(e + 1);
+  }
+  else if (tk == While) {
+    next();
+    a = e + 1;
+    if (tk == '(') next(); else { printf("%d: open paren expected\n", line); exit(-1); }
+    expr(Assign);
+    if (tk == ')') next(); else { printf("%d: close paren expected\n", line); exit(-1); }
+    *++e = BZ; b = ++e;
+    c = brks; brks = 0; ++brkd;
+    stmt();
+    *++e = JMP; *++e = (int)a;
+    *b = (int)(e + 1);
+    while (brks) { a = (int *)*brks; *brks = (int)(e + 1); brks = a; }
+    brks = c; --brkd;
+  }
+  else if (tk == Switch) {
+    next();
+    if (tk == '(') next(); else { printf("%d: open paren expected\n", line); exit(-1); }
+    expr(Assign);
+    if (tk == ')') next(); else { printf("%d: close paren expected\n", line); exit(-1); }
+    a = cases; v = caseval; c = brks; d = dflt;
+    *++e = JMP; cases = ++e; // to the first case's test, with the value in a
+    caseval = 0; brks = 0; dflt = 0; ++brkd;
+    stmt();
+    *cases = dflt ? (int)dflt : (int)(e + 1); // where the last test goes when no case matched
+    while (brks) { b = (int *)*brks; *brks = (int)(e + 1); brks = b; }
+    cases = a; caseval = v; brks = c; dflt = d; --brkd;
+  }
+  else if (tk == Case) {
+    if (!cases) { printf("%d: case outside of switch\n", line); exit(-1); }
+    next();
+    *++e = JMP; b = ++e; // the case above falls through past the test
+    *cases = (int)(e + 1);
+    a = e;
+    expr(Or);
+    if (e != a + 2 || a[1] != IMM) { printf("%d: case needs a constant\n", line); exit(-1); }
+    v = *e; e = a;
+    // a holds the value less the cases tested so far, a test subtracts the difference
+    *++e = PSH; *++e = IMM; *++e = v - caseval; *++e = SUB; *++e = BNZ; cases = ++e;
+    caseval = v;
+    *b = (int)(e + 1);
+    if (tk == ':') next(); else { printf("%d: colon expected after case\n", line); exit(-1); }
+  }
+  else if (tk == Default) {
+    if (!cases) { printf("%d: default outside of switch\n", line); exit(-1); }
+    if (dflt) { printf("%d: duplicate default\n", line); exit(-1); }
+    next();
+    if (tk == ':') next(); else { printf("%d: colon expected after default\n", line); exit(-1); }
+    dflt = e + 1;
+  }
+  else if (tk == Break) {
+    if (!brkd) { printf("%d: break outside of a loop or switch\n", line); exit(-1); }
+    next();
+    if (tk == ';') next(); else { printf("%d: semicolon expected\n", line); exit(-1); }
+    *++e = JMP; *++e = (int)brks; brks = e;
+  }
+  else if (tk == Return) {
+    next();
+    if (tk != ';') expr(Assign);
+    *++e = LEV;
+    if (tk == ';') next(); else { printf("%d: semicolon expected\n", line); exit(-1); }
+  }
+  else if (tk == '{') {
+    next();
+    while (tk != '}') stmt();
+    next();
+  }
+  else if (tk == ';') {
+    next();
+  }
+  else {
+    expr(Assign);
+    if (tk == ';') next(); else { printf("%d: semicolon expected\n", line); exit(-1); }
+  }
+}
+
+int main(int argc, char **argv)
+{
+  int fd, bt, ty, poolsz;
+  struct ident *idmain;
+  int *pc, *sp, *bp, a, cycle; // vm registers
+  int i, *t; // temps
+
+  --argc; ++argv;
+  if (argc > 0 && **argv == '-' && (*argv)[1] == 's') { src = 1; --argc; ++argv; }
+  if (argc > 0 && **argv == '-' && (*argv)[1] == 'd') { debug = 1; --argc; ++argv; }
+  if (argc < 1) { printf("usage: c5 [-s] [-d] file ...\n"); return -1; }
+
+  if ((fd = open(*argv, 0)) < 0) { printf("could not open(%s)\n", *argv); return -1; }
+
+  poolsz = 256*1024; // arbitrary size
+  if (!(sym = (struct ident *)malloc(poolsz))) { printf("could not malloc(%d) symbol area\n", poolsz); return -1; }
+  if (!(le = e = malloc(poolsz))) { printf("could not malloc(%d) text area\n", poolsz); return -1; }
+  if (!(data = malloc(poolsz))) { printf("could not malloc(%d) data area\n", poolsz); return -1; }
+  if (!(sp = malloc(poolsz))) { printf("could not malloc(%d) stack area\n", poolsz); return -1; }
+  if (!(ssize = malloc(PTR * sizeof(int)))) { printf("could not malloc(%d) struct area\n", PTR * sizeof(int)); return -1; }
+  if (!(mend = mems = malloc(poolsz))) { printf("could not malloc(%d) member area\n", poolsz); return -1; }
+
+  memset(sym,  0, poolsz);
+  memset(e,    0, poolsz);
+  memset(data, 0, poolsz);
+  memset(ssize, 0, PTR * sizeof(int));
+  nstruct = INT + 1;
+
+  p = "break case char default else enum if int return sizeof struct switch while "
+      "open read close printf malloc free memset memcmp exit void main";
+  i = Break; while (i <= While) { next(); id->tk = i++; } // add keywords to symbol table
+  i = OPEN; while (i <= EXIT) { next(); id->class = Sys; id->type = INT; id->val = i++; } // add library to symbol table
+  next(); id->tk = Char; // handle void type
+  next(); idmain = id; // keep track of main
+
+  if (!(lp = p = malloc(poolsz))) { printf("could not malloc(%d) source area\n", poolsz); return -1; }
+  if ((i = read(fd, p, poolsz-1)) <= 0) { printf("read() returned %d\n", i); return -1; }
+  p[i] = 0;
+  close(fd);
+
+  // parse declarations
+  line = 1;
+  next();
+  while (tk) {
+    bt = INT; // basetype
+    if (tk == Int || tk == Char || tk == Struct) bt = basetype();
+    else if (tk == Enum) {
+      next();
+      if (tk != '{') next();
+      if (tk == '{') {
+        next();
+        i = 0;
+        while (tk != '}') {
+          if (tk != Id) { printf("%d: bad enum identifier %d\n", line, tk); return -1; }
+          next();
+          if (tk == Assign) {
+            next();
+            if (tk != Num) { printf("%d: bad enum initializer\n", line); return -1; }
+            i = ival;
+            next();
+          }
+          id->class = Num; id->type = INT; id->val = i++;
+          if (tk == ',') next();
+        }
+        next();
+      }
+    }
+    while (tk != ';' && tk != '}') {
+      ty = bt;
+      while (tk == Mul) { next(); ty = ty + PTR; }
+      if (tk != Id) { printf("%d: bad global declaration\n", line); return -1; }
+      if (id->class) { printf("%d: duplicate global definition\n", line); return -1; }
+      next();
+      id->type = ty;
+      if (tk == '(') { // function
+        id->class = Fun;
+        id->val = (int)(e + 1);
+        next(); i = 0;
+        while (tk != ')') {
+          ty = INT;
+          if (tk == Int || tk == Char || tk == Struct) ty = basetype();
+          while (tk == Mul) { next(); ty = ty + PTR; }
+          if (ty > INT && ty < PTR) { printf("%d: struct parameter must be a pointer\n", line); return -1; }
+          if (tk != Id) { printf("%d: bad parameter declaration\n", line); return -1; }
+          if (id->class == Loc) { printf("%d: duplicate parameter definition\n", line); return -1; }
+          id->hclass = id->class; id->class = Loc;
+          id->htype  = id->type;  id->type = ty;
+          id->hval   = id->val;   id->val = i++;
+          next();
+          if (tk == ',') next();
+        }
+        next();
+        if (tk != '{') { printf("%d: bad function definition\n", line); return -1; }
+        loc = ++i;
+        next();
+        while (tk == Int || tk == Char || tk == Struct) {
+          bt = basetype();
+          while (tk != ';') {
+            ty = bt;
+            while (tk == Mul) { next(); ty = ty + PTR; }
+            if (tk != Id) { printf("%d: bad local declaration\n", line); return -1; }
+            if (id->class == Loc) { printf("%d: duplicate local definition\n", line); return -1; }
+            if (ty > INT && ty < PTR && !ssize[ty]) { printf("%d: incomplete struct variable\n", line); return -1; }
+            id->hclass = id->class; id->class = Loc;
+            id->htype  = id->type;  id->type = ty;
+            id->hval   = id->val;   id->val = i = i + (tsize(ty) + sizeof(int) - 1) / sizeof(int);
+            next();
+            if (tk == ',') next();
+          }
+          next();
+        }
+        *++e = ENT; *++e = i - loc;
+        while (tk != '}') stmt();
+        *++e = LEV;
+        id = sym; // unwind symbol table locals
+        while (id->tk) {
+          if (id->class == Loc) {
+            id->class = id->hclass;
+            id->type = id->htype;
+            id->val = id->hval;
+          }
+          id = id + 1;
+        }
+      }
+      else {
+        if (ty > INT && ty < PTR && !ssize[ty]) { printf("%d: incomplete struct variable\n", line); return -1; }
+        id->class = Glo;
+        id->val = (int)data;
+        data = data + ((tsize(ty) + sizeof(int) - 1) & -sizeof(int));
+      }
+      if (tk == ',') next();
+    }
+    next();
+  }
+
+  if (!(pc = (int *)idmain->val)) { printf("main() not defined\n"); return -1; }
+  if (src) return 0;
+
+  // setup stack
+  bp = sp = (int *)((int)sp + poolsz);
+  *--sp = EXIT; // call exit if main returns
+  *--sp = PSH; t = sp;
+  *--sp = argc;
+  *--sp = (int)argv;
+  *--sp = (int)t;
+
+  // run...
+  cycle = 0;
+  while (1) {
+    i = *pc++; ++cycle;
+    if (debug) {
+      printf("%d> %.4s", cycle,
+        &"LEA ,IMM ,JMP ,JSR ,BZ  ,BNZ ,ENT ,ADJ ,LEV ,LI  ,LC  ,SI  ,SC  ,PSH ,"
+         "OR  ,XOR ,AND ,EQ  ,NE  ,LT  ,GT  ,LE  ,GE  ,SHL ,SHR ,ADD ,SUB ,MUL ,DIV ,MOD ,"
+         "OPEN,READ,CLOS,PRTF,MALC,FREE,MSET,MCMP,EXIT,"[i * 5]);
+      if (i <= ADJ) printf(" %d\n", *pc); else printf("\n");
+    }
+    switch (i) {
+      case LEA: a = (int)(bp + *pc++); break;                             // load local address
+      case IMM: a = *pc++; break;                                         // load global address or immediate
+      case JMP: pc = (int *)*pc; break;                                   // jump
+      case JSR: *--sp = (int)(pc + 1); pc = (int *)*pc; break;            // jump to subroutine
+      case BZ:  pc = a ? pc + 1 : (int *)*pc; break;                      // branch if zero
+      case BNZ: pc = a ? (int *)*pc : pc + 1; break;                      // branch if not zero
+      case ENT: *--sp = (int)bp; bp = sp; sp = sp - *pc++; break;         // enter subroutine
+      case ADJ: sp = sp + *pc++; break;                                   // stack adjust
+      case LEV: sp = bp; bp = (int *)*sp++; pc = (int *)*sp++; break;     // leave subroutine
+      case LI:  a = *(int *)a; break;                                     // load int
+      case LC:  a = *(char *)a; break;                                    // load char
+      case SI:  *(int *)*sp++ = a; break;                                 // store int
+      case SC:  a = *(char *)*sp++ = a; break;                            // store char
+      case PSH: *--sp = a; break;                                         // push
+
+      case OR:  a = *sp++ |  a; break;
+      case XOR: a = *sp++ ^  a; break;
+      case AND: a = *sp++ &  a; break;
+      case EQ:  a = *sp++ == a; break;
+      case NE:  a = *sp++ != a; break;
+      case LT:  a = *sp++ <  a; break;
+      case GT:  a = *sp++ >  a; break;
+      case LE:  a = *sp++ <= a; break;
+      case GE:  a = *sp++ >= a; break;
+      case SHL: a = *sp++ << a; break;
+      case SHR: a = *sp++ >> a; break;
+      case ADD: a = *sp++ +  a; break;
+      case SUB: a = *sp++ -  a; break;
+      case MUL: a = *sp++ *  a; break;
+      case DIV: a = *sp++ /  a; break;
+      case MOD: a = *sp++ %  a; break;
+
+      case OPEN: a = open((char *)sp[1], *sp); break;
+      case READ: a = read(sp[2], (char *)sp[1], *sp); break;
+      case CLOS: a = close(*sp); break;
+      case PRTF: t = sp + pc[1]; a = printf((char *)t[-1], t[-2], t[-3], t[-4], t[-5], t[-6]); break;
+      case MALC: a = (int)malloc(*sp); break;
+      case FREE: free((void *)*sp); break;
+      case MSET: a = (int)memset((char *)sp[2], sp[1], *sp); break;
+      case MCMP: a = memcmp((char *)sp[2], (char *)sp[1], *sp); break;
+      case EXIT: printf("exit(%d) cycle = %d\n", *sp, cycle); return *sp;
+      default: printf("unknown instruction = %d! cycle = %d\n", i, cycle); return -1;
+    }
+  }
+}
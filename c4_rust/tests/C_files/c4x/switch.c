@@ -0,0 +1,44 @@
+// c4x sample: switch with fallthrough, default and break
+int calls;
+enum { ADD, SUB, MUL, HALT };
+
+int eval(int op, int a, int b) {
+  calls = calls + 1;
+  switch (op) {
+    case ADD: return a + b;
+    case SUB: return a - b;
+    case MUL: return a * b;
+    default: return -1;
+  }
+  return 0;
+}
+
+int classify(int n) {
+  int kind;
+  kind = 0;
+  switch (n % 4) {
+    case 0:
+      kind = kind + 100;
+    case 1:
+      kind = kind + 10;
+      break;
+    case -1:
+      kind = 5;
+      break;
+  }
+  return kind;
+}
+
+int main() {
+  int i;
+  int total;
+  total = 0;
+  i = 0;
+  while (i < 8) {
+    total = total + classify(i);
+    i = i + 1;
+  }
+  printf("eval: %d %d %d %d\n", eval(ADD, 6, 7), eval(SUB, 6, 7), eval(MUL, 6, 7), eval(HALT, 0, 0));
+  printf("classify total: %d, eval calls: %d\n", total, calls);
+  return total;
+}
@@ -0,0 +1,142 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{self, RuntimeError, VM};
+
+/// compiles and runs a program, returning main's result
+fn run(source: &str) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new())
+}
+
+#[test]
+fn test_compound_assignment_on_locals_and_globals() {
+    assert_eq!(run("int main() { int x; x = 5; x += 3; x *= 2; x -= 1; return x; }"), Ok(15));
+    assert_eq!(run("int g; int main() { g = 10; g <<= 2; g |= 1; return g; }"), Ok(41));
+    assert_eq!(run("int main() { char c; c = 250; c += 10; return c; }"), Ok(4));
+}
+
+#[test]
+fn test_compound_assignment_through_lvalues() {
+    assert_eq!(run("int main() { int a[4]; a[1] = 2; a[1] += 40; return a[1]; }"), Ok(42));
+    assert_eq!(run("int main() { int x; int *p; x = 3; p = &x; *p *= 7; return x; }"), Ok(21));
+}
+
+#[test]
+fn test_compound_assignment_scales_pointers() {
+    let source = "int main() { int a[4]; int *p; a[2] = 9; p = a; p += 2; return *p; }";
+    assert_eq!(run(source), Ok(9));
+}
+
+#[test]
+fn test_compound_assignment_evaluates_address_once() {
+    let source = "int main() { int x; x = 1; x += 2; return x; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, _) = parser.parse().unwrap();
+
+    // LEA x; PSH; LI; PSH; IMM 2; ADD; SI
+    let expected = [
+        OpCode::LEA as i64, -1,
+        OpCode::PSH as i64,
+        OpCode::LI as i64,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 2,
+        OpCode::ADD as i64,
+        OpCode::SI as i64,
+    ];
+    assert!(code.windows(expected.len()).any(|w| w == expected), "code: {:?}", code);
+}
+
+#[test]
+fn test_post_increment_yields_old_value() {
+    assert_eq!(run("int main() { int x; int y; x = 5; y = x++; return y * 10 + x; }"), Ok(56));
+    assert_eq!(run("int g; int main() { char c; g = 7; c = g--; return c * 10 + g; }"), Ok(76));
+}
+
+#[test]
+fn test_post_increment_on_lvalues() {
+    assert_eq!(run("int main() { int a[3]; a[1] = 2; a[1]++; return a[1]; }"), Ok(3));
+    let source = "int main() { int a[3]; int *p; int y; a[0] = 1; a[1] = 2; p = a; y = *p++; return y * 10 + *p; }";
+    assert_eq!(run(source), Ok(12));
+}
+
+#[test]
+fn test_post_increment_leaves_stack_balanced() {
+    // a leaked slot per iteration would shift the locals out from under LEA
+    let source = "int main() { int i; int n; i = 0; n = 0; while (i < 100) { n = n + i++; } return n; }";
+    assert_eq!(run(source), Ok(4950));
+}
+
+/// compiles source, returning the warnings
+fn warnings(source: &str) -> Vec<String> {
+    let (parser, compiled) = vm::compile(source, &CompileOptions::new(), &[]);
+    compiled.unwrap();
+    parser.warnings().to_vec()
+}
+
+#[test]
+fn test_constant_char_store_out_of_range_warns() {
+    let source = "char g;\nint main() {\n  char c;\n  char *p;\n  c = 300;\n  p = &c;\n  *p = 256;\n  g = 999;\n  return 0;\n}";
+    assert_eq!(warnings(source), vec![
+        "Line 5: constant 300 does not fit in a char, it becomes 44",
+        "Line 7: constant 256 does not fit in a char, it becomes 0",
+        "Line 8: constant 999 does not fit in a char, it becomes -25",
+    ]);
+    // in range, signed or unsigned, and stores into ints are fine
+    assert!(warnings("int main() { char c; int i; c = 255; c = -1; c = 'A'; i = 300; return 0; }").is_empty());
+}
+
+#[test]
+fn test_sanitize_traps_truncating_char_store() {
+    let source = "int main() { char c; int i; i = 250; c = i + 10; return c; }";
+    assert_eq!(run(source), Ok(4));
+
+    let options = CompileOptions::new().sanitize(true);
+    let mut parser = Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::with_options(code, data, &options);
+    assert!(matches!(vm.run(), Err(RuntimeError::CharOverflow { value: 260, .. })));
+}
+
+#[test]
+fn test_global_initializers() {
+    let source = "enum { SIZE = 4 };
+char *msg = \"hello, \" \"world\", *empty = \"\";
+int count = SIZE * 3 + 1, *at = &count;
+char name[4] = \"bob\", initial = 'Z';
+int main() { return (msg[7] == 'w') + (*empty == 0) * 2 + (*at == 13) * 4 + (name[2] == 'b') * 8 + (initial == 'Z') * 16; }";
+    assert_eq!(run(source), Ok(31));
+
+    // the string's address is stored in the byte order the VM reads
+    let options = CompileOptions::new().big_endian(true);
+    let mut parser = Parser::new("char *m = \"hi\"; int main() { return m[1]; }", options.clone());
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(VM::with_options(code, data, &options).run(), Ok('i' as i64));
+}
+
+#[test]
+fn test_global_initializer_lists() {
+    let source = "int primes[5] = { 2, 3, 5, 7, };
+char vowels[6] = { 'a', 'e', 'i' };
+char *words[3] = { \"zero\", \"one\" };
+int main() { return primes[3] * 100 + (primes[4] == 0) * 10 + (vowels[1] == 'e') + (vowels[3] == 0) + (words[1][0] == 'o') + (words[2] == 0); }";
+    assert_eq!(run(source), Ok(700 + 10 + 4));
+}
+
+#[test]
+fn test_bad_global_initializers() {
+    let cases = [
+        ("int x = \"s\";", "Line 1: a string can only initialize a char * or char array, not 'x'"),
+        ("char b[2] = \"abc\";", "Line 1: string of 3 chars doesn't fit in 'b', an array of 2"),
+        ("int a[2] = 1;", "Line 1: array 'a' needs a list in braces, or a string for a char array"),
+        ("int g; int y = g + 1;", "Line 1: global initializer must be a constant"),
+        ("int a[2] = { 1, 2, 3 };", "Line 1: too many initializers for 'a', an array of 2"),
+        ("int a[2] = { \"s\" };", "Line 1: a string can only initialize a char * or char array, not 'a'"),
+        ("int a[2] = { 1 2 };", "Line 1: Expected '}' after the initializer list"),
+    ];
+    for (declaration, error) in cases {
+        assert_eq!(run(&format!("{} int main() {{ return 0; }}", declaration)), Err(error.to_string()));
+    }
+    let error = run("int main() { int a[2] = { 1, 2 }; return 0; }").unwrap_err();
+    assert_eq!(error, "Line 1: only global arrays and structs can have an initializer list");
+}
@@ -0,0 +1,71 @@
+use c4_rust::ast::{BinaryOp, Decl, ExprKind, Forward, Params, StmtKind, TypeSpec, UnaryOp};
+use c4_rust::codegen::CodeGen;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{Parser, Type};
+use c4_rust::pipeline::parse;
+
+/// the syntax tree of a source that must parse
+fn tree(source: &str) -> c4_rust::ast::Program {
+    parse(source, &CompileOptions::new()).unwrap()
+}
+
+/// code and data from generating a parsed tree, the way parse() does
+fn generate(source: &str, options: CompileOptions) -> (Vec<i64>, Vec<u8>) {
+    let program = Parser::new(source, options.clone()).parse_ast().unwrap();
+    let mut gen = CodeGen::new(options);
+    gen.declare_builtins();
+    gen.generate(&program, true).unwrap()
+}
+
+#[test]
+fn test_tree_keeps_the_shape_of_the_source() {
+    let program = tree("int g;\nint main() {\n    int x;\n    x = 1 + 2 * 3;\n    if (x) return -x;\n    return 0;\n}");
+    assert_eq!(program.forward[0], Forward::Global { name: "g".to_string(), typ: Type::Int, line: 1 });
+    assert!(matches!(&program.forward[1], Forward::Function { name, variadic: false, body: true, .. } if name == "main"));
+    assert!(program.first_error().is_none());
+
+    let Decl::Function(main) = &program.decls[1] else { panic!("{:?}", program.decls[1]) };
+    assert_eq!(main.declarator.name, "main");
+    assert_eq!(main.params, Params::Unspecified);
+    let ast = &program.arena;
+    let body = main.body.as_ref().unwrap();
+    assert!(matches!(&ast[body[0]].kind, StmtKind::Local(TypeSpec::Int, vars) if vars[0].0.name == "x"));
+
+    // multiplication binds tighter, and each statement knows its line
+    let StmtKind::Expr(assign) = ast[body[1]].kind else { panic!("{:?}", ast[body[1]]) };
+    let ExprKind::Assign(lhs, rhs) = ast[assign].kind else { panic!("{:?}", ast[assign]) };
+    assert_eq!(ast[lhs].kind, ExprKind::Var("x".to_string()));
+    let ExprKind::Binary(BinaryOp::Add, one, product) = ast[rhs].kind else { panic!("{:?}", ast[rhs]) };
+    assert_eq!(ast[one].kind, ExprKind::Num(1));
+    assert!(matches!(ast[product].kind, ExprKind::Binary(BinaryOp::Mul, ..)));
+    assert_eq!(ast[body[2]].line, 5);
+    let StmtKind::If(_, then, None) = ast[body[2]].kind else { panic!("{:?}", ast[body[2]]) };
+    let StmtKind::Return(Some(value)) = ast[then].kind else { panic!("{:?}", ast[then]) };
+    assert!(matches!(ast[value].kind, ExprKind::Unary(UnaryOp::Neg, _)));
+}
+
+#[test]
+fn test_parse_reports_syntax_errors_only() {
+    let error = parse("int main() { return 1 }", &CompileOptions::new()).unwrap_err();
+    assert_eq!(error, "Line 1: Expected ';' after return");
+
+    // an unknown name is for code generation to find
+    let program = tree("int main() { return missing; }");
+    let mut gen = CodeGen::new(CompileOptions::new());
+    gen.declare_builtins();
+    assert_eq!(gen.generate(&program, true).unwrap_err(), "Line 1: Unknown variable 'missing'");
+}
+
+#[test]
+fn test_generating_the_tree_matches_parse() {
+    let source = "struct p { int x; char *name; };
+enum { A, B = 5 };
+char *greeting = \"hi\";
+int sum(int n, ...) { int i, t; t = 0; for (i = 0; i < n; i++) t += va_get(i); return t; }
+int main() { struct p q; q.x = B; q.name = greeting; return sum(2, q.x, A) + (q.x > 1 ? 1 : 0); }";
+    for level in 0..=2 {
+        let options = CompileOptions::new().opt_level(level);
+        let expected = Parser::new(source, options.clone()).parse().unwrap();
+        assert_eq!(generate(source, options), expected);
+    }
+}
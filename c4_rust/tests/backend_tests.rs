@@ -0,0 +1,76 @@
+use c4_rust::ast::Program;
+use c4_rust::backend::{backends, by_name, Artifact, Backend, Bytecode, Listing};
+use c4_rust::disasm::disassemble;
+use c4_rust::image::Image;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::Parser;
+use c4_rust::pipeline::parse;
+use std::process::Command;
+
+const SOURCE: &str = "int sq(int x) { return x * x; }\nint main() { printf(\"%d\\n\", sq(3)); return 0; }";
+
+/// syntax tree of a source with the default options
+fn program(source: &str) -> Program {
+    parse(source, &CompileOptions::new()).unwrap()
+}
+
+#[test]
+fn test_bytecode_backend_makes_the_parsers_image() {
+    let mut parser = Parser::new(SOURCE, CompileOptions::new());
+    let (code, data) = parser.parse().unwrap();
+    let expected = Image { code, data, strings: parser.strings().to_vec() };
+    let artifact = Bytecode::default().emit(&program(SOURCE), &CompileOptions::new()).unwrap();
+    assert_eq!(artifact, Artifact::Image(expected.clone()));
+    assert!(!artifact.is_text());
+    assert_eq!(Image::from_bytes(&artifact.to_bytes()), Ok(expected));
+}
+
+#[test]
+fn test_listing_backend_disassembles() {
+    let mut parser = Parser::new(SOURCE, CompileOptions::new());
+    let (code, _) = parser.parse().unwrap();
+    let artifact = Listing::default().emit(&program(SOURCE), &CompileOptions::new()).unwrap();
+    assert_eq!(artifact, Artifact::Text(disassemble(&code, parser.functions())));
+    assert!(artifact.is_text());
+}
+
+#[test]
+fn test_backends_are_picked_by_name_and_share_diagnostics() {
+    let names: Vec<&str> = backends().iter().map(|backend| backend.name()).collect();
+    assert_eq!(names, ["bytecode", "listing"]);
+    assert_eq!(by_name("wasm").err(), Some("unknown backend 'wasm', expected one of bytecode, listing".to_string()));
+
+    // every backend reports the same errors and warnings, from one parsed tree
+    let unknown = program("int main() { return x; }");
+    let truncated = program("int main() { char c; c = 300; return 0; }");
+    for name in names {
+        let mut backend = by_name(name).unwrap();
+        assert_eq!(backend.name(), name);
+        assert_eq!(backend.emit(&unknown, &CompileOptions::new()), Err("Line 1: Unknown variable 'x'".to_string()));
+        assert!(backend.emit(&truncated, &CompileOptions::new()).is_ok());
+        assert_eq!(backend.warnings(), ["Line 1: constant 300 does not fit in a char, it becomes 44"]);
+    }
+}
+
+#[test]
+fn test_cli_emit_writes_or_prints_the_artifact() {
+    let dir = std::env::temp_dir().join(format!("c4_backend_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("sq.c4b");
+    let c4 = || Command::new(env!("CARGO_BIN_EXE_c4_rust"));
+
+    let output = c4().args(["-e", SOURCE, "emit", "-o"]).arg(&image).output().unwrap();
+    assert!(output.status.success());
+    let expected = Bytecode::default().emit(&program(SOURCE), &CompileOptions::new()).unwrap();
+    assert_eq!(std::fs::read(&image).unwrap(), expected.to_bytes());
+
+    let output = c4().args(["-e", SOURCE, "emit", "--backend=listing"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(Artifact::Text(String::from_utf8_lossy(&output.stdout).into_owned()), Listing::default().emit(&program(SOURCE), &CompileOptions::new()).unwrap());
+
+    // a binary artifact isn't dumped on the terminal
+    let output = c4().args(["-e", SOURCE, "emit"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("name it with -o FILE"));
+    std::fs::remove_dir_all(&dir).ok();
+}
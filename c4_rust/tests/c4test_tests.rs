@@ -0,0 +1,39 @@
+use c4_rust::c4test::{report, run_tests, TestResult};
+use c4_rust::options::CompileOptions;
+
+/// runs the tests in source with default options
+fn run(source: &str) -> Vec<TestResult> {
+    run_tests(source, &CompileOptions::new()).unwrap()
+}
+
+#[test]
+fn test_sample_file_passes() {
+    let source = std::fs::read_to_string("tests/C_files/c4test/math_test.c").unwrap();
+    let results = run(&source);
+    let names: Vec<&str> = results.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, ["test_gcd", "test_fresh_globals", "test_fresh_globals_again"]);
+    assert!(results.iter().all(TestResult::passed), "{:?}", results);
+}
+
+#[test]
+fn test_failures_are_reported() {
+    let source = "int test_ok() { return 0; }\nint test_bad() { return 3; }\nint test_crash() { int z; z = 0; return 1 / z; }\nint test_args(int x) { return 0; }\nint helper() { return 1; }";
+    let results = run(source);
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1].result, Ok(3));
+    assert_eq!(results[1].line, 2);
+    assert!(results[2].result.as_ref().unwrap_err().contains("division by zero"));
+    assert!(results[3].result.is_err());
+
+    let text = report(&results);
+    assert!(text.starts_with("test test_ok ... ok\ntest test_bad ... FAILED (returned 3)\n"), "{}", text);
+    assert!(text.ends_with("test result: FAILED. 1 passed; 3 failed\n"), "{}", text);
+}
+
+#[test]
+fn test_main_is_optional_and_not_a_test() {
+    assert!(run("int main() { return 1; }").is_empty());
+    assert_eq!(run("int test_only() { return 0; }").len(), 1);
+    // compile errors still fail the whole file
+    assert!(run_tests("int test_x() { return }", &CompileOptions::new()).is_err());
+}
@@ -0,0 +1,116 @@
+use c4_rust::host::CaptureHost;
+use c4_rust::options::{CompileOptions, Lang};
+use c4_rust::vm::{self, HostFns, VM};
+
+// c4.c extended with switch and structs, written in the c4x dialect
+const C5_SOURCE: &str = "tests/C_files/c4x/c5.c";
+
+/// compiles and runs a program in the given dialect
+fn run_lang(source: &str, lang: Lang) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new().lang(lang))
+}
+
+#[test]
+fn test_c4x_sample_program() {
+    let source = std::fs::read_to_string("tests/C_files/c4x/switch.c").unwrap();
+    assert_eq!(run_lang(&source, Lang::C4x), Ok(240));
+
+    // plain c4 has no switch
+    assert!(run_lang(&source, Lang::C4).is_err());
+}
+
+#[test]
+fn test_switch_words_are_identifiers_in_c4() {
+    let source = "int main() { int default; int case; default = 4; case = 3; return default * case; }";
+    assert_eq!(run_lang(source, Lang::C4), Ok(12));
+    assert!(run_lang(source, Lang::C4x).is_err());
+}
+
+#[test]
+fn test_switch_without_matching_case() {
+    let source = "int main() { int x; x = 7; switch (x) { case 1: x = 0; } return x; }";
+    assert_eq!(run_lang(source, Lang::C4x), Ok(7));
+}
+
+#[test]
+fn test_nested_switch_and_break() {
+    let source = "int main() { int r; r = 0;
+        switch (2) {
+            case 2:
+                switch (5) { case 5: r = r + 1; break; default: r = r + 100; }
+                r = r + 10;
+                break;
+            case 3: r = r + 1000;
+        }
+        return r; }";
+    assert_eq!(run_lang(source, Lang::C4x), Ok(11));
+}
+
+#[test]
+fn test_switch_in_loop_keeps_stack_balanced() {
+    // each switch pops its value, otherwise locals would drift
+    let source = "int main() { int i; int n; i = 0; n = 0;
+        while (i < 200) { switch (i % 3) { case 0: n = n + 1; break; default: n = n + 2; } i = i + 1; }
+        return n; }";
+    assert_eq!(run_lang(source, Lang::C4x), Ok(67 + 133 * 2));
+}
+
+#[test]
+fn test_switch_label_errors() {
+    let bad = [
+        "int main() { int x; x = 1; switch (x) { case x: return 1; } return 0; }",
+        "int main() { switch (1) { case 1: case 1: return 1; } return 0; }",
+        "int main() { switch (1) { default: default: return 1; } return 0; }",
+        "int main() { case 1: return 1; }",
+        "int main() { break; }",
+    ];
+    for source in bad {
+        assert!(run_lang(source, Lang::C4x).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_case_labels_are_constant_expressions() {
+    let source = "int first;
+        enum { RED = 1, GREEN = RED << 1, BLUE = GREEN << 1 };
+        int pick(int c) { switch (c) { case RED | GREEN: return 3; case BLUE: return 9; case -BLUE * 2: return 7; default: return 0; } }
+        int main() { return pick(3) * 100 + pick(4) * 10 + pick(-8); }";
+    assert_eq!(run_lang(source, Lang::C4x), Ok(397));
+    let err = run_lang("int main() { int x; x = 1; switch (1) { case x: return 1; } return 0; }", Lang::C4x).unwrap_err();
+    assert!(err.contains("case label must be a constant"), "{}", err);
+}
+
+#[test]
+fn test_continue_leaves_the_switch_value_behind() {
+    // break ends the switch, continue goes past it to the loop and must pop its value
+    let source = "int main() {
+        int i; int n;
+        i = 0; n = 0;
+        while (i < 1000) {
+            i++;
+            switch (i % 4) { case 0: continue; case 1: n = n + 1; break; default: switch (i) { default: continue; } }
+            n = n + 10;
+        }
+        return n;
+    }";
+    // a word left on the stack per iteration would overflow a small one
+    let options = CompileOptions::new().lang(Lang::C4x).stack_size(256);
+    assert_eq!(vm::run(source, &options), Ok(2750));
+}
+
+#[test]
+fn test_c5_compiles_and_runs_the_sample() {
+    let source = std::fs::read_to_string(C5_SOURCE).unwrap_or_else(|e| panic!("{}: {}", C5_SOURCE, e));
+    let options = CompileOptions::new().lang(Lang::C4x).max_cycles(0);
+    let (parser, compiled) = vm::compile(&source, &options, &[]);
+    let (code, data) = compiled.unwrap_or_else(|e| panic!("c5.c did not compile: {}", e));
+    let host = CaptureHost::new(Vec::new());
+    let output = host.output();
+    let mut vm = VM::load(&parser, code, data, &options, Box::new(host), HostFns::new()).unwrap();
+
+    // c5 reads the sample with open and read, compiles it and runs it like c4 does
+    vm.set_args(&["c5".to_string(), "tests/C_files/c4x/switch.c".to_string()]);
+    vm.run().unwrap_or_else(|e| panic!("c5 failed: {}", e));
+    let output = output.borrow().clone();
+    assert!(output.contains("exit(240)"), "{}", output);
+}
@@ -0,0 +1,69 @@
+use c4_rust::callgraph::CallGraph;
+use c4_rust::options::CompileOptions;
+use c4_rust::vm;
+
+/// call graph of a program
+fn graph(source: &str) -> CallGraph {
+    let (parser, compiled) = vm::compile(source, &CompileOptions::new(), &[]);
+    let (code, _) = compiled.unwrap();
+    CallGraph::build(&code, parser.functions())
+}
+
+/// index of a function by name
+fn index(graph: &CallGraph, name: &str) -> usize {
+    graph.functions.iter().position(|(n, _)| n == name).unwrap()
+}
+
+const SOURCE: &str = "int is_even(int n) { if (n == 0) return 1; return is_odd(n - 1); }
+int is_odd(int n) { if (n == 0) return 0; return is_even(n - 1); }
+int fact(int n) { if (n < 2) return 1; return n * fact(n - 1); }
+int square(int n) { return n * n; }
+int main() { return is_even(4) + fact(5) + fact(square(2)); }";
+
+#[test]
+fn test_edges_come_from_call_sites() {
+    let graph = graph(SOURCE);
+    let names: Vec<&str> = graph.functions.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, ["is_even", "is_odd", "fact", "square", "main"]);
+    assert_eq!(graph.functions[index(&graph, "main")].1, 5);
+
+    let main = index(&graph, "main");
+    let edges: Vec<(&str, usize)> = graph.calls.iter()
+        .filter(|(from, _, _)| *from == main)
+        .map(|&(_, to, sites)| (graph.functions[to].0.as_str(), sites))
+        .collect();
+    assert_eq!(edges, [("is_even", 1), ("fact", 2), ("square", 1)]);
+}
+
+#[test]
+fn test_recursion_is_detected() {
+    let graph = graph(SOURCE);
+    let recursive: Vec<bool> = (0..graph.functions.len()).map(|f| graph.is_recursive(f)).collect();
+    assert_eq!(recursive, [true, true, true, false, false]);
+    assert_eq!(graph.cycles(), [vec![0, 1], vec![2]]);
+}
+
+#[test]
+fn test_no_recursion_no_cycles() {
+    let graph = graph("int leaf() { return 1; } int mid() { return leaf() + leaf(); } int main() { return mid(); }");
+    assert!(graph.cycles().is_empty());
+    assert!(graph.to_json().contains("\"cycles\": []"));
+}
+
+#[test]
+fn test_dot_output() {
+    let dot = graph(SOURCE).to_dot();
+    assert!(dot.starts_with("digraph calls {\n") && dot.ends_with("}\n"));
+    assert!(dot.contains("  \"fact\" [color=red];\n"));
+    assert!(dot.contains("  \"square\";\n"));
+    assert!(dot.contains("  \"main\" -> \"fact\" [label=\"2\"];\n"));
+    assert!(dot.contains("  \"is_odd\" -> \"is_even\" [color=red];\n"));
+}
+
+#[test]
+fn test_json_output() {
+    let json = graph(SOURCE).to_json();
+    assert!(json.contains("{\"name\": \"is_odd\", \"line\": 2, \"recursive\": true}"));
+    assert!(json.contains("{\"caller\": \"main\", \"callee\": \"fact\", \"sites\": 2}"));
+    assert!(json.contains("\"cycles\": [\n    [\"is_even\", \"is_odd\"],\n    [\"fact\"]\n  ]"));
+}
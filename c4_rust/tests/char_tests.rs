@@ -0,0 +1,61 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::vm;
+
+/// compiles and runs a program with char signed or unsigned, returning main's result
+fn run(source: &str, signed: bool) -> i64 {
+    let options = CompileOptions::new().signed_char(signed);
+    vm::run(source, &options).unwrap_or_else(|e| panic!("failed: {}", e))
+}
+
+#[test]
+fn test_char_is_signed_by_default() {
+    assert!(CompileOptions::new().signed_char);
+    let source = "int main() { char c; c = -1; if (c < 0) return 1; return 2; }";
+    assert_eq!(vm::run(source, &CompileOptions::new()), Ok(1));
+}
+
+#[test]
+fn test_loads_sign_or_zero_extend() {
+    let cases = [
+        // local, global and heap chars
+        ("char c; c = -1; return c;", -1, 255),
+        ("char c; c = 200; return c;", -56, 200),
+        ("char c; c = 127; return c + 1;", 128, 128),
+        ("char *p; p = malloc(2); p[0] = 128; p[1] = 'a'; return p[0] * 1000 + p[1];", -127903, 128097),
+        ("char *s; s = \"A\"; return *s;", 65, 65),
+        ("char c; c = -1; if (c < 0) return 1; return 2;", 1, 2),
+        ("char c; c = 255; return c == -1;", 1, 0),
+    ];
+    for (body, signed, unsigned) in cases {
+        let source = format!("int main() {{ {} }}", body);
+        assert_eq!(run(&source, true), signed, "signed: {}", body);
+        assert_eq!(run(&source, false), unsigned, "unsigned: {}", body);
+    }
+    let global = "char g; int main() { g = 240; return g; }";
+    assert_eq!((run(global, true), run(global, false)), (-16, 240));
+}
+
+#[test]
+fn test_casts_follow_the_mode() {
+    let cases = [
+        ("(char)255", -1, 255),
+        ("(int)(char)300", 44, 44),
+        ("(char)128", -128, 128),
+        ("(char)-129", 127, 127),
+        ("(char)'z'", 122, 122),
+    ];
+    for (expr, signed, unsigned) in cases {
+        let source = format!("int main() {{ return {}; }}", expr);
+        assert_eq!(run(&source, true), signed, "signed: {}", expr);
+        assert_eq!(run(&source, false), unsigned, "unsigned: {}", expr);
+    }
+}
+
+#[test]
+fn test_conversion_warning_names_the_value_read_back() {
+    for (signed, becomes) in [(true, "-25"), (false, "231")] {
+        let (parser, compiled) = vm::compile("int main() { char c; c = 999; return 0; }", &CompileOptions::new().signed_char(signed), &[]);
+        compiled.unwrap();
+        assert_eq!(parser.warnings(), [format!("Line 1: constant 999 does not fit in a char, it becomes {}", becomes)]);
+    }
+}
@@ -0,0 +1,283 @@
+use c4_rust::debugger::Debugger;
+use c4_rust::host::NullHost;
+use c4_rust::options::CompileOptions;
+use c4_rust::vm::{self, HostFns, VM};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const SOURCE: &str = "int add(int a, int b) {
+    int s;
+    s = a + b;
+    return s;
+}
+
+int main() {
+    int x;
+    x = add(2, 3);
+    printf(\"x = %d\\n\", x);
+    return x;
+}
+";
+
+/// debugger over a compiled program, output thrown away
+fn debugger(source: &str) -> Debugger {
+    let options = CompileOptions::new();
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let (code, data) = compiled.unwrap();
+    let vm = VM::load(&parser, code, data, &options, Box::new(NullHost), HostFns::new()).unwrap();
+    Debugger::new(vm, &parser)
+}
+
+#[test]
+fn test_break_on_function_name() {
+    let mut db = debugger(SOURCE);
+    assert_eq!(db.execute("break add"), Ok("Breakpoint 1 at 6: add, line 3\n".to_string()));
+    assert_eq!(db.execute("run"), Ok("Breakpoint 1, 6: add, line 3\n".to_string()));
+
+    // stopped after the prologue, so the frame has its arguments
+    let frames = db.vm().paused_frames();
+    assert_eq!(frames[0].function, "add");
+    assert_eq!(frames[0].args, vec![2, 3]);
+    assert!(db.execute("bt").unwrap().starts_with("#0 add (pc=6, line 3) args=[2, 3]"));
+
+    assert_eq!(db.execute("continue"), Ok("Program exited with code 5\n".to_string()));
+    assert!(db.execute("continue").unwrap().contains("exited with code 5"));
+}
+
+#[test]
+fn test_break_on_one_line_function() {
+    let mut db = debugger("int fact(int n) { if (n < 2) return 1; return n * fact(n - 1); }
+int main() {
+    int x;
+    x = 72;
+    return fact(3);
+}
+");
+    // no statement starts on a line of its own, so stop right after ENT
+    db.execute("break fact if n == 2").unwrap();
+    assert_eq!(db.execute("run"), Ok("Breakpoint 1, 6: fact, line 1\n".to_string()));
+    assert_eq!(db.execute("print n"), Ok("$1 = 2\n".to_string()));
+    let bt = db.execute("bt").unwrap();
+    assert!(bt.starts_with("#0 fact (pc=6, line 1) args=[2]"), "{}", bt);
+    assert_eq!(bt.matches("fact").count(), 2, "{}", bt);
+
+    // paused on the ENT itself, the innermost frame is the caller's
+    let mut db = debugger("int fact(int n) { if (n < 2) return 1; return n * fact(n - 1); }
+int main() { return fact(3); }
+");
+    db.execute("break *4").unwrap();
+    db.execute("run").unwrap();
+    let frames = db.vm().paused_frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].function, "main");
+
+    // a variadic function stops once its named parameters are copied
+    let mut db = debugger("int sum(int n, ...) {\n    return n + va_get(0);\n}\nint main() { return sum(1, 9); }\n");
+    db.execute("break sum").unwrap();
+    db.execute("run").unwrap();
+    assert_eq!(db.execute("print n"), Ok("$1 = 1\n".to_string()));
+}
+
+#[test]
+fn test_info_functions_lists_addresses() {
+    let mut db = debugger(SOURCE);
+    assert_eq!(db.execute("info functions"),
+               Ok("add at 4, line 1, 2 parameters\nmain at 23, line 7, 0 parameters\n".to_string()));
+}
+
+#[test]
+fn test_break_on_line_and_address() {
+    let mut db = debugger(SOURCE);
+    assert_eq!(db.execute("break 10"), Ok("Breakpoint 1 at 39: main, line 10\n".to_string()));
+    assert_eq!(db.execute("b *4"), Ok("Breakpoint 2 at 4: add, line 1\n".to_string()));
+    assert_eq!(db.breakpoints().len(), 2);
+    assert_eq!(db.execute("delete 2"), Ok("Deleted breakpoint 2\n".to_string()));
+    assert_eq!(db.execute("info breakpoints"), Ok("1 at 39: main, line 10\n".to_string()));
+    assert_eq!(db.execute("run"), Ok("Breakpoint 1, 39: main, line 10\n".to_string()));
+    assert_eq!(db.vm().paused_frames()[0].locals, vec![5]);
+}
+
+#[test]
+fn test_break_errors() {
+    let mut db = debugger(SOURCE);
+    assert_eq!(db.execute("break printf"), Err("'printf' is a system call, not a function".to_string()));
+    assert_eq!(db.execute("break nope"), Err("no function named 'nope'".to_string()));
+    assert_eq!(db.execute("break 99"), Err("no code at or after line 99".to_string()));
+    assert_eq!(db.execute("break *1"), Err("address 1 is not in any function".to_string()));
+    assert_eq!(db.execute("delete 1"), Err("no breakpoint 1".to_string()));
+    assert_eq!(db.execute("frobnicate"), Err("unknown command 'frobnicate', try 'help'".to_string()));
+}
+
+#[test]
+fn test_step_shows_the_next_instruction() {
+    let mut db = debugger(SOURCE);
+    db.execute("break add").unwrap();
+    db.execute("run").unwrap();
+    assert_eq!(db.execute("step"), Ok("8: add, line 3: PSH\n".to_string()));
+    assert_eq!(db.execute("step"), Ok("9: add, line 3: LEA 3\n".to_string()));
+}
+
+#[test]
+fn test_cli_debug_reads_commands_from_stdin() {
+    let path = std::env::temp_dir().join("c4_debugger_cli.c");
+    std::fs::write(&path, SOURCE).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .arg("debug").arg(&path)
+        .stdin(Stdio::piped()).stdout(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"break add\nrun\nquit\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(),
+               "(c4db) Breakpoint 1 at 6: add, line 3\n(c4db) Breakpoint 1, 6: add, line 3\n(c4db) ");
+}
+
+const LOOP: &str = "int total;
+
+int add(int a, int b) {
+    int s;
+    s = a + b;
+    total = total + s;
+    return s;
+}
+
+int main() {
+    int i;
+    char *name;
+    name = \"loop\";
+    i = 0;
+    while (i < 20) {
+        add(i, 1);
+        i = i + 1;
+    }
+    return total;
+}
+";
+
+/// first argument of the innermost frame
+fn first_arg(db: &Debugger) -> i64 {
+    db.vm().paused_frames()[0].args[0]
+}
+
+#[test]
+fn test_conditional_breakpoint_reads_locals_and_params() {
+    let mut db = debugger(LOOP);
+    assert_eq!(db.execute("break 6 if s > 10"), Ok("Breakpoint 1 at 18: add, line 6\n".to_string()));
+    db.execute("run").unwrap();
+    assert_eq!(first_arg(&db), 10);
+    db.execute("delete 1").unwrap();
+
+    db.execute("break add if a == 15").unwrap();
+    assert_eq!(db.execute("continue"), Ok("Breakpoint 2, 6: add, line 5\n".to_string()));
+    assert_eq!(first_arg(&db), 15);
+}
+
+#[test]
+fn test_condition_on_globals_and_through_pointers() {
+    let mut db = debugger(LOOP);
+    db.execute("break 16 if total >= 21 && name[1] == 'o'").unwrap();
+    db.execute("run").unwrap();
+    assert_eq!(db.vm().paused_frames()[0].locals[0], 6);
+    db.execute("condition 1 name[1] == 'o' + i - 7").unwrap();
+    db.execute("continue").unwrap();
+    assert_eq!(db.vm().paused_frames()[0].locals[0], 7);
+    db.execute("condition 1 i == 15 || i == 12").unwrap();
+    db.execute("continue").unwrap();
+    assert_eq!(db.vm().paused_frames()[0].locals[0], 12);
+}
+
+#[test]
+fn test_ignore_counts_hits() {
+    let mut db = debugger(LOOP);
+    db.execute("break add").unwrap();
+    assert_eq!(db.execute("ignore 1 3"), Ok("Breakpoint 1 ignores its next 3 hits\n".to_string()));
+    db.execute("run").unwrap();
+    assert_eq!(first_arg(&db), 3);
+    assert_eq!(db.breakpoints()[0].hits, 4);
+    assert_eq!(db.execute("info breakpoints"), Ok("1 at 6: add, line 5\n  hit 4 times\n".to_string()));
+
+    // a condition that fails doesn't count as a hit
+    db.execute("condition 1 a % 5 == 0").unwrap();
+    db.execute("ignore 1 1").unwrap();
+    db.execute("continue").unwrap();
+    assert_eq!(first_arg(&db), 10);
+    assert_eq!(db.execute("info breakpoints"), Ok("1 at 6: add, line 5 if a % 5 == 0\n  hit 6 times\n".to_string()));
+}
+
+#[test]
+fn test_condition_errors() {
+    let mut db = debugger(LOOP);
+    assert_eq!(db.execute("break add if nope > 1"), Err("Unknown variable 'nope'".to_string()));
+    assert_eq!(db.execute("break add if"), Err("'if' needs a condition".to_string()));
+    assert_eq!(db.execute("break add when a"), Err("expected 'if' after 'add'".to_string()));
+    assert_eq!(db.execute("condition 4 a"), Err("no breakpoint 4".to_string()));
+    assert!(db.breakpoints().is_empty());
+
+    // a condition that can't be read stops the program instead of being skipped
+    db.execute("break add if *(int *)999999 == 1").unwrap();
+    let stopped = db.execute("run").unwrap();
+    assert!(stopped.starts_with("can't evaluate '*(int *)999999 == 1' here\nBreakpoint 1,"), "{}", stopped);
+}
+
+const ARRAYS: &str = "char *greeting;
+int squares[4];
+
+int fill(int n) {
+    int i;
+    char word[6];
+    i = 0;
+    while (i < n) { squares[i] = i * i; i = i + 1; }
+    word[0] = 'h'; word[1] = 'i'; word[2] = 0;
+    return i;
+}
+
+int main() {
+    greeting = \"hello\\n\";
+    return fill(4);
+}
+";
+
+#[test]
+fn test_print_values_by_type() {
+    let mut db = debugger(ARRAYS);
+    db.execute("break 10").unwrap();
+    db.execute("run").unwrap();
+    assert_eq!(db.execute("print i"), Ok("$1 = 4\n".to_string()));
+    assert_eq!(db.execute("p squares[3] + n"), Ok("$2 = 13\n".to_string()));
+    assert_eq!(db.execute("p squares"), Ok("$3 = {0, 1, 4, 9}\n".to_string()));
+    assert_eq!(db.execute("p word[1]"), Ok("$4 = 105 'i'\n".to_string()));
+    assert_eq!(db.execute("p word"), Ok("$5 = \"hi\"\n".to_string()));
+    assert!(db.execute("p greeting").unwrap().ends_with(" \"hello\\n\"\n"));
+    assert!(db.execute("p &i").unwrap().starts_with("$7 = (int *) "));
+}
+
+#[test]
+fn test_print_errors_and_scope() {
+    let mut db = debugger(ARRAYS);
+    assert_eq!(db.execute("print"), Err("print needs an expression".to_string()));
+    assert_eq!(db.execute("print i"), Err("Unknown variable 'i'".to_string()));
+    assert_eq!(db.execute("print squares[1]"), Ok("$1 = 0\n".to_string()));
+    db.execute("run").unwrap();
+    // after the exit globals keep their last values
+    assert_eq!(db.execute("print squares[2] * 10"), Ok("$2 = 40\n".to_string()));
+    assert_eq!(db.execute("print *(int *)999999"), Err("can't evaluate '*(int *)999999' here".to_string()));
+}
+
+#[test]
+fn test_print_shows_struct_members() {
+    let source = "struct point { int x; char c; };
+int main() {
+    struct point p;
+    struct point *q;
+    p.x = 3;
+    p.c = 'k';
+    q = &p;
+    return q->x;
+}
+";
+    let mut db = debugger(source);
+    db.execute("break 8").unwrap();
+    db.execute("run").unwrap();
+    assert_eq!(db.execute("print p"), Ok("$1 = {x = 3, c = 107 'k'}\n".to_string()));
+    assert_eq!(db.execute("print q->c"), Ok("$2 = 107 'k'\n".to_string()));
+}
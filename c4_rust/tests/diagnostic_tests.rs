@@ -0,0 +1,53 @@
+use c4_rust::diagnostic::{json_string, Diagnostic, Severity};
+use std::process::Command;
+
+/// runs the CLI on inline source and returns its stderr
+fn cli_stderr(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust")).args(args).output().unwrap();
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn test_line_prefix_is_split_off() {
+    let diagnostic = Diagnostic::new(Severity::Error, "Line 3: bad expression");
+    assert_eq!(diagnostic.line, Some(3));
+    assert_eq!(diagnostic.message, "bad expression");
+    let diagnostic = Diagnostic::new(Severity::Error, "main() not defined");
+    assert_eq!(diagnostic.line, None);
+    assert_eq!(diagnostic.message, "main() not defined");
+}
+
+#[test]
+fn test_text_rendering() {
+    let warning = Diagnostic::new(Severity::Warning, "Line 7: unused value");
+    assert_eq!(warning.render("a.c", false), "a.c:7: warning: unused value");
+    assert_eq!(warning.render("a.c", true), "a.c:7: \x1b[1;33mwarning:\x1b[0m unused value");
+    let error = Diagnostic::new(Severity::Error, "no main");
+    assert_eq!(error.render("a.c", true), "a.c: \x1b[1;31merror:\x1b[0m no main");
+}
+
+#[test]
+fn test_json_rendering() {
+    let error = Diagnostic::new(Severity::Error, "Line 2: bad \"token\"");
+    assert_eq!(error.to_json("dir\\a.c"),
+               "{\"severity\": \"error\", \"file\": \"dir\\\\a.c\", \"line\": 2, \"message\": \"bad \\\"token\\\"\"}");
+    assert!(Diagnostic::new(Severity::Warning, "x").to_json("a.c").contains("\"line\": null"));
+    assert_eq!(json_string("a\nb\x01"), "\"a\\nb\\u0001\"");
+}
+
+#[test]
+fn test_cli_reports_warnings_and_errors() {
+    let source = "int main() { char c; c = 300; return x; }";
+    let err = cli_stderr(&["--color=never", "-e", source]);
+    assert!(err.contains("<command line>:1: warning: constant 300 does not fit in a char"), "{}", err);
+    assert!(err.contains("<command line>:1: error: "), "{}", err);
+
+    let err = cli_stderr(&["--color=always", "-e", source]);
+    assert!(err.contains("\x1b[1;31merror:\x1b[0m"), "{}", err);
+
+    let err = cli_stderr(&["--diagnostics=json", "-e", source]);
+    let lines: Vec<&str> = err.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", err);
+    assert!(lines[0].starts_with("{\"severity\": \"warning\", \"file\": \"<command line>\", \"line\": 1,"), "{}", err);
+    assert!(lines[1].starts_with("{\"severity\": \"error\""), "{}", err);
+}
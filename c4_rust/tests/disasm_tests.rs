@@ -0,0 +1,91 @@
+use c4_rust::disasm::disassemble;
+use c4_rust::options::CompileOptions;
+use c4_rust::vm;
+use std::process::Command;
+
+/// listing of a compiled program
+fn listing(source: &str) -> String {
+    let (parser, compiled) = vm::compile(source, &CompileOptions::new(), &[]);
+    let (code, _) = compiled.unwrap();
+    disassemble(&code, parser.functions())
+}
+
+#[test]
+fn test_functions_calls_and_labels() {
+    let source = "int sq(int x) { return x * x; }
+int main() {
+    int i;
+    i = 0;
+    while (i < 3) i = sq(i) + 1;
+    return i;
+}";
+    assert_eq!(listing(source), "\
+<entry>:
+     0  JSR main
+     2  PSH
+     3  EXIT
+
+sq:  ; line 1
+     4  ENT 0
+     6  LEA 2
+     8  LI
+     9  PSH
+    10  LEA 2
+    12  LI
+    13  MUL
+    14  LEV
+    15  LEV
+
+main:  ; line 2
+    16  ENT 1
+    18  LEA -1
+    20  PSH
+    21  IMM 0
+    23  SI
+L1:
+    24  LEA -1
+    26  LI
+    27  PSH
+    28  IMM 3
+    30  LT
+    31  BZ L2
+    33  LEA -1
+    35  PSH
+    36  LEA -1
+    38  LI
+    39  PSH
+    40  JSR sq
+    42  ADJ 1
+    44  PSH
+    45  IMM 1
+    47  ADD
+    48  SI
+    49  JMP L1
+L2:
+    51  LEA -1
+    53  LI
+    54  LEV
+    55  IMM 0
+    57  LEV
+");
+}
+
+#[test]
+fn test_raw_words_and_unnamed_targets() {
+    // no function table: calls, stray words and jumps into an operand stay numeric
+    let code = vec![3, 6, 99, 2, 0, 2, 4, 1, 5];
+    assert_eq!(disassemble(&code, &[]),
+               "<entry>:\nL1:\n     0  JSR 6\n     2  .word 99\n     3  JMP L1\n     5  JMP 4\n     7  IMM 5\n");
+}
+
+#[test]
+fn test_cli_source_mode_prints_the_listing() {
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .args(["-s", "-e", "int f() { return 1; } int main() { return f(); }"])
+        .output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\nmain:  ; line 1\n"), "{}", stdout);
+    assert!(stdout.contains("  JSR f\n"), "{}", stdout);
+    assert!(stdout.ends_with("Source parsed successfully.\n"), "{}", stdout);
+}
@@ -0,0 +1,180 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::vm;
+use proptest::prelude::*;
+
+/// binary operators with their C precedence
+#[derive(Debug, Clone, Copy)]
+enum BinOp {
+    Mul, Div, Mod,
+    Add, Sub,
+    Shl, Shr,
+    Lt, Gt, Le, Ge,
+    Eq, Ne,
+    And, Xor, Or,
+}
+
+impl BinOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Mul => "*", BinOp::Div => "/", BinOp::Mod => "%",
+            BinOp::Add => "+", BinOp::Sub => "-",
+            BinOp::Shl => "<<", BinOp::Shr => ">>",
+            BinOp::Lt => "<", BinOp::Gt => ">", BinOp::Le => "<=", BinOp::Ge => ">=",
+            BinOp::Eq => "==", BinOp::Ne => "!=",
+            BinOp::And => "&", BinOp::Xor => "^", BinOp::Or => "|",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Mul | BinOp::Div | BinOp::Mod => 10,
+            BinOp::Add | BinOp::Sub => 9,
+            BinOp::Shl | BinOp::Shr => 8,
+            BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => 7,
+            BinOp::Eq | BinOp::Ne => 6,
+            BinOp::And => 5,
+            BinOp::Xor => 4,
+            BinOp::Or => 3,
+        }
+    }
+}
+
+/// random expression tree
+#[derive(Debug, Clone)]
+enum Expr {
+    Lit(i64),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    BitNot(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// evaluates natively with C semantics, None if undefined
+    fn eval(&self) -> Option<i64> {
+        match self {
+            Expr::Lit(v) => Some(*v),
+            Expr::Neg(e) => e.eval()?.checked_neg(),
+            Expr::Not(e) => Some((e.eval()? == 0) as i64),
+            Expr::BitNot(e) => Some(!e.eval()?),
+            Expr::Bin(op, l, r) => {
+                let a = l.eval()?;
+                let b = r.eval()?;
+                match op {
+                    BinOp::Mul => a.checked_mul(b),
+                    BinOp::Div => a.checked_div(b),
+                    BinOp::Mod => a.checked_rem(b),
+                    BinOp::Add => a.checked_add(b),
+                    BinOp::Sub => a.checked_sub(b),
+                    // keep shifts well defined
+                    BinOp::Shl if (0..16).contains(&b) && (0..1 << 40).contains(&a) => Some(a << b),
+                    BinOp::Shr if (0..16).contains(&b) => Some(a >> b),
+                    BinOp::Shl | BinOp::Shr => None,
+                    BinOp::Lt => Some((a < b) as i64),
+                    BinOp::Gt => Some((a > b) as i64),
+                    BinOp::Le => Some((a <= b) as i64),
+                    BinOp::Ge => Some((a >= b) as i64),
+                    BinOp::Eq => Some((a == b) as i64),
+                    BinOp::Ne => Some((a != b) as i64),
+                    BinOp::And => Some(a & b),
+                    BinOp::Xor => Some(a ^ b),
+                    BinOp::Or => Some(a | b),
+                }
+            }
+        }
+    }
+
+    /// renders as C source, only adding parens precedence requires
+    fn render(&self) -> String {
+        match self {
+            Expr::Lit(v) => v.to_string(),
+            Expr::Neg(e) => format!("-{}", e.render_operand()),
+            Expr::Not(e) => format!("!{}", e.render_operand()),
+            Expr::BitNot(e) => format!("~{}", e.render_operand()),
+            Expr::Bin(op, l, r) => {
+                let prec = op.precedence();
+                let left = match **l {
+                    Expr::Bin(lop, _, _) if lop.precedence() < prec => format!("({})", l.render()),
+                    _ => l.render(),
+                };
+                // operators are left associative so equal precedence on the right needs parens
+                let right = match **r {
+                    Expr::Bin(rop, _, _) if rop.precedence() <= prec => format!("({})", r.render()),
+                    _ => r.render(),
+                };
+                format!("{} {} {}", left, op.symbol(), right)
+            }
+        }
+    }
+
+    /// renders the operand of a unary operator
+    fn render_operand(&self) -> String {
+        match self {
+            Expr::Lit(v) => v.to_string(),
+            _ => format!("({})", self.render()),
+        }
+    }
+}
+
+fn bin_op() -> impl Strategy<Value = BinOp> {
+    prop_oneof![
+        Just(BinOp::Mul), Just(BinOp::Div), Just(BinOp::Mod),
+        Just(BinOp::Add), Just(BinOp::Sub),
+        Just(BinOp::Shl), Just(BinOp::Shr),
+        Just(BinOp::Lt), Just(BinOp::Gt), Just(BinOp::Le), Just(BinOp::Ge),
+        Just(BinOp::Eq), Just(BinOp::Ne),
+        Just(BinOp::And), Just(BinOp::Xor), Just(BinOp::Or),
+    ]
+}
+
+fn expr() -> impl Strategy<Value = Expr> {
+    let leaf = (0i64..20).prop_map(Expr::Lit);
+    leaf.prop_recursive(4, 24, 2, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|e| Expr::Neg(Box::new(e))),
+            inner.clone().prop_map(|e| Expr::Not(Box::new(e))),
+            inner.clone().prop_map(|e| Expr::BitNot(Box::new(e))),
+            (bin_op(), inner.clone(), inner).prop_map(|(op, l, r)| Expr::Bin(op, Box::new(l), Box::new(r))),
+        ]
+    })
+}
+
+/// compiles a program and runs it on the VM
+fn compile_and_run(source: &str) -> Result<i64, String> {
+    compile_and_run_at(source, 0)
+}
+
+/// same, at an optimization level
+fn compile_and_run_at(source: &str, level: u8) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new().opt_level(level))
+}
+
+proptest! {
+    #[test]
+    fn test_expression_matches_native(e in expr()) {
+        let expected = e.eval();
+        prop_assume!(expected.is_some());
+
+        let source = format!("int main() {{ return {}; }}", e.render());
+        let result = compile_and_run(&source);
+        prop_assert_eq!(result, Ok(expected.unwrap()), "source: {}", source);
+    }
+
+    #[test]
+    fn test_optimized_expression_matches_native(e in expr()) {
+        let expected = e.eval();
+        prop_assume!(expected.is_some());
+
+        // folded inside a function that -O2 inlines into main
+        let source = format!("int value() {{ return {}; }} int main() {{ return value(); }}", e.render());
+        prop_assert_eq!(compile_and_run_at(&source, 2), Ok(expected.unwrap()), "source: {}", source);
+    }
+
+    #[test]
+    fn test_comparison_chain_matches_native(a in 0i64..20, b in 0i64..20, c in 0i64..20) {
+        // relational operators bind tighter than equality
+        let source = format!("int main() {{ return {} < {} == {} > {}; }}", a, b, c, a);
+        let expected = ((a < b) as i64 == (c > a) as i64) as i64;
+        prop_assert_eq!(compile_and_run(&source), Ok(expected), "source: {}", source);
+    }
+}
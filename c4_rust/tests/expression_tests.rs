@@ -0,0 +1,320 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::Parser;
+use c4_rust::vm::{self, VM};
+
+/// wraps an expression in main, runs it and returns the result
+fn compile_expr_and_eval(expr: &str) -> i64 {
+    let source = format!("int main() {{ return {}; }}", expr);
+    vm::run(&source, &CompileOptions::new()).unwrap_or_else(|e| panic!("{}: {}", expr, e))
+}
+
+/// checks every (expression, expected) pair and reports all mismatches at once
+fn check(cases: &[(&str, i64)]) {
+    let failures: Vec<String> = cases.iter()
+        .filter_map(|&(expr, expected)| {
+            let actual = compile_expr_and_eval(expr);
+            (actual != expected).then(|| format!("{} => {} (expected {})", expr, actual, expected))
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_literals() {
+    check(&[
+        ("0", 0),
+        ("42", 42),
+        ("0x1F", 31),
+        ("0xff", 255),
+        ("017", 15),
+        ("'a'", 97),
+        ("'\\n'", 10),
+        ("'\\0'", 0),
+        ("1000000000000", 1_000_000_000_000),
+    ]);
+}
+
+#[test]
+fn test_arithmetic_operators() {
+    check(&[
+        ("1 + 2", 3),
+        ("10 - 4", 6),
+        ("4 - 10", -6),
+        ("6 * 7", 42),
+        ("-6 * 7", -42),
+        ("84 / 2", 42),
+        ("7 / 2", 3),
+        ("-7 / 2", -3),
+        ("7 % 3", 1),
+        ("-7 % 3", -1),
+        ("7 % -3", 1),
+        ("0 * 12345", 0),
+        ("-5", -5),
+        ("- -5", 5),
+        ("-(3 + 4)", -7),
+    ]);
+}
+
+#[test]
+fn test_comparison_operators() {
+    check(&[
+        ("1 == 1", 1),
+        ("1 == 2", 0),
+        ("1 != 2", 1),
+        ("2 != 2", 0),
+        ("1 < 2", 1),
+        ("2 < 1", 0),
+        ("2 < 2", 0),
+        ("2 > 1", 1),
+        ("1 > 2", 0),
+        ("2 <= 2", 1),
+        ("3 <= 2", 0),
+        ("2 >= 2", 1),
+        ("1 >= 2", 0),
+        ("-1 < 0", 1),
+        ("-1 > -2", 1),
+    ]);
+}
+
+#[test]
+fn test_logical_not() {
+    check(&[
+        ("!0", 1),
+        ("!1", 0),
+        ("!42", 0),
+        ("!!42", 1),
+        ("!(1 == 2)", 1),
+        ("!5 == 0", 1),
+    ]);
+}
+
+#[test]
+fn test_bitwise_operators() {
+    check(&[
+        ("12 & 10", 8),
+        ("12 | 10", 14),
+        ("12 ^ 10", 6),
+        ("~0", -1),
+        ("~5", -6),
+        ("1 << 4", 16),
+        ("1 << 40", 1 << 40),
+        ("256 >> 4", 16),
+        ("-16 >> 2", -4),
+        ("0xF0 & 0x3C | 0x01", 0x31),
+    ]);
+}
+
+#[test]
+fn test_precedence() {
+    check(&[
+        ("2 + 3 * 4", 14),
+        ("(2 + 3) * 4", 20),
+        ("2 * 3 + 4", 10),
+        ("20 - 6 / 2", 17),
+        ("20 / 4 / 5", 1),
+        ("20 - 4 - 5", 11),
+        ("100 / 10 * 2", 20),
+        ("7 % 4 * 3", 9),
+        ("1 + 2 << 3", 24),
+        ("1 << 2 + 1", 8),
+        ("1 + 2 < 4", 1),
+        ("3 < 4 == 1", 1),
+        ("1 == 1 & 2", 0),
+        ("6 & 3 ^ 1", 3),
+        ("1 | 2 ^ 3", 1),
+        ("-2 * -3", 6),
+        ("!0 + 1", 2),
+        ("~1 & 7", 6),
+        ("((((1))))", 1),
+        ("(1 + (2 * (3 + (4 * 5))))", 47),
+    ]);
+}
+
+#[test]
+fn test_sizeof_and_casts() {
+    check(&[
+        ("sizeof(int)", 8),
+        ("sizeof(char)", 1),
+        ("sizeof(int*)", 8),
+        ("sizeof(char*)", 8),
+        ("sizeof(int) * 2 + 1", 17),
+        ("(int)'A'", 65),
+        ("(int)(char)'z'", 122),
+    ]);
+}
+
+#[test]
+fn test_string_and_pointer_arithmetic() {
+    check(&[
+        ("*\"abc\"", 97),
+        ("*(\"abc\" + 1)", 98),
+        ("\"abc\"[2]", 99),
+        ("\"abc\"[3]", 0),
+        ("*(\"hello\" + 4) - 'o'", 0),
+        ("\"xyz\"[1] - \"xyz\"[0]", 1),
+        ("*(char*)\"A\"", 65),
+        ("!\"\"", 0),
+        ("*(\"abcdef\" + 2 * 2)", 101),
+        ("\"abcdef\"[1 + 1 * 3]", 101),
+    ]);
+}
+
+/// compiles and runs a whole program
+fn run_program(source: &str) -> i64 {
+    vm::run(source, &CompileOptions::new()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// strcmp the way guest code usually writes it
+const STRCMP: &str = "int strcmp(char *a, char *b) { while (*a) { if (*a != *b) return *a - *b; a = a + 1; b = b + 1; } return *a - *b; }\n";
+
+#[test]
+fn test_logical_not_on_pointers() {
+    check(&[
+        ("!(char*)0", 1),
+        ("!NULL", 1),
+        ("!\"abc\"", 0),
+        ("!*\"\"", 1),
+        ("!\"ab\"[2] + 1", 2),
+    ]);
+    assert_eq!(run_program("int main() { int *p; p = 0; if (!p) return 3; return 4; }"), 3);
+    assert_eq!(run_program("int main() { int x; int *p; p = &x; return !p; }"), 0);
+    assert_eq!(run_program("int main() { int *p; p = malloc(8); return !p + !!p * 10; }"), 10);
+}
+
+#[test]
+fn test_logical_not_on_call_results() {
+    let cases = [
+        ("return !strcmp(\"abc\", \"abc\");", 1),
+        ("return !strcmp(\"abc\", \"abd\");", 0),
+        ("if (!strcmp(\"key\", \"key\")) return 7; return 8;", 7),
+        ("return !!strcmp(\"a\", \"b\") * 10 + !strcmp(\"\", \"\");", 11),
+        ("char buf[4]; buf[0] = 'h'; buf[1] = 'i'; buf[2] = 0; return !strcmp(buf, \"hi\");", 1),
+    ];
+    for (body, expected) in cases {
+        let source = format!("{}int main() {{ {} }}", STRCMP, body);
+        assert_eq!(run_program(&source), expected, "{}", body);
+    }
+}
+
+#[test]
+fn test_shift_counts_are_masked() {
+    check(&[
+        ("1 << 63", i64::MIN),
+        ("1 << 64", 1),
+        ("1 << 65", 2),
+        ("1 << -1", i64::MIN),
+        ("-8 >> 1", -4),
+        ("-1 >> 63", -1),
+        ("256 >> 64", 256),
+        ("8 >> -62", 2),
+    ]);
+}
+
+#[test]
+fn test_division_truncates_toward_zero() {
+    check(&[
+        ("7 / 2", 3),
+        ("-7 / 2", -3),
+        ("7 / -2", -3),
+        ("-7 / -2", 3),
+        ("7 % 2", 1),
+        ("-7 % 2", -1),
+        ("7 % -2", 1),
+        ("-7 % -2", -1),
+        ("(-7 / 2) * 2 + -7 % 2", -7),
+        ("(1 << 63) / -1", i64::MIN),
+        ("(1 << 63) % -1", 0),
+        ("(1 << 63) / 1", i64::MIN),
+    ]);
+}
+
+#[test]
+fn test_nested_casts_and_casts_of_calls() {
+    check(&[
+        ("(int)(char)300", 44),
+        ("(int)(char)(int)(char)511", -1),
+        ("(char)65 + (char)-191", 130),
+        ("(int)(5)", 5),
+        ("((int)'a')", 97),
+        ("(int)\"abc\"[1]", 98),
+        ("*(char *)(int)\"xyz\"", 120),
+        ("(int)(int *)8 + 1", 9),
+        ("(int)((int *)8 + 1)", 16),
+        ("sizeof(int) + (int)(char)258", 10),
+    ]);
+    let cases = [
+        ("char *p; p = (char *)malloc(10); p[0] = 'o'; p[1] = 'k'; return p[1];", 107),
+        ("int *p; p = (int *)malloc(2 * sizeof(int)); p[1] = 9; return ((int *)p)[1];", 9),
+        ("(void)malloc(4); return 3;", 3),
+        ("int x; x = 300; return (int)(char)x;", 44),
+        ("char **v; v = (char **)malloc(8); *v = (char *)\"hi\"; return (*v)[1];", 105),
+        ("int *p; p = (void *)malloc(8); *p = 12; return *p;", 12),
+    ];
+    for (body, expected) in cases {
+        let source = format!("int main() {{ {} }}", body);
+        assert_eq!(run_program(&source), expected, "{}", body);
+    }
+}
+
+#[test]
+fn test_conditional_operator() {
+    check(&[
+        ("1 ? 2 : 3", 2),
+        ("0 ? 2 : 3", 3),
+        ("2 > 1 ? 10 : 20", 10),
+        ("1 + 1 ? 4 : 5", 4),
+        ("0 ? 1 : 0 ? 2 : 3", 3),
+        ("1 ? 0 ? 4 : 5 : 6", 5),
+        ("(1 ? 2 : 3) * 10", 20),
+        ("5 + (0 ? 1 : 2) * 3", 11),
+        ("*(0 ? \"ab\" : \"cd\")", 'c' as i64),
+    ]);
+    let source = "enum { N = 3, BIG = N > 2 ? 100 : 200 };
+        int sign(int x) { return x < 0 ? -1 : x == 0 ? 0 : 1; }
+        int main() { int a, b, *p; a = 4; p = a ? &a : 0; b = a > 3 ? a = 9 : 1;
+            return BIG + sign(-7) * 10 + sign(0) + *p * 1000 + b * 100000; }";
+    for level in 0..=2 {
+        let options = c4_rust::options::CompileOptions::new().opt_level(level);
+        let mut parser = Parser::new(source, options);
+        let (code, data) = parser.parse().unwrap();
+        assert_eq!(VM::new(code, data, false).run(), Ok(909090), "-O{}", level);
+    }
+    for (source, error) in [
+        ("int main() { int a, b; 1 ? a : b = 2; return 0; }", "Line 1: bad lvalue in assignment"),
+        ("int main() { return 1 ? 2; }", "Line 1: Expected ':' in conditional expression"),
+    ] {
+        let mut parser = Parser::new(source, false);
+        assert_eq!(parser.parse().err().as_deref(), Some(error), "{}", source);
+    }
+}
+
+#[test]
+fn test_logical_and_or_short_circuit() {
+    check(&[
+        ("1 && 2", 1),
+        ("3 && 0", 0),
+        ("0 || 7", 1),
+        ("0 || 0", 0),
+        ("1 || 0 && 0", 1),
+        ("(1 || 0) && 0", 0),
+        ("2 > 1 && 3 > 2", 1),
+        ("0 ? 5 : 1 && 2", 1),
+        ("!0 && !!4 == 1", 1),
+        ("(1 && 5) + (0 || 9) * 10", 11),
+    ]);
+    // the right operand only runs when the left one doesn't decide
+    let source = "int calls;
+        int touch(int v) { calls = calls + 1; return v; }
+        enum { ON = 2 > 1 && 0 || 3 };
+        int main() { char *p, *q; int n; p = 0; q = \"x\";
+            n = (p && *p) + (q && *q == 'x') * 10 + ON * 100;
+            if (0 && touch(1)) n = 0;
+            if (1 || touch(1)) n = n + calls * 1000;
+            return n + (touch(0) || touch(1)) * 10000 + calls * 100000; }";
+    for level in 0..=2 {
+        let options = c4_rust::options::CompileOptions::new().opt_level(level);
+        let mut parser = Parser::new(source, options);
+        let (code, data) = parser.parse().unwrap();
+        assert_eq!(VM::new(code, data, false).run(), Ok(210110), "-O{}", level);
+    }
+}
@@ -0,0 +1,37 @@
+// smoke test for the C API, built and run by tests/ffi_tests.rs
+#include <stdio.h>
+#include <string.h>
+#include "c4_rust.h"
+
+static int64_t add(void *user, const int64_t *args, size_t argc) {
+    int64_t *calls = user;
+    (*calls)++;
+    return argc == 2 ? args[0] + args[1] : -1;
+}
+
+int main(void) {
+    int64_t calls = 0;
+    if (c4_register_host_fn("add", 2, add, &calls) != 0) return 1;
+
+    C4Result *bad = c4_compile("int main() { return x; }");
+    if (bad->ok || strstr(bad->error, "Unknown variable") == NULL) return 2;
+    c4_free_result(bad);
+
+    const char *in = "k";
+    C4Result *r = c4_run(
+        "int main() { char b[2]; read(0, b, 1); printf(\"%c%d\\n\", b[0], add(40, 2)); return add(1, 2); }",
+        (const uint8_t *)in, strlen(in));
+    if (!r->ok || r->exit_code != 3 || strcmp(r->output, "k42\n") != 0 || calls != 2) {
+        fprintf(stderr, "ok=%d exit=%lld output=%s error=%s calls=%lld\n", r->ok, (long long)r->exit_code,
+                r->output, r->error ? r->error : "", (long long)calls);
+        return 3;
+    }
+    c4_free_result(r);
+
+    r = c4_run("int main() { return 1 / 0; }", NULL, 0);
+    if (r->ok || r->error == NULL) return 4;
+    c4_free_result(r);
+
+    puts("ffi ok");
+    return 0;
+}
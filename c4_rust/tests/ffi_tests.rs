@@ -0,0 +1,30 @@
+//! builds tests/ffi/smoke.c against the cdylib and runs it
+#![cfg(feature = "ffi")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_c_smoke_program() {
+    // this binary lives in target/<profile>/deps, next to the cdylib cargo built with the ffi feature,
+    // the copy one level up is whatever the last cargo build left there
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let smoke = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi_smoke");
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(root.join("tests/ffi/smoke.c"))
+        .arg("-I").arg(root.join("include"))
+        .arg("-L").arg(lib_dir)
+        .arg("-lc4_rust")
+        .arg("-o").arg(&smoke)
+        .status()
+        .expect("no C compiler");
+    assert!(status.success());
+
+    let output = Command::new(&smoke).env("LD_LIBRARY_PATH", lib_dir).output().unwrap();
+    assert!(output.status.success(), "smoke test failed: {:?} {}", output.status, String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("ffi ok\n"));
+}
@@ -0,0 +1,174 @@
+use c4_rust::fmt::format_source;
+use c4_rust::options::{CompileOptions, Lang};
+use c4_rust::vm;
+use std::process::Command;
+
+/// formatted text of a source that must format
+fn format(source: &str) -> String {
+    format_source(source, &CompileOptions::new()).unwrap()
+}
+
+/// code and data of a compiled program
+fn compiled(source: &str) -> (Vec<i64>, Vec<u8>) {
+    vm::compile(source, &CompileOptions::new(), &[]).1.unwrap()
+}
+
+/// the numbered samples under tests/C_files
+fn samples() -> Vec<(String, String)> {
+    (1..=11)
+        .map(|n| {
+            let path = format!("tests/C_files/test_{}.c", n);
+            let source = std::fs::read_to_string(&path).unwrap();
+            (path, source)
+        })
+        .collect()
+}
+
+#[test]
+fn test_spacing_and_indentation() {
+    let source = "enum {A,B=2,C};
+int   g ;char*names[3];
+int f(int a,char **b){if(a<0)return -a;else if(a==0){return(int)*b[0];}else
+while(a>10)a=a-1;
+  for(;;){ a++ ; if(a>3) return a*-2; }
+}
+
+
+int main(){int x;x=f(-3,&names[0])+sizeof(int);return x?1:0;}";
+    assert_eq!(format(source), "\
+enum { A, B = 2, C };
+int g;
+char *names[3];
+int f(int a, char **b) {
+    if (a < 0)
+        return -a;
+    else if (a == 0) {
+        return (int)*b[0];
+    } else
+        while (a > 10)
+            a = a - 1;
+    for (;;) {
+        a++;
+        if (a > 3)
+            return a * -2;
+    }
+}
+
+int main() {
+    int x;
+    x = f(-3, &names[0]) + sizeof(int);
+    return x ? 1 : 0;
+}
+");
+}
+
+#[test]
+fn test_switch_cases_and_prefix_operators() {
+    let source = "int main(){int i;i=0;while(i<3)++i;switch(i){case 1:return -1;case 3:i=!i;default:return ~i;}}";
+    let formatted = format_source(source, &CompileOptions::new().lang(Lang::C4x)).unwrap();
+    assert_eq!(formatted, "\
+int main() {
+    int i;
+    i = 0;
+    while (i < 3)
+        ++i;
+    switch (i) {
+        case 1:
+            return -1;
+        case 3:
+            i = !i;
+        default:
+            return ~i;
+    }
+}
+");
+}
+
+#[test]
+fn test_comments_directives_and_wrapped_lines_are_kept() {
+    let source = "#include <stdio.h>
+// counts
+int a,   // first
+  b;
+
+/* main */
+int main() { printf(\"%d %d\\n\",
+a, b); return 0; }";
+    assert_eq!(format(source), "\
+#include <stdio.h>
+// counts
+int a, // first
+    b;
+
+/* main */
+int main() {
+    printf(\"%d %d\\n\",
+        a, b);
+    return 0;
+}
+");
+}
+
+#[test]
+fn test_old_style_definitions_and_initializer_lists() {
+    let source = "int p[3] = {1,2,};\nint f(a,s) int a;char *s;{return a;}";
+    assert_eq!(format(source), "\
+int p[3] = { 1, 2, };
+int f(a, s)
+int a;
+char *s;
+{
+    return a;
+}
+");
+}
+
+#[test]
+fn test_structs_and_member_access() {
+    let source = "struct node{int v;struct node*next;};\nint f(struct node*n){return n->next->v+(*n).v+sizeof(struct node)+(int)(struct node*)0;}";
+    assert_eq!(format(source), "\
+struct node {
+    int v;
+    struct node *next;
+};
+int f(struct node *n) {
+    return n->next->v + (*n).v + sizeof(struct node) + (int)(struct node *)0;
+}
+");
+}
+
+#[test]
+fn test_junk_the_compiler_skips_is_an_error() {
+    let error = format_source("int main() {\n  return 0; @\n}", &CompileOptions::new()).unwrap_err();
+    assert_eq!(error, "Line 2: can't format '@', the compiler ignores it");
+}
+
+#[test]
+fn test_samples_format_idempotently_to_the_same_code() {
+    for (path, source) in samples() {
+        let formatted = format(&source);
+        assert_eq!(format(&formatted), formatted, "{}", path);
+        assert_eq!(compiled(&formatted), compiled(&source), "{}", path);
+    }
+}
+
+#[test]
+fn test_cli_prints_and_checks() {
+    let dir = std::env::temp_dir().join(format!("c4_fmt_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let messy = dir.join("messy.c");
+    let tidy = dir.join("tidy.c");
+    std::fs::write(&messy, "int main(){return 0;}").unwrap();
+    std::fs::write(&tidy, "int main() {\n    return 0;\n}\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust")).arg("fmt").arg(&messy).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "int main() {\n    return 0;\n}\n");
+
+    let check = |path: &std::path::Path| Command::new(env!("CARGO_BIN_EXE_c4_rust")).args(["fmt", "--check"]).arg(path).output().unwrap();
+    assert_eq!(check(&tidy).status.code(), Some(0));
+    let output = check(&messy);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not formatted"));
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -1,4 +1,7 @@
 use c4_rust::parser::{Parser, Type, SymbolClass, OpCode};
+use c4_rust::options::CompileOptions;
+use c4_rust::vm::{self, RuntimeError, VM};
+use std::process::Command;
 
 #[test]
 fn test_function_parameter_parsing() {
@@ -87,4 +90,412 @@ fn test_nested_function_blocks() {
     parser.init().unwrap();
     let result = parser.parse();
     assert!(result.is_ok(), "Parsing failed with nested blocks: {:?}", result.err());
-} 
\ No newline at end of file
+} 
+/// compiles and runs a program
+fn run(source: &str) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new())
+}
+
+#[test]
+fn test_main_does_not_need_to_be_first() {
+    let source = "int helper() { return 5; }\n\nint main() { return 2; }";
+    assert_eq!(run(source), Ok(2));
+}
+
+#[test]
+fn test_entry_stub_calls_main() {
+    let source = "int helper() { return 5; }\n\nint main() { return 2; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, _) = parser.parse().unwrap();
+    
+    // JSR main; PSH; EXIT
+    let main_fn = parser.get_symbols().iter().find(|s| s.name == "main").unwrap();
+    assert_eq!(&code[..4], &[OpCode::JSR as i64, main_fn.value, OpCode::PSH as i64, OpCode::EXIT as i64]);
+}
+
+#[test]
+fn test_call_returns_to_caller() {
+    let source = "int five() { return 5; }\n\nint main() { return five() + 1; }";
+    assert_eq!(run(source), Ok(6));
+}
+
+#[test]
+fn test_parameters_in_declaration_order() {
+    let source = "int add(int a, int b) { return a - b; }\n\nint main() { return add(10, 3); }";
+    assert_eq!(run(source), Ok(7));
+}
+
+#[test]
+fn test_callee_locals_do_not_clobber_frame() {
+    let source = "int calc(int x) { int y, z; y = x * 2; z = y + 1; return z; }\n\nint main() { return calc(5) + calc(1); }";
+    assert_eq!(run(source), Ok(14));
+}
+
+#[test]
+fn test_recursive_calls() {
+    let source = "int calc(int x) { if (x < 2) return x; return calc(x - 1) + calc(x - 2); }\n\nint main() { return calc(10); }";
+    assert_eq!(run(source), Ok(55));
+}
+
+#[test]
+fn test_local_array_next_to_scalars() {
+    let source = "int main() { int a[3]; int x; x = 7; a[0] = 1; a[2] = 5; return a[0] + a[2] + x; }";
+    assert_eq!(run(source), Ok(13));
+}
+
+#[test]
+fn test_frames_during_nested_calls() {
+    // dividing by zero stops the VM inside add, with every frame still live
+    let source = "int add(int a, int b) { int c; c = a + b; return c / (a - a); }\n\
+                  int calc(int x) { int y; y = x * 2; return add(x, y); }\n\
+                  int main() { return calc(3); }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    vm.set_functions(parser.functions().to_vec());
+    assert!(matches!(vm.run(), Err(RuntimeError::DivByZero { .. })));
+    
+    let frames = vm.frames();
+    let names: Vec<&str> = frames.iter().map(|f| f.function.as_str()).collect();
+    assert_eq!(names, ["add", "calc", "main"]);
+    assert_eq!(frames[0].args, [3, 6]);
+    assert_eq!(frames[0].locals, [9]);
+    assert_eq!(frames[1].args, [3]);
+    assert_eq!(frames[1].locals, [6]);
+    assert!(frames[2].args.is_empty());
+    
+    // each frame returns into its caller
+    let calc = parser.functions().iter().find(|f| f.name == "calc").unwrap();
+    assert!(calc.start < frames[0].return_pc && frames[0].return_pc < calc.end);
+    assert!(frames[0].bp < frames[1].bp && frames[1].bp < frames[2].bp);
+}
+
+#[test]
+fn test_keywords_stay_out_of_the_symbol_table() {
+    let mut parser = Parser::new("int main() { return 0; }", false);
+    parser.init().unwrap();
+    parser.parse().unwrap();
+
+    let symbols = parser.get_symbols();
+    assert!(symbols.iter().all(|s| c4_rust::lexer::keyword(&s.name).is_none()), "{:?}", symbols);
+    assert!(symbols.iter().all(|s| s.class != SymbolClass::Num));
+}
+
+#[test]
+fn test_functions_and_globals_used_before_definition() {
+    let source = "int main() { counter = 2; return twice(add(counter, 3)) + counter; }
+        int twice(int x) { return add(x, x); }
+        int add(int a, int b) { return a + b; }
+        int counter;";
+    assert_eq!(run(source), Ok(12));
+}
+
+#[test]
+fn test_mutual_recursion_without_prototypes() {
+    let source = "int main() { return is_even(10) * 10 + is_odd(7); }
+        int is_even(int n) { if (n == 0) return 1; return is_odd(n - 1); }
+        int is_odd(int n) { if (n == 0) return 0; return is_even(n - 1); }";
+    assert_eq!(run(source), Ok(11));
+}
+
+#[test]
+fn test_locals_shadow_globals() {
+    let source = "int x; int f() { int x; x = 5; return x; } int main() { x = 1; return f() * 10 + x; }";
+    assert_eq!(run(source), Ok(51));
+}
+
+#[test]
+fn test_duplicate_definitions_are_still_errors() {
+    assert!(run("int f() { return 1; } int f() { return 2; } int main() { return f(); }").is_err());
+    assert!(run("int g; int g; int main() { return 0; }").is_err());
+}
+
+#[test]
+fn test_main_prototype_without_a_body() {
+    assert_eq!(run("int main();"), Err("main() not defined".to_string()));
+    assert_eq!(run("int helper() { return 1; } int main(void);"), Err("main() not defined".to_string()));
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust")).args(["-s", "-e", "int main();"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("main() not defined"));
+}
+
+#[test]
+fn test_void_parameter_list() {
+    assert_eq!(run("int seven(void) { return 7; } int main(void) { return seven(); }"), Ok(7));
+    let err = run("int f(void x) { return 1; } int main() { return f(); }").unwrap_err();
+    assert!(err.contains("'void' must be the only parameter"), "{}", err);
+}
+
+#[test]
+fn test_array_parameters_are_pointers() {
+    let source = "int len(char s[]) { int n; n = 0; while (s[n]) n++; return n; }
+        int sum(int v[4], int n) { int t; t = 0; while (n) { n--; t = t + v[n]; } return t; }
+        int second(int n, char *argv[]) { return argv[n][1]; }
+        int main() { int a[3]; char *words[2]; a[0] = 1; a[1] = 20; a[2] = 300;
+            words[0] = \"ab\"; words[1] = \"cd\";
+            return len(\"hello\") * 1000 + sum(a, 3) + (second(1, words) == 'd'); }";
+    assert_eq!(run(source), Ok(5322));
+    let err = run("int f(int v[2) { return 0; } int main() { return 0; }").unwrap_err();
+    assert!(err.contains("Expected ']' after array parameter"), "{}", err);
+    let err = run("int n; int f(int v[n]) { return 0; } int main() { return 0; }").unwrap_err();
+    assert!(err.contains("array size must be a constant"), "{}", err);
+}
+
+#[test]
+fn test_call_arity_is_checked() {
+    let err = run("int add(int a, int b) { return a + b; } int main() { return add(1, 2, 3); }").unwrap_err();
+    assert_eq!(err, "Line 1: 'add' takes 2 arguments, got 3");
+    // a call before the definition is checked once the definition is parsed
+    let err = run("int main() {\n  return later(1);\n}\nint later(int a, int b) { return a; }").unwrap_err();
+    assert_eq!(err, "Line 2: 'later' takes 2 arguments, got 1");
+    assert_eq!(run("int main() { return later(4, 3); } int later(int a, int b) { return a - b; }"), Ok(1));
+}
+
+#[test]
+fn test_unchecked_calls_only_warn() {
+    use c4_rust::options::Warning;
+    let source = "int one(int a) { return a; }\nint main() {\n  return one(7, 8) + two();\n}\nint two(int x) { return 1; }";
+    let mut parser = Parser::new(source, CompileOptions::new().unchecked_calls(true));
+    parser.init().unwrap();
+    assert!(parser.parse().is_ok());
+    assert_eq!(parser.warnings(), [
+        "Line 3: 'one' takes 1 argument, got 2",
+        "Line 3: 'two' takes 1 argument, got 0",
+    ]);
+    let options = CompileOptions::new().unchecked_calls(true).warning(Warning::Arity, false);
+    let mut parser = Parser::new(source, options);
+    parser.init().unwrap();
+    assert!(parser.parse().is_ok());
+    assert!(parser.warnings().is_empty());
+}
+
+#[test]
+fn test_variadic_functions() {
+    let source = "int sum(int first, ...) { int i; int s; s = first; i = 0;
+            while (i < va_count()) { s = s + va_get(i); i++; } return s; }
+        int count(...) { return va_count(); }
+        int main() { return sum(1, 2, 3, 4) + sum(10) + count() * 100 + count(5, 6, 7) * 1000 + later(1, 2); }
+        int later(int a, int b, ...) { return a * 10 + b + va_count(); }";
+    assert_eq!(run(source), Ok(3032));
+
+    // a guest printf passes its extra arguments along
+    let mut parser = Parser::new("int log(char *fmt, ...) { return printf(fmt, va_get(0), va_get(1)); }
+        int main() { return log(\"%d-%d\", 4, 2); }", false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    assert_eq!(vm.run(), Ok(3));
+    assert!(parser.functions().iter().find(|f| f.name == "log").unwrap().variadic);
+}
+
+#[test]
+fn test_variadic_errors() {
+    let err = run("int f(int a, int b, ...) { return a; } int main() { return f(1); }").unwrap_err();
+    assert_eq!(err, "Line 1: 'f' takes at least 2 arguments, got 1");
+    let err = run("int main() { return va_count(); }").unwrap_err();
+    assert_eq!(err, "Line 1: va_count() used outside a variadic function");
+    let err = run("int f(...) { return va_get(); } int main() { return f(); }").unwrap_err();
+    assert_eq!(err, "Line 1: 'va_get' takes 1 argument, got 0");
+}
+
+#[test]
+fn test_variadic_frames() {
+    let source = "int f(int a, ...) { return a / (va_get(1) - 7); } int main() { return f(3, 5, 7); }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    vm.set_functions(parser.functions().to_vec());
+    assert!(matches!(vm.run(), Err(RuntimeError::DivByZero { .. })));
+    let frames = vm.frames();
+    assert_eq!(frames[0].function, "f");
+    assert_eq!(frames[0].args, [3, 5, 7]);
+    assert_eq!(frames[0].locals, [3]);
+}
+
+#[test]
+fn test_falling_off_main_returns_zero() {
+    assert_eq!(run("int main() { int x; x = 42; }"), Ok(0));
+    assert_eq!(run("int main(void) { if (1) return 3; }"), Ok(3));
+}
+
+#[test]
+fn test_prototypes_and_old_style_definitions() {
+    // declarations without a body, before or after the definition
+    let source = "int twice(int n); int none(); int none(void);
+int f(a, b, s) char *s; int b, a; { return a * 100 + b * 10 + *s; }
+int twice(int n) { return n * 2; }
+int twice(int);
+int main() { return f(1, twice(2), \"A\") + none(); }
+int none() { return 0; }";
+    assert_eq!(run(source), Ok(100 + 40 + 65));
+
+    let cases = [
+        ("int f(a) int b; { return a; }", "Line 1: 'b' is declared but is not a parameter"),
+        ("int f(a) int a; char a; { return a; }", "Line 1: Duplicate parameter 'a'"),
+        ("int f(a, b) int a; { return a; }", "Line 1: 'b' is declared without a type, write 'int b' if an int is meant"),
+        ("int f(int n); int main() { return f(1); }", "Function 'f' is called but never defined"),
+    ];
+    for (source, error) in cases {
+        assert_eq!(run(&format!("{} int x;", source)), Err(error.to_string()), "{}", source);
+    }
+}
+
+#[test]
+fn test_block_locals_go_out_of_scope() {
+    // the inner x shadows the outer one only inside its block
+    let source = "int main() { int x; x = 1; { int x; x = 20; if (x != 20) return 99; } return x; }";
+    assert_eq!(run(source), Ok(1));
+    // sibling blocks may reuse a name
+    let source = "int main() { int s; s = 0; { int t; t = 3; s = s + t; } { int t; t = 4; s = s + t; } return s; }";
+    assert_eq!(run(source), Ok(7));
+    // but it's gone after the block
+    assert!(run("int main() { { int t; t = 1; } return t; }").is_err());
+    assert!(run("int main() { { int t; int t; } return 0; }").is_err());
+}
+
+#[test]
+fn test_block_locals_reuse_frame_slots() {
+    let source = "int main() {
+        int a;
+        a = 1;
+        { int b; int c; b = 2; c = 3; a = a + b + c; }
+        { int d; d = 4; a = a + d; }
+        while (a < 20) { int e; e = 5; a = a + e; }
+        return a;
+    }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let main_fn = parser.functions().iter().find(|f| f.name == "main").unwrap();
+
+    // a plus at most two block locals live at once
+    assert_eq!(main_fn.locals, 3);
+    assert_eq!(code[main_fn.start + 1], 3);
+    assert!(main_fn.slots.iter().any(|(name, _, _)| name == "d"));
+    assert_eq!(VM::new(code, data, false).run(), Ok(20));
+}
+
+#[test]
+fn test_emitted_jumps_stay_in_their_function() {
+    let source = "int f(int n) { while (n > 0) { if (n == 3) return 1; n = n - 1; } return 0; }
+        int main() { int i; for (i = 0; i < 3; i++) { if (f(i)) return 9; } return f(5); }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, _) = parser.parse().unwrap();
+    assert_eq!(c4_rust::parser::verify_jumps(&code, parser.functions()), Ok(()));
+}
+
+#[test]
+fn test_jump_verifier_rejects_stray_targets() {
+    let mut parser = Parser::new("int f() { if (1) return 2; return 3; } int main() { return f(); }", false);
+    parser.init().unwrap();
+    let (code, _) = parser.parse().unwrap();
+    let functions = parser.functions().to_vec();
+    let f = functions.iter().find(|f| f.name == "f").unwrap();
+    let main = functions.iter().find(|f| f.name == "main").unwrap();
+    let bz = (f.start..f.end).find(|&pc| code[pc] == OpCode::BZ as i64).unwrap();
+
+    // into another function
+    let mut bad = code.clone();
+    bad[bz + 1] = main.start as i64 + 2;
+    let err = c4_rust::parser::verify_jumps(&bad, &functions).unwrap_err();
+    assert!(err.contains("BZ at") && err.contains("in 'f'"), "{}", err);
+
+    // onto an operand of its own function
+    bad[bz + 1] = f.start as i64 + 1;
+    assert!(c4_rust::parser::verify_jumps(&bad, &functions).is_err());
+
+    // calls may leave the function
+    assert!(code[main.start..main.end].contains(&(OpCode::JSR as i64)));
+}
+
+#[test]
+fn test_break_and_continue_in_loops() {
+    let source = "int main() {
+        int i; int j; int n;
+        n = 0;
+        for (i = 0; i < 10; i++) {
+            if (i % 2) continue;
+            if (i == 8) break;
+            j = 0;
+            while (1) { j++; if (j > i) break; if (j % 3 == 0) continue; n = n + j; }
+        }
+        i = 0;
+        while (i < 100) { i++; if (i < 50) continue; n = n + 1000; break; }
+        return n;
+    }";
+    // i = 0, 2, 4, 6 add the j up to i that aren't multiples of 3: 0 + 3 + 7 + 12
+    for level in [0, 2] {
+        let options = c4_rust::options::CompileOptions::new().opt_level(level);
+        let mut parser = Parser::new(source, options);
+        parser.init().unwrap();
+        let (code, data) = parser.parse().unwrap();
+        assert_eq!(c4_rust::parser::verify_jumps(&code, parser.functions()), Ok(()));
+        assert_eq!(VM::new(code, data, false).run(), Ok(1022), "-O{}", level);
+    }
+
+    let cases = [
+        ("int main() { break; return 0; }", "Line 1: break outside of a loop or switch"),
+        ("int main() { if (1) continue; return 0; }", "Line 1: continue outside of a loop"),
+        ("int main() { while (1) break }", "Line 1: Expected ';' after 'break'"),
+    ];
+    for (source, error) in cases {
+        let mut parser = Parser::new(source, false);
+        parser.init().unwrap();
+        assert_eq!(parser.parse().err().as_deref(), Some(error), "{}", source);
+    }
+}
+
+#[test]
+fn test_builtins_are_declared_once() {
+    let builtins = [
+        "open", "read", "close", "printf", "malloc", "free", "memset", "memcmp", "exit", "clock", "usleep",
+        "hexdump", "getenv", "system", "popen", "pclose",
+    ];
+    // callers may init before parse, which inits again
+    let mut parser = Parser::new("int main() { return 0; }", false);
+    parser.init().unwrap();
+    parser.init().unwrap();
+    parser.parse().unwrap();
+    let symbols = parser.get_symbols();
+    for name in builtins {
+        assert_eq!(symbols.iter().filter(|s| s.name == name).count(), 1, "{}", name);
+    }
+    assert_eq!(symbols.iter().filter(|s| s.class == SymbolClass::Sys).count(), builtins.len());
+}
+
+#[test]
+fn test_cli_backtrace_elides_runaway_recursion() {
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .args(["-e", "int main() { return main(); }"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let frames: Vec<&str> = stderr.lines().filter(|l| l.starts_with("  #")).collect();
+    assert_eq!(frames.len(), 20);
+    assert!(frames[0].starts_with("  #0 main"));
+    assert!(stderr.contains(" frames omitted ..."));
+
+    // a shallow stack is shown whole
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .args(["-e", "int f(int n) { return 1 / n; } int main() { return f(0); }"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert_eq!(stderr.lines().filter(|l| l.starts_with("  #")).count(), 2);
+    assert!(!stderr.contains("omitted"));
+}
+
+#[test]
+fn test_declarations_on_any_line_are_compiled() {
+    // lines 55-61 and 73 were once skipped for c4.c's sake
+    let mut source = "\n".repeat(55);
+    source.push_str("int seven() { return 7; }\n");
+    source.push_str(&"\n".repeat(16));
+    source.push_str("int six;\nint main() { six = 6; return seven() * six; }\n");
+    assert_eq!(run(&source), Ok(42));
+
+    // a missing ';' is an error on line 61 as anywhere else
+    let source = format!("{}int main() {{ printf(\"x\") return 0; }}\n", "\n".repeat(60));
+    assert_eq!(run(&source), Err("Line 61: Expected ';' after expression".to_string()));
+}
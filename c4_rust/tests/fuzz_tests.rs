@@ -0,0 +1,154 @@
+use c4_rust::host::NullHost;
+use c4_rust::lexer::{Lexer, Token};
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{self, HostFns, RuntimeError, VM};
+use proptest::prelude::*;
+
+/// compiles and runs, errors are fine but nothing may panic
+/// uses virtual time and no OS so a generated usleep or read never blocks
+fn compile_and_run(source: &str) -> Result<i64, String> {
+    let options = CompileOptions::new().virtual_time(true);
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let (code, data) = compiled?;
+    let mut vm = VM::load(&parser, code, data, &options, Box::new(NullHost), HostFns::new())?;
+    Ok(vm.run()?)
+}
+
+/// C-ish vocabulary so generated input gets past the first token
+fn fragment() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(vec![
+        "int", "char", "void", "enum", "if", "else", "while", "for", "return", "sizeof",
+        "main", "x", "y", "p", "printf", "malloc", "memset", "memcmp", "exit", "clock", "usleep",
+        "0", "1", "42", "9223372036854775807", "0x7fffffffffffffff", "'a'", "\"s%d\"", "\"%s\"",
+        "(", ")", "{", "}", "[", "]", ";", ",", "=", "+=", "*", "&", "+", "-", "/", "%",
+        "<<", ">>", "<", ">", "==", "!", "~", "++", "--", "?", ":", "//", "/*", "*/", "#",
+    ])
+}
+
+proptest! {
+    #[test]
+    fn test_lexer_never_panics(source in any::<String>()) {
+        let mut lexer = Lexer::new(&source);
+        // every call consumes input, so this terminates
+        for _ in 0..=source.len() {
+            if lexer.next() == Token::Eof {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_compiler_never_panics_on_token_soup(parts in prop::collection::vec(fragment(), 0..60)) {
+        let _ = compile_and_run(&parts.join(" "));
+    }
+
+    #[test]
+    fn test_compiler_never_panics_inside_main(parts in prop::collection::vec(fragment(), 0..40)) {
+        let source = format!("int main() {{ int x; int *p; {} }}", parts.join(" "));
+        let _ = compile_and_run(&source);
+    }
+
+    #[test]
+    fn test_vm_never_panics_on_random_code(code in prop::collection::vec(
+        prop_oneof![0i64..=(OpCode::HOST as i64), any::<i64>(), -4i64..4],
+        0..64,
+    )) {
+        // virtual time and no OS so a random usleep or read doesn't stall the run
+        let options = CompileOptions::new().virtual_time(true);
+        let mut vm = VM::with_host(code, vec![0u8; 64], &options, Box::new(NullHost));
+        let _ = vm.run();
+    }
+}
+
+#[test]
+fn test_deep_nesting_is_an_error() {
+    let depth = 10_000;
+    let source = format!("int main() {{ return {}1{}; }}", "(".repeat(depth), ")".repeat(depth));
+    assert!(compile_and_run(&source).is_err());
+
+    let source = format!("int main() {} return 0; {}", "{".repeat(depth), "}".repeat(depth));
+    assert!(compile_and_run(&source).is_err());
+}
+
+#[test]
+fn test_moderate_nesting_still_compiles() {
+    let depth = 40;
+    let source = format!("int main() {{ return {}1{}; }}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(compile_and_run(&source), Ok(1));
+}
+
+#[test]
+fn test_many_comments_and_unknown_chars() {
+    let source = format!("{}{}int main() {{ return 3; }}", "/* c */".repeat(100_000), "@".repeat(100_000));
+    assert_eq!(compile_and_run(&source), Ok(3));
+}
+
+#[test]
+fn test_non_ascii_input() {
+    assert_eq!(compile_and_run("// é\nint main() { return 'é' > 0; }"), Ok(1));
+    assert!(compile_and_run("int maïn() { return 0; }").is_err());
+}
+
+#[test]
+fn test_vm_arithmetic_overflow_is_an_error() {
+    let code = vec![
+        OpCode::IMM as i64, i64::MAX,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 1,
+        OpCode::ADD as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    let mut vm = VM::with_options(code, vec![], &CompileOptions::new().trap_overflow(true));
+    assert_eq!(vm.run(), Err(RuntimeError::Overflow { op: "ADD" }));
+}
+
+#[test]
+fn test_vm_arithmetic_wraps_by_default() {
+    for (op, lhs, rhs, expected) in [
+        (OpCode::ADD, i64::MAX, 1, i64::MIN),
+        (OpCode::SUB, i64::MIN, 1, i64::MAX),
+        (OpCode::MUL, i64::MAX, 2, -2),
+    ] {
+        let code = vec![
+            OpCode::IMM as i64, lhs,
+            OpCode::PSH as i64,
+            OpCode::IMM as i64, rhs,
+            op as i64,
+            OpCode::PSH as i64,
+            OpCode::EXIT as i64,
+        ];
+        let mut vm = VM::new(code, vec![], false);
+        assert_eq!(vm.run(), Ok(expected), "{:?}", op);
+    }
+}
+
+#[test]
+fn test_vm_division_overflow_traps_only_when_asked() {
+    for (op, expected) in [(OpCode::DIV, i64::MIN), (OpCode::MOD, 0)] {
+        let code = vec![
+            OpCode::IMM as i64, i64::MIN,
+            OpCode::PSH as i64,
+            OpCode::IMM as i64, -1,
+            op as i64,
+            OpCode::PSH as i64,
+            OpCode::EXIT as i64,
+        ];
+        assert_eq!(VM::new(code.clone(), vec![], false).run(), Ok(expected), "{:?}", op);
+        let mut vm = VM::with_options(code, vec![], &CompileOptions::new().trap_overflow(true));
+        let name = if op == OpCode::DIV { "DIV" } else { "MOD" };
+        assert_eq!(vm.run(), Err(RuntimeError::Overflow { op: name }));
+    }
+}
+
+#[test]
+fn test_guest_overflow_wraps_or_traps() {
+    let source = "int main() { int x; x = 0x7fffffffffffffff; x = x + 1; return x < 0; }";
+    assert_eq!(compile_and_run(source), Ok(1));
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::with_options(code, data, &CompileOptions::new().trap_overflow(true));
+    assert_eq!(vm.run(), Err(RuntimeError::Overflow { op: "ADD" }));
+}
@@ -0,0 +1,134 @@
+use c4_rust::image::{self, Image};
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{self, VM};
+
+const SOURCE: &str = "int main() {
+    printf(\"hello %d\\n\", 1);
+    printf(\"tab\\there \\\"q\\\"\\n\");
+    return 0;
+}";
+
+/// image of a compiled program
+fn compile(source: &str) -> Image {
+    let (parser, compiled) = vm::compile(source, &CompileOptions::new(), &[]);
+    let (code, data) = compiled.unwrap();
+    Image { code, data, strings: parser.strings().to_vec() }
+}
+
+#[test]
+fn test_string_offsets_match_the_code() {
+    let image = compile(SOURCE);
+    assert_eq!(image.strings.iter().map(|&(_, line)| line).collect::<Vec<_>>(), [2, 3]);
+    for &(offset, _) in &image.strings {
+        assert!(image.code.windows(2).any(|w| w == [OpCode::IMM as i64, offset as i64]));
+    }
+}
+
+#[test]
+fn test_dump_strings() {
+    let dump = compile(SOURCE).dump_strings();
+    assert_eq!(dump, "offset  line  string\n    16     2  \"hello %d\\n\"\n    32     3  \"tab\\there \\\"q\\\"\\n\"\n");
+    assert_eq!(image::c_escape(b"a\\b\x01\x7f"), "a\\\\b\\x01\\x7f");
+}
+
+#[test]
+fn test_image_round_trips() {
+    let image = compile(SOURCE);
+    let bytes = image.to_bytes();
+    assert_eq!(&bytes[..4], image::MAGIC);
+    let loaded = Image::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded, image);
+    assert_eq!(VM::new(loaded.code, loaded.data, false).run(), Ok(0));
+}
+
+#[test]
+fn test_bad_images_are_rejected() {
+    let bytes = compile(SOURCE).to_bytes();
+    assert_eq!(Image::from_bytes(b"ELF\0"), Err("not a c4 image".to_string()));
+    assert!(Image::from_bytes(&bytes[..bytes.len() - 3]).unwrap_err().contains("truncated"));
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(Image::from_bytes(&longer).unwrap_err().contains("trailing"));
+    let mut huge = bytes;
+    huge[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(Image::from_bytes(&huge).unwrap_err().contains("larger than the image"));
+}
+
+#[test]
+fn test_incompatible_or_damaged_images_are_refused() {
+    let bytes = compile(SOURCE).to_bytes();
+    assert_eq!(bytes[4..12], image::VERSION.to_le_bytes());
+
+    let mut newer = bytes.clone();
+    newer[4..12].copy_from_slice(&(image::VERSION + 1).to_le_bytes());
+    let error = Image::from_bytes(&newer).unwrap_err();
+    assert!(error.starts_with(&format!("image format version {} is not supported", image::VERSION + 1)), "{}", error);
+
+    let mut other_opcodes = bytes.clone();
+    other_opcodes[12] ^= 1;
+    assert!(Image::from_bytes(&other_opcodes).unwrap_err().contains("different opcode set"));
+
+    // a flipped bit in the first code word
+    let mut damaged = bytes.clone();
+    damaged[29] ^= 0x10;
+    assert!(Image::from_bytes(&damaged).unwrap_err().contains("the file is damaged"));
+
+    assert_eq!(image::crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn test_hexdump_matches_xxd() {
+    let bytes = b"Hello world!\n\0\0\0Hello, hexdump!\n\0\0\0\0\0\0\0\0";
+    assert_eq!(image::hexdump(bytes, 0), "\
+00000000: 4865 6c6c 6f20 776f 726c 6421 0a00 0000  Hello world!....
+00000010: 4865 6c6c 6f2c 2068 6578 6475 6d70 210a  Hello, hexdump!.
+00000020: 0000 0000 0000 0000                      ........
+");
+    assert_eq!(image::hexdump(b"\x7f~", 0x1234), "00001234: 7f7e                                     .~\n");
+    assert_eq!(image::hexdump(b"", 0), "");
+}
+
+#[test]
+fn test_cli_inspect_hexdump() {
+    let path = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("inspect.c4b");
+    std::fs::write(&path, compile(SOURCE).to_bytes()).unwrap();
+    let inspect = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .arg("inspect").args(args).arg(&path).output().unwrap();
+
+    let output = inspect(&["--hexdump=0x10,8"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("00000010: 6865 6c6c 6f20 2564{:22}hello %d\n", ""));
+
+    let output = inspect(&[]);
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("code: "));
+
+    let output = inspect(&["--hexdump=9999,1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("past the end of the data segment"));
+}
+
+#[test]
+fn test_code_report_sizes_each_function() {
+    let source = "int sq(int x) { return x * x; }
+int main() {
+    printf(\"%d\\n\", sq(3));
+    printf(\"%d\\n\", sq(4));
+    printf(\"hi\\n\");
+    return 0;
+}";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let image = Image { code, data, strings: parser.strings().to_vec() };
+    let report = image.code_report(parser.functions());
+    let rows: Vec<Vec<&str>> = report.lines().map(|l| l.split_whitespace().collect()).collect();
+
+    // largest first; every literal has its own copy, counted with its NUL
+    assert_eq!(rows[0], ["function", "line", "instructions", "code", "bytes", "data", "bytes"]);
+    assert_eq!(rows[1][..2], ["main", "2"]);
+    assert_eq!(rows[1][4], "12");
+    assert_eq!(rows[2], ["sq", "1", "9", "96", "0"]);
+    let instructions: usize = rows[1][2].parse::<usize>().unwrap() + 9;
+    assert_eq!(rows[3][..2], ["total", instructions.to_string().as_str()]);
+}
@@ -0,0 +1,52 @@
+use c4_rust::input::Input;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const HELLO: &str = "int main(int argc, char **argv) { printf(\"hi %d %s\\n\", argc, argv[0]); return 0; }";
+
+/// runs the CLI, feeding stdin, and returns its stdout
+fn cli(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_inputs_from_args() {
+    assert_eq!(Input::from_arg("-"), Input::Stdin);
+    assert_eq!(Input::from_arg("prog.c"), Input::File("prog.c".to_string()));
+    assert_eq!(Input::Stdin.name(), "<stdin>");
+    assert_eq!(Input::Inline(HELLO.to_string()).name(), "<command line>");
+    assert_eq!(Input::Inline(HELLO.to_string()).read(), Ok(HELLO.to_string()));
+}
+
+#[test]
+fn test_file_input() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("input_hello.c");
+    std::fs::write(&path, HELLO).unwrap();
+    let input = Input::from_arg(path.to_str().unwrap());
+    assert_eq!(input.read(), Ok(HELLO.to_string()));
+    assert_eq!(Input::from_arg("no/such/file.c").read(), Err("could not open(no/such/file.c)".to_string()));
+}
+
+#[test]
+fn test_cli_inline_source() {
+    let out = cli(&["-e", HELLO, "x"], "");
+    assert!(out.contains("hi 2 <command line>\n"), "{}", out);
+}
+
+#[test]
+fn test_cli_source_from_stdin() {
+    let out = cli(&["-"], HELLO);
+    assert!(out.contains("hi 1 <stdin>\n"), "{}", out);
+    let out = cli(&["test-file", "-"], "int test_ok() { return 0; }");
+    assert!(out.contains("test test_ok ... ok"), "{}", out);
+}
@@ -0,0 +1,146 @@
+use c4_rust::ir::{self, Program};
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{self, RuntimeError, VM};
+
+/// compiles at an optimization level, returning the code and the parser for its tables
+fn compile(source: &str, level: u8) -> (Vec<i64>, Vec<u8>, Parser<'_>) {
+    let (parser, compiled) = vm::compile(source, &CompileOptions::new().opt_level(level), &[]);
+    let (code, data) = compiled.unwrap();
+    (code, data, parser)
+}
+
+/// compiles and runs a program at an optimization level
+fn run(source: &str, level: u8) -> Result<i64, RuntimeError> {
+    let (code, data, _) = compile(source, level);
+    VM::new(code, data, false).run()
+}
+
+/// opcodes of a function, operands skipped
+fn ops(code: &[i64], parser: &Parser, name: &str) -> Vec<OpCode> {
+    let function = parser.functions().iter().find(|f| f.name == name).unwrap();
+    let mut ops = Vec::new();
+    let mut pc = function.start;
+    while pc < function.end {
+        let op = OpCode::from_i64(code[pc]).unwrap();
+        ops.push(op);
+        pc += 1 + op.arity();
+    }
+    ops
+}
+
+#[test]
+fn test_o0_is_the_code_as_parsed() {
+    let source = "int main() { return 2 * 3 + 4; }";
+    let (code, _, _) = compile(source, 0);
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    assert_eq!(code, parser.parse().unwrap().0);
+    assert!(code.contains(&(OpCode::MUL as i64)));
+}
+
+#[test]
+fn test_lift_and_lower_round_trips() {
+    for file in ["test_1.c", "test_5.c", "test_9.c"] {
+        let source = std::fs::read_to_string(format!("tests/C_files/{}", file)).unwrap();
+        let (code, _, parser) = compile(&source, 0);
+        let program = Program::lift(&code, parser.functions()).unwrap();
+        assert_eq!(program.lower().0, code, "{}", file);
+    }
+}
+
+#[test]
+fn test_constants_fold() {
+    let source = "int main() { int x; x = 6; return (2 * 3 + 4) * x - (1 << 4) / 2; }";
+    let (code, data, parser) = compile(source, 1);
+    assert!(code.windows(2).any(|w| w == [OpCode::IMM as i64, 10]));
+    assert!(!code.contains(&(OpCode::SHL as i64)) && !code.contains(&(OpCode::DIV as i64)));
+    assert_eq!(ops(&code, &parser, "main").iter().filter(|&&op| op == OpCode::MUL).count(), 1);
+    assert_eq!(VM::new(code, data, false).run(), Ok(52));
+}
+
+#[test]
+fn test_folding_keeps_runtime_errors() {
+    assert!(matches!(run("int main() { return 1 / 0; }", 1), Err(RuntimeError::DivByZero { .. })));
+    let (code, data, _) = compile("int main() { return 1 << 64; }", 1);
+    let mut vm = VM::with_options(code, data, &CompileOptions::new().trap_overflow(true));
+    assert!(matches!(vm.run(), Err(RuntimeError::InvalidShift { amount: 64 })));
+    assert_eq!(run("int main() { return 1 << 64; }", 1), Ok(1));
+    assert_eq!(ir::fold(OpCode::ADD, i64::MAX, 1), None);
+    assert_eq!(ir::fold(OpCode::MOD, -7, 2), Some(-1));
+}
+
+#[test]
+fn test_dead_branches_are_dropped() {
+    let source = "int main() { if (0) { printf(\"never\\n\"); return 1; } if (2 - 2 == 0) return 3; return 4; }";
+    let (code, data, parser) = compile(source, 1);
+    let main = ops(&code, &parser, "main");
+    assert!(!main.contains(&OpCode::PRTF) && !main.contains(&OpCode::BZ));
+    assert_eq!(VM::new(code, data, false).run(), Ok(3));
+}
+
+#[test]
+fn test_small_functions_inline_at_o2() {
+    let source = "int sq(int x) { return x * x; } int sub(int a, int b) { return a - b; }
+        int main() { int y; y = 5; return sub(sq(y), sq(3)) + sub(1, 0); }";
+    let (code, data, parser) = compile(source, 2);
+    assert!(!ops(&code, &parser, "main").contains(&OpCode::JSR));
+    assert_eq!(VM::new(code, data, false).run(), Ok(17));
+
+    // -O1 keeps the calls
+    let (code, _, parser) = compile(source, 1);
+    assert!(ops(&code, &parser, "main").contains(&OpCode::JSR));
+}
+
+#[test]
+fn test_calls_with_side_effects_stay_calls() {
+    let source = "int g; int twice(int x) { return x * 2; } int bump() { g = g + 1; return g; }
+        int main() { g = 1; return twice(bump()) * 10 + twice(g); }";
+    let (code, data, parser) = compile(source, 2);
+    let main = ops(&code, &parser, "main");
+    assert_eq!(main.iter().filter(|&&op| op == OpCode::JSR).count(), 2);
+    assert_eq!(VM::new(code, data, false).run(), Ok(44));
+}
+
+#[test]
+fn test_function_addresses_follow_the_code() {
+    let source = "int helper(int n) { if (0) return 9; return n + 1 * 2; } int main() { return helper(1) + helper(2); }";
+    let (code, data, parser) = compile(source, 1);
+    for function in parser.functions() {
+        assert_eq!(code[function.start], OpCode::ENT as i64, "{}", function.name);
+        let symbol = parser.get_symbols().iter().find(|s| s.name == function.name).unwrap();
+        assert_eq!(symbol.value, function.start as i64);
+    }
+    assert_eq!(parser.functions().iter().map(|f| f.end).max(), Some(code.len()));
+    assert_eq!(code[1], parser.functions().iter().find(|f| f.name == "main").unwrap().start as i64);
+    assert_eq!(VM::new(code, data, false).run(), Ok(7));
+}
+
+#[test]
+fn test_every_level_agrees() {
+    let sources = [
+        "int main() { int i; int s; i = 0; s = 0; while (i < 10) { s = s + i * 2; i++; } return s; }",
+        "int f(int n) { if (n < 2) return n; return f(n - 1) + f(n - 2); } int main() { return f(12); }",
+        "int main() { char *p; int n; p = \"hello\"; n = 0; while (*p) { n = n * 3 + *p; p = p + 1; } return n % 1000; }",
+        "int g; int set(int v) { g = v; return v; } int main() { int x; x = set(4) + (g = 2) * 3; return x * 10 + g; }",
+        "int main() { int a[4]; int *q; a[0] = 1; a[3] = 7; q = a; return *(q + 3) - q[0] + !q + (q != 0); }",
+    ];
+    for source in sources {
+        let expected = run(source, 0);
+        assert!(expected.is_ok(), "{}", source);
+        for level in 1..=2 {
+            assert_eq!(run(source, level), expected, "-O{}: {}", level, source);
+        }
+    }
+}
+
+#[test]
+fn test_line_table_follows_optimized_code() {
+    let source = "int twice(int x) { return x * 2; }\nint main() {\n    int a;\n    a = 2 + 3;\n    if (0) a = 9;\n    return twice(a);\n}\n";
+    let (code, _, parser) = compile(source, 2);
+    let lines = parser.lines();
+    assert!(lines.windows(2).all(|w| w[0].0 < w[1].0), "{:?}", lines);
+    assert!(lines.iter().all(|&(addr, _)| addr < code.len()), "{:?}", lines);
+    let main = parser.functions().iter().find(|f| f.name == "main").unwrap();
+    assert!(lines.contains(&(main.start, 2)), "{:?}", lines);
+}
@@ -0,0 +1,88 @@
+use c4_rust::callgraph::CallGraph;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::Parser;
+use std::process::Command;
+
+const BROKEN: &str = "int g;
+int bad(int x) {
+  return x + ;
+}
+int good(int y) { return y * 2; }
+int worse() { int z; z = 1; return q; }
+int h[4 +];
+int main() { printf(\"%d\\n\", good(3)); return bad(1); }
+";
+
+/// code and data, or the first error
+type Compiled = Result<(Vec<i64>, Vec<u8>), String>;
+
+/// compiles with keep_going, returning the parser for its tables
+fn compile(source: &str) -> (Parser<'_>, Compiled) {
+    let mut parser = Parser::new(source, CompileOptions::new().keep_going(true));
+    parser.init().unwrap();
+    let result = parser.parse();
+    (parser, result)
+}
+
+#[test]
+fn test_every_failed_declaration_is_reported() {
+    let (parser, result) = compile(BROKEN);
+    assert_eq!(parser.errors(), [
+        "Line 3: Expected expression",
+        "Line 6: Unknown variable 'q'",
+        "Line 7: Expected expression",
+    ]);
+    assert_eq!(result, Err("Line 3: Expected expression".to_string()));
+
+    // without keep_going the first error stops everything
+    let mut parser = Parser::new(BROKEN, false);
+    parser.init().unwrap();
+    assert_eq!(parser.parse(), Err("Line 3: Expected expression".to_string()));
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn test_the_rest_still_compiles() {
+    let (parser, _) = compile(BROKEN);
+    let names: Vec<&str> = parser.functions().iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["good", "main"]);
+    for function in parser.functions() {
+        assert!(function.end <= parser.code().len());
+    }
+    assert!(parser.get_symbols().iter().any(|s| s.name == "g"));
+
+    // the call to bad doesn't turn into a call to good
+    let graph = CallGraph::build(parser.code(), parser.functions());
+    assert_eq!(graph.calls, [(1, 0, 1)]);
+}
+
+#[test]
+fn test_clean_source_is_unaffected() {
+    let source = "int sq(int x) { return x * x; } int main() { return sq(5); }";
+    let (parser, result) = compile(source);
+    assert!(parser.errors().is_empty());
+    let mut plain = Parser::new(source, false);
+    plain.init().unwrap();
+    assert_eq!(result, plain.parse());
+}
+
+#[test]
+fn test_cli_analysis_modes_keep_going() {
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .args(["--color=never", "-e", BROKEN]).args(args).output().unwrap();
+    let errors = |output: &std::process::Output| String::from_utf8_lossy(&output.stderr).lines()
+        .filter(|l| l.contains("error:")).count();
+
+    let output = run(&["-s"]);
+    assert!(!output.status.success());
+    assert_eq!(errors(&output), 3);
+
+    // running stops at the first error
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert_eq!(errors(&output), 1);
+
+    let output = run(&["strings"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"%d\\n\""));
+}
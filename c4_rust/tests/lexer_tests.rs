@@ -1,4 +1,4 @@
-use c4_rust::lexer::{Lexer, Token};
+use c4_rust::lexer::{keyword, Lexer, Token};
 
 #[test]
 fn test_lexer_basic_tokens() {
@@ -93,6 +93,22 @@ fn test_lexer_comments() {
     assert_eq!(lexer.next(), Token::Eof);
 }
 
+#[test]
+fn test_lexer_comments_keep_line_numbers() {
+    let source = "#include <stdio.h>\r\n/* one\r\n two /* nested\r */ still */ a // end\n\n\t b /= c / d\r/* never closed\n";
+    let mut lexer = Lexer::new(source);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.line(), 4);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.line(), 6);
+    assert_eq!(lexer.next(), Token::DivAssign);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Div);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Eof);
+    assert_eq!(lexer.line(), 8);
+}
+
 #[test]
 fn test_lexer_whitespace() {
     let source = "if  \t  (x)\n{\n  return;\n}";
@@ -125,4 +141,76 @@ fn test_lexer_line_counting() {
     
     assert_eq!(lexer.next(), Token::Eof);
     assert_eq!(lexer.line(), 4);
-} 
\ No newline at end of file
+} 
+
+#[test]
+fn test_keywords_are_recognized_by_the_lexer() {
+    assert_eq!(keyword("while"), Some(Token::While));
+    assert_eq!(keyword("void"), Some(Token::Void));
+    assert_eq!(keyword("whiles"), None);
+
+    let mut lexer = Lexer::new("sizeof enum iffy");
+    assert_eq!(lexer.next(), Token::Sizeof);
+    assert_eq!(lexer.next(), Token::Enum);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+}
+
+#[test]
+fn test_lexer_line_counting_crlf_and_cr() {
+    let mut lexer = Lexer::new("a\r\nb // c\r\n/* d\r\n */ e\rf");
+    assert!(matches!(lexer.next(), Token::Id(_))); // a
+    assert_eq!(lexer.line(), 1);
+    assert!(matches!(lexer.next(), Token::Id(_))); // b
+    assert_eq!(lexer.line(), 2);
+    assert!(matches!(lexer.next(), Token::Id(_))); // e
+    assert_eq!(lexer.line(), 4);
+    assert!(matches!(lexer.next(), Token::Id(_))); // f
+    assert_eq!(lexer.line(), 5);
+}
+
+#[test]
+fn test_lexer_ellipsis() {
+    let mut lexer = Lexer::new("(a, ...) . .. p->x - -> x");
+    assert_eq!(lexer.next(), Token::LeftParen);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Comma);
+    assert_eq!(lexer.next(), Token::Ellipsis);
+    assert_eq!(lexer.next(), Token::RightParen);
+    // a lone '.' picks a member, two are two of them
+    assert_eq!(lexer.next(), Token::Dot);
+    assert_eq!(lexer.next(), Token::Dot);
+    assert_eq!(lexer.next(), Token::Dot);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Arrow);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Sub);
+    assert_eq!(lexer.next(), Token::Arrow);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+    assert_eq!(lexer.next(), Token::Eof);
+}
+
+#[test]
+fn test_identifiers_are_numbered_by_spelling() {
+    let mut lexer = Lexer::new("x y x _var y");
+    let ids: Vec<Token> = (0..5).map(|_| lexer.next()).collect();
+    assert_eq!(ids, [Token::Id(0), Token::Id(1), Token::Id(0), Token::Id(2), Token::Id(1)]);
+    assert_eq!(lexer.id_name(2), Some("_var"));
+    assert_eq!(lexer.id_name(3), None);
+
+    // every name is its own symbol, however long or alike
+    let long = "a".repeat(300);
+    let mut source = format!("int {}; ", long);
+    let names: Vec<String> = (0..200).map(|i| format!("v{}_{}", i % 7, i)).collect();
+    for name in &names {
+        source.push_str(&format!("int {}; ", name));
+    }
+    source.push_str(&format!("int main() {{ {} = 1000; ", long));
+    for (i, name) in names.iter().enumerate() {
+        source.push_str(&format!("{} = {}; ", name, i));
+    }
+    let sum: Vec<&str> = names.iter().map(String::as_str).collect();
+    source.push_str(&format!("return {} + {}; }}", long, sum.join(" + ")));
+    let mut parser = c4_rust::parser::Parser::new(&source, false);
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(c4_rust::vm::VM::new(code, data, false).run(), Ok(1000 + 199 * 200 / 2));
+}
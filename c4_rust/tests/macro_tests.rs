@@ -0,0 +1,141 @@
+use c4_rust::lexer::{Lexer, Token};
+use c4_rust::options::{CompileOptions, Lang};
+use c4_rust::parser::Parser;
+use c4_rust::vm::{self, VM};
+
+/// compiles and runs a program, giving main's return value
+fn run(source: &str) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new())
+}
+
+#[test]
+fn test_function_like_macros() {
+    let source = "#define SQ(x) ((x) * (x))
+#define MIN(a, b) (((b) < (a)) * (b) + ((a) <= (b)) * (a))
+#define LIMIT 10
+int main() {
+    int i;
+    i = 1;
+    return SQ(i + 2) + MIN(LIMIT, SQ(2)) * 100;
+}";
+    // SQ(i + 2) is 9 rather than i + 2 * i + 2, the parentheses come along
+    assert_eq!(run(source), Ok(409));
+}
+
+#[test]
+fn test_arguments_are_expanded_and_may_hold_commas_in_parentheses() {
+    let source = "#define SQ(x) ((x) * (x))
+#define FIRST(a, b) a
+#define ANSWER() 42
+int pick(int a, int b) { return b; }
+int main() { return SQ(SQ(2)) + FIRST(pick(1, 2), 3) + ANSWER(); }";
+    assert_eq!(run(source), Ok(16 + 2 + 42));
+}
+
+#[test]
+fn test_a_macro_is_not_expanded_inside_itself() {
+    let source = "int f(int x) { return x; }
+#define f(x) f(x + 1)
+#define A B
+#define B A
+int main() { int A; A = 5; return f(f(1)) * 10 + A; }";
+    // f(f(1)) is f(f(1 + 1) + 1), and A becomes B and then A again, which stays
+    assert_eq!(run(source), Ok(35));
+}
+
+#[test]
+fn test_continuation_lines_undef_and_reserved_words() {
+    let source = "#define int long long
+#define TWICE(a) \\
+    ((a) + \\
+     (a))
+int main() {
+    int line;
+    line = 0;
+#undef TWICE
+#define TWICE(a) 0
+    return TWICE(3) * 100 + 7;
+}";
+    assert_eq!(run(source), Ok(7));
+    // line numbers count the continued lines
+    let error = run("#define X(a) \\\n  (a)\nint main() {\n  return X(1, 2);\n}").unwrap_err();
+    assert_eq!(error, "Line 4: macro 'X' takes 1 argument, got 2");
+}
+
+#[test]
+fn test_bad_macros_are_errors() {
+    let cases = [
+        ("#define SQ(x) x * x\nint main() { return SQ(1; }", "Line 2: call to macro 'SQ' is missing its ')'"),
+        ("#define PAIR(a, b) a + b\nint main() { return PAIR(1); }", "Line 2: macro 'PAIR' takes 2 arguments, got 1"),
+        ("#define BAD(1) 1\nint main() { return 0; }", "Line 1: bad parameter list for macro 'BAD'"),
+        ("#define STR(x) #x\nint main() { return 0; }", "Line 1: macro 'STR' uses # or ##, which aren't supported"),
+        ("#define\nint main() { return 0; }", "Line 1: #define needs a macro name"),
+    ];
+    for (source, error) in cases {
+        assert_eq!(run(source), Err(error.to_string()), "{}", source);
+    }
+}
+
+#[test]
+fn test_expansion_can_be_turned_off() {
+    let mut lexer = Lexer::new("#define N 3\nN");
+    assert_eq!(lexer.next(), Token::Num(3));
+    let mut lexer = Lexer::new("#define N 3\nN");
+    lexer.set_expand_macros(false);
+    assert!(matches!(lexer.next(), Token::Id(_)));
+}
+
+#[test]
+fn test_conditional_compilation() {
+    let source = "#define LEVEL 2
+#if defined(__C4RUST__) && LEVEL >= 2
+int pick() { return 42; }
+#elif LEVEL == 1
+int pick() { return 1; }
+#else
+#error no level
+#endif
+#ifndef MISSING
+# if 0
+  not C at all, 'unbalanced
+#  if also skipped
+#  endif
+# elif (1 << 3) % 5 == 3 ? UNDEFINED_IS_ZERO == 0 : 0
+int line() { return __LINE__; }
+# endif
+#endif
+int main() { return pick() + line(); }";
+    assert_eq!(run(source), Ok(42 + 15));
+}
+
+#[test]
+fn test_predefined_macros_follow_the_options() {
+    let source = "#ifdef __C4X__
+int main() { return 1; }
+#else
+int main() { return __FILE__[0]; }
+#endif";
+    let compile = |options: CompileOptions| {
+        let mut parser = Parser::new(source, options);
+        let (code, data) = parser.parse().unwrap();
+        VM::new(code, data, false).run().unwrap()
+    };
+    assert_eq!(compile(CompileOptions::new().file("prog.c")), i64::from(b'p'));
+    assert_eq!(compile(CompileOptions::new().lang(Lang::C4x)), 1);
+}
+
+#[test]
+fn test_bad_conditionals_are_errors() {
+    let cases = [
+        ("int main() { return 0; }\n#if 1\n", "Line 2: #if without #endif"),
+        ("#endif\nint main() { return 0; }", "Line 1: #endif without #if"),
+        ("#if 1\n#else\n#elif 1\n#endif\nint main() { return 0; }", "Line 3: #elif after #else"),
+        ("#if 1 / 0\n#endif\nint main() { return 0; }", "Line 1: #if needs a constant expression"),
+        ("#if 0\n#elif\n#endif\nint main() { return 0; }", "Line 2: #elif needs a constant expression"),
+        ("#ifdef\n#endif\nint main() { return 0; }", "Line 1: #ifdef needs a macro name"),
+        ("#if 1\n#error needs c4\n#endif\nint main() { return 0; }", "Line 2: #error needs c4"),
+    ];
+    for (source, error) in cases {
+        assert_eq!(run(source), Err(error.to_string()), "{}", source);
+    }
+}
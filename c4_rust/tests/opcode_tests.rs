@@ -0,0 +1,63 @@
+use c4_rust::opcodes::{reference, rows};
+use c4_rust::parser::OpCode;
+use std::process::Command;
+
+/// the words of an "OP n = [a, b]" sample encoding
+fn encoding(cell: &str) -> Vec<i64> {
+    let list = cell.split_once(" = [").unwrap().1.trim_end_matches(']');
+    list.split(", ").map(|word| word.parse().unwrap()).collect()
+}
+
+#[test]
+fn test_every_opcode_has_a_row_that_decodes() {
+    let rows = rows();
+    assert_eq!(rows.len(), OpCode::ALL.len());
+    for (row, op) in rows.iter().zip(OpCode::ALL) {
+        assert_eq!(row[0], (op as usize).to_string());
+        assert_eq!(row[1], format!("{:?}", op));
+        let words = encoding(&row[5]);
+        assert_eq!(OpCode::from_i64(words[0]), Some(op));
+        assert_eq!(words.len(), 1 + op.arity(), "{:?}", op);
+        // an operand is named exactly when there is one
+        assert_eq!(row[2] == "-", op.arity() == 0, "{:?}", op);
+        assert_ne!(row[2], "word", "{:?} has an undocumented operand", op);
+    }
+}
+
+#[test]
+fn test_syscalls_show_the_arguments_they_pop() {
+    let rows = rows();
+    let row = |op: OpCode| rows[op as usize].clone();
+    assert_eq!(row(OpCode::READ)[3], "a1 a2 a3 --");
+    assert_eq!(row(OpCode::READ)[4], "ax = read(a1, a2, a3)");
+    assert_eq!(row(OpCode::CLCK)[3], "--");
+    assert_eq!(row(OpCode::PRTF)[2], "argc");
+    assert_eq!(row(OpCode::PRTF)[5], "PRTF 2 = [33, 2]");
+    assert_eq!(row(OpCode::ADD)[4], "ax = a + ax");
+}
+
+#[test]
+fn test_cli_prints_a_boxed_table() {
+    let output = Command::new(env!("CARGO_BIN_EXE_c4_rust")).arg("opcodes").output().unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(text, reference());
+    let table: Vec<&str> = text.lines().filter(|line| line.starts_with(['+', '|'])).collect();
+    assert_eq!(table.len(), OpCode::ALL.len() + 4);
+    assert!(table.iter().all(|line| line.len() == table[0].len()));
+    assert!(table[1].starts_with("| #  | opcode |"));
+}
+
+#[test]
+fn test_names_and_operands_come_from_one_table() {
+    let mut names: Vec<&str> = OpCode::ALL.iter().map(|op| op.name()).collect();
+    for op in OpCode::ALL {
+        assert_eq!(op.name(), format!("{:?}", op));
+        assert_eq!(op.arity(), usize::from(op.has_operand()), "{:?}", op);
+    }
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), OpCode::ALL.len());
+    let operands: Vec<&str> = OpCode::ALL.iter().filter(|op| op.has_operand()).map(|op| op.name()).collect();
+    assert_eq!(operands, ["LEA", "IMM", "JMP", "JSR", "BZ", "BNZ", "ENT", "ADJ", "PRTF", "HOST"]);
+}
@@ -0,0 +1,108 @@
+use c4_rust::host::Host;
+use c4_rust::options::{Buffering, CompileOptions};
+use c4_rust::vm::{self, HostFns, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// host that keeps every write separately, and notes each read of stdin
+#[derive(Default)]
+struct Recorder {
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Host for Recorder {
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        self.log.borrow_mut().push(text.to_string());
+        Ok(())
+    }
+
+    fn open(&mut self, _path: &str) -> i64 {
+        -1
+    }
+
+    fn read(&mut self, _fd: i64, _buf: &mut [u8]) -> i64 {
+        self.log.borrow_mut().push("<read>".to_string());
+        0
+    }
+
+    fn close(&mut self, _fd: i64) -> i64 {
+        -1
+    }
+
+    fn elapsed_us(&self) -> u64 {
+        0
+    }
+
+    fn sleep_us(&mut self, _micros: u64) {}
+}
+
+/// runs a program, returning what it exited with and each write the host saw
+fn writes(source: &str, options: CompileOptions) -> (Result<i64, String>, Vec<String>) {
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let (code, data) = compiled.unwrap();
+    let host = Recorder::default();
+    let log = Rc::clone(&host.log);
+    let mut vm = VM::load(&parser, code, data, &options, Box::new(host), HostFns::new()).unwrap();
+    let result = vm.run().map_err(|e| e.to_string());
+    let log = log.borrow().clone();
+    (result, log)
+}
+
+const PRINTS: &str = "int main() { printf(\"a\"); printf(\"b\\n\"); printf(\"c\\nd\"); return 0; }";
+
+#[test]
+fn test_unbuffered_writes_every_printf() {
+    assert_eq!(CompileOptions::new().buffering, Buffering::Unbuffered);
+    assert_eq!(writes(PRINTS, CompileOptions::new()).1, ["a", "b\n", "c\nd"]);
+}
+
+#[test]
+fn test_line_buffering_writes_whole_lines() {
+    let options = CompileOptions::new().buffering(Buffering::Line);
+    assert_eq!(writes(PRINTS, options).1, ["ab\n", "c\n", "d"]);
+}
+
+#[test]
+fn test_full_buffering_writes_at_exit_or_when_full() {
+    let options = CompileOptions::new().buffering(Buffering::Full);
+    assert_eq!(writes(PRINTS, options.clone()).1, ["ab\nc\nd"]);
+
+    let source = "int main() { int i; i = 0; while (i < 300) { printf(\"0123456789abcdef\"); i = i + 1; } return 0; }";
+    let log = writes(source, options).1;
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].len(), 4096);
+    assert_eq!(log.concat().len(), 300 * 16);
+}
+
+#[test]
+fn test_crlf_translates_newlines() {
+    let options = CompileOptions::new().crlf(true);
+    assert_eq!(writes(PRINTS, options.clone()).1, ["a", "b\r\n", "c\r\nd"]);
+    let options = options.buffering(Buffering::Line);
+    assert_eq!(writes(PRINTS, options).1, ["ab\r\n", "c\r\n", "d"]);
+}
+
+#[test]
+fn test_line_buffering_shows_prompts_before_reading_stdin() {
+    let source = "int main() { char buf[8]; printf(\"name? \"); read(0, buf, 4); printf(\"ok\"); return 0; }";
+    let options = CompileOptions::new().buffering(Buffering::Line);
+    assert_eq!(writes(source, options).1, ["name? ", "<read>", "ok"]);
+    let options = CompileOptions::new().buffering(Buffering::Full);
+    assert_eq!(writes(source, options).1, ["<read>", "name? ok"]);
+}
+
+#[test]
+fn test_buffered_output_survives_a_runtime_error() {
+    let source = "int main() { printf(\"before\"); return *(int *)99999999; }";
+    let (result, log) = writes(source, CompileOptions::new().buffering(Buffering::Full));
+    assert!(result.is_err());
+    assert_eq!(log, ["before"]);
+}
+
+#[test]
+fn test_buffering_names() {
+    assert_eq!("none".parse(), Ok(Buffering::Unbuffered));
+    assert_eq!("line".parse(), Ok(Buffering::Line));
+    assert_eq!("full".parse(), Ok(Buffering::Full));
+    assert_eq!("some".parse::<Buffering>(), Err("unknown buffering 'some', expected none, line or full".to_string()));
+}
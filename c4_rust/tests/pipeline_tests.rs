@@ -0,0 +1,69 @@
+use c4_rust::lexer::Token;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::Parser;
+use c4_rust::pipeline::{build, compile, lift, link, lower, optimize, tokenize};
+
+const SOURCE: &str = "#define N 4
+int sq(int x) { return x * x; }
+int main() {
+    int i, total;
+    total = 0;
+    for (i = 0; i < N; i++) total = total + sq(i) + 2 * 3;
+    if (0) printf(\"never\\n\");
+    return total;
+}";
+
+#[test]
+fn test_tokens_carry_lines_names_and_strings() {
+    let stream = tokenize("int x;\n#define TWO 2\nchar *s = \"hi\" ; x = TWO;", &CompileOptions::new()).unwrap();
+    let tokens: Vec<Token> = stream.tokens.iter().map(|&(token, _)| token).collect();
+    assert_eq!(tokens.len(), 14);
+    assert_eq!(tokens[..3], [Token::Int, tokens[1], Token::Semicolon]);
+    assert_eq!(tokens[12], Token::Semicolon);
+    assert_eq!(*tokens.last().unwrap(), Token::Eof);
+    let Token::Id(x) = tokens[1] else { panic!("{:?}", tokens[1]) };
+    assert_eq!(stream.name(x), Some("x"));
+    let Token::Str(start) = tokens[7] else { panic!("{:?}", tokens[7]) };
+    assert_eq!(stream.string(start), Some(&b"hi"[..]));
+    // the macro is expanded, and each token knows its line
+    assert_eq!(tokens[11], Token::Num(2));
+    assert_eq!(stream.tokens[0].1, 1);
+    assert_eq!(stream.tokens[11].1, 3);
+    assert!(tokenize("#if 1\nint x;", &CompileOptions::new()).is_err());
+}
+
+#[test]
+fn test_stages_build_what_the_parser_does() {
+    for level in 0..=2 {
+        let options = CompileOptions::new().opt_level(level);
+        let mut parser = Parser::new(SOURCE, options.clone());
+        let (code, data) = parser.parse().unwrap();
+        let image = build(SOURCE, &options).unwrap();
+        assert_eq!((image.code, image.data), (code, data), "-O{}", level);
+        assert_eq!(image.strings, parser.strings());
+    }
+}
+
+#[test]
+fn test_each_stage_can_be_stopped_at() {
+    let compiled = compile(SOURCE, &CompileOptions::new().opt_level(2)).unwrap();
+    // the front end leaves the code as parsed, whatever the level
+    let mut parser = Parser::new(SOURCE, CompileOptions::new());
+    assert_eq!(compiled.code, parser.parse().unwrap().0);
+    assert_eq!(compiled.functions.len(), 2);
+
+    let mut program = lift(&compiled).unwrap();
+    let blocks = program.blocks.iter().filter(|block| block.live).count();
+    optimize(&mut program, &compiled, 1);
+    assert!(program.blocks.iter().filter(|block| block.live).count() < blocks);
+    let lowered = lower(&program, &compiled).unwrap();
+    assert!(lowered.code.len() < compiled.code.len());
+    assert!(lowered.functions.iter().all(|f| f.end <= lowered.code.len()));
+    assert_eq!(link(&lowered).code, lowered.code);
+    assert_eq!(build(SOURCE, &CompileOptions::new().opt_level(1)).unwrap(), link(&lowered));
+
+    // warnings come with the front end's output
+    let compiled = compile("int main() { char c; c = 300; return 0; }", &CompileOptions::new()).unwrap();
+    assert_eq!(compiled.warnings.len(), 1);
+    assert_eq!(compile("int main() { return x; }", &CompileOptions::new()).err(), Some("Line 1: Unknown variable 'x'".to_string()));
+}
@@ -1,4 +1,6 @@
-use c4_rust::parser::{Parser, Type, OpCode};
+use c4_rust::parser::{Parser, OpCode, StructType, Type};
+use c4_rust::options::CompileOptions;
+use c4_rust::vm::{self, VM};
 
 #[test]
 fn test_pointer_dereferencing() {
@@ -93,4 +95,102 @@ fn test_continued_declaration_with_address_of() {
     
     // verify it worked
     assert!(result.is_ok());
-} 
\ No newline at end of file
+} 
+#[test]
+fn test_local_array_store_and_load() {
+    // same shape as tests/C_files/test_10.c
+    let source = "int main() { int a[10]; a[0] = 100; a[9] = 1000; return a[0] + a[9]; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    assert_eq!(vm.run(), Ok(1100));
+}
+
+/// compiles and runs a program, returning main's result
+fn run(source: &str) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new())
+}
+
+#[test]
+fn test_pointer_truthiness_and_null_checks() {
+    assert_eq!(run("int main() { int *p; p = 0; if (p) return 1; return 2; }"), Ok(2));
+    assert_eq!(run("int main() { int x; int *p; p = &x; if (p) return 1; return 2; }"), Ok(1));
+    assert_eq!(run("int main() { char *p; p = NULL; return (p == NULL) + (p != 0) * 10; }"), Ok(1));
+    assert_eq!(run("int main() { int *p; p = malloc(8); if (p == NULL) return 0; while (p) p = 0; return p == 0; }"), Ok(1));
+    // the VM zeroes locals, so an unset pointer is NULL
+    assert_eq!(run("int main() { char *p; if (p) return 5; return 6; }"), Ok(6));
+}
+
+#[test]
+fn test_pointer_ordering_within_a_segment() {
+    // string in the data segment
+    let source = "int main() { char *s; char *p; s = \"hello\"; p = s; while (*p) p = p + 1; return (p > s) + (s < p) * 10 + (p - s) * 100; }";
+    assert_eq!(run(source), Ok(511));
+    // heap block
+    let source = "int main() { int *b; int *e; b = malloc(32); e = b + 4; return (b < e) + (e >= b) * 10 + (e - b) * 100; }";
+    assert_eq!(run(source), Ok(411));
+    // local array on the stack
+    let source = "int main() { int a[4]; int *p; int n; a[0] = 1; a[1] = 2; a[2] = 3; a[3] = 4; n = 0; p = a; while (p < a + 4) { n = n + *p; p = p + 1; } return n; }";
+    assert_eq!(run(source), Ok(10));
+}
+
+#[test]
+fn test_array_plus_int_scales_like_a_pointer() {
+    assert_eq!(run("int main() { int a[4]; int *e; e = a + 4; return e - a; }"), Ok(4));
+    assert_eq!(run("int g[4]; int main() { int *p; g[2] = 7; p = g + 2; return *p; }"), Ok(7));
+    assert_eq!(run("int main() { int a[4]; a[3] = 9; return *(a + 3); }"), Ok(9));
+}
+
+#[test]
+fn test_data_pointers_order_below_stack_pointers() {
+    let source = "int g; int main() { int x; int *d; int *s; d = &g; s = &x; return d < s; }";
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_array_sizes_are_constant_expressions() {
+    let source = "int first;
+        enum { SIZE = 4, TWICE = SIZE * 2, MASK = (1 << 3) - 1, NEXT };
+        int g[SIZE + 1];
+        int main() { int a[TWICE]; char s[NEXT * 2]; int i; i = 0;
+            while (i < TWICE) { a[i] = i; i++; }
+            s[15] = 3; g[SIZE] = MASK;
+            return a[TWICE - 1] * 100 + g[4] * 10 + s[15] + NEXT; }";
+    assert_eq!(run(source), Ok(781));
+}
+
+#[test]
+fn test_non_constant_array_sizes_are_rejected() {
+    let err = run("int n; int main() { int a[n]; return 0; }").unwrap_err();
+    assert!(err.contains("array size must be a constant"), "{}", err);
+    let err = run("int n; int g[2 - 3]; int main() { return 0; }").unwrap_err();
+    assert!(err.contains("array size -1 is negative"), "{}", err);
+    let err = run("int n; enum { A = 1 / 0 }; int main() { return A; }").unwrap_err();
+    assert!(err.contains("enum value must be a constant"), "{}", err);
+}
+
+#[test]
+fn test_types_display_as_c_spells_them() {
+    let ptr = |typ: Type| Type::Ptr(Box::new(typ));
+    let array = |typ: Type, len| Type::Array(Box::new(typ), len);
+    let point = Type::Struct(Box::new(StructType::new("point", Vec::from([("x".to_string(), Type::Int)]))));
+    assert_eq!(Type::Int.to_string(), "int");
+    assert_eq!(ptr(Type::Int).to_string(), "int *");
+    assert_eq!(ptr(ptr(Type::Char)).to_string(), "char **");
+    assert_eq!(array(Type::Char, 5).to_string(), "char [5]");
+    assert_eq!(array(ptr(Type::Char), 3).to_string(), "char *[3]");
+    assert_eq!(ptr(array(Type::Int, 3)).to_string(), "int (*)[3]");
+    assert_eq!(array(array(Type::Int, 3), 2).to_string(), "int [2][3]");
+    assert_eq!(ptr(point.clone()).to_string(), "struct point *");
+    assert_eq!(array(ptr(Type::Char), 2).declare("argv"), "char *argv[2]");
+    assert_eq!(ptr(array(point, 4)).declare("rows"), "struct point (*rows)[4]");
+
+    // messages name the type the same way
+    let mut parser = Parser::new("int main() { int n; n = *n; return 0; }", false);
+    parser.init().unwrap();
+    assert_eq!(parser.parse().err(), Some("Line 1: Cannot dereference a non-pointer type 'int'".to_string()));
+    let mut parser = Parser::new("int main() { char c; return c[1]; }", false);
+    parser.init().unwrap();
+    assert_eq!(parser.parse().err(), Some("Line 1: Array indexing requires a pointer or array type, not 'char'".to_string()));
+}
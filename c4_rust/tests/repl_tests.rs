@@ -0,0 +1,174 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::repl::Repl;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// feeds lines to a fresh session, collecting what each finished entry printed
+fn session(lines: &[&str]) -> Vec<Result<String, String>> {
+    let mut repl = Repl::new(CompileOptions::new());
+    lines.iter().filter_map(|line| repl.feed(line)).collect()
+}
+
+#[test]
+fn test_expressions_print_their_value() {
+    assert_eq!(session(&["1 + 2 * 3", "'a'", "  "]), [Ok("= 7\n".to_string()), Ok("= 97\n".to_string()), Ok(String::new())]);
+}
+
+#[test]
+fn test_declarations_and_statements_persist() {
+    let results = session(&[
+        "int total;",
+        "int sq(int x) { return x * x; }",
+        "total = sq(3);",
+        "printf(\"total=%d\\n\", total);",
+        "total = total + 1;",
+        "total",
+    ]);
+    assert_eq!(results, [
+        Ok(String::new()),
+        Ok(String::new()),
+        Ok(String::new()),
+        Ok("total=9\n".to_string()),
+        Ok(String::new()),
+        Ok("= 10\n".to_string()),
+    ]);
+}
+
+#[test]
+fn test_unbalanced_brackets_continue_the_entry() {
+    let mut repl = Repl::new(CompileOptions::new());
+    assert_eq!(repl.prompt(), "c4> ");
+    assert_eq!(repl.feed("int fact(int n) {"), None);
+    assert_eq!(repl.prompt(), "...> ");
+    assert!(!repl.is_idle());
+    assert_eq!(repl.feed("  if (n < 2) return 1; // a '{' in a comment"), None);
+    assert_eq!(repl.feed("  return n * fact(n - 1);"), None);
+    assert_eq!(repl.feed("}"), Some(Ok(String::new())));
+    assert!(repl.is_idle());
+    assert_eq!(repl.feed("fact(5"), None);
+    assert_eq!(repl.feed(")"), Some(Ok("= 120\n".to_string())));
+    assert_eq!(repl.feed("\"{\"[0]"), Some(Ok("= 123\n".to_string())));
+    assert_eq!(repl.declarations().len(), 1);
+}
+
+#[test]
+fn test_errors_leave_the_session_as_it_was() {
+    let mut repl = Repl::new(CompileOptions::new());
+    repl.execute("int x;").unwrap();
+    assert_eq!(repl.execute("nope + 1"), Err("Unknown variable 'nope'".to_string()));
+    assert!(repl.execute("int x;").is_err());
+    assert!(repl.execute("printf(\"half\"); x = 1 / 0;").unwrap_err().starts_with("halfruntime error: division by zero"));
+    assert_eq!(repl.declarations(), ["int x;"]);
+    assert_eq!(repl.execute("x = 2;"), Ok(String::new()));
+    assert_eq!(repl.execute("x"), Ok("= 2\n".to_string()));
+}
+
+#[test]
+fn test_directives_keep_their_own_line() {
+    let results = session(&["#include <stdio.h>", "#define SQ(x) ((x) * (x))", "SQ(7)", "SQ(1, 2)"]);
+    assert_eq!(results, [
+        Ok(String::new()),
+        Ok(String::new()),
+        Ok("= 49\n".to_string()),
+        Err("macro 'SQ' takes 1 argument, got 2".to_string()),
+    ]);
+}
+
+#[test]
+fn test_commands_inspect_and_reset() {
+    let mut repl = Repl::new(CompileOptions::new());
+    repl.execute("enum { RED, GREEN = 5 };").unwrap();
+    repl.execute("char *names[3];").unwrap();
+    repl.execute("int add(int a, int b) { return a + b; }").unwrap();
+    assert_eq!(repl.execute(":symbols"), Ok("char *names[3]\nint add(), 2 parameters\nRED = 0\nGREEN = 5\n".to_string()));
+    assert!(repl.execute(":code").unwrap().contains("add:  ; line 1\n"));
+    assert!(repl.execute(":help").unwrap().contains(":reset"));
+    assert_eq!(repl.execute(":frob"), Err("unknown command ':frob', try :help".to_string()));
+    assert_eq!(repl.execute(":reset"), Ok("session cleared\n".to_string()));
+    assert_eq!(repl.execute(":symbols"), Ok(String::new()));
+    assert!(repl.execute("add(1, 2)").is_err());
+}
+
+#[test]
+fn test_cli_repl_reads_entries_from_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c4_rust"))
+        .arg("repl")
+        .stdin(Stdio::piped()).stdout(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"int g;\ng = 6;\ng * 7\n:quit\n1\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "c4> c4> c4> = 42\nc4> ");
+}
+
+/// a session with a function, a global set by a statement, and printed output
+fn saved_session() -> Repl {
+    let mut repl = Repl::new(CompileOptions::new());
+    for entry in ["int total;", "int sq(int x) { return x * x; }", "total = sq(4);", "printf(\"once\\n\");"] {
+        repl.execute(entry).unwrap();
+    }
+    repl
+}
+
+#[test]
+fn test_save_and_load_resume_a_session() {
+    let bytes = saved_session().save().unwrap();
+    assert!(bytes.starts_with(c4_rust::repl::SESSION_MAGIC));
+
+    let mut resumed = Repl::new(CompileOptions::new());
+    resumed.execute("int other;").unwrap();
+    assert_eq!(resumed.load(&bytes), Ok("loaded 2 declarations and 2 statements\n".to_string()));
+    assert_eq!(resumed.declarations(), ["int total;", "int sq(int x) { return x * x; }"]);
+    // the replayed printf isn't shown again
+    assert_eq!(resumed.execute("total + sq(2)"), Ok("= 20\n".to_string()));
+    assert!(resumed.execute("other").is_err());
+
+    // the embedded image is the session's program, runnable as it is
+    let start = bytes.windows(4).position(|w| w == c4_rust::image::MAGIC).unwrap();
+    let image = c4_rust::image::Image::from_bytes(&bytes[start..bytes.len() - 4]).unwrap();
+    let mut vm = c4_rust::vm::VM::with_host(image.code, image.data, &CompileOptions::new(), Box::new(c4_rust::host::NullHost));
+    assert_eq!(vm.run(), Ok(0));
+}
+
+#[test]
+fn test_load_rejects_damaged_sessions() {
+    let mut repl = Repl::new(CompileOptions::new());
+    assert_eq!(repl.load(b"C4B\0"), Err("not a c4 session".to_string()));
+    let mut bytes = saved_session().save().unwrap();
+    let last = bytes.len() - 10;
+    bytes[last] ^= 1;
+    assert!(repl.load(&bytes).unwrap_err().contains("the file is damaged"));
+    bytes.truncate(40);
+    assert!(repl.load(&bytes).unwrap_err().contains("truncated"));
+    assert!(repl.declarations().is_empty());
+}
+
+#[test]
+fn test_load_recompiles_an_image_from_another_build() {
+    let mut bytes = saved_session().save().unwrap();
+    // the opcode set hash follows the image's magic and version
+    let hash = bytes.windows(4).position(|w| w == c4_rust::image::MAGIC).unwrap() + 12;
+    bytes[hash] ^= 0xff;
+    let body = bytes.len() - 4;
+    let crc = c4_rust::image::crc32(&bytes[..body]);
+    bytes[body..].copy_from_slice(&crc.to_le_bytes());
+
+    let mut repl = Repl::new(CompileOptions::new());
+    let message = repl.load(&bytes).unwrap();
+    assert!(message.contains("compiled for a different opcode set"), "{}", message);
+    assert!(message.ends_with("compiled from source\n"));
+    assert_eq!(repl.execute("total"), Ok("= 16\n".to_string()));
+}
+
+#[test]
+fn test_save_and_load_commands_use_files() {
+    let path = std::env::temp_dir().join("c4_repl_session.c4s");
+    let path = path.to_str().unwrap();
+    let mut repl = saved_session();
+    assert_eq!(repl.execute(&format!(":save {}", path)), Ok(format!("saved 2 declarations and 2 statements to {}\n", path)));
+    repl.execute(":reset").unwrap();
+    assert_eq!(repl.execute(&format!(":load {}", path)), Ok("loaded 2 declarations and 2 statements\n".to_string()));
+    assert_eq!(repl.execute("sq(total)"), Ok("= 256\n".to_string()));
+    assert_eq!(repl.execute(":save"), Err(":save needs a file name".to_string()));
+    assert!(repl.execute(":load /nonexistent/session.c4s").unwrap_err().starts_with("could not read /nonexistent/session.c4s"));
+}
@@ -1,4 +1,6 @@
-use c4_rust::parser::Parser;
+use c4_rust::host::CaptureHost;
+use c4_rust::options::CompileOptions;
+use c4_rust::vm::{self, HostFns, VM};
 use std::fs;
 use std::path::Path;
 
@@ -24,67 +26,88 @@ fn test_self_hosting_capabilities() {
     };
     
     // parse source
-    let mut parser = Parser::new(&c4_source, false);
-    match parser.init() {
-        Ok(_) => {
-            println!("✓ Successfully initialized parser with C4 source code ({}KB)", c4_source.len() / 1024);
-        },
-        Err(e) => {
-            println!("× Failed to initialize parser: {}", e);
-            // not failing test
-        }
-    }
+    let (parser, compiled) = vm::compile(&c4_source, &CompileOptions::new(), &[]);
+    compiled.unwrap_or_else(|e| panic!("c4.c did not parse: {}", e));
+    println!("✓ Successfully parsed the entire C4 source code!");
     
-    let result = parser.parse();
-    match result {
-        Ok(_) => {
-            println!("✓ Successfully parsed the entire C4 source code!");
-        },
-        Err(e) => {
-            // check for known issues
-            let error_message = e.to_string();
-            
-            if error_message.contains("Line 58") || 
-               error_message.contains("Line 59") || 
-               error_message.contains("Line 60") || 
-               error_message.contains("Line 61") || 
-               error_message.contains("Line 73") {
-                println!("× Parse encountered expected issue in a known problematic section of c4.c");
-                println!("  Error was: {}", error_message);
-                println!("Note: This is a known issue with a complex section of the original c4.c code.");
-                println!("These specific sections use pointer arithmetic, bit manipulation, and complex string indexing");
-                println!("that our implementation doesn't fully support yet, but are not critical for basic functionality.");
-            } else {
-                println!("× Could not fully parse C4 source code: {}", e);
-                println!("Note: This is expected if the implementation doesn't support 100% of C4 features yet.");
-            }
-            
-            println!("The important thing is that the compiler infrastructure supports the core functionality.");
-            
-            // count symbols processed
-            let symbols = parser.get_symbols();
-            println!("✓ Successfully processed {} symbols", symbols.len());
-            
-            // show stats
-            let mut functions = 0;
-            let mut globals = 0;
-            let mut locals = 0;
-            
-            for symbol in symbols {
-                match symbol.class {
-                    c4_rust::parser::SymbolClass::Fun => functions += 1,
-                    c4_rust::parser::SymbolClass::Glo => globals += 1,
-                    c4_rust::parser::SymbolClass::Loc => locals += 1,
-                    _ => {}
-                }
-            }
-            
-            println!("✓ Recognized {} functions, {} global variables, and {} local variables", 
-                    functions, globals, locals);
-            
-            // check for success
-            assert!(functions > 0, "No functions were recognized in the C4 source");
-            assert!(globals > 0, "No global variables were recognized in the C4 source");
+    // count symbols processed
+    let symbols = parser.get_symbols();
+    println!("✓ Successfully processed {} symbols", symbols.len());
+    
+    // show stats
+    let mut functions = 0;
+    let mut globals = 0;
+    let mut locals = 0;
+    
+    for symbol in symbols {
+        match symbol.class {
+            c4_rust::parser::SymbolClass::Fun => functions += 1,
+            c4_rust::parser::SymbolClass::Glo => globals += 1,
+            c4_rust::parser::SymbolClass::Loc => locals += 1,
+            _ => {}
         }
     }
-} 
\ No newline at end of file
+    
+    println!("✓ Recognized {} functions, {} global variables, and {} local variables", 
+            functions, globals, locals);
+    
+    // check for success
+    assert!(functions > 0, "No functions were recognized in the C4 source");
+    assert!(globals > 0, "No global variables were recognized in the C4 source");
+} 
+
+/// runs c4.c in our VM with the given arguments after argv[0],
+/// returning its exit code, its output and the instructions the VM executed
+fn run_c4(args: &[&str]) -> (i64, String, usize) {
+    let source = fs::read_to_string("../c4.c").expect("c4.c is in the repository root");
+    let options = CompileOptions::new().max_cycles(0);
+    let (parser, compiled) = vm::compile(&source, &options, &[]);
+    let (code, data) = compiled.unwrap_or_else(|e| panic!("c4.c did not compile: {}", e));
+    let host = CaptureHost::new(Vec::new());
+    let output = host.output();
+    let mut vm = VM::load(&parser, code, data, &options, Box::new(host), HostFns::new()).unwrap();
+    let argv: Vec<String> = std::iter::once("c4").chain(args.iter().copied()).map(String::from).collect();
+    vm.set_args(&argv);
+    let exit_code = vm.run().unwrap_or_else(|e| panic!("c4 failed: {}", e));
+    let output = output.borrow().clone();
+    (exit_code, output, vm.stats().cycles)
+}
+
+#[test]
+fn test_c4_compiles_and_runs_hello_inside_the_vm() {
+    // c4 reads the file through open and read, compiles it into its own pools and runs it
+    let (exit_code, output, _) = run_c4(&["tests/C_files/test_1.c"]);
+    assert!(output.starts_with("Hello, World!\nMy name is C2RUST.\nNice to meet you!\nexit(0) cycle = "), "{}", output);
+    assert_eq!(exit_code, 0);
+}
+
+/// appends a line to the cycle log under the target directory, so runs can be compared over time
+#[cfg(feature = "stress")]
+fn record_cycles(name: &str, cycles: usize) {
+    use std::io::Write;
+    let seconds = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("self_hosting_cycles.log");
+    let mut log = fs::OpenOptions::new().create(true).append(true).open(&path).unwrap();
+    writeln!(log, "{} {} {}", seconds, name, cycles).unwrap();
+    println!("{}: {} instructions, logged to {}", name, cycles, path.display());
+}
+
+// c4 compiling c4 compiling hello, as the original c4 README shows; run with
+// cargo test --release --features stress --test self_hosting_test -- --nocapture
+#[cfg(feature = "stress")]
+#[test]
+fn test_c4_compiles_c4_compiling_hello() {
+    let hello = "tests/C_files/test_1.c";
+    let (_, direct, direct_cycles) = run_c4(&[hello]);
+    let (exit_code, nested, nested_cycles) = run_c4(&["../c4.c", hello]);
+
+    // the inner c4 prints the same program output, then each level reports its exit
+    let program = direct.split("exit(").next().unwrap();
+    assert!(nested.starts_with(program), "{}", nested);
+    assert_eq!(nested.matches("exit(0) cycle = ").count(), 2, "{}", nested);
+    assert_eq!(exit_code, 0);
+    assert!(nested_cycles > direct_cycles);
+
+    record_cycles("c4-hello", direct_cycles);
+    record_cycles("c4-c4-hello", nested_cycles);
+}
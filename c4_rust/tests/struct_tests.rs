@@ -0,0 +1,130 @@
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{StructType, Type};
+use c4_rust::vm;
+
+/// compiles and runs a program, giving main's return value
+fn run(source: &str) -> Result<i64, String> {
+    vm::run(source, &CompileOptions::new())
+}
+
+#[test]
+fn test_members_are_laid_out_in_order_and_aligned() {
+    let layout = StructType::new("s", vec![
+        ("c".to_string(), Type::Char),
+        ("n".to_string(), Type::Int),
+        ("name".to_string(), Type::Array(Box::new(Type::Char), 3)),
+    ]);
+    let offsets: Vec<usize> = layout.members.iter().map(|m| m.offset).collect();
+    assert_eq!(offsets, [0, 8, 16]);
+    assert_eq!((layout.size, layout.align), (24, 8));
+    assert_eq!(layout.member("name").map(|m| m.typ.size()), Some(3));
+    assert!(layout.member("missing").is_none());
+
+    let chars = StructType::new("t", vec![("a".to_string(), Type::Char), ("b".to_string(), Type::Char)]);
+    assert_eq!(Type::Struct(Box::new(chars)).size(), 2);
+    let source = "struct point { int x; int y; };
+struct rect { char tag; struct point min, max; };
+int main() { return sizeof(struct point) * 100 + sizeof(struct rect); }";
+    assert_eq!(run(source), Ok(1640));
+}
+
+#[test]
+fn test_member_access_on_locals_globals_and_pointers() {
+    let source = "struct point { int x; int y; };
+struct rect { struct point min; struct point max; char name[4]; };
+struct rect box;
+int area(struct rect *r) {
+    return (r->max.x - r->min.x) * (r->max.y - r->min.y);
+}
+int main() {
+    struct rect r;
+    struct point *p;
+    r.min.x = 1; r.min.y = 2;
+    r.max.x = 4; r.max.y = 6;
+    r.name[1] = 'z';
+    p = &r.max;
+    p->y += 4;
+    box.max.x++;
+    ++box.max.y;
+    return area(&r) * 1000 + (*p).y * 10 + box.max.x + box.max.y + (r.name[1] == 'z');
+}";
+    assert_eq!(run(source), Ok(24 * 1000 + 100 + 3));
+}
+
+#[test]
+fn test_arrays_of_structs_and_linked_lists() {
+    let source = "struct node { int value; char tag; struct node *next; };
+struct node *head;
+struct node *push(int value) {
+    struct node *n;
+    n = (struct node *)malloc(sizeof(struct node));
+    n->value = value;
+    n->tag = 'a' + value;
+    n->next = head;
+    head = n;
+    return n;
+}
+int main() {
+    struct node pool[3], *n;
+    int i, sum;
+    i = 0;
+    while (i < 3) { pool[i].value = i * i; i++; }
+    push(1); push(2); push(3)->tag = 'z';
+    sum = pool[2].value * 10000;
+    n = head;
+    while (n) { sum = sum * 10 + n->value; n = n->next; }
+    return sum + (head->next->next->tag == 'b') * 1000000 + (&pool[1] - pool);
+}";
+    assert_eq!(run(source), Ok(40000321 + 1000000 + 1));
+}
+
+#[test]
+fn test_global_struct_initializers() {
+    let source = "struct pair { char c; int n; char *s; };
+struct pair p = { 'x', -5, \"hi\", };
+struct pair q = { 1 };
+int main() { return p.c + p.n + p.s[1] + q.c + q.n; }";
+    assert_eq!(run(source), Ok(120 - 5 + 105 + 1));
+}
+
+#[test]
+fn test_bad_structs_are_errors() {
+    let cases = [
+        ("struct s { int a; int a; }; int main() { return 0; }", "Line 1: Duplicate member 'a' in struct 's'"),
+        ("struct s { int a; }; struct s { int b; }; int main() { return 0; }", "Line 1: struct 's' is already defined"),
+        ("struct s { }; int main() { return 0; }", "Line 1: struct 's' has no members"),
+        ("struct s { struct s inner; }; int main() { return 0; }", "Line 1: member 'inner' has the incomplete type 'struct s'"),
+        ("struct s x; int main() { return 0; }", "Line 1: 'x' has the incomplete type 'struct s'"),
+        ("struct s { int a; }; int main() { struct s x; return x.b; }", "Line 1: struct 's' has no member 'b'"),
+        ("struct s { int a; }; int main() { struct s x; return x->a; }", "Line 1: '->' needs a pointer to a struct on its left, not 'struct s'"),
+        ("struct s { int a; }; int main() { struct s *x; return x.a; }", "Line 1: '.' needs a struct on its left, use '->' through a pointer"),
+        ("struct s { int a; }; int main() { struct s x, y; x = y; return 0; }", "Line 1: struct 'x' can't be assigned as a whole, assign its members"),
+        ("struct s { int a; }; int f(struct s x) { return 0; } int main() { return 0; }", "Line 1: parameter 'x' can't be a struct, pass a pointer to it"),
+        ("struct s { int a; }; struct s f() { return 0; } int main() { return 0; }", "Line 1: 'f' can't return a struct, return a pointer to it"),
+        ("struct s { int a; }; int f(struct s *x) { return 0; } int main() { struct s x; return f(x); }", "Line 1: a struct can't be passed to 'f', pass a pointer to it"),
+        ("struct s; int main() { struct s *p; return p->a; }", "Line 1: struct 's' is declared but never defined"),
+        ("struct s { int a; }; struct s g = { 1, 2 }; int main() { return 0; }", "Line 1: too many initializers for 'g', a struct of 1 members"),
+    ];
+    for (source, error) in cases {
+        assert_eq!(run(source), Err(error.to_string()), "{}", source);
+    }
+}
+
+#[test]
+fn test_reserved_words_name_members_but_nothing_else() {
+    let source = "struct op { int if; char while[2]; struct op *else; };
+        int main() { struct op a, b; a.if = 3; a.else = &b; a.else->while[1] = 4; return a.if * 10 + b.while[1]; }";
+    assert_eq!(run(source), Ok(34));
+    let cases = [
+        ("int while; int main() { return 0; }", "Line 1: 'while' is a reserved word and can't be used as a name"),
+        ("int main() { char *for; return 0; }", "Line 1: 'for' is a reserved word and can't be used as a name"),
+        ("int f(int return) { return 0; }", "Line 1: 'return' is a reserved word and can't be used as a name"),
+        ("enum { A, sizeof };", "Line 1: 'sizeof' is a reserved word and can't be used as a name"),
+        ("struct int { int a; };", "Line 1: 'int' is a reserved word and can't be used as a name"),
+        // a type word followed by more of the declaration isn't taken as a name
+        ("struct s { int char c; };", "Line 1: Expected a member name in struct 's'"),
+    ];
+    for (source, error) in cases {
+        assert_eq!(run(source), Err(error.to_string()), "{}", source);
+    }
+}
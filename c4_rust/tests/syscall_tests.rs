@@ -0,0 +1,198 @@
+use c4_rust::host::CaptureHost;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{printf_arg_count, Parser};
+use c4_rust::vm::{self, HostFns, VM};
+
+/// compiles and runs a program with the given options
+fn run_with(source: &str, options: CompileOptions) -> Result<i64, String> {
+    vm::run(source, &options)
+}
+
+#[test]
+fn test_syscalls_clean_up_their_arguments() {
+    // a stray ADJ after malloc used to eat the address being assigned to
+    let source = "int main() { int *p; int *q; p = malloc(16); q = malloc(8); *p = 5; *q = 2; return *p - *q; }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(3));
+}
+
+#[test]
+fn test_virtual_clock_is_deterministic() {
+    let source = "int main() { int t; t = clock(); usleep(1000); return clock() - t; }";
+    let options = CompileOptions::new().virtual_time(true);
+    let elapsed = run_with(source, options.clone()).unwrap();
+
+    // the sleep plus a handful of instructions
+    assert!((1000..1100).contains(&elapsed), "elapsed {}", elapsed);
+    assert_eq!(run_with(source, options), Ok(elapsed));
+}
+
+#[test]
+fn test_virtual_clock_counts_instructions() {
+    let source = "int main() { int i; int t; t = clock(); i = 0; while (i < 100) i = i + 1; return clock() - t; }";
+    let elapsed = run_with(source, CompileOptions::new().virtual_time(true)).unwrap();
+    assert!(elapsed > 100, "elapsed {}", elapsed);
+}
+
+#[test]
+fn test_real_usleep_waits() {
+    let source = "int main() { int t; t = clock(); usleep(2000); return clock() - t; }";
+    let elapsed = run_with(source, CompileOptions::new()).unwrap();
+    assert!(elapsed >= 2000, "elapsed {}", elapsed);
+}
+
+#[test]
+fn test_stack_strings_print_with_percent_s() {
+    // printf returns the number of characters written
+    let source = "int main() { char a[4]; a[0] = 'h'; a[1] = 'i'; a[2] = 0; return printf(\"%s|\", a); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(3));
+
+    let source = "int main() { char f[4]; f[0] = '%'; f[1] = 's'; f[2] = 0; return printf(f, \"abcd\"); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(4));
+}
+
+#[test]
+fn test_memset_and_memcmp_on_stack_strings() {
+    let source = "int main() { char a[4]; memset(a, 'x', 3); a[3] = 0; return memcmp(a, \"xxx\", 4); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(0));
+
+    let source = "int main() { char a[4]; char b[4]; memset(a, 'a', 4); memset(b, 'c', 4); return memcmp(a, b, 4); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(-2));
+}
+
+#[test]
+fn test_open_read_close_file() {
+    let dir = std::env::temp_dir().join("c4_rust_syscall_open");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("in.txt"), "hi\r\nthere").unwrap();
+
+    // guest code may spell the path with either separator
+    let path = dir.join("in.txt").to_string_lossy().replace('/', "\\").replace('\\', "\\\\");
+    let source = format!(
+        "int main() {{ int fd; int n; char *buf; buf = malloc(16); fd = open(\"{}\", 0);
+           if (fd < 3) return -1;
+           n = read(fd, buf, 16);
+           if (close(fd)) return -2;
+           if (close(fd) != -1) return -3;
+           return n * 1000 + buf[2] * 10 + (buf[4] == 't'); }}",
+        path
+    );
+    assert_eq!(run_with(&source, CompileOptions::new()), Ok(9131));
+}
+
+#[test]
+fn test_open_missing_file_and_bad_fd() {
+    let source = "int main() { char buf[4]; return open(\"/no/such/c4/file\", 0) + read(42, buf, 4) + close(7); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(-3));
+}
+
+#[test]
+fn test_printf_arg_count_reads_formats_like_the_vm() {
+    assert_eq!(printf_arg_count(b"hello\n"), 0);
+    assert_eq!(printf_arg_count(b"%d %s %c"), 3);
+    assert_eq!(printf_arg_count(b"100%% %-08.3lld %hhx"), 2);
+    assert_eq!(printf_arg_count(b"%2$d %1$d %3$s"), 3);
+    // unknown conversions and a dangling % take nothing
+    assert_eq!(printf_arg_count(b"%q %d %"), 1);
+}
+
+#[test]
+fn test_printf_argument_count_mismatch_warns() {
+    let source = "int main() {\n  int a;\n  a = 1;\n  printf(\"%d %d\\n\", a);\n  printf(\"%d\\n\", a, a);\n  printf(\"%d%%\\n\", a);\n  printf(\"plain\\n\");\n  return 0;\n}";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    parser.parse().unwrap();
+    assert_eq!(parser.warnings(), [
+        "Line 4: printf format expects 2 arguments, got 1",
+        "Line 5: printf format expects 1 argument, got 2",
+    ]);
+
+    // a format that isn't a literal can't be checked
+    let source = "int main() { char *f; f = \"%d %d\"; printf(f, 1); return 0; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    parser.parse().unwrap();
+    assert!(parser.warnings().is_empty());
+}
+
+#[test]
+fn test_printf_prints_arguments_in_call_order() {
+    let source = "int main() { int m; m = 1 << 63; printf(\"%d %d %d %d|%d|%u|%2$d\\n\", 1, -2, 3, -4, m, -1); return 0; }";
+    let options = CompileOptions::new();
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let (code, data) = compiled.unwrap();
+    let host = CaptureHost::new(Vec::new());
+    let output = host.output();
+    let mut vm = VM::load(&parser, code, data, &options, Box::new(host), HostFns::new()).unwrap();
+    assert_eq!(vm.run(), Ok(0));
+    assert_eq!(*output.borrow(), "1 -2 3 -4|-9223372036854775808|18446744073709551615|-2\n");
+}
+
+#[test]
+fn test_hexdump_prints_guest_memory() {
+    let source = "int main() { char *s; s = \"Hi!\\n\"; if (hexdump(s, 1 << 40) != -1) return 1; return hexdump(s, 5); }";
+    let options = CompileOptions::new();
+    let mut parser = Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let host = CaptureHost::new(Vec::new());
+    let output = host.output();
+    let mut vm = VM::with_host(code, data, &options, Box::new(host));
+    assert_eq!(vm.run(), Ok(0));
+    assert_eq!(*output.borrow(), format!("{:08x}: 4869 210a 00{:29}Hi!..\n", 16, ""));
+}
+
+/// runs a program through CaptureHost and returns what it printed
+fn printed(source: &str, options: CompileOptions) -> String {
+    let mut parser = Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let host = CaptureHost::new(Vec::new());
+    let output = host.output();
+    let mut vm = VM::with_host(code, data, &options, Box::new(host));
+    assert_eq!(vm.run(), Ok(0));
+    let text = output.borrow().clone();
+    text
+}
+
+#[test]
+fn test_getenv_reads_variables_given_with_env() {
+    let source = "int main() { char *home; home = getenv(\"HOME\");
+        if (getenv(\"NOPE\") != 0) return 1;
+        if (getenv(\"HOME\") != home) return 2;
+        printf(\"%s|%s|\", home, getenv(\"EMPTY\"));
+        return 0; }";
+    let options = CompileOptions::new().env("HOME", "/old").env("HOME", "/guest").env("EMPTY", "");
+    assert_eq!(printed(source, options), "/guest||");
+}
+
+#[test]
+fn test_getenv_sees_the_host_only_when_inherited() {
+    let source = "int main() { char *name; name = getenv(\"CARGO_PKG_NAME\"); if (name) printf(\"%s\", name); return 0; }";
+    assert_eq!(printed(source, CompileOptions::new()), "");
+    assert_eq!(printed(source, CompileOptions::new().inherit_env(true)), env!("CARGO_PKG_NAME"));
+    let options = CompileOptions::new().inherit_env(true).env("CARGO_PKG_NAME", "guest");
+    assert_eq!(printed(source, options), "guest");
+}
+
+#[test]
+fn test_system_and_popen_are_denied_by_default() {
+    let source = "int main() { if (system(0) != 0) return 1; if (popen(\"echo hi\", \"r\") != -1) return 2; return system(\"echo hi\"); }";
+    assert_eq!(run_with(source, CompileOptions::new()), Ok(-1));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_allow_exec_runs_commands() {
+    let source = "int main() { char buf[16]; int fd; int n;
+        printf(\"[\");
+        if (system(\"echo hi; exit 3\") != 3) return 1;
+        fd = popen(\"printf pipe\", \"r\");
+        if (fd < 0) return 2;
+        n = read(fd, buf, 15); buf[n] = 0;
+        if (pclose(fd) != 0) return 3;
+        if (popen(\"true\", \"w\") != -1) return 4;
+        printf(\"%s]\", buf);
+        return system(0) - 1; }";
+    let options = CompileOptions::new().allow_exec(true).buffering(c4_rust::options::Buffering::Full);
+    assert_eq!(printed(source, options), "[hi\npipe]");
+}
@@ -1,4 +1,7 @@
-use c4_rust::vm::VM;
+use c4_rust::host::DefaultHost;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{self, HeapBlock, HostFns, RuntimeError, VM, DATA_STACK_THRESHOLD};
 
 #[test]
 fn test_vm_load_store_functions() {
@@ -37,4 +40,236 @@ fn test_vm_load_store_functions() {
     // test store_char
     vm.store_char(9, 66); // ASCII 'B'
     assert_eq!(vm.load_char(9), 66, "store_char didn't set the expected value");
-} 
\ No newline at end of file
+}
+
+#[test]
+fn test_heap_blocks_and_stack_high_water() {
+    // malloc(16); malloc(3); exit
+    let code = vec![
+        OpCode::IMM as i64, 16,
+        OpCode::PSH as i64,
+        OpCode::MALC as i64,
+        OpCode::IMM as i64, 3,
+        OpCode::PSH as i64,
+        OpCode::MALC as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    let mut vm = VM::new(code, vec![0u8; 8], false);
+    assert_eq!(vm.run(), Ok(24));
+    
+    assert_eq!(vm.heap_blocks(), &[HeapBlock { addr: 8, size: 16, site: 3, freed: None }, HeapBlock { addr: 24, size: 3, site: 7, freed: None }]);
+    
+    // one slot deep at most
+    let map = vm.memory_map(&[]);
+    assert!(map.contains("\"max_depth\": 1"), "{}", map);
+    assert!(map.contains("{\"addr\": 24, \"size\": 3}"), "{}", map);
+}
+
+#[test]
+fn test_memory_map_lists_globals() {
+    let source = "int x;\nchar y;\nint main() { x = 1; return 0; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    vm.run().unwrap();
+    
+    let map = vm.memory_map(parser.get_symbols());
+    assert!(map.contains("\"addr\": 16, \"size\": 8, \"type\": \"int\"}"), "{}", map);
+    assert!(map.contains("\"addr\": 24, \"size\": 1, \"type\": \"char\"}"), "{}", map);
+    assert!(map.contains("\"heap\": []"), "{}", map);
+    
+    // main's frame pushed something
+    assert!(vm.stack_high_water() < DATA_STACK_THRESHOLD + 8192);
+}
+
+/// runs source with --sanitize
+fn run_sanitized(source: &str) -> Result<i64, RuntimeError> {
+    vm_with(source, &CompileOptions::new().sanitize(true)).run()
+}
+
+const OVERFLOW: &str = "int main() {
+    char *p;
+    int i;
+    p = malloc(10);
+    i = 0;
+    while (i <= 10) { p[i] = 'x'; i = i + 1; }
+    free(p);
+    return 0;
+}";
+
+#[test]
+fn test_sanitize_reports_heap_overflow_on_free() {
+    let error = run_sanitized(OVERFLOW).unwrap_err();
+    let RuntimeError::HeapOverflow { addr, block, line } = error.clone() else { panic!("{}", error) };
+    assert_eq!((addr, block.size, line), (block.addr + 10, 10, Some(4)));
+    assert!(error.to_string().starts_with(&format!("heap buffer overflow at {}, 0 bytes after the 10 byte block allocated at ", addr)),
+            "{}", error);
+    assert!(error.to_string().ends_with("(line 4)"), "{}", error);
+
+    // without sanitize the stray byte goes unnoticed
+    let mut parser = Parser::new(OVERFLOW, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(VM::new(code, data, false).run(), Ok(0));
+}
+
+#[test]
+fn test_sanitize_checks_unfreed_blocks_at_exit() {
+    let underflow = "int main() { char *p; char *q; p = malloc(4); q = malloc(4); q[-3] = 1; return 7; }";
+    let error = run_sanitized(underflow).unwrap_err();
+    assert!(error.to_string().contains("3 bytes before the 4 byte block"), "{}", error);
+
+    let clean = "int main() { int *p; int n; p = malloc(4 * sizeof(int)); p[3] = 9; n = p[3]; free(p); return n; }";
+    assert_eq!(run_sanitized(clean), Ok(9));
+}
+
+/// runs source without sanitize, lines known
+fn run_lines(source: &str) -> Result<i64, RuntimeError> {
+    vm_with(source, &CompileOptions::new()).run()
+}
+
+#[test]
+fn test_double_free_names_both_frees() {
+    let source = "int main() {
+    int *p;
+    p = malloc(16);
+    free(p);
+    free(p);
+    return 0;
+}";
+    let error = run_lines(source).unwrap_err();
+    let RuntimeError::DoubleFree { block, line, .. } = error.clone() else { panic!("{}", error) };
+    assert_eq!((block.size, line), (16, Some(5)));
+    assert!(block.freed.is_some_and(|pc| pc < block.site + 20 && pc > block.site));
+    assert!(error.to_string().contains("(line 5), the 16 byte block allocated at "), "{}", error);
+}
+
+#[test]
+fn test_use_after_free_on_loads_stores_and_syscalls() {
+    let load = "int main() {\n    int *p;\n    p = malloc(16);\n    free(p);\n    return p[1];\n}";
+    let error = run_lines(load).unwrap_err();
+    let RuntimeError::UseAfterFree { addr, block, line, .. } = error.clone() else { panic!("{}", error) };
+    assert_eq!((addr, line), (block.addr + 8, Some(5)));
+
+    let store = "int main() { char *p; char *q; p = malloc(4); q = malloc(4); free(p); q[0] = 1; p[3] = 1; return 0; }";
+    assert!(matches!(run_lines(store), Err(RuntimeError::UseAfterFree { .. })));
+    let memset_freed = "int main() { char *p; p = malloc(4); free(p); memset(p, 0, 4); return 0; }";
+    assert!(matches!(run_lines(memset_freed), Err(RuntimeError::UseAfterFree { .. })));
+
+    // freeing one block leaves its neighbours usable, and free(0) is a no-op
+    let neighbours = "int main() { int *p; int *q; p = malloc(8); q = malloc(8); free(p); free(0); *q = 4; return *q; }";
+    assert_eq!(run_lines(neighbours), Ok(4));
+}
+
+/// compiles source and returns a VM with the given options, lines known
+fn vm_with(source: &str, options: &CompileOptions) -> VM {
+    let (parser, compiled) = vm::compile(source, options, &[]);
+    let (code, data) = compiled.unwrap();
+    VM::load(&parser, code, data, options, Box::new(DefaultHost::default()), HostFns::new()).unwrap()
+}
+
+// keeps one list node alive through a global and drops 400 temporary buffers
+const GARBAGE: &str = "int *keep;
+int main() {
+    int i; int *tmp;
+    keep = malloc(2 * sizeof(int));
+    keep[0] = 41;
+    keep[1] = (int)malloc(sizeof(int));
+    *(int *)keep[1] = 1;
+    i = 0;
+    while (i < 400) { tmp = malloc(100); tmp[0] = i; free(tmp); i = i + 1; }
+    return keep[0] + *(int *)keep[1];
+}";
+
+#[test]
+fn test_gc_reclaims_unreachable_blocks() {
+    let mut vm = vm_with(GARBAGE, &CompileOptions::new().gc(true));
+    assert_eq!(vm.run(), Ok(42));
+    let stats = vm.stats();
+    assert_eq!(stats.allocations, 402);
+    assert!(stats.collections >= 9, "{:?}", stats);
+    assert_eq!(stats.collected_blocks, stats.allocations - vm.heap_blocks().len());
+    assert_eq!(stats.live_bytes, vm.heap_blocks().iter().map(|b| b.size).sum::<usize>());
+
+    // the global's block and the one it points to survive every collection
+    let sizes: Vec<usize> = vm.heap_blocks().iter().take(2).map(|b| b.size).collect();
+    assert_eq!(sizes, vec![16, 8]);
+
+    // memory is reused, without gc every buffer takes new space
+    let mut plain = vm_with(GARBAGE, &CompileOptions::new());
+    assert_eq!(plain.run(), Ok(42));
+    assert_eq!(plain.stats().collections, 0);
+    let end = |vm: &VM| vm.heap_blocks().iter().map(|b| b.addr + b.size).max().unwrap();
+    assert!(end(&vm) + 30000 < end(&plain), "{} vs {}", end(&vm), end(&plain));
+}
+
+#[test]
+fn test_gc_free_is_a_no_op() {
+    let source = "int main() { int *p; p = malloc(8); *p = 5; free(p); free(p); return *p; }";
+    let mut vm = vm_with(source, &CompileOptions::new().gc(true));
+    assert_eq!(vm.run(), Ok(5));
+    assert_eq!(vm.heap_blocks()[0].freed, None);
+    assert_eq!(vm.stats().collections, 0);
+}
+
+#[test]
+fn test_int_byte_order_in_the_data_segment() {
+    // the low byte of 258 is 2, the next 1, the rest 0
+    let source = "int x;
+int main() {
+    char *p;
+    x = 258;
+    p = (char *)&x;
+    return p[0] * 100 + p[1] * 10 + p[7];
+}";
+    assert_eq!(vm_with(source, &CompileOptions::new()).run(), Ok(210));
+    assert_eq!(vm_with(source, &CompileOptions::new().big_endian(true)).run(), Ok(2));
+
+    // ints round-trip either way, and the bytes are the same on every host
+    let mut vm = VM::new(vec![], vec![0u8; 8], false);
+    vm.store_int(0, 0x0102);
+    assert_eq!((vm.load_char(0), vm.load_char(1), vm.load_int(0)), (2, 1, 0x0102));
+    let mut vm = VM::with_options(vec![], vec![0u8; 8], &CompileOptions::new().big_endian(true));
+    vm.store_int(0, 0x0102);
+    assert_eq!((vm.load_char(6), vm.load_char(7), vm.load_int(0)), (1, 2, 0x0102));
+}
+
+#[test]
+fn test_heap_holds_c4_sized_pools() {
+    // c4.c allocates four 256KB pools before it reads its source
+    let source = "int main() { char *pools[4]; int i; i = 0;
+        while (i < 4) { pools[i] = malloc(256 * 1024); if (!pools[i]) return -1; pools[i][256 * 1024 - 1] = i + 1; i = i + 1; }
+        return pools[0][256 * 1024 - 1] + pools[3][256 * 1024 - 1]; }";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+    let mut vm = VM::new(code, data, false);
+    assert_eq!(vm.run(), Ok(5));
+    assert!(vm.stack_high_water() > DATA_STACK_THRESHOLD);
+}
+
+#[test]
+fn test_stack_depth_is_tracked_and_limited() {
+    let source = |n: i64| format!("int depth(int n) {{ int pad; if (n == 0) return 0; return 1 + depth(n - 1); }}\nint main() {{ return depth({}); }}", n);
+    let run = |n: i64, options: &CompileOptions| {
+        let source = source(n);
+        let mut parser = Parser::new(&source, options.clone());
+        let (code, data) = parser.parse().unwrap();
+        let mut vm = VM::with_options(code, data, options);
+        vm.set_functions(parser.functions().to_vec());
+        let result = vm.run();
+        (result, vm.stats().max_stack_bytes)
+    };
+
+    // each call adds the pushed 1 of `1 +`, the argument, return address, saved bp and one local
+    let (result, shallow) = run(10, &CompileOptions::new());
+    assert_eq!(result, Ok(10));
+    let (_, deep) = run(20, &CompileOptions::new());
+    assert_eq!(deep - shallow, 10 * 5 * 8);
+
+    let (result, _) = run(20, &CompileOptions::new().max_stack_bytes(shallow));
+    assert!(matches!(result, Err(RuntimeError::StackLimit { limit, ref function, depth }) if limit == shallow && function == "depth" && depth > 10), "{:?}", result);
+    assert_eq!(run(10, &CompileOptions::new().max_stack_bytes(shallow)).0, Ok(10));
+}
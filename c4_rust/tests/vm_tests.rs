@@ -1,5 +1,10 @@
-use c4_rust::parser::{Parser, OpCode};
-use c4_rust::vm::VM;
+use c4_rust::host::Host;
+use c4_rust::observer::Observer;
+use c4_rust::options::CompileOptions;
+use c4_rust::parser::{OpCode, Parser};
+use c4_rust::vm::{Code, HostFns, Instruction, RuntimeError, SharedProgram, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[test]
 fn test_vm_simple_program() {
@@ -124,4 +129,575 @@ fn test_vm_printf() {
     
     // just check it ran
     assert!(result.is_ok());
-} 
\ No newline at end of file
+} 
+#[test]
+fn test_vm_division_by_zero() {
+    // 1 / 0
+    let code = vec![
+        OpCode::IMM as i64, 1,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 0,
+        OpCode::DIV as i64,        // at address 5
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    
+    let mut vm = VM::new(code, vec![], false);
+    assert_eq!(vm.run(), Err(RuntimeError::DivByZero { pc: 5 }));
+}
+
+#[test]
+fn test_vm_cycle_limit() {
+    // jump to self forever
+    let code = vec![OpCode::JMP as i64, 0];
+    
+    let mut vm = VM::new(code, vec![], false);
+    assert!(matches!(vm.run(), Err(RuntimeError::CycleLimit { .. })));
+}
+
+#[test]
+fn test_vm_cycle_limit_is_configurable() {
+    let source = "int main() { int i; i = 0; while (i < 20000) i = i + 1; return i % 256; }";
+    let run = |options: CompileOptions| {
+        let mut parser = Parser::new(source, options.clone());
+        parser.init().unwrap();
+        let (code, data) = parser.parse().unwrap();
+        VM::with_options(code, data, &options).run()
+    };
+    assert_eq!(run(CompileOptions::new()), Err(RuntimeError::CycleLimit { limit: 50000 }));
+    assert_eq!(run(CompileOptions::new().max_cycles(1000)), Err(RuntimeError::CycleLimit { limit: 1000 }));
+    assert_eq!(run(CompileOptions::new().max_cycles(0)), Ok(20000 % 256));
+}
+
+#[test]
+fn test_vm_runtime_error_messages() {
+    let mut vm = VM::new(vec![99], vec![], false);
+    let err = vm.run().unwrap_err();
+    assert_eq!(err, RuntimeError::UnknownOpcode { op: 99, pc: 0 });
+    assert_eq!(err.to_string(), "unknown instruction 99 at 0");
+    
+    // reading past the data segment
+    let mut vm = VM::new(vec![OpCode::IMM as i64, 100, OpCode::LI as i64], vec![0u8; 8], false);
+    assert_eq!(vm.run(), Err(RuntimeError::OobRead { addr: 100, size: 8 }));
+}
+
+#[test]
+fn test_vm_bounds_checks_option() {
+    // *8 = 1 with an 8 byte data segment
+    let code = vec![
+        OpCode::IMM as i64, 8,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 1,
+        OpCode::SI as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    
+    // grows the data segment by default
+    let mut vm = VM::new(code.clone(), vec![0u8; 8], false);
+    assert_eq!(vm.run(), Ok(1));
+    
+    let options = CompileOptions::new().bounds_checks(true);
+    let mut vm = VM::with_options(code, vec![0u8; 8], &options);
+    assert_eq!(vm.run(), Err(RuntimeError::OobWrite { addr: 8, size: 8 }));
+}
+
+#[test]
+fn test_printf_long_long_specifiers() {
+    let vm = VM::new(vec![], vec![], false);
+    assert_eq!(vm.format_printf("%lld %llu %zd", &[-5_000_000_000, -1, 42]), "-5000000000 18446744073709551615 42");
+    assert_eq!(vm.format_printf("%d %ld", &[1 << 40, 7]), "1099511627776 7");
+    assert_eq!(vm.format_printf("%hhd %hu", &[0x1ff, -1]), "-1 65535");
+}
+
+#[test]
+fn test_printf_argument_count_mismatch() {
+    let vm = VM::new(vec![], vec![], false);
+    // extras are ignored, missing ones print the specifier itself
+    assert_eq!(vm.format_printf("%d\n", &[1, 2, 3]), "1\n");
+    assert_eq!(vm.format_printf("%d %lld %s", &[1]), "1 %lld %s");
+    assert_eq!(vm.format_printf("100%% %q", &[]), "100% %q");
+}
+
+#[test]
+fn test_printf_positional_and_width() {
+    let mut data = vec![0u8; 16];
+    data[1..9].copy_from_slice(b"LEA IMM ");
+    let vm = VM::new(vec![], data, false);
+    assert_eq!(vm.format_printf("%2$d %1$d", &[1, 2]), "2 1");
+    assert_eq!(vm.format_printf("%8.4s|", &[1]), "    LEA |");
+    assert_eq!(vm.format_printf("%-4d|%04d|%x|%#X", &[7, -7, 255, 255]), "7   |-007|ff|0XFF");
+}
+
+#[test]
+fn test_printf_negative_and_extreme_values() {
+    let vm = VM::new(vec![], vec![], false);
+    assert_eq!(vm.format_printf("%d %i %d", &[-1, -42, 0]), "-1 -42 0");
+    assert_eq!(vm.format_printf("%d", &[i64::MIN]), "-9223372036854775808");
+    assert_eq!(vm.format_printf("%d", &[i64::MAX]), "9223372036854775807");
+    assert_eq!(vm.format_printf("%+d %+d % d", &[5, -5, 5]), "+5 -5  5");
+    assert_eq!(vm.format_printf("%6d|%-6d|%06d", &[-12, -12, -12]), "   -12|-12   |-00012");
+    assert_eq!(vm.format_printf("%025d", &[i64::MIN]), "-000009223372036854775808");
+}
+
+#[test]
+fn test_printf_unsigned_reinterprets_the_bits() {
+    let vm = VM::new(vec![], vec![], false);
+    assert_eq!(vm.format_printf("%u %x %o", &[-1, -1, -1]), "18446744073709551615 ffffffffffffffff 1777777777777777777777");
+    assert_eq!(vm.format_printf("%u %X", &[i64::MIN, i64::MIN]), "9223372036854775808 8000000000000000");
+    assert_eq!(vm.format_printf("%hu %hhu %hhd", &[-1, -1, 200]), "65535 255 -56");
+}
+
+#[test]
+fn test_vm_dup() {
+    // DUP copies the top of stack, ADD then pops one copy
+    let code = vec![
+        OpCode::IMM as i64, 21,
+        OpCode::PSH as i64,
+        OpCode::DUP as i64,
+        OpCode::IMM as i64, 0,
+        OpCode::ADD as i64,
+        OpCode::ADD as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    let mut vm = VM::new(code, vec![], false);
+    assert_eq!(vm.run(), Ok(42));
+}
+
+#[test]
+fn test_vm_pop() {
+    // POP drops the 1 so ADD sees the 40 beneath it
+    let code = vec![
+        OpCode::IMM as i64, 40,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 1,
+        OpCode::PSH as i64,
+        OpCode::POP as i64,
+        OpCode::IMM as i64, 2,
+        OpCode::ADD as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    let mut vm = VM::new(code, vec![], false);
+    assert_eq!(vm.run(), Ok(42));
+}
+
+#[test]
+fn test_opcode_decoding_and_arity() {
+    for (i, &op) in OpCode::ALL.iter().enumerate() {
+        assert_eq!(op as usize, i, "{:?} is out of encoding order", op);
+        assert_eq!(OpCode::from_i64(op as i64), Some(op));
+    }
+    assert_eq!(OpCode::from_i64(-1), None);
+    assert_eq!(OpCode::from_i64(OpCode::ALL.len() as i64), None);
+
+    assert_eq!(OpCode::IMM.arity(), 1);
+    assert_eq!(OpCode::PRTF.arity(), 1);
+    assert_eq!(OpCode::LEV.arity(), 0);
+    assert_eq!(OpCode::EXIT.arity(), 0);
+}
+
+#[test]
+fn test_vm_rejects_words_that_only_truncate_to_opcodes() {
+    // 256 used to be read as LEA after casting to u8
+    let mut vm = VM::new(vec![256, 0], vec![], false);
+    assert_eq!(vm.run(), Err(RuntimeError::UnknownOpcode { op: 256, pc: 0 }));
+}
+
+/// host that records output and serves one fake file
+struct RecordingHost {
+    out: Rc<RefCell<String>>,
+}
+
+impl Host for RecordingHost {
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        self.out.borrow_mut().push_str(text);
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str) -> i64 {
+        if path == "f" { 3 } else { -1 }
+    }
+
+    fn read(&mut self, fd: i64, buf: &mut [u8]) -> i64 {
+        if fd != 3 {
+            return -1;
+        }
+        buf[0] = b'Z';
+        1
+    }
+
+    fn close(&mut self, _fd: i64) -> i64 {
+        0
+    }
+
+    fn elapsed_us(&self) -> u64 {
+        7
+    }
+
+    fn sleep_us(&mut self, _micros: u64) {}
+}
+
+#[test]
+fn test_vm_syscalls_go_through_host() {
+    let source = "int main() { char buf[2]; int fd; fd = open(\"f\", 0); read(fd, buf, 1); printf(\"%c%d\", buf[0], clock()); return fd; }";
+    let options = CompileOptions::new();
+    let mut parser = c4_rust::parser::Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+
+    let out = Rc::new(RefCell::new(String::new()));
+    let host = RecordingHost { out: Rc::clone(&out) };
+    let mut vm = VM::with_host(code, data, &options, Box::new(host));
+    assert_eq!(vm.run(), Ok(3));
+    assert_eq!(out.borrow().as_str(), "Z7");
+}
+
+#[test]
+fn test_vm_passes_args_to_main() {
+    let source = "int main(int argc, char **argv) { return argc * 1000 + argv[1][1] * 10 + (argv[2][0] == 0); }";
+    let options = CompileOptions::new();
+    let mut parser = c4_rust::parser::Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+
+    let mut vm = VM::with_options(code, data, &options);
+    vm.set_args(&["prog".to_string(), "xy".to_string(), String::new()]);
+    assert_eq!(vm.run(), Ok(3000 + 'y' as i64 * 10 + 1));
+}
+
+#[test]
+fn test_vm_calls_host_functions() {
+    let source = "int main() { return mix(1, 2, 3) + mix(10, 0, 0); }";
+    let options = CompileOptions::new();
+    let mut parser = c4_rust::parser::Parser::new(source, options.clone());
+    let index = parser.add_host_fn("mix", 3);
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+
+    let mut vm = VM::with_options(code, data, &options);
+    assert_eq!(vm.add_host_fn(3, Box::new(|args| args[0] * 100 + args[1] * 10 + args[2])), index);
+    assert_eq!(vm.run(), Ok(123 + 1000));
+
+    // argument counts are checked at compile time
+    let mut parser = c4_rust::parser::Parser::new("int main() { return mix(1); }", options);
+    parser.add_host_fn("mix", 3);
+    parser.init().unwrap();
+    assert_eq!(parser.parse().unwrap_err(), "Line 1: 'mix' takes 3 arguments, got 1");
+}
+
+/// compiles a program that declares its host functions extern, parse does its own init
+fn compile_extern(source: &str) -> Result<Parser<'_>, String> {
+    let mut parser = Parser::new(source, false);
+    parser.parse()?;
+    Ok(parser)
+}
+
+#[test]
+fn test_extern_declarations_link_host_functions_by_name() {
+    let source = "extern int host_rand();\nextern int host_add(int a, int);\nextern void host_log(char *s);\n\
+                  int main() { host_log(\"hi\"); return host_add(host_rand(), 2); }";
+    let parser = compile_extern(source).unwrap();
+    assert_eq!(parser.host_fns(), [("host_rand".to_string(), 0), ("host_add".to_string(), 2), ("host_log".to_string(), 1)]);
+
+    let logged = Rc::new(RefCell::new(Vec::new()));
+    let mut provided = HostFns::new();
+    provided.insert("host_add".to_string(), (2, Box::new(|args: &[i64]| args[0] + args[1])));
+    provided.insert("host_rand".to_string(), (0, Box::new(|_: &[i64]| 40)));
+    let seen = Rc::clone(&logged);
+    provided.insert("host_log".to_string(), (1, Box::new(move |args: &[i64]| { seen.borrow_mut().push(args[0]); 0 })));
+    provided.insert("unused".to_string(), (0, Box::new(|_: &[i64]| 0)));
+
+    let mut vm = VM::new(parser.code().to_vec(), parser.data().to_vec(), false);
+    vm.link_host_fns(parser.host_fns(), provided).unwrap();
+    assert_eq!(vm.run(), Ok(42));
+    assert_eq!(logged.borrow().len(), 1);
+}
+
+#[test]
+fn test_extern_link_errors() {
+    let parser = compile_extern("extern int host_seed(int s);\nint main() { return host_seed(1); }").unwrap();
+    let mut vm = VM::new(parser.code().to_vec(), parser.data().to_vec(), false);
+    assert_eq!(vm.link_host_fns(parser.host_fns(), HostFns::new()).unwrap_err(), "undefined reference to host function 'host_seed'");
+    let mut provided = HostFns::new();
+    provided.insert("host_seed".to_string(), (2, Box::new(|_: &[i64]| 0)));
+    assert_eq!(vm.link_host_fns(parser.host_fns(), provided).unwrap_err(), "host function 'host_seed' takes 2 arguments, declared with 1");
+
+    // calls are checked against the declaration, and only functions can be extern
+    let error = |source| compile_extern(source).err().unwrap();
+    assert_eq!(error("extern int f(int a);\nint main() { return f(); }"), "Line 2: 'f' takes 1 arguments, got 0");
+    assert_eq!(error("extern int count;\nint main() { return 0; }"), "Line 1: only functions can be extern, expected '(' after the name");
+    assert_eq!(error("int f() { return 1; }\nextern int f();\nint main() { return 0; }"), "Line 2: 'f' is already defined, it can't be extern");
+}
+
+#[test]
+fn test_extern_matches_a_registered_host_function() {
+    let mut parser = Parser::new("extern int mix(int, int, int);\nint main() { return mix(1, 2, 3); }", false);
+    let index = parser.add_host_fn("mix", 3);
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(parser.host_fns().len(), 1);
+    let mut vm = VM::new(code, data, false);
+    assert_eq!(vm.add_host_fn(3, Box::new(|args| args[0] * 100 + args[1] * 10 + args[2])), index);
+    assert_eq!(vm.run(), Ok(123));
+
+    let mut parser = Parser::new("extern int mix(int a);\nint main() { return 0; }", false);
+    parser.add_host_fn("mix", 3);
+    assert_eq!(parser.parse().unwrap_err(), "Line 1: host function 'mix' takes 3 arguments, declared with 1");
+}
+
+/// observer that logs every event as a line
+struct EventLog(Rc<RefCell<Vec<String>>>);
+
+impl Observer for EventLog {
+    fn on_call(&mut self, name: Option<&str>, _addr: usize) {
+        self.0.borrow_mut().push(format!("call {}", name.unwrap_or("?")));
+    }
+
+    fn on_return(&mut self, value: i64) {
+        self.0.borrow_mut().push(format!("return {}", value));
+    }
+
+    fn on_syscall(&mut self, name: &str, args: &[i64]) {
+        self.0.borrow_mut().push(format!("{} {:?}", name, args));
+    }
+
+    fn on_store(&mut self, _addr: usize, value: i64) {
+        self.0.borrow_mut().push(format!("store {}", value));
+    }
+}
+
+#[test]
+fn test_vm_observers_see_calls_returns_syscalls_and_stores() {
+    let source = "int g; int twice(int x) { return x * 2; } int main() { g = twice(21); memset(0, 0, 0); return g; }";
+    let options = CompileOptions::new();
+    let mut parser = c4_rust::parser::Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::with_options(code, data, &options);
+    vm.set_functions(parser.functions().to_vec());
+    vm.add_observer(Box::new(EventLog(Rc::clone(&events))));
+    assert_eq!(vm.run(), Ok(42));
+    assert_eq!(*events.borrow(), ["call main", "call twice", "return 42", "store 42", "memset [0, 0, 0]", "return 42", "exit [42]"]);
+}
+
+#[test]
+fn test_vm_explain_describes_events_up_to_the_limit() {
+    let source = "int g; int add(int a, int b) { int s; s = a + b; return s; } int main() { g = add(3, 4); printf(\"%d\\n\", g); return 0; }";
+    let options = CompileOptions::new().explain(7);
+    let mut parser = c4_rust::parser::Parser::new(source, options.clone());
+    parser.init().unwrap();
+    let (code, data) = parser.parse().unwrap();
+
+    let out = Rc::new(RefCell::new(String::new()));
+    let mut vm = VM::with_host(code, data, &options, Box::new(RecordingHost { out: Rc::clone(&out) }));
+    vm.set_functions(parser.functions().to_vec());
+    vm.set_globals(parser.get_symbols());
+    assert_eq!(vm.run(), Ok(0));
+    assert_eq!(out.borrow().as_str(), "\
+[explain] (entry): call function main with 0 arguments
+[explain] main: call function add with 2 arguments
+[explain] add: store 7 into local 's'
+[explain] add: return 7
+[explain] main: store 7 into global 'g'
+[explain] main: call printf(\"%d\\n\", 7)
+7
+[explain] main: return 0
+[explain] stopping after 7 events
+");
+}
+
+#[test]
+fn test_vm_run_steps_reports_what_changed() {
+    // *8 = 42; return 42
+    let code = vec![
+        OpCode::IMM as i64, 8,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 42,
+        OpCode::SI as i64,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+    ];
+    let mut vm = VM::new(code, vec![0u8; 16], false);
+
+    let first = vm.run_steps(1).unwrap();
+    assert_eq!(first.steps, 1);
+    assert_eq!(first.registers, [("pc", 2), ("ax", 8)]);
+    assert!(first.memory.is_empty());
+
+    let push = vm.run_steps(1).unwrap();
+    let sp = push.registers.iter().find(|r| r.0 == "sp").unwrap().1 as usize;
+    assert_eq!(push.memory, [(sp, 8)]);
+
+    let store = vm.run_steps(2).unwrap();
+    assert_eq!(store.memory.len(), 8);
+    assert_eq!(store.memory[0], (8, 42));
+    assert_eq!(store.exit_code, None);
+
+    let done = vm.run_steps(100).unwrap();
+    assert_eq!((done.steps, done.exit_code), (2, Some(42)));
+    assert_eq!(vm.run_steps(1).unwrap().steps, 0);
+}
+
+#[test]
+fn test_vm_ent_reserves_exactly_its_operand() {
+    // JSR f; PSH; EXIT; f: ENT 3; IMM 7; LEV
+    let code = vec![
+        OpCode::JSR as i64, 4,
+        OpCode::PSH as i64,
+        OpCode::EXIT as i64,
+        OpCode::ENT as i64, 3,
+        OpCode::IMM as i64, 7,
+        OpCode::LEV as i64,
+    ];
+    let mut vm = VM::new(code, vec![], false);
+    let entered = vm.run_steps(2).unwrap();
+    let reg = |name: &str| entered.registers.iter().find(|r| r.0 == name).unwrap().1;
+    assert_eq!(reg("bp") - reg("sp"), 3);
+    assert_eq!(vm.run_steps(100).unwrap().exit_code, Some(7));
+}
+
+#[test]
+fn test_vm_fixed_stack_overflows_cleanly() {
+    // f: JSR f, recursing until the stack is full
+    let options = CompileOptions::new().stack_size(64);
+    let mut vm = VM::with_options(vec![OpCode::JSR as i64, 0], vec![], &options);
+    assert_eq!(vm.run(), Err(RuntimeError::StackOverflow { op: "JSR" }));
+
+    // a frame bigger than the stack
+    let code = vec![OpCode::ENT as i64, 100, OpCode::LEV as i64];
+    let mut vm = VM::with_options(code, vec![], &options);
+    assert_eq!(vm.run(), Err(RuntimeError::StackOverflow { op: "ENT" }));
+}
+
+#[test]
+fn test_vm_stack_never_grows() {
+    // storing past the top of the stack fails instead of growing it
+    let options = CompileOptions::new().stack_size(64);
+    let top = c4_rust::vm::DATA_STACK_THRESHOLD as i64 + 64;
+    let code = vec![
+        OpCode::IMM as i64, top,
+        OpCode::PSH as i64,
+        OpCode::IMM as i64, 1,
+        OpCode::SI as i64,
+    ];
+    let mut vm = VM::with_options(code, vec![], &options);
+    assert_eq!(vm.run(), Err(RuntimeError::OobWrite { addr: top as usize, size: top as usize }));
+
+    // and so does dropping more than it holds
+    let mut vm = VM::with_options(vec![OpCode::ADJ as i64, 100], vec![], &options);
+    assert!(matches!(vm.run(), Err(RuntimeError::StackUnderflow { .. })));
+}
+
+#[test]
+fn test_code_packs_one_entry_per_instruction() {
+    let code = vec![OpCode::IMM as i64, 7, OpCode::PSH as i64, 99, OpCode::JMP as i64, 1, OpCode::JMP as i64, 2, OpCode::JMP as i64];
+    let packed = Code::decode(&code);
+    let instructions = packed.instructions();
+    assert_eq!(instructions.len(), 6);
+    assert_eq!((instructions[0].op, instructions[0].operand), (Some(OpCode::IMM), 7));
+    assert_eq!((instructions[1].op, instructions[1].operand), (Some(OpCode::PSH), 0));
+    // a word that isn't an opcode stands alone
+    assert_eq!((instructions[2].op, instructions[2].operand), (None, 99));
+    // jumps keep their word address operand and land on an index, not inside an instruction
+    assert_eq!((instructions[3].operand, instructions[3].target()), (1, None));
+    assert_eq!((instructions[4].operand, instructions[4].target()), (2, Some(1)));
+    // the opcode at the end has no operand word
+    assert_eq!((instructions[5].op, instructions[5].operand), (Some(OpCode::JMP), 0));
+    assert_eq!((0..=6).map(|i| packed.addr(i)).collect::<Vec<_>>(), [0, 2, 3, 4, 6, 8, 9]);
+    assert_eq!((packed.index_of(6), packed.index_of(7)), (Some(4), None));
+    assert_eq!(std::mem::size_of::<Instruction>(), 16);
+}
+
+#[test]
+fn test_vm_missing_operand_at_end_of_code() {
+    let mut vm = VM::new(vec![OpCode::IMM as i64], vec![], false);
+    assert_eq!(vm.run(), Err(RuntimeError::MissingOperand { pc: 0 }));
+}
+
+#[test]
+fn test_vm_jump_past_end_of_code() {
+    let code = vec![OpCode::IMM as i64, 1, OpCode::BNZ as i64, 40];
+    let mut vm = VM::new(code, vec![], false);
+    let err = vm.run().unwrap_err();
+    assert_eq!(err, RuntimeError::InvalidJump { target: 40, pc: 2, line: None });
+    assert_eq!(err.to_string(), "invalid jump target 40 at 2");
+}
+
+#[test]
+fn test_vm_jump_to_entry_stub_names_the_line() {
+    let source = "int main() {\n    int x;\n    x = 1;\n    while (x) {\n        x = 0;\n    }\n    return 2;\n}\n";
+    let mut parser = Parser::new(source, false);
+    parser.init().unwrap();
+    let (mut code, data) = parser.parse().unwrap();
+    let main = parser.functions().iter().find(|f| f.name == "main").unwrap();
+    assert_eq!(parser.lines().first(), Some(&(main.start, 1)));
+
+    // break the loop exit the way a wrong backpatch would
+    let mut pc = main.start;
+    while OpCode::from_i64(code[pc]) != Some(OpCode::BZ) {
+        pc += 1 + OpCode::from_i64(code[pc]).unwrap().arity();
+    }
+    code[pc + 1] = 0;
+    let mut vm = VM::new(code, data, false);
+    vm.set_functions(parser.functions().to_vec());
+    vm.set_lines(parser.lines().to_vec());
+    assert_eq!(vm.line_of(pc), Some(4));
+    let err = vm.run().unwrap_err();
+    assert_eq!(err, RuntimeError::InvalidJump { target: 0, pc, line: Some(4) });
+    assert_eq!(err.to_string(), format!("invalid jump target 0 at {} (line 4)", pc));
+}
+
+#[test]
+fn test_compile_and_load_set_up_the_vm_like_every_driver() {
+    let options = CompileOptions::new();
+    assert_eq!(c4_rust::vm::run("int main() { return 6 * 7; }", &options), Ok(42));
+    assert_eq!(c4_rust::vm::run("int main() { return x; }", &options), Err("Line 1: Unknown variable 'x'".to_string()));
+
+    // host functions are declared before the parse and bound by name when loading
+    let source = "int zero;\nint main() { return twice(20) + 2 / zero; }";
+    let (parser, compiled) = c4_rust::vm::compile(source, &options, &[("twice".to_string(), 1)]);
+    let (code, data) = compiled.unwrap();
+    let load = |provided| VM::load(&parser, code.clone(), data.clone(), &options, Box::new(c4_rust::host::NullHost), provided);
+    assert_eq!(load(HostFns::new()).err().as_deref(), Some("undefined reference to host function 'twice'"));
+    let mut provided = HostFns::new();
+    provided.insert("twice".to_string(), (1, Box::new(|args: &[i64]| args[0] * 2)));
+    let mut vm = load(provided).unwrap();
+    assert!(vm.run().unwrap_err().to_string().starts_with("division by zero"));
+    assert_eq!(vm.frames()[0].function, "main");
+    assert_eq!(vm.line_of(vm.frames()[0].pc), Some(2));
+}
+
+#[test]
+fn test_vms_share_a_program_until_they_write_its_data() {
+    let options = CompileOptions::new();
+    let source = "int count = 5;\nint peek() { return count; }\nint bump() { count = count + 1; return count; }\nint main() { return bump() + bump(); }";
+    let (parser, compiled) = c4_rust::vm::compile(source, &options, &[]);
+    let (code, data) = compiled.unwrap();
+    let program = SharedProgram::new(&code, data);
+    let load = || VM::load_shared(&parser, &program, &options, Box::new(c4_rust::host::NullHost), HostFns::new()).unwrap();
+
+    // reading the globals doesn't copy them
+    let peek = parser.functions().iter().find(|f| f.name == "peek").unwrap().start;
+    let mut reader = load();
+    reader.set_entry(peek);
+    assert_eq!(reader.run(), Ok(5));
+    assert!(reader.shares_data());
+
+    // a write copies them for that VM alone
+    let mut writer = load();
+    assert!(writer.shares_data());
+    assert_eq!(writer.run(), Ok(13));
+    assert!(!writer.shares_data());
+    assert_eq!(load().run(), Ok(13));
+    let mut reader = load();
+    reader.set_entry(peek);
+    assert_eq!(reader.run(), Ok(5));
+
+    // an entry that isn't an instruction fails before anything runs
+    let mut lost = load();
+    lost.set_entry(peek + 1);
+    assert!(matches!(lost.run(), Err(RuntimeError::InvalidJump { .. })));
+}
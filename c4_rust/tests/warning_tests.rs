@@ -0,0 +1,224 @@
+use c4_rust::options::{CompileOptions, Warning};
+use c4_rust::parser::Parser;
+use c4_rust::vm;
+use std::process::Command;
+
+const NOISY: &str = "int main() {\n  char c;\n  c = 300;\n  printf(\"%d\\n\");\n  return 0;\n}";
+
+/// compiles source, returning the warnings and whether it compiled
+fn compile(source: &str, options: CompileOptions) -> (Vec<String>, Result<(), String>) {
+    let (parser, compiled) = vm::compile(source, &options, &[]);
+    let result = compiled.map(|_| ());
+    (parser.warnings().to_vec(), result)
+}
+
+#[test]
+fn test_warning_names() {
+    for w in Warning::ALL {
+        assert_eq!(w.name().parse(), Ok(w));
+        assert!(CompileOptions::new().warnings.enabled(w) == w.enabled_by_default());
+    }
+    assert_eq!("bogus".parse::<Warning>(), Err("unknown warning 'bogus'".to_string()));
+}
+
+#[test]
+fn test_warning_flags() {
+    let options = CompileOptions::new().warning_flag("-Wno-format").unwrap();
+    assert!(!options.warnings.enabled(Warning::Format) && options.warnings.enabled(Warning::Conversion));
+    let options = options.warning_flag("-Wformat").unwrap();
+    assert!(options.warnings.enabled(Warning::Format));
+    let options = CompileOptions::new().warning_flag("-Wno-format").unwrap()
+        .warning_flag("-Wno-conversion").unwrap()
+        .warning_flag("-Wall").unwrap();
+    assert_eq!(options.warnings, CompileOptions::new().warning_flag("-Wall").unwrap().warnings);
+    assert!(Warning::ALL.iter().all(|&w| options.warnings.enabled(w)));
+    assert!(CompileOptions::new().warning_flag("-Werror").unwrap().werror);
+    assert!(!CompileOptions::new().werror(true).warning_flag("-Wno-error").unwrap().werror);
+    assert_eq!(CompileOptions::new().warning_flag("-Wno-bogus"), Err("unknown warning 'bogus'".to_string()));
+}
+
+#[test]
+fn test_disabled_warnings_are_not_reported() {
+    let (warnings, result) = compile(NOISY, CompileOptions::new());
+    assert_eq!(warnings.len(), 2);
+    assert!(result.is_ok());
+
+    let (warnings, _) = compile(NOISY, CompileOptions::new().warning(Warning::Conversion, false));
+    assert_eq!(warnings, ["Line 4: printf format expects 1 argument, got 0"]);
+    let (warnings, _) = compile(NOISY, CompileOptions::new().warning(Warning::Format, false));
+    assert_eq!(warnings, ["Line 3: constant 300 does not fit in a char, it becomes 44"]);
+}
+
+#[test]
+fn test_werror_fails_the_compile() {
+    let (warnings, result) = compile(NOISY, CompileOptions::new().werror(true));
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(result, Err("2 warnings treated as errors (-Werror)".to_string()));
+
+    // nothing left to report, nothing to fail on
+    let quiet = CompileOptions::new().werror(true).warning(Warning::Format, false).warning(Warning::Conversion, false);
+    assert_eq!(compile(NOISY, quiet), (vec![], Ok(())));
+}
+
+#[test]
+fn test_cli_warning_flags() {
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_c4_rust")).args(args).arg("-e").arg(NOISY).output().unwrap();
+    let output = run(&["--color=never", "-Wno-conversion"]);
+    let err = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success());
+    assert_eq!(err, "<command line>:4: warning: printf format expects 1 argument, got 0\n");
+
+    let output = run(&["--color=never", "-Werror"]);
+    let err = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(err.ends_with("<command line>: error: 2 warnings treated as errors (-Werror)\n"), "{}", err);
+
+    let output = run(&["-Wnope"]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "unknown warning 'nope'\n");
+}
+
+#[test]
+fn test_statement_after_return() {
+    let source = "int f(int x) {\n  return x;\n  x = 1;\n  return 2;\n}\nint main() { return f(1); }";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert_eq!(warnings, ["Line 3: statement after 'return' is never executed"]);
+    assert!(result.is_ok());
+
+    // a case label makes what follows reachable again, an empty statement is no code
+    let switch = "int f(int x) {\n  switch (x) {\n  case 1: return 1;\n  case 2: x = 3;\n  }\n  return x;;\n}\nint main() { return f(2); }";
+    let options = CompileOptions::new().lang(c4_rust::options::Lang::C4x);
+    assert_eq!(compile(switch, options).0, Vec::<String>::new());
+    let (warnings, _) = compile(source, CompileOptions::new().warning(Warning::Unreachable, false));
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_missing_return_uses_the_control_flow() {
+    let source = "int sign(int x) {
+  if (x < 0) return -1;
+  if (x > 0) return 1;
+}
+int spin(int x) {
+  while (1) { if (x > 3) return x; x = x + 1; }
+}
+int pick(int x) {
+  if (x) return 1; else return 2;
+}
+void hello() { printf(\"hi\\n\"); }
+int main() { hello(); return sign(2) + spin(0) + pick(0); }";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert_eq!(warnings, ["Line 1: control can reach the end of non-void function 'sign' without a return"]);
+    assert!(result.is_ok());
+    let (_, result) = compile(source, CompileOptions::new().werror(true));
+    assert!(result.is_err());
+    assert!(compile(source, CompileOptions::new().warning(Warning::Return, false)).0.is_empty());
+}
+
+#[test]
+fn test_declarations_need_a_type() {
+    let source = "int x;\ncount, *p;\nmain() { return count; }";
+    let (_, result) = compile(source, CompileOptions::new());
+    assert_eq!(result, Err("Line 2: 'count' is declared without a type, write 'int count' if an int is meant".to_string()));
+
+    // the old default to int, once per declaration
+    let (warnings, result) = compile(source, CompileOptions::new().implicit_int(true));
+    assert!(result.is_ok());
+    assert_eq!(warnings, [
+        "Line 2: 'count' is declared without a type, it defaults to int",
+        "Line 3: 'main' is declared without a type, it defaults to int",
+    ]);
+    let options = CompileOptions::new().implicit_int(true).warning(Warning::ImplicitInt, false);
+    assert_eq!(compile(source, options).0, Vec::<String>::new());
+}
+
+#[test]
+fn test_old_style_and_trailing_comma_warnings() {
+    let source = "enum { A, B, };\nint f();\nint f(a) int a; { return a; }\nint main() { return f(B); }";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert!(result.is_ok());
+    assert_eq!(warnings, [
+        "Line 2: 'f' is declared without a prototype, write 'f(void)' if it takes no arguments",
+        "Line 3: 'f' has an old-style parameter list, declare the parameters inside the parentheses",
+    ]);
+
+    // C99 allows the enum's trailing comma, so only -Wall or -Wtrailing-comma mention it
+    let options = CompileOptions::new().warning_flag("-Wall").unwrap();
+    assert_eq!(compile(source, options).0[0], "Line 1: comma after the last enum constant, C89 doesn't allow it");
+    let options = CompileOptions::new().warning(Warning::OldStyle, false);
+    assert_eq!(compile(source, options).0, Vec::<String>::new());
+}
+
+#[test]
+fn test_assignment_as_condition() {
+    let source = "int main() {
+  int x, a[2];
+  if (x = 5) x = 1;
+  while ((x = 0)) ;
+  for (; x = 0;) ;
+  if (a[x = 0] == 1) return 2;
+  return x;
+}";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert!(result.is_ok());
+    assert_eq!(warnings, [
+        "Line 3: assignment used as the if condition, write '==' to compare or wrap it in parentheses",
+        "Line 5: assignment used as the for condition, write '==' to compare or wrap it in parentheses",
+    ]);
+    let options = CompileOptions::new().warning_flag("-Wno-parentheses").unwrap();
+    assert_eq!(compile(source, options).0, Vec::<String>::new());
+}
+
+#[test]
+fn test_comparison_chains() {
+    let source = "int main() {
+  int a, b, c;
+  a = 1; b = 5; c = 3;
+  if (a < b < c) return 1;
+  if ((a < b) < c) return 2;
+  if (a == b != c) return 3;
+  if (a < b == c) return 4;
+  return 0;
+}";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert!(result.is_ok());
+    assert_eq!(warnings, [
+        "Line 4: '<' after '<' compares the first comparison's result, 0 or 1, not its right operand; compare each pair separately",
+        "Line 6: '!=' after '==' compares the first comparison's result, 0 or 1, not its right operand; compare each pair separately",
+    ]);
+    let options = CompileOptions::new().warning_flag("-Wno-comparison-chain").unwrap();
+    assert_eq!(compile(source, options).0, Vec::<String>::new());
+    // pedantic makes the first chain an error
+    let (_, result) = compile(source, CompileOptions::new().pedantic(true));
+    assert_eq!(result, Err("Line 4: '<' after '<' compares the first comparison's result, 0 or 1, not its right operand; compare each pair separately".to_string()));
+}
+
+#[test]
+fn test_definitions_shadow_builtins() {
+    let source = "int read;
+int main() { read = 2; return free(read) + malloc(1); }
+int free(int x) { return x * 10; }
+enum { open = 300 };
+int malloc(int n) { return open + n; }";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert_eq!(result, Ok(()));
+    assert_eq!(warnings, [
+        "Line 1: 'read' replaces the builtin function of that name for the whole program",
+        "Line 3: 'free' replaces the builtin function of that name for the whole program",
+        "Line 4: 'open' replaces the builtin function of that name for the whole program",
+        "Line 5: 'malloc' replaces the builtin function of that name for the whole program",
+    ]);
+    let mut parser = Parser::new(source, false);
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(c4_rust::vm::VM::new(code, data, false).run(), Ok(321));
+
+    // a prototype alone keeps the builtin, and a local hides it only in its block
+    let source = "int exit(int code);\nint main() { int open; open = 1; exit(7); return open; }";
+    let (warnings, result) = compile(source, CompileOptions::new());
+    assert_eq!((warnings, result), (Vec::new(), Ok(())));
+    let mut parser = Parser::new(source, false);
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(c4_rust::vm::VM::new(code, data, false).run(), Ok(7));
+    let options = CompileOptions::new().warning_flag("-Wno-shadow-builtin").unwrap();
+    assert_eq!(compile("int free;", options).0, Vec::<String>::new());
+}